@@ -0,0 +1,205 @@
+//! Throughput benchmarks on generated workloads.
+//!
+//! Covers three stages separately so regressions can be attributed:
+//!
+//! - `parse`: reading transactions from CSV (serde and `--fast-parse` paths, each
+//!   also with `--parallel-parse`)
+//! - `engine`: applying already parsed transactions
+//! - `end_to_end`: reading, processing and writing the account summaries
+//!
+//! Each stage runs on generated datasets with different dispute rates. The
+//! number of rows defaults to one million; set `BENCH_ROWS` to a comma-separated
+//! list to run other sizes, e.g. `BENCH_ROWS=1000000,10000000 cargo bench`.
+//! Generated files are cached in the system temp directory.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use project_diamond_hands::engine::Engine;
+use project_diamond_hands::io::{self, OutputOptions};
+use project_diamond_hands::types::Transaction;
+
+/// Share of rows that are disputes, resolves or chargebacks.
+const DISPUTE_RATES: [u32; 3] = [0, 5, 20];
+
+/// Number of distinct clients in the generated workloads.
+const CLIENTS: u64 = 10_000;
+
+/// A deterministic xorshift generator, so every run benchmarks the same data.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+fn bench_rows() -> Vec<u64> {
+    std::env::var("BENCH_ROWS")
+        .ok()
+        .map(|rows| {
+            rows.split(',')
+                .map(|row| {
+                    row.trim()
+                        .parse()
+                        .expect("BENCH_ROWS must be a list of integers")
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| vec![1_000_000])
+}
+
+/// Returns the path of a workload with `rows` rows, generating it if needed.
+///
+/// `dispute_rate` percent of the rows are disputes, resolves and chargebacks
+/// (in a 6:3:1 ratio) referencing earlier deposits; the rest are deposits and
+/// withdrawals in a 3:1 ratio.
+fn workload(rows: u64, dispute_rate: u32) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("diamond-hands-bench-{}-{}.csv", rows, dispute_rate));
+    if path.exists() {
+        return path;
+    }
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d ^ rows ^ u64::from(dispute_rate));
+    let mut writer = BufWriter::new(File::create(&path).expect("create workload"));
+    let mut deposits: Vec<(u64, u64)> = Vec::new();
+    writeln!(writer, "type,client,tx,amount").unwrap();
+
+    for tx in 1..=rows {
+        let client = rng.below(CLIENTS) + 1;
+        let amount = format!("{}.{:04}", rng.below(1_000), rng.below(10_000));
+        let roll = rng.below(100) as u32;
+
+        if roll < dispute_rate && !deposits.is_empty() {
+            let (client, deposit) = deposits[rng.below(deposits.len() as u64) as usize];
+            let kind = match rng.below(10) {
+                0..=5 => "dispute",
+                6..=8 => "resolve",
+                _ => "chargeback",
+            };
+            writeln!(writer, "{},{},{},", kind, client, deposit).unwrap();
+        } else if rng.below(4) == 0 {
+            writeln!(writer, "withdrawal,{},{},{}", client, tx, amount).unwrap();
+        } else {
+            writeln!(writer, "deposit,{},{},{}", client, tx, amount).unwrap();
+            deposits.push((client, tx));
+        }
+    }
+
+    writer.flush().unwrap();
+    path
+}
+
+fn read_all(path: &str, fast_parse: bool) -> Vec<Transaction> {
+    io::read_transactions_from_file(path)
+        .unwrap()
+        .fast_parse(fast_parse)
+        .map(|result| result.unwrap())
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+
+    for rows in bench_rows() {
+        let path = workload(rows, 5);
+        let path = path.to_str().unwrap();
+        group.throughput(Throughput::Elements(rows));
+        for (name, fast_parse, parallel_parse) in [
+            ("serde", false, false),
+            ("fast", true, false),
+            ("serde-parallel", false, true),
+            ("fast-parallel", true, true),
+        ] {
+            group.bench_with_input(BenchmarkId::new(name, rows), path, |b, path| {
+                b.iter(|| {
+                    for result in io::read_transactions_from_file(path)
+                        .unwrap()
+                        .fast_parse(fast_parse)
+                        .parallel_parse(parallel_parse)
+                    {
+                        black_box(result.unwrap());
+                    }
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn engine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine");
+    group.sample_size(10);
+
+    for rows in bench_rows() {
+        group.throughput(Throughput::Elements(rows));
+        for dispute_rate in DISPUTE_RATES {
+            let path = workload(rows, dispute_rate);
+            let transactions = read_all(path.to_str().unwrap(), true);
+            let id = BenchmarkId::new(format!("disputes-{}%", dispute_rate), rows);
+            group.bench_with_input(id, &transactions, |b, transactions| {
+                b.iter_batched(
+                    || transactions.clone(),
+                    |transactions| {
+                        let mut engine = Engine::default();
+                        for tx in transactions {
+                            engine.process(tx).unwrap();
+                        }
+                        black_box(engine.into_accounts())
+                    },
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("end_to_end");
+    group.sample_size(10);
+
+    for rows in bench_rows() {
+        group.throughput(Throughput::Elements(rows));
+        for dispute_rate in DISPUTE_RATES {
+            let path = workload(rows, dispute_rate);
+            let id = BenchmarkId::new(format!("disputes-{}%", dispute_rate), rows);
+            group.bench_with_input(id, path.to_str().unwrap(), |b, path| {
+                b.iter(|| {
+                    let mut engine = Engine::default();
+                    for tx in io::read_transactions_from_file(path)
+                        .unwrap()
+                        .fast_parse(true)
+                    {
+                        engine.process(tx.unwrap()).unwrap();
+                    }
+                    io::write_accounts_as_csv(
+                        engine.into_accounts(),
+                        std::io::sink(),
+                        &OutputOptions::default(),
+                    )
+                    .unwrap()
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, engine, end_to_end);
+criterion_main!(benches);
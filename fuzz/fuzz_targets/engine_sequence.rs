@@ -0,0 +1,110 @@
+//! Applies arbitrary transaction sequences under arbitrary engine options and
+//! checks that the accounts keep their invariants.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use project_diamond_hands::amount::parse_amount;
+use project_diamond_hands::engine::{
+    self, DisputePolicy, Engine, EngineConfig, LockPolicy, LockedOperations,
+};
+use project_diamond_hands::types::{Amount, ClientId, Transaction, TxId, TxType};
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    credit_limit: FuzzAmount,
+    dispute_policy: u8,
+    allow_redispute: bool,
+    lock_after: u8,
+    locked_operations: (bool, bool, bool),
+    authorization_expiry: Option<u8>,
+    transactions: Vec<FuzzTransaction>,
+}
+
+/// An amount of up to four decimal places, e.g. `(-12345, 2)` for `-123.45`.
+#[derive(Debug, Arbitrary)]
+struct FuzzAmount(i64, u8);
+
+impl FuzzAmount {
+    fn amount(&self) -> Option<Amount> {
+        let scale = usize::from(self.1 % 5);
+        let digits = format!("{:0>5}", self.0.unsigned_abs());
+        let (integer, fraction) = digits.split_at(digits.len() - scale);
+        let sign = if self.0 < 0 { "-" } else { "" };
+        parse_amount(&format!("{}{}.{}", sign, integer, fraction)).ok()
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+struct FuzzTransaction {
+    tx_type: u8,
+    // Few clients and transactions, so that they refer to each other.
+    client: u8,
+    tx: u8,
+    amount: FuzzAmount,
+}
+
+const TX_TYPES: [TxType; 14] = [
+    TxType::Deposit,
+    TxType::Withdrawal,
+    TxType::Dispute,
+    TxType::Resolve,
+    TxType::Chargeback,
+    TxType::Refund,
+    TxType::Authorize,
+    TxType::Capture,
+    TxType::Close,
+    TxType::Hold,
+    TxType::Release,
+    TxType::Interest,
+    TxType::Open,
+    TxType::OpenLocked,
+];
+
+fuzz_target!(|input: Input| {
+    let Some(credit_limit) = input.credit_limit.amount() else {
+        return;
+    };
+    let (dispute, resolve, chargeback) = input.locked_operations;
+    let mut engine = Engine::new(EngineConfig {
+        credit_limit: credit_limit.abs(),
+        dispute_policy: match input.dispute_policy % 3 {
+            0 => DisputePolicy::RequireAvailable,
+            1 => DisputePolicy::AllowNegativeAvailable,
+            _ => DisputePolicy::HoldPartial,
+        },
+        allow_redispute: input.allow_redispute,
+        lock_policy: match input.lock_after {
+            0 => LockPolicy::Never,
+            1 => LockPolicy::Immediate,
+            threshold => LockPolicy::AfterChargebacks(u32::from(threshold % 4)),
+        },
+        locked_operations: LockedOperations {
+            dispute,
+            resolve,
+            chargeback,
+        },
+        authorization_expiry: input.authorization_expiry.map(u64::from),
+        ..Default::default()
+    });
+
+    for fuzz_tx in &input.transactions {
+        let Some(amount) = fuzz_tx.amount.amount() else {
+            continue;
+        };
+        let tx = Transaction {
+            tx_type: TX_TYPES[usize::from(fuzz_tx.tx_type) % TX_TYPES.len()],
+            client: ClientId((fuzz_tx.client % 4).into()),
+            tx: TxId((fuzz_tx.tx % 16).into()),
+            amount,
+        };
+        // Overflowing balances are reported as errors, which end the run.
+        if engine.process(tx).is_err() {
+            return;
+        }
+    }
+
+    let violations = engine::check_invariants(engine.accounts());
+    assert!(violations.is_empty(), "{:?}", violations);
+});
@@ -0,0 +1,28 @@
+//! Feeds arbitrary bytes as an input file to both transaction parsers and
+//! applies whatever they accept.
+//!
+//! The reader re-reads records from the file for quarantine and error messages,
+//! so the input is written to a file rather than parsed from memory.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use project_diamond_hands::engine::{Engine, EngineConfig};
+use project_diamond_hands::io::read_transactions_from_file;
+
+fuzz_target!(|data: &[u8]| {
+    let path = std::env::temp_dir().join(format!("fuzz-transactions-{}.csv", std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let path = path.to_str().unwrap();
+
+    for fast_parse in [false, true] {
+        let Ok(reader) = read_transactions_from_file(path) else {
+            return;
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        // A file of n bytes holds at most n + 1 records.
+        for tx in reader.fast_parse(fast_parse).take(data.len() + 1).flatten() {
+            let _ = engine.process(tx);
+        }
+    }
+});
@@ -0,0 +1,68 @@
+/*
+ * C interface of the project-diamond-hands transaction engine.
+ *
+ * Build the shared library with the `ffi` feature:
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Every function returning int returns one of the DH_* status codes. Negative
+ * codes are errors, whose message dh_engine_last_error returns.
+ */
+#ifndef DIAMOND_HANDS_H
+#define DIAMOND_HANDS_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The transaction was applied. */
+#define DH_OK 0
+/* The transaction was valid but ignored by the processing rules. */
+#define DH_IGNORED 1
+/* A required pointer argument was null. */
+#define DH_NULL_POINTER (-1)
+/* A string argument was not valid UTF-8. */
+#define DH_INVALID_UTF8 (-2)
+/* The CSV record could not be parsed. */
+#define DH_PARSE_ERROR (-3)
+/* The engine failed, e.g. on an overflowing balance. */
+#define DH_ENGINE_ERROR (-4)
+/* The buffer is too small for the output; the required size was stored. */
+#define DH_BUFFER_TOO_SMALL (-5)
+
+/* An engine handle. */
+typedef struct DhEngine DhEngine;
+
+/* Creates an engine with the default configuration. */
+DhEngine *dh_engine_new(void);
+
+/* Releases an engine. Null is ignored. */
+void dh_engine_free(DhEngine *engine);
+
+/*
+ * Applies the transaction of one `type,client,tx,amount` CSV record without a
+ * header line, e.g. "deposit,1,1,10.0". Returns DH_OK, DH_IGNORED or an error.
+ */
+int dh_engine_apply_csv_line(DhEngine *engine, const char *line);
+
+/*
+ * Writes the accounts as NUL-terminated CSV text into buffer. The size of the
+ * text including the NUL is stored in *size; if it exceeds capacity, nothing
+ * is written and DH_BUFFER_TOO_SMALL is returned. Pass a null buffer and a
+ * capacity of 0 to query the size.
+ */
+int dh_engine_export_accounts_csv(DhEngine *engine, char *buffer, size_t capacity, size_t *size);
+
+/*
+ * Returns the message of the last error of engine, or null if there was none.
+ * The string is valid until the next call with engine.
+ */
+const char *dh_engine_last_error(const DhEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DIAMOND_HANDS_H */
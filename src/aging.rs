@@ -0,0 +1,235 @@
+//! Aging of open disputes (`--dispute-aging-report`).
+//!
+//! Card schemes give a deadline to answer a dispute, so disputes that stay open
+//! too long need attention. [`DisputeAging`] notes when every dispute of a run
+//! was opened and lists the disputes still open at its end that are older than
+//! an [`AgeThreshold`].
+//!
+//! The input has no timestamps, so ages are measured in days between effective
+//! dates, for inputs with an `effective_date` column, or in input records as a
+//! proxy. A dispute opened on a record without an effective date counts as
+//! opened on the latest date seen before it. The age of a dispute that was
+//! already open when the run started, e.g. in a `--snapshot`, is unknown, and
+//! such disputes are always listed.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::engine::{Decision, DepositRecord};
+use crate::types::{Amount, ClientId, Date, DisputeState, TxId};
+
+/// The age above which open disputes are reported, written `30d` for days or
+/// `5000` for input records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeThreshold {
+    /// Days between the effective date of the dispute and the end of the run.
+    Days(u64),
+    /// Input records processed after the dispute.
+    Records(u64),
+}
+
+impl AgeThreshold {
+    /// Returns the unit of the age, as used in the report header.
+    pub fn unit(self) -> &'static str {
+        match self {
+            AgeThreshold::Days(_) => "days",
+            AgeThreshold::Records(_) => "records",
+        }
+    }
+
+    fn value(self) -> u64 {
+        match self {
+            AgeThreshold::Days(value) | AgeThreshold::Records(value) => value,
+        }
+    }
+}
+
+impl std::str::FromStr for AgeThreshold {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let invalid = || {
+            format!(
+                "invalid dispute age '{}', expected a number of days such as 30d or of input records such as 5000",
+                value
+            )
+        };
+        match value.strip_suffix('d') {
+            Some(days) => days.parse().map(AgeThreshold::Days).map_err(|_| invalid()),
+            None => value
+                .parse()
+                .map(AgeThreshold::Records)
+                .map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for AgeThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeThreshold::Days(days) => write!(f, "{}d", days),
+            AgeThreshold::Records(records) => write!(f, "{}", records),
+        }
+    }
+}
+
+/// When a dispute was opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opened {
+    /// Number of the input record that opened the dispute, counted from 1.
+    pub record: u64,
+    /// Effective date of the dispute, if the input has any.
+    pub date: Option<Date>,
+}
+
+/// An open dispute older than the threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgedDispute {
+    pub client: ClientId,
+    pub tx: TxId,
+    /// The held amount of the dispute.
+    pub amount: Amount,
+    /// When the dispute was opened, or `None` if it was open before the run.
+    pub opened: Option<Opened>,
+    /// Age of the dispute in the unit of the threshold, or `None` if unknown.
+    pub age: Option<u64>,
+}
+
+/// Tracks when the disputes of a run were opened, see the
+/// [module documentation](self).
+#[derive(Debug, Default)]
+pub struct DisputeAging {
+    opened: HashMap<TxId, Opened>,
+    records: u64,
+    last_date: Option<Date>,
+}
+
+impl DisputeAging {
+    /// Notes the decision applied for the next input record, which has the
+    /// effective date `date`, if any.
+    pub fn record(&mut self, decision: &Decision, date: Option<Date>) {
+        self.records += 1;
+        if date > self.last_date {
+            self.last_date = date;
+        }
+        match *decision {
+            Decision::Hold { tx, .. } => {
+                self.opened.insert(
+                    tx,
+                    Opened {
+                        record: self.records,
+                        date: date.or(self.last_date),
+                    },
+                );
+            }
+            Decision::Release { tx, .. } | Decision::Chargeback { tx, .. } => {
+                self.opened.remove(&tx);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the disputes open in `history` that are older than `threshold`
+    /// or of unknown age, ordered by client and transaction. Ages in days are
+    /// measured up to `as_of`, or else the latest effective date seen.
+    pub fn overdue(
+        &self,
+        history: &HashMap<TxId, DepositRecord>,
+        threshold: AgeThreshold,
+        as_of: Option<Date>,
+    ) -> Vec<AgedDispute> {
+        let end = as_of.or(self.last_date);
+        let mut overdue: Vec<_> = history
+            .iter()
+            .filter(|(_, deposit)| deposit.state == DisputeState::Disputed)
+            .map(|(&tx, deposit)| {
+                let opened = self.opened.get(&tx).copied();
+                let age = opened.and_then(|opened| match threshold {
+                    AgeThreshold::Records(_) => Some(self.records - opened.record),
+                    AgeThreshold::Days(_) => opened
+                        .date
+                        .zip(end)
+                        .map(|(date, end)| end.days_since(date).max(0) as u64),
+                });
+                AgedDispute {
+                    client: deposit.client,
+                    tx,
+                    amount: deposit.held,
+                    opened,
+                    age,
+                }
+            })
+            .filter(|dispute| dispute.age.is_none_or(|age| age > threshold.value()))
+            .collect();
+        overdue.sort_unstable_by_key(|dispute| (dispute.client, dispute.tx));
+        overdue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::types::{Transaction, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn reports_disputes_open_longer_than_the_threshold() {
+        assert_eq!("30d".parse(), Ok(AgeThreshold::Days(30)));
+        assert_eq!("5000".parse(), Ok(AgeThreshold::Records(5000)));
+        assert!("30 days".parse::<AgeThreshold>().is_err());
+
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let date = |value: &str| Some(value.parse::<Date>().unwrap());
+        let mut engine = Engine::default();
+        let mut aging = DisputeAging::default();
+        for (tx, date) in [
+            (tx(TxType::Deposit, 1, 1, "10"), date("2024-01-01")),
+            (tx(TxType::Deposit, 2, 2, "5"), date("2024-01-01")),
+            (tx(TxType::Deposit, 1, 3, "1"), date("2024-01-02")),
+            (tx(TxType::Dispute, 1, 1, "0"), date("2024-01-05")),
+            (tx(TxType::Dispute, 1, 3, "0"), None),
+            (tx(TxType::Dispute, 2, 2, "0"), date("2024-02-01")),
+            (tx(TxType::Resolve, 1, 3, "0"), date("2024-02-10")),
+        ] {
+            let decision = engine.process(tx).unwrap();
+            aging.record(&decision, date);
+        }
+        // A dispute from before the run has an unknown age.
+        let mut history = engine.state().deposit_history.clone();
+        let mut earlier = DepositRecord::new(ClientId(3), Amount::from_str("2").unwrap());
+        earlier.state = DisputeState::Disputed;
+        earlier.held = earlier.amount;
+        history.insert(TxId(9), earlier);
+
+        let overdue = aging.overdue(&history, AgeThreshold::Days(30), None);
+        let ages: Vec<_> = overdue
+            .iter()
+            .map(|dispute| (dispute.tx, dispute.age))
+            .collect();
+        assert_eq!(ages, [(TxId(1), Some(36)), (TxId(9), None)]);
+        assert_eq!(overdue[0].amount, Amount::from_str("10").unwrap());
+        assert_eq!(
+            overdue[0].opened,
+            Some(Opened {
+                record: 4,
+                date: date("2024-01-05")
+            })
+        );
+
+        let overdue = aging.overdue(&history, AgeThreshold::Days(30), date("2024-03-03"));
+        assert_eq!(overdue.len(), 3);
+        let overdue = aging.overdue(&history, AgeThreshold::Records(1), None);
+        let ages: Vec<_> = overdue
+            .iter()
+            .map(|dispute| (dispute.tx, dispute.age))
+            .collect();
+        assert_eq!(ages, [(TxId(1), Some(3)), (TxId(9), None)]);
+    }
+}
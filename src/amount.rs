@@ -0,0 +1,688 @@
+//! Parsing and normalization of monetary amounts.
+//!
+//! All readers (CSV input, auxiliary files, command-line options) parse amounts
+//! through [`ParseAmount::parse_lenient`] so every format accepts exactly the same
+//! notation and reports errors the same way.
+//!
+//! # Examples
+//!
+//! ```
+//! use project_diamond_hands::amount::ParseAmount;
+//! use project_diamond_hands::types::Amount;
+//!
+//! assert_eq!(Amount::parse_lenient(" 1.5 ").unwrap().to_string(), "1.5");
+//! assert_eq!(Amount::parse_lenient("").unwrap(), Amount::ZERO);
+//!
+//! let err = Amount::parse_lenient("12.3x").unwrap_err();
+//! assert_eq!(err.position(), 4);
+//! ```
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserializer;
+use serde::de::{self, Visitor};
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use crate::types::Amount;
+
+/// The reason an amount could not be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountParseErrorKind {
+    /// A character that is neither a digit, a sign, nor a decimal point.
+    InvalidCharacter(char),
+    /// A sign that is not at the start of the amount.
+    MisplacedSign,
+    /// More than one decimal point.
+    MultipleDecimalPoints,
+    /// A sign or decimal point without any digits.
+    MissingDigits,
+    /// The amount does not fit into the amount representation.
+    OutOfRange,
+    /// The amount has more decimal places than the amount representation supports.
+    TooManyDecimalPlaces,
+}
+
+/// Error returned when an amount cannot be parsed.
+///
+/// The error records the byte offset of the offending character within the
+/// original (untrimmed) input so callers can point users at the exact column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountParseError {
+    input: String,
+    position: usize,
+    kind: AmountParseErrorKind,
+}
+
+impl AmountParseError {
+    pub(crate) fn new(input: &str, position: usize, kind: AmountParseErrorKind) -> Self {
+        AmountParseError {
+            input: input.to_string(),
+            position,
+            kind,
+        }
+    }
+
+    /// Returns the input that failed to parse.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Returns the zero-based byte offset of the error within the input.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the reason the amount could not be parsed.
+    pub fn kind(&self) -> AmountParseErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for AmountParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            AmountParseErrorKind::InvalidCharacter(c) => write!(f, "invalid character {:?}", c)?,
+            AmountParseErrorKind::MisplacedSign => f.write_str("sign must precede all digits")?,
+            AmountParseErrorKind::MultipleDecimalPoints => {
+                f.write_str("more than one decimal point")?
+            }
+            AmountParseErrorKind::MissingDigits => f.write_str("no digits")?,
+            AmountParseErrorKind::OutOfRange => f.write_str("value out of range")?,
+            AmountParseErrorKind::TooManyDecimalPlaces => f.write_str("too many decimal places")?,
+        }
+        write!(
+            f,
+            " at position {} in amount {:?}",
+            self.position, self.input
+        )
+    }
+}
+
+impl Error for AmountParseError {}
+
+/// Types that can be parsed from the lenient amount notation.
+///
+/// The lenient notation accepts:
+/// - Surrounding whitespace, which is ignored
+/// - An empty (or whitespace-only) string, which parses as zero
+/// - An optional leading `+` or `-` sign
+/// - Digits with at most one decimal point, which may be leading or trailing (`.5`, `5.`)
+pub trait ParseAmount: Sized {
+    /// Parses an amount using the lenient notation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AmountParseError`] describing the first offending character.
+    fn parse_lenient(input: &str) -> Result<Self, AmountParseError>;
+
+    /// Parses an amount in the lenient notation from raw bytes, e.g. a CSV field.
+    ///
+    /// The default implementation validates the bytes as UTF-8 and delegates to
+    /// [`ParseAmount::parse_lenient`]; implementations may parse directly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`AmountParseError`] describing the first offending character.
+    fn parse_lenient_bytes(input: &[u8]) -> Result<Self, AmountParseError> {
+        match std::str::from_utf8(input) {
+            Ok(input) => Self::parse_lenient(input),
+            Err(err) => {
+                let input = String::from_utf8_lossy(input);
+                let c = input[err.valid_up_to()..].chars().next().unwrap_or('?');
+                Err(AmountParseError::new(
+                    &input,
+                    err.valid_up_to(),
+                    AmountParseErrorKind::InvalidCharacter(c),
+                ))
+            }
+        }
+    }
+}
+
+/// Parsed decimals are normalized (trailing zeros are removed), so `10.50` and
+/// `10.5` parse to the same representation.
+impl ParseAmount for Decimal {
+    fn parse_lenient(input: &str) -> Result<Self, AmountParseError> {
+        let canonical = canonicalize(input)?;
+        if canonical.is_empty() {
+            return Ok(Decimal::ZERO);
+        }
+        Decimal::from_str(&canonical)
+            .map(|amount| amount.normalize())
+            .map_err(|_| {
+                let offset = input.len() - input.trim_start().len();
+                AmountParseError::new(input, offset, AmountParseErrorKind::OutOfRange)
+            })
+    }
+
+    fn parse_lenient_bytes(input: &[u8]) -> Result<Self, AmountParseError> {
+        match parse_plain_decimal(input) {
+            Some(amount) => Ok(amount),
+            None => match std::str::from_utf8(input) {
+                Ok(input) => Self::parse_lenient(input),
+                Err(_) => Self::parse_lenient(&String::from_utf8_lossy(input)),
+            },
+        }
+    }
+}
+
+/// Number of decimal places amounts are processed with.
+pub const MAX_DECIMAL_PLACES: u32 = 4;
+
+/// How amounts with more than [`MAX_DECIMAL_PLACES`] decimal places are handled.
+///
+/// Trailing zeros do not count as decimal places, so `1.50000` is always accepted.
+/// The default keeps amounts as they are; the command line rounds them unless
+/// `--precision` says otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrecisionPolicy {
+    /// Keep the amount as it is. `Decimal` amounts keep every decimal place,
+    /// while representations that cannot hold them, such as
+    /// [`FixedPoint`](crate::fixed_point::FixedPoint), fail to parse it.
+    #[default]
+    Keep,
+    /// Round to the nearest amount, rounding midpoints away from zero.
+    Round,
+    /// Drop the excess decimal places, rounding towards zero.
+    Truncate,
+    /// Reject the amount.
+    Reject,
+}
+
+impl PrecisionPolicy {
+    /// Every policy, in declaration order.
+    pub const ALL: [PrecisionPolicy; 4] = [
+        PrecisionPolicy::Keep,
+        PrecisionPolicy::Round,
+        PrecisionPolicy::Truncate,
+        PrecisionPolicy::Reject,
+    ];
+
+    /// Returns the name of the policy, e.g. `truncate`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PrecisionPolicy::Keep => "keep",
+            PrecisionPolicy::Round => "round",
+            PrecisionPolicy::Truncate => "truncate",
+            PrecisionPolicy::Reject => "reject",
+        }
+    }
+
+    /// Applies the policy to an amount in the lenient notation.
+    ///
+    /// Returns `Ok(None)` if the amount can be parsed as is or the policy is
+    /// [`PrecisionPolicy::Keep`], and the rounded or truncated amount in
+    /// canonical notation otherwise. Syntax errors are left
+    /// to [`ParseAmount::parse_lenient`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`AmountParseErrorKind::TooManyDecimalPlaces`] if
+    /// the policy is [`PrecisionPolicy::Reject`] and the amount has excess decimal
+    /// places.
+    pub fn adjust(self, input: &[u8]) -> Result<Option<String>, AmountParseError> {
+        if self == PrecisionPolicy::Keep || !has_excess_digits(input) {
+            return Ok(None);
+        }
+        let input = String::from_utf8_lossy(input);
+        let Ok(canonical) = canonicalize(&input) else {
+            return Ok(None);
+        };
+        let (_, fraction) = canonical.split_once('.').unwrap_or_default();
+        if fraction.trim_end_matches('0').len() <= MAX_DECIMAL_PLACES as usize {
+            return Ok(None);
+        }
+
+        let Some(strategy) = self.rounding_strategy() else {
+            let point = input.find('.').unwrap_or_default();
+            return Err(AmountParseError::new(
+                &input,
+                point + 1 + MAX_DECIMAL_PLACES as usize,
+                AmountParseErrorKind::TooManyDecimalPlaces,
+            ));
+        };
+        let amount = Decimal::from_str(&canonical).map_err(|_| {
+            let offset = input.len() - input.trim_start().len();
+            AmountParseError::new(&input, offset, AmountParseErrorKind::OutOfRange)
+        })?;
+        let adjusted = amount.round_dp_with_strategy(MAX_DECIMAL_PLACES, strategy);
+        Ok(Some(adjusted.normalize().to_string()))
+    }
+
+    /// Returns the rounding strategy of the policy, or `None` for `Keep` and
+    /// `Reject`.
+    fn rounding_strategy(self) -> Option<RoundingStrategy> {
+        match self {
+            PrecisionPolicy::Keep => None,
+            PrecisionPolicy::Round => Some(RoundingStrategy::MidpointAwayFromZero),
+            PrecisionPolicy::Truncate => Some(RoundingStrategy::ToZero),
+            PrecisionPolicy::Reject => None,
+        }
+    }
+}
+
+impl FromStr for PrecisionPolicy {
+    type Err = String;
+
+    /// Parses the name returned by [`PrecisionPolicy::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        PrecisionPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.as_str() == value)
+            .ok_or_else(|| format!("unknown precision policy '{}'", value))
+    }
+}
+
+impl fmt::Display for PrecisionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns `true` if more than [`MAX_DECIMAL_PLACES`] digits follow the decimal
+/// point, a cheap pre-check for [`PrecisionPolicy::adjust`].
+fn has_excess_digits(input: &[u8]) -> bool {
+    input
+        .iter()
+        .position(|&byte| byte == b'.')
+        .is_some_and(|point| {
+            input[point + 1..]
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .count()
+                > MAX_DECIMAL_PLACES as usize
+        })
+}
+
+/// Arithmetic the engine needs from a monetary type.
+///
+/// The engine, account and transaction types are generic over this trait, so a
+/// different representation (e.g. a wider fixed-point integer or a
+/// currency-aware money type) can be plugged in without forking the crate. It
+/// is implemented for `rust_decimal::Decimal` and
+/// [`FixedPoint`](crate::fixed_point::FixedPoint); reading transactions from
+/// CSV additionally requires [`ParseAmount`].
+pub trait AmountOps: Copy + Default + Ord + fmt::Debug {
+    /// The additive identity.
+    const ZERO: Self;
+
+    /// Adds two amounts, returning `None` on overflow.
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// Subtracts two amounts, returning `None` on overflow.
+    fn checked_sub(self, other: Self) -> Option<Self>;
+
+    /// Adds two amounts, saturating at the bounds of the representation.
+    fn saturating_add(self, other: Self) -> Self;
+
+    /// Applies `policy` to an amount with more than [`MAX_DECIMAL_PLACES`]
+    /// decimal places, returning `None` if the policy rejects it.
+    ///
+    /// The default implementation returns the amount unchanged, which suits
+    /// representations that cannot hold excess decimal places.
+    fn with_precision(self, policy: PrecisionPolicy) -> Option<Self> {
+        let _ = policy;
+        Some(self)
+    }
+}
+
+impl AmountOps for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Decimal::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Decimal::checked_sub(self, other)
+    }
+
+    fn saturating_add(self, other: Self) -> Self {
+        Decimal::saturating_add(self, other)
+    }
+
+    fn with_precision(self, policy: PrecisionPolicy) -> Option<Self> {
+        if policy == PrecisionPolicy::Keep || self.normalize().scale() <= MAX_DECIMAL_PLACES {
+            return Some(self);
+        }
+        let strategy = policy.rounding_strategy()?;
+        Some(
+            self.round_dp_with_strategy(MAX_DECIMAL_PLACES, strategy)
+                .normalize(),
+        )
+    }
+}
+
+/// Parses the common case of an already trimmed `[+-]digits[.digits]` amount
+/// without allocating.
+///
+/// Returns `None` for anything else (including empty input, whitespace, values
+/// that do not fit and negative zero) so the caller can fall back to the full
+/// parser, which also produces the error messages.
+fn parse_plain_decimal(input: &[u8]) -> Option<Decimal> {
+    let (negative, digits) = match input.split_first()? {
+        (b'-', rest) => (true, rest),
+        (b'+', rest) => (false, rest),
+        _ => (false, input),
+    };
+
+    let mut mantissa: u128 = 0;
+    let mut scale: Option<u32> = None;
+    let mut seen_digit = false;
+    for &byte in digits {
+        match byte {
+            b'0'..=b'9' => {
+                seen_digit = true;
+                mantissa = mantissa
+                    .checked_mul(10)?
+                    .checked_add(u128::from(byte - b'0'))?;
+                if let Some(scale) = &mut scale {
+                    *scale += 1;
+                }
+            }
+            b'.' if scale.is_none() => scale = Some(0),
+            _ => return None,
+        }
+    }
+
+    let scale = scale.unwrap_or(0);
+    if !seen_digit || scale > Decimal::MAX_SCALE || (negative && mantissa == 0) {
+        return None;
+    }
+    let mantissa = i128::try_from(mantissa).ok()?;
+    let mantissa = if negative { -mantissa } else { mantissa };
+
+    Decimal::try_from_i128_with_scale(mantissa, scale)
+        .ok()
+        .map(|amount| amount.normalize())
+}
+
+/// Validates the lenient notation and returns the amount in canonical form
+/// (`[-]digits[.digits]`), or an empty string for an empty input.
+pub(crate) fn canonicalize(input: &str) -> Result<String, AmountParseError> {
+    let offset = input.len() - input.trim_start().len();
+    let trimmed = input.trim();
+
+    let mut normalized = String::with_capacity(trimmed.len() + 1);
+    let mut seen_digit = false;
+    let mut seen_point = false;
+    let mut last_position = offset;
+
+    for (index, c) in trimmed.char_indices() {
+        let position = offset + index;
+        last_position = position;
+        match c {
+            '0'..='9' => {
+                seen_digit = true;
+                normalized.push(c);
+            }
+            '+' | '-' if index == 0 => {
+                if c == '-' {
+                    normalized.push('-');
+                }
+            }
+            '+' | '-' => {
+                return Err(AmountParseError::new(
+                    input,
+                    position,
+                    AmountParseErrorKind::MisplacedSign,
+                ));
+            }
+            '.' if seen_point => {
+                return Err(AmountParseError::new(
+                    input,
+                    position,
+                    AmountParseErrorKind::MultipleDecimalPoints,
+                ));
+            }
+            '.' => {
+                seen_point = true;
+                if !seen_digit {
+                    normalized.push('0');
+                }
+                normalized.push('.');
+            }
+            _ => {
+                return Err(AmountParseError::new(
+                    input,
+                    position,
+                    AmountParseErrorKind::InvalidCharacter(c),
+                ));
+            }
+        }
+    }
+
+    if trimmed.is_empty() {
+        return Ok(normalized);
+    }
+    if !seen_digit {
+        return Err(AmountParseError::new(
+            input,
+            last_position,
+            AmountParseErrorKind::MissingDigits,
+        ));
+    }
+    if normalized.ends_with('.') {
+        normalized.pop();
+    }
+
+    Ok(normalized)
+}
+
+/// Parses an amount for use as a `clap` value parser or other `&str` based APIs.
+///
+/// # Errors
+///
+/// Returns an [`AmountParseError`] if the input is not a valid amount.
+pub fn parse_amount(input: &str) -> Result<Amount, AmountParseError> {
+    Amount::parse_lenient(input)
+}
+
+/// Custom deserializer for amounts using the lenient notation.
+///
+/// Handles empty strings and missing values by defaulting to zero.
+/// This allows dispute, resolve, and chargeback transactions to omit the amount field.
+pub fn deserialize_amount_or_zero<'de, D, A>(deserializer: D) -> Result<A, D::Error>
+where
+    D: Deserializer<'de>,
+    A: ParseAmount,
+{
+    struct AmountVisitor<A>(PhantomData<A>);
+
+    impl<'de, A: ParseAmount> Visitor<'de> for AmountVisitor<A> {
+        type Value = A;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a decimal number or empty string")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            A::parse_lenient(value).map_err(de::Error::custom)
+        }
+
+        fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.visit_str(&value.to_string())
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            self.visit_str(&value.to_string())
+        }
+    }
+
+    // Request the raw string: `deserialize_any` would let formats such as CSV
+    // infer a float and lose precision before the amount reaches the visitor.
+    deserializer.deserialize_str(AmountVisitor(PhantomData))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lenient_notation() {
+        assert_eq!(
+            Decimal::parse_lenient("10.5").unwrap(),
+            Decimal::from_str("10.5").unwrap()
+        );
+        assert_eq!(
+            Decimal::parse_lenient("  +3 ").unwrap(),
+            Decimal::from_str("3").unwrap()
+        );
+        assert_eq!(
+            Decimal::parse_lenient("-.25").unwrap(),
+            Decimal::from_str("-0.25").unwrap()
+        );
+        assert_eq!(
+            Decimal::parse_lenient("7.").unwrap(),
+            Decimal::from_str("7").unwrap()
+        );
+        assert_eq!(Decimal::parse_lenient("   ").unwrap(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let err = Decimal::parse_lenient("  12a.0").unwrap_err();
+        assert_eq!(err.position(), 4);
+        assert_eq!(err.kind(), AmountParseErrorKind::InvalidCharacter('a'));
+        assert_eq!(
+            err.to_string(),
+            "invalid character 'a' at position 4 in amount \"  12a.0\""
+        );
+
+        let err = Decimal::parse_lenient("1.2.3").unwrap_err();
+        assert_eq!(err.position(), 3);
+        assert_eq!(err.kind(), AmountParseErrorKind::MultipleDecimalPoints);
+
+        let err = Decimal::parse_lenient("1-2").unwrap_err();
+        assert_eq!(err.position(), 1);
+        assert_eq!(err.kind(), AmountParseErrorKind::MisplacedSign);
+
+        let err = Decimal::parse_lenient(" -.").unwrap_err();
+        assert_eq!(err.position(), 2);
+        assert_eq!(err.kind(), AmountParseErrorKind::MissingDigits);
+    }
+
+    #[test]
+    fn parses_bytes_like_strings() {
+        for input in [
+            "10.5",
+            "10.50",
+            "+3",
+            "-0.25",
+            "-0",
+            ".5",
+            "7.",
+            "  1.5 ",
+            "",
+            "0.00001",
+            "79228162514264337593543950335",
+            "1.2.3",
+            "12a",
+            "-",
+        ] {
+            let expected = Decimal::parse_lenient(input).map(|amount| amount.to_string());
+            let actual =
+                Decimal::parse_lenient_bytes(input.as_bytes()).map(|amount| amount.to_string());
+            assert_eq!(actual, expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn normalizes_parsed_amounts() {
+        assert_eq!(
+            Decimal::parse_lenient("10.500").unwrap().to_string(),
+            "10.5"
+        );
+        assert_eq!(
+            Decimal::parse_lenient_bytes(b"10.500").unwrap().to_string(),
+            "10.5"
+        );
+    }
+
+    #[test]
+    fn rejects_non_numeric_amounts() {
+        for input in ["NaN", "inf", "-Infinity", "1e5"] {
+            assert!(Decimal::parse_lenient(input).is_err(), "{:?}", input);
+            assert!(Amount::parse_lenient(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn reports_out_of_range_amounts() {
+        let err = Decimal::parse_lenient("99999999999999999999999999999999").unwrap_err();
+        assert_eq!(err.position(), 0);
+        assert_eq!(err.kind(), AmountParseErrorKind::OutOfRange);
+    }
+
+    #[test]
+    fn precision_policy_adjusts_excess_decimal_places() {
+        let adjust = |policy: PrecisionPolicy, input: &str| policy.adjust(input.as_bytes());
+
+        for input in ["1.2345", "1.23450000", "12", "", "1.2x3456"] {
+            assert_eq!(
+                adjust(PrecisionPolicy::Reject, input),
+                Ok(None),
+                "{:?}",
+                input
+            );
+        }
+        assert_eq!(
+            adjust(PrecisionPolicy::Round, "1.23455"),
+            Ok(Some("1.2346".to_string()))
+        );
+        assert_eq!(
+            adjust(PrecisionPolicy::Round, "-0.99999"),
+            Ok(Some("-1".to_string()))
+        );
+        assert_eq!(
+            adjust(PrecisionPolicy::Truncate, "-1.23459"),
+            Ok(Some("-1.2345".to_string()))
+        );
+        assert_eq!(
+            adjust(PrecisionPolicy::Truncate, "0.00001"),
+            Ok(Some("0".to_string()))
+        );
+
+        assert_eq!(adjust(PrecisionPolicy::Keep, "1.23456"), Ok(None));
+
+        let err = adjust(PrecisionPolicy::Reject, " 1.23456").unwrap_err();
+        assert_eq!(err.kind(), AmountParseErrorKind::TooManyDecimalPlaces);
+        assert_eq!(err.position(), 7);
+    }
+
+    #[test]
+    fn decimal_applies_precision_policy() {
+        let amount = Decimal::from_str("2.00005").unwrap();
+        assert_eq!(
+            amount.with_precision(PrecisionPolicy::Round),
+            Some(Decimal::from_str("2.0001").unwrap())
+        );
+        assert_eq!(
+            amount.with_precision(PrecisionPolicy::Truncate),
+            Some(Decimal::from_str("2").unwrap())
+        );
+        assert_eq!(amount.with_precision(PrecisionPolicy::Reject), None);
+        assert_eq!(amount.with_precision(PrecisionPolicy::Keep), Some(amount));
+
+        let exact = Decimal::from_str("2.50000").unwrap();
+        assert_eq!(exact.with_precision(PrecisionPolicy::Reject), Some(exact));
+    }
+}
@@ -0,0 +1,196 @@
+//! Apache Arrow output (`arrow` feature).
+//!
+//! [`account_record_batches`] converts the final account states into Arrow
+//! [`RecordBatch`]es, and [`write_accounts_as_arrow_ipc`] writes them in the Arrow
+//! IPC stream format. Consumers such as pandas (via pyarrow) load the stream
+//! without re-parsing text:
+//!
+//! ```python
+//! import pyarrow as pa
+//! accounts = pa.ipc.open_stream("accounts.arrow").read_pandas()
+//! ```
+//!
+//! Balances are `Decimal128(38, 4)` columns, so no digits are lost, and client
+//! IDs keep their unsigned integer type.
+
+use anyhow::{Context, Result};
+use arrow_array::builder::{BooleanBuilder, Decimal128Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::types::{AccountDetails, Accounts, Amount, ClientId, sorted_accounts};
+
+/// Maximum number of accounts per record batch.
+pub const BATCH_SIZE: usize = 65_536;
+
+/// Number of decimal places of the balance columns.
+const SCALE: u32 = 4;
+
+#[cfg(not(feature = "wide-ids"))]
+type ClientIdArray = arrow_array::UInt16Array;
+#[cfg(not(feature = "wide-ids"))]
+const CLIENT_ID_TYPE: DataType = DataType::UInt16;
+#[cfg(feature = "wide-ids")]
+type ClientIdArray = arrow_array::UInt32Array;
+#[cfg(feature = "wide-ids")]
+const CLIENT_ID_TYPE: DataType = DataType::UInt32;
+
+/// Returns the schema of the account batches:
+/// `client`, `available`, `held`, `total` and `locked`.
+pub fn account_schema() -> SchemaRef {
+    let balance = DataType::Decimal128(38, SCALE as i8);
+    Arc::new(Schema::new(vec![
+        Field::new("client", CLIENT_ID_TYPE, false),
+        Field::new("available", balance.clone(), false),
+        Field::new("held", balance.clone(), false),
+        Field::new("total", balance, false),
+        Field::new("locked", DataType::Boolean, false),
+    ]))
+}
+
+/// Converts `accounts` into record batches of at most [`BATCH_SIZE`] rows, in
+/// ascending client order.
+///
+/// # Errors
+///
+/// Returns an error if a batch cannot be assembled.
+pub fn account_record_batches(accounts: &Accounts) -> Result<Vec<RecordBatch>> {
+    let schema = account_schema();
+    sorted_accounts(accounts)
+        .chunks(BATCH_SIZE)
+        .map(|chunk| record_batch(&schema, chunk))
+        .collect()
+}
+
+fn record_batch(
+    schema: &SchemaRef,
+    accounts: &[(ClientId, &AccountDetails)],
+) -> Result<RecordBatch> {
+    let balance = |field: fn(&AccountDetails) -> Amount| -> Result<ArrayRef> {
+        let mut builder = Decimal128Builder::with_capacity(accounts.len());
+        for (_, account) in accounts {
+            let amount = field(account);
+            let units = amount_units(amount).with_context(|| {
+                format!("Balance {} does not fit into 4 decimal places", amount)
+            })?;
+            builder.append_value(units);
+        }
+        Ok(Arc::new(
+            builder.finish().with_precision_and_scale(38, SCALE as i8)?,
+        ))
+    };
+    let mut locked = BooleanBuilder::with_capacity(accounts.len());
+    for (_, account) in accounts {
+        locked.append_value(account.locked);
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(ClientIdArray::from_iter_values(
+            accounts.iter().map(|(client, _)| client.0),
+        )),
+        balance(|account| account.available)?,
+        balance(|account| account.held)?,
+        balance(|account| account.total)?,
+        Arc::new(locked.finish()),
+    ];
+    RecordBatch::try_new(schema.clone(), columns).context("Failed to build record batch")
+}
+
+/// Returns `amount` as a count of 1/10000 units, or `None` if it is too large
+/// to be represented with four decimal places.
+#[cfg(not(feature = "fixed-point"))]
+fn amount_units(amount: Amount) -> Option<i128> {
+    let mut amount = amount;
+    amount.rescale(SCALE);
+    (amount.scale() == SCALE).then(|| amount.mantissa())
+}
+
+/// Returns `amount` as a count of 1/10000 units.
+#[cfg(feature = "fixed-point")]
+fn amount_units(amount: Amount) -> Option<i128> {
+    Some(i128::from(amount.units()))
+}
+
+/// Writes `accounts` to `writer` in the Arrow IPC stream format.
+///
+/// # Errors
+///
+/// Returns an error if the batches cannot be built or written.
+pub fn write_accounts_as_arrow_ipc<W: Write>(accounts: &Accounts, writer: W) -> Result<()> {
+    let mut writer = StreamWriter::try_new(writer, &account_schema())?;
+    for batch in account_record_batches(accounts)? {
+        writer
+            .write(&batch)
+            .context("Failed to write record batch")?;
+    }
+    writer.finish().context("Failed to finish Arrow stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, BooleanArray, Decimal128Array};
+    use arrow_ipc::reader::StreamReader;
+    use std::str::FromStr;
+
+    #[test]
+    fn writes_accounts_as_arrow_stream() {
+        let accounts = Accounts::from([
+            (
+                ClientId(2),
+                AccountDetails {
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                ClientId(1),
+                AccountDetails {
+                    available: Amount::from_str("-1.5").unwrap(),
+                    held: Amount::from_str("2.0001").unwrap(),
+                    total: Amount::from_str("0.5001").unwrap(),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let mut buffer = Vec::new();
+        write_accounts_as_arrow_ipc(&accounts, &mut buffer).unwrap();
+        let batches: Vec<RecordBatch> = StreamReader::try_new(buffer.as_slice(), None)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), account_schema());
+        let clients = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<ClientIdArray>()
+            .unwrap();
+        assert_eq!(clients.values().as_ref(), &[1, 2]);
+        let held = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(held.value_as_string(0), "2.0001");
+        let available = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(available.value_as_string(0), "-1.5000");
+        let locked = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+        assert!(!locked.value(0) && locked.value(1));
+        assert_eq!(locked.null_count(), 0);
+    }
+}
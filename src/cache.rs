@@ -0,0 +1,163 @@
+//! Cache of the final accounts of batch runs.
+//!
+//! Processing the same immutable input files with the same options always
+//! yields the same accounts, so repeated runs, e.g. of a regression suite in
+//! CI, can reuse them instead of processing the files again. An
+//! [`AccountCache`] stores the accounts of a run in a directory, under a key
+//! from [`cache_key`] that hashes the contents of the input files and the
+//! settings the accounts depend on. Renaming or moving a file keeps its key,
+//! while changing a single byte of it or an option misses the cache.
+//!
+//! Entries are JSON files named after their key, holding `[client, account]`
+//! pairs. They are never evicted; the directory can be deleted at any time.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::digest::{file_sha256_hex, hex};
+use crate::types::{AccountDetails, Accounts, ClientId, sorted_accounts};
+
+/// Returns the cache key of processing the files at `inputs`, in order, with
+/// `settings`, a canonical description of every option the accounts depend on.
+///
+/// The key also covers the version of the crate and the amount and ID
+/// representation, since cached accounts are only valid for the code that
+/// computed them.
+///
+/// # Errors
+///
+/// Returns an error if an input file cannot be read.
+pub fn cache_key(inputs: &[impl AsRef<Path>], settings: &str) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{} {} fixed-point={} wide-ids={}\n{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        cfg!(feature = "fixed-point"),
+        cfg!(feature = "wide-ids"),
+        settings
+    ));
+    for input in inputs {
+        hasher.update(file_sha256_hex(input.as_ref())?);
+        hasher.update("\n");
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// A directory of cached accounts, see the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct AccountCache {
+    dir: PathBuf,
+}
+
+impl AccountCache {
+    /// Creates a cache in `dir`, which is created when the first entry is
+    /// stored.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        AccountCache { dir: dir.into() }
+    }
+
+    /// Returns the default cache directory, `project-diamond-hands` in the
+    /// system temp directory.
+    pub fn default_dir() -> PathBuf {
+        std::env::temp_dir().join(env!("CARGO_PKG_NAME"))
+    }
+
+    /// Returns the accounts cached under `key`, or `None` if there are none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be read or parsed.
+    pub fn get(&self, key: &str) -> Result<Option<Accounts>> {
+        let path = self.path(key);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Failed to open cache entry: {}", path.display()));
+            }
+        };
+        let accounts: Vec<(ClientId, AccountDetails)> =
+            serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Failed to parse cache entry: {}", path.display()))?;
+        Ok(Some(accounts.into_iter().collect()))
+    }
+
+    /// Stores `accounts` under `key`, replacing an existing entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be written.
+    pub fn put(&self, key: &str, accounts: &Accounts) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory: {}", self.dir.display()))?;
+        let path = self.path(key);
+        // Concurrent runs write the same accounts, so the last rename wins.
+        let temporary = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        let write = || -> Result<()> {
+            let mut writer = BufWriter::new(File::create(&temporary)?);
+            serde_json::to_writer(&mut writer, &sorted_accounts(accounts))?;
+            writer.into_inner().map_err(|err| err.into_error())?;
+            fs::rename(&temporary, &path)?;
+            Ok(())
+        };
+        write().with_context(|| format!("Failed to write cache entry: {}", path.display()))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Amount;
+    use std::str::FromStr;
+
+    #[test]
+    fn caches_accounts_by_input_contents_and_settings() {
+        let dir = std::env::temp_dir().join(format!("cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.csv");
+        let moved = dir.join("moved.csv");
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1\n").unwrap();
+        fs::write(&moved, "type,client,tx,amount\ndeposit,1,1,1\n").unwrap();
+
+        let key = cache_key(&[&input], "credit_limit: 0").unwrap();
+        assert_eq!(key, cache_key(&[&moved], "credit_limit: 0").unwrap());
+        assert_ne!(key, cache_key(&[&input], "credit_limit: 1").unwrap());
+        assert_ne!(
+            key,
+            cache_key(&[&input, &moved], "credit_limit: 0").unwrap()
+        );
+        fs::write(&moved, "type,client,tx,amount\ndeposit,1,1,2\n").unwrap();
+        assert_ne!(key, cache_key(&[&moved], "credit_limit: 0").unwrap());
+
+        let cache = AccountCache::new(dir.join("cache"));
+        assert!(cache.get(&key).unwrap().is_none());
+        let accounts: Accounts = [1, 2]
+            .into_iter()
+            .map(|client| {
+                // The engine leaves `client` unset, the key is the client.
+                let account = AccountDetails {
+                    available: Amount::from_str("1.5").unwrap(),
+                    total: Amount::from_str("1.5").unwrap(),
+                    locked: client == 2,
+                    ..Default::default()
+                };
+                (ClientId(client), account)
+            })
+            .collect();
+        cache.put(&key, &accounts).unwrap();
+        assert_eq!(cache.get(&key).unwrap(), Some(accounts));
+
+        fs::write(cache.path(&key), "not json").unwrap();
+        assert!(cache.get(&key).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+//! Engine applying the transactions of different clients concurrently
+//! (`concurrent` feature).
+//!
+//! A [`ConcurrentEngine`] keeps the accounts and the deposit history in sharded
+//! concurrent maps and applies transactions on a pool of worker threads. Every
+//! client has a queue of its pending transactions:
+//!
+//! ```text
+//! process ─▶ queue of client 1 ─┐
+//! process ─▶ queue of client 2 ─┼─▶ ready clients ─▶ worker 0..N
+//! process ─▶ queue of client 3 ─┘
+//! ```
+//!
+//! A client is handed to a worker when its first transaction is queued, and the
+//! worker applies its transactions until the queue is empty. The transactions of
+//! a client are therefore applied one after the other in the order they were
+//! submitted, while those of different clients run in parallel.
+//!
+//! Each worker applies a transaction with its own [`Engine`], loading the
+//! client's account and the referenced deposit into it and writing them back
+//! afterwards. Like the shards of the `async` pipeline, a worker only changes the
+//! records of the client it works for, so transaction IDs must be unique across
+//! clients, as the input format requires.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::engine::{Decision, DepositRecord, Engine, EngineConfig};
+use crate::stats::Stats;
+use crate::types::{AccountDetails, Accounts, ClientId, Transaction, TxId};
+
+/// A submitted transaction and where to send its decision.
+struct Job {
+    tx: Transaction,
+    reply: Sender<Result<Decision>>,
+}
+
+/// The state shared by the engine and its workers.
+struct Shared {
+    config: EngineConfig,
+    accounts: DashMap<ClientId, AccountDetails>,
+    deposit_history: DashMap<TxId, DepositRecord>,
+    /// Pending transactions by client. A client is in the map, and handed to
+    /// at most one worker, while it has any.
+    queues: DashMap<ClientId, VecDeque<Job>>,
+    deposits: AtomicU64,
+    transactions: AtomicU64,
+    stats: Mutex<Stats>,
+}
+
+/// Applies transactions on a pool of worker threads, see the
+/// [module documentation](self).
+pub struct ConcurrentEngine {
+    shared: Arc<Shared>,
+    /// Clients with pending transactions, taken by the workers. `None` once
+    /// the workers are stopped.
+    ready: Option<Sender<ClientId>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ConcurrentEngine {
+    /// Creates an engine applying the rules of `config` on `workers` threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `config` enables an option the concurrent engine
+    /// does not support, or a worker thread cannot be started.
+    pub fn new(config: EngineConfig, workers: usize) -> Result<Self> {
+        let unsupported = [
+            ("idempotency keys", config.idempotency.is_some()),
+            (
+                "authorization expiry",
+                config.authorization_expiry.is_some(),
+            ),
+            ("a history retention", config.history_retention.is_some()),
+            ("a memory cap", config.max_memory.is_some()),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+            anyhow::bail!("The concurrent engine does not support {}", option);
+        }
+        anyhow::ensure!(
+            workers > 0,
+            "The concurrent engine needs at least one worker"
+        );
+
+        let shared = Arc::new(Shared {
+            config,
+            accounts: DashMap::new(),
+            deposit_history: DashMap::new(),
+            queues: DashMap::new(),
+            deposits: AtomicU64::new(0),
+            transactions: AtomicU64::new(0),
+            stats: Mutex::new(Stats::default()),
+        });
+        let (ready, clients) = mpsc::channel();
+        let clients = Arc::new(Mutex::new(clients));
+        let mut engine = ConcurrentEngine {
+            shared,
+            ready: Some(ready),
+            workers: Vec::with_capacity(workers),
+        };
+        for index in 0..workers {
+            let shared = Arc::clone(&engine.shared);
+            let clients = Arc::clone(&clients);
+            let worker = thread::Builder::new()
+                .name(format!("engine-worker-{}", index))
+                .spawn(move || work(&shared, &clients))?;
+            engine.workers.push(worker);
+        }
+        Ok(engine)
+    }
+
+    /// Returns the configuration the engine processes transactions with.
+    pub fn config(&self) -> &EngineConfig {
+        &self.shared.config
+    }
+
+    /// Returns the counters of the transactions processed so far.
+    pub fn stats(&self) -> Stats {
+        self.shared
+            .stats
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Returns the account of `client`, or `None` if it has none.
+    pub fn account(&self, client: ClientId) -> Option<AccountDetails> {
+        self.shared
+            .accounts
+            .get(&client)
+            .map(|account| account.clone())
+    }
+
+    /// Returns a copy of all accounts. Transactions applied meanwhile may or
+    /// may not be included.
+    pub fn accounts(&self) -> Accounts {
+        self.shared
+            .accounts
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect()
+    }
+
+    /// Queues a transaction behind the pending transactions of its client and
+    /// returns where its decision will be sent, see [`ConcurrentEngine::process`].
+    pub fn submit(&self, tx: Transaction) -> Receiver<Result<Decision>> {
+        let (reply, decision) = mpsc::channel();
+        let client = tx.client;
+        let mut queue = self.shared.queues.entry(client).or_default();
+        queue.push_back(Job { tx, reply });
+        if queue.len() == 1
+            && let Some(ready) = &self.ready
+        {
+            // The workers run until the engine is dropped.
+            let _ = ready.send(client);
+        }
+        decision
+    }
+
+    /// Applies a transaction after the pending transactions of its client and
+    /// returns the decision that was applied, see [`Engine::process`].
+    ///
+    /// Calls from several threads are applied concurrently unless they are for
+    /// the same client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a balance calculation overflows. The state is left
+    /// unchanged then.
+    pub fn process(&self, tx: Transaction) -> Result<Decision> {
+        self.submit(tx)
+            .recv()
+            .map_err(|_| anyhow::anyhow!("Engine worker stopped"))?
+    }
+
+    /// Waits for the pending transactions, stops the workers and returns the
+    /// final accounts.
+    pub fn into_accounts(mut self) -> Accounts {
+        self.stop();
+        self.accounts()
+    }
+
+    /// Lets the workers apply the pending transactions and waits for them to
+    /// stop.
+    fn stop(&mut self) {
+        // The workers stop once the clients already handed out are done.
+        self.ready = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for ConcurrentEngine {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Runs a worker: takes clients with pending transactions and applies their
+/// transactions until their queue is empty.
+fn work(shared: &Shared, clients: &Mutex<Receiver<ClientId>>) {
+    let mut engine = Engine::new(shared.config.clone());
+    loop {
+        let client = clients.lock().unwrap_or_else(|err| err.into_inner()).recv();
+        let Ok(client) = client else { return };
+        loop {
+            // The job stays queued while it is applied, so that the client is
+            // not handed to another worker meanwhile.
+            let Some((tx, reply)) = shared
+                .queues
+                .get(&client)
+                .and_then(|queue| queue.front().map(|job| (job.tx.clone(), job.reply.clone())))
+            else {
+                break;
+            };
+            let tx_type = tx.tx_type;
+            let result = apply(shared, &mut engine, tx);
+            if let Ok(decision) = &result {
+                shared
+                    .stats
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .record(tx_type, decision);
+            }
+            // The caller may have stopped waiting for the decision.
+            let _ = reply.send(result);
+            match shared.queues.entry(client) {
+                Entry::Occupied(mut queue) => {
+                    queue.get_mut().pop_front();
+                    if queue.get().is_empty() {
+                        queue.remove();
+                        break;
+                    }
+                }
+                Entry::Vacant(_) => break,
+            }
+        }
+    }
+}
+
+/// Applies `tx` with the worker's `engine`, loading the records it may refer
+/// to from the shared maps and writing the changed ones back.
+fn apply(shared: &Shared, engine: &mut Engine, tx: Transaction) -> Result<Decision> {
+    let client = tx.client;
+    let state = engine.state_mut();
+    state.deposits = shared.deposits.load(Ordering::Relaxed);
+    state.transactions = shared.transactions.load(Ordering::Relaxed);
+    let counters = (state.deposits, state.transactions);
+    if let Some(account) = shared.accounts.get(&client) {
+        state.accounts.insert(client, account.clone());
+    }
+    if let Some(deposit) = shared.deposit_history.get(&tx.tx) {
+        state.deposit_history.insert(tx.tx, deposit.clone());
+    }
+
+    let result = engine.process(tx);
+    let state = engine.state_mut();
+    if let Ok(decision) = &result
+        && !matches!(decision, Decision::Ignore(_))
+    {
+        for (client, account) in state.accounts.drain() {
+            shared.accounts.insert(client, account);
+        }
+        // A record of another client was only read.
+        for (tx, deposit) in state.deposit_history.drain() {
+            if deposit.client == client {
+                shared.deposit_history.insert(tx, deposit);
+            }
+        }
+    }
+    if result.is_ok() {
+        shared
+            .deposits
+            .fetch_add(state.deposits - counters.0, Ordering::Relaxed);
+        shared
+            .transactions
+            .fetch_add(state.transactions - counters.1, Ordering::Relaxed);
+    }
+    state.accounts.clear();
+    state.deposit_history.clear();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Amount, RawTxId, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn keeps_the_order_of_each_client_and_matches_the_engine() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        // Every withdrawal and dispute depends on the transactions before it.
+        let clients: Vec<Vec<Transaction>> = (1..=8)
+            .map(|client| {
+                let id = |n| RawTxId::from(client) * 1000 + n;
+                let mut txs = Vec::new();
+                for n in 0..50 {
+                    txs.push(tx(TxType::Deposit, client, id(2 * n), "1"));
+                    txs.push(tx(TxType::Withdrawal, client, id(2 * n + 1), "1"));
+                }
+                txs.push(tx(TxType::Deposit, client, id(100), "5"));
+                txs.push(tx(TxType::Dispute, client, id(100), "0"));
+                txs.push(tx(TxType::Withdrawal, client, id(101), "1"));
+                if client % 2 == 0 {
+                    txs.push(tx(TxType::Chargeback, client, id(100), "0"));
+                } else {
+                    txs.push(tx(TxType::Resolve, client, id(100), "0"));
+                }
+                txs.push(tx(TxType::Deposit, client, id(102), "2"));
+                txs
+            })
+            .collect();
+
+        let mut expected = Engine::default();
+        for tx in clients.iter().flatten() {
+            expected.process(tx.clone()).unwrap();
+        }
+
+        let engine = ConcurrentEngine::new(EngineConfig::default(), 4).unwrap();
+        thread::scope(|scope| {
+            for txs in &clients {
+                let engine = &engine;
+                scope.spawn(move || {
+                    // Submitted without waiting, so the queues fill up.
+                    let decisions: Vec<_> =
+                        txs.iter().map(|tx| engine.submit(tx.clone())).collect();
+                    for decision in decisions {
+                        decision.recv().unwrap().unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(engine.stats(), *expected.stats());
+        // A dispute of another client's deposit is only read.
+        assert!(matches!(
+            engine.process(tx(TxType::Dispute, 2, 1100, "0")).unwrap(),
+            Decision::Ignore(_)
+        ));
+        assert_eq!(engine.into_accounts(), *expected.accounts());
+
+        let err = ConcurrentEngine::new(
+            EngineConfig {
+                max_memory: Some(1 << 20),
+                ..Default::default()
+            },
+            4,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "The concurrent engine does not support a memory cap"
+        );
+    }
+}
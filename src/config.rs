@@ -0,0 +1,252 @@
+//! Configuration files.
+//!
+//! A configuration file sets command-line options in TOML, so deployments do not
+//! need long shell commands. Keys are the long option names without the leading
+//! dashes and may be grouped into tables, whose names are only for readability:
+//!
+//! ```toml
+//! [engine]
+//! credit-limit = "100"
+//! dispute-policy = "hold-partial"
+//!
+//! [input]
+//! strict = true
+//! column-alias = ["Kind=type", "Customer=client"]
+//!
+//! [output]
+//! bool-style = "yes-no"
+//! ```
+//!
+//! [`config_args`] translates such a table into the arguments it stands for.
+//! Options given on the command line take precedence over the file.
+
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgMatches, Command};
+use toml::{Table, Value};
+
+/// Reads and parses the TOML configuration file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not valid TOML.
+pub fn read_config_file(path: &str) -> Result<Table> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path))?;
+    contents
+        .parse()
+        .with_context(|| format!("Failed to parse config file: {}", path))
+}
+
+/// Returns the arguments that `config` sets for the (sub)command selected by
+/// `matches`, as `--name=value` arguments to append to the command line.
+///
+/// `matches` are the arguments parsed from the command line by `command`;
+/// options given there are skipped, so they take precedence over the file.
+/// Options of other subcommands are ignored, so that one file can serve all
+/// subcommands. Flags, and options whose value is optional, are set with `true`
+/// and left unset with `false`. Arrays repeat an option.
+///
+/// # Errors
+///
+/// Returns an error if a key is not a long option of any (sub)command or its
+/// value does not fit the option.
+pub fn config_args(config: &Table, command: &Command, matches: &ArgMatches) -> Result<Vec<String>> {
+    // Building propagates global options to the subcommands.
+    let mut command = command.clone();
+    command.build();
+    let (selected, matches) = match matches.subcommand() {
+        Some((name, matches)) => (
+            command
+                .find_subcommand(name)
+                .with_context(|| format!("Unknown subcommand '{}'", name))?,
+            matches,
+        ),
+        None => (&command, matches),
+    };
+
+    let mut args = Vec::new();
+    for (key, value) in flatten(config)? {
+        let Some(arg) = find_option(selected, key) else {
+            anyhow::ensure!(
+                std::iter::once(&command)
+                    .chain(command.get_subcommands())
+                    .any(|command| find_option(command, key).is_some()),
+                "Unknown option '{}' in config file",
+                key
+            );
+            continue;
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let optional_value = arg.get_action().takes_values()
+            && arg
+                .get_num_args()
+                .is_some_and(|count| count.min_values() == 0);
+        if !arg.get_action().takes_values() || optional_value {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{}", key)),
+                Value::Boolean(false) => {}
+                _ if optional_value => args.push(format!("--{}={}", key, scalar(key, value)?)),
+                _ => anyhow::bail!("Option '{}' in config file must be true or false", key),
+            }
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            args.push(format!("--{}={}", key, scalar(key, value)?));
+        }
+    }
+    Ok(args)
+}
+
+/// Returns the option of `command` with the long name `name`.
+fn find_option<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(name) && arg.get_id() != "config")
+}
+
+/// Returns the command-line form of a single option value.
+fn scalar(key: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Float(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        _ => anyhow::bail!(
+            "Option '{}' in config file must be a string, number or boolean",
+            key
+        ),
+    }
+}
+
+/// Returns the keys and values of `config`, looking into its tables.
+fn flatten(config: &Table) -> Result<Vec<(&str, &Value)>> {
+    let mut entries = Vec::new();
+    for (key, value) in config {
+        match value {
+            Value::Table(table) => {
+                for (key, value) in table {
+                    anyhow::ensure!(
+                        !value.is_table(),
+                        "Table '{}' in config file is nested too deeply",
+                        key
+                    );
+                    entries.push((key.as_str(), value));
+                }
+            }
+            value => entries.push((key.as_str(), value)),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ArgAction;
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("input"))
+            .arg(Arg::new("credit_limit").long("credit-limit"))
+            .arg(Arg::new("strict").long("strict").action(ArgAction::SetTrue))
+            .arg(
+                Arg::new("header_name")
+                    .long("header-name")
+                    .action(ArgAction::Append),
+            )
+            .arg(Arg::new("max_disputes").long("max-disputes"))
+            .arg(
+                Arg::new("stats")
+                    .long("stats")
+                    .num_args(0..=1)
+                    .default_missing_value("-"),
+            )
+            .subcommand(Command::new("listen").arg(Arg::new("ingest").long("ingest")))
+    }
+
+    fn args(config: &str, command_line: &[&str]) -> Result<Vec<String>> {
+        let command = command();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["test"].iter().chain(command_line))
+            .unwrap();
+        config_args(&config.parse().unwrap(), &command, &matches)
+    }
+
+    #[test]
+    fn translates_keys_into_long_options() {
+        let config = "
+            max-disputes = 2
+
+            [engine]
+            credit-limit = \"100\"
+            strict = true
+
+            [output]
+            header-name = [\"locked=frozen\", \"client=id\"]
+        ";
+
+        assert_eq!(
+            args(config, &["in.csv"]).unwrap(),
+            [
+                "--credit-limit=100",
+                "--strict",
+                "--max-disputes=2",
+                "--header-name=locked=frozen",
+                "--header-name=client=id",
+            ]
+        );
+    }
+
+    #[test]
+    fn options_with_an_optional_value_accept_booleans() {
+        assert_eq!(args("stats = true", &[]).unwrap(), ["--stats"]);
+        assert!(args("stats = false", &[]).unwrap().is_empty());
+        assert_eq!(
+            args("stats = \"stats.json\"", &[]).unwrap(),
+            ["--stats=stats.json"]
+        );
+    }
+
+    #[test]
+    fn options_of_other_subcommands_are_ignored() {
+        let config = "strict = true\ningest = \"127.0.0.1:7000\"";
+
+        assert_eq!(args(config, &[]).unwrap(), ["--strict"]);
+        assert_eq!(
+            args(config, &["listen"]).unwrap(),
+            ["--ingest=127.0.0.1:7000"]
+        );
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = "credit-limit = \"100\"\nstrict = false\nheader-name = [\"a=b\"]";
+
+        assert_eq!(
+            args(config, &["--credit-limit", "5", "--header-name", "c=d"]).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_mismatched_values() {
+        let err = args("credit-limt = \"1\"", &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown option 'credit-limt' in config file"
+        );
+        assert!(args("input = \"in.csv\"", &[]).is_err());
+        assert!(args("strict = \"yes\"", &[]).is_err());
+        assert!(args("[a.b]\nstrict = true", &[]).is_err());
+    }
+}
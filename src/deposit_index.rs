@@ -0,0 +1,410 @@
+//! Deposit history persisted as a compact index file (`--save-deposit-index`
+//! and `--deposit-index`).
+//!
+//! A run writes its deposit history to an index file, and a later run looks
+//! the deposits its own history lacks up in it with a [`DepositIndex`], the
+//! [`TxLookup`] of the file. Disputes of deposits from old input files are then
+//! decided without reprocessing those files.
+//!
+//! The file is a 16-byte header followed by one 48-byte record per deposit,
+//! sorted by transaction ID, with all integers little-endian:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 8 | Magic `DHDEPIX1` |
+//! | 8 | 8 | Number of records |
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 8 | Transaction ID |
+//! | 8 | 4 | Client ID |
+//! | 12 | 4 | Number of disputes |
+//! | 16 | 8 | Amount, in units of 1/10000 |
+//! | 24 | 8 | Held amount, in units of 1/10000 |
+//! | 32 | 8 | Refunded amount, in units of 1/10000 |
+//! | 40 | 1 | Dispute state |
+//! | 41 | 7 | Padding |
+//!
+//! The records are fixed-width and aligned, so the file can be memory-mapped
+//! and searched in place. [`DepositIndex`] binary-searches it with positional
+//! reads and keeps none of it in memory. The layout does not depend on the
+//! `fixed-point` and `wide-ids` features.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::amount::MAX_DECIMAL_PLACES;
+use crate::engine::DepositRecord;
+use crate::lookup::TxLookup;
+use crate::types::{Amount, ClientId, DisputeState, RawClientId, RawTxId, TxId};
+
+/// Magic bytes at the start of an index file.
+const MAGIC: &[u8; 8] = b"DHDEPIX1";
+/// Size of the header.
+const HEADER_SIZE: u64 = 16;
+/// Size of a record.
+const RECORD_SIZE: usize = 48;
+
+/// Writes `history` to an index file, replacing `path` once it is complete.
+///
+/// The deposits of `base`, typically the index the run looked deposits up in,
+/// are carried over unless `history` has a newer record of them, so that an
+/// index covers all runs up to the current one.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written, `base` cannot be read, or
+/// an amount has more than [`MAX_DECIMAL_PLACES`] decimal places or is too
+/// large for the index.
+pub fn write_deposit_index_file(
+    path: impl AsRef<Path>,
+    history: &HashMap<TxId, DepositRecord>,
+    base: Option<&DepositIndex>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let file = File::create(&temporary)
+        .with_context(|| format!("Failed to create deposit index: {}", temporary.display()))?;
+    let mut writer = BufWriter::new(file);
+    write_deposit_index(&mut writer, history, base)
+        .with_context(|| format!("Failed to write deposit index: {}", temporary.display()))?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("Failed to replace deposit index: {}", path.display()))
+}
+
+/// Writes the header and the sorted records of `history` and `base` to
+/// `writer`.
+fn write_deposit_index<W: Write>(
+    writer: &mut W,
+    history: &HashMap<TxId, DepositRecord>,
+    base: Option<&DepositIndex>,
+) -> Result<()> {
+    let mut deposits: Vec<_> = history.iter().collect();
+    deposits.sort_unstable_by_key(|(tx, _)| **tx);
+    let superseded = |record: &[u8; RECORD_SIZE]| {
+        RawTxId::try_from(record_tx(record)).is_ok_and(|tx| history.contains_key(&TxId(tx)))
+    };
+    let mut carried = 0;
+    if let Some(base) = base {
+        for record in base.records()? {
+            carried += u64::from(!superseded(&record?));
+        }
+    }
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(deposits.len() as u64 + carried).to_le_bytes())?;
+
+    let mut base = base.map(DepositIndex::records).transpose()?;
+    let mut next_record = || base.as_mut().and_then(Iterator::next).transpose();
+    let mut record = next_record()?;
+    let mut deposits = deposits.into_iter().peekable();
+    loop {
+        let next_tx = deposits.peek().map(|(tx, _)| raw_tx(**tx));
+        match (record, next_tx) {
+            (None, None) => break,
+            (Some(current), next_tx) if next_tx.is_none_or(|tx| record_tx(&current) < tx) => {
+                if !superseded(&current) {
+                    writer.write_all(&current)?;
+                }
+                record = next_record()?;
+            }
+            _ => {
+                if let Some((&tx, deposit)) = deposits.next() {
+                    writer.write_all(&encode(tx, deposit)?)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The deposits of an index file, read from the file when looked up.
+#[derive(Debug)]
+pub struct DepositIndex {
+    path: String,
+    len: u64,
+    file: Mutex<File>,
+}
+
+impl DepositIndex {
+    /// Opens an index file written by [`write_deposit_index_file`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not an index file.
+    pub fn open(path: &str) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Failed to open deposit index: {}", path))?;
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .ok()
+            .filter(|_| header[..8] == MAGIC[..])
+            .with_context(|| format!("Not a deposit index: {}", path))?;
+        let len = u64::from_le_bytes(header[8..].try_into().unwrap_or_default());
+        let size = file
+            .metadata()
+            .with_context(|| format!("Failed to read deposit index: {}", path))?
+            .len();
+        anyhow::ensure!(
+            size == HEADER_SIZE + len * RECORD_SIZE as u64,
+            "Deposit index of {} records has {} bytes: {}",
+            len,
+            size,
+            path
+        );
+        Ok(DepositIndex {
+            path: path.to_string(),
+            len,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Returns the number of deposits in the index.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the index has no deposits.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the records of the index in order, read from a file handle of
+    /// their own.
+    fn records(&self) -> Result<impl Iterator<Item = Result<[u8; RECORD_SIZE]>> + use<>> {
+        let mut file = std::io::BufReader::new(File::open(&self.path)?);
+        file.seek(SeekFrom::Start(HEADER_SIZE))?;
+        let mut remaining = self.len;
+        Ok(std::iter::from_fn(move || {
+            if remaining == 0 {
+                return None;
+            }
+            remaining -= 1;
+            let mut record = [0; RECORD_SIZE];
+            Some(
+                file.read_exact(&mut record)
+                    .map(|()| record)
+                    .map_err(Into::into),
+            )
+        }))
+    }
+
+    /// Reads the record at `index`.
+    fn read(&self, file: &mut File, index: u64) -> Result<[u8; RECORD_SIZE]> {
+        let mut record = [0; RECORD_SIZE];
+        file.seek(SeekFrom::Start(HEADER_SIZE + index * RECORD_SIZE as u64))?;
+        file.read_exact(&mut record)?;
+        Ok(record)
+    }
+}
+
+impl TxLookup for DepositIndex {
+    fn lookup(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        let search = || -> Result<Option<DepositRecord>> {
+            let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+            let (mut low, mut high) = (0, self.len);
+            while low < high {
+                let middle = low + (high - low) / 2;
+                let record = self.read(&mut file, middle)?;
+                match record_tx(&record).cmp(&raw_tx(tx)) {
+                    std::cmp::Ordering::Less => low = middle + 1,
+                    std::cmp::Ordering::Greater => high = middle,
+                    std::cmp::Ordering::Equal => return decode(&record).map(Some),
+                }
+            }
+            Ok(None)
+        };
+        search().with_context(|| format!("Failed to look up deposit {} in: {}", tx, self.path))
+    }
+}
+
+/// Returns `tx` widened to the 8 bytes of the index, whatever the width of
+/// [`RawTxId`].
+#[allow(clippy::useless_conversion)]
+fn raw_tx(tx: TxId) -> u64 {
+    u64::from(tx.0)
+}
+
+/// Returns `client` widened to the 4 bytes of the index, whatever the width of
+/// [`RawClientId`].
+#[allow(clippy::useless_conversion)]
+fn raw_client(client: ClientId) -> u32 {
+    u32::from(client.0)
+}
+
+fn record_tx(record: &[u8; RECORD_SIZE]) -> u64 {
+    u64::from_le_bytes(record[0..8].try_into().unwrap_or_default())
+}
+
+fn encode(tx: TxId, deposit: &DepositRecord) -> Result<[u8; RECORD_SIZE]> {
+    let units = |amount| {
+        to_units(amount).with_context(|| {
+            format!(
+                "Deposit {} has an amount the index cannot represent: {}",
+                tx, amount
+            )
+        })
+    };
+    let mut record = [0; RECORD_SIZE];
+    record[0..8].copy_from_slice(&raw_tx(tx).to_le_bytes());
+    record[8..12].copy_from_slice(&raw_client(deposit.client).to_le_bytes());
+    record[12..16].copy_from_slice(&deposit.dispute_count.to_le_bytes());
+    record[16..24].copy_from_slice(&units(deposit.amount)?.to_le_bytes());
+    record[24..32].copy_from_slice(&units(deposit.held)?.to_le_bytes());
+    record[32..40].copy_from_slice(&units(deposit.refunded)?.to_le_bytes());
+    record[40] = match deposit.state {
+        DisputeState::Authorized => 0,
+        DisputeState::Undisputed => 1,
+        DisputeState::Disputed => 2,
+        DisputeState::Resolved => 3,
+        DisputeState::ChargedBack => 4,
+        DisputeState::Withdrawn => 5,
+        DisputeState::Reversed => 6,
+    };
+    Ok(record)
+}
+
+fn decode(record: &[u8; RECORD_SIZE]) -> Result<DepositRecord> {
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap_or_default())
+    };
+    let amount_at = |offset: usize| {
+        from_units(i64::from_le_bytes(
+            record[offset..offset + 8].try_into().unwrap_or_default(),
+        ))
+    };
+    let tx = record_tx(record);
+    RawTxId::try_from(tx).with_context(|| format!("Transaction ID {} is out of range", tx))?;
+    let client = RawClientId::try_from(u32_at(8))
+        .with_context(|| format!("Client ID {} of deposit {} is out of range", u32_at(8), tx))?;
+    let state = match record[40] {
+        0 => DisputeState::Authorized,
+        1 => DisputeState::Undisputed,
+        2 => DisputeState::Disputed,
+        3 => DisputeState::Resolved,
+        4 => DisputeState::ChargedBack,
+        5 => DisputeState::Withdrawn,
+        6 => DisputeState::Reversed,
+        state => anyhow::bail!("Unknown dispute state {} of deposit {}", state, tx),
+    };
+    Ok(DepositRecord {
+        state,
+        held: amount_at(24),
+        dispute_count: u32_at(12),
+        refunded: amount_at(32),
+        ..DepositRecord::new(ClientId(client), amount_at(16))
+    })
+}
+
+/// Returns `amount` in units of 1/10000, or `None` if it has more decimal
+/// places or is out of range.
+#[cfg(not(feature = "fixed-point"))]
+fn to_units(amount: Amount) -> Option<i64> {
+    let mut scaled = amount;
+    scaled.rescale(MAX_DECIMAL_PLACES);
+    (scaled == amount)
+        .then(|| i64::try_from(scaled.mantissa()).ok())
+        .flatten()
+}
+
+#[cfg(not(feature = "fixed-point"))]
+fn from_units(units: i64) -> Amount {
+    Amount::new(units, MAX_DECIMAL_PLACES).normalize()
+}
+
+#[cfg(feature = "fixed-point")]
+fn to_units(amount: Amount) -> Option<i64> {
+    const _: () = assert!(crate::fixed_point::FixedPoint::SCALE == MAX_DECIMAL_PLACES);
+    Some(amount.units())
+}
+
+#[cfg(feature = "fixed-point")]
+fn from_units(units: i64) -> Amount {
+    Amount::from_units(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn looks_up_the_deposits_written_to_the_index() {
+        let amount = |value| Amount::from_str(value).unwrap();
+        let history: HashMap<_, _> = (1..=100)
+            .map(|tx| {
+                let mut deposit =
+                    DepositRecord::new(ClientId(tx as RawClientId % 7), amount("1.5"));
+                if tx % 10 == 0 {
+                    deposit.state = DisputeState::Disputed;
+                    deposit.held = amount("1.5");
+                    deposit.dispute_count = 1;
+                }
+                (TxId(tx * 3), deposit)
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("deposit-index-{}", std::process::id()));
+        write_deposit_index_file(&path, &history, None).unwrap();
+        let path = path.to_str().unwrap();
+
+        let index = DepositIndex::open(path).unwrap();
+        assert_eq!(index.len(), 100);
+        for (tx, deposit) in &history {
+            let found = index.lookup(*tx).unwrap().unwrap();
+            assert_eq!(
+                (found.client, found.amount, found.state, found.held),
+                (deposit.client, deposit.amount, deposit.state, deposit.held)
+            );
+        }
+        for tx in [0, 1, 4, 301, 1000] {
+            assert!(index.lookup(TxId(tx)).unwrap().is_none());
+        }
+
+        // A later run carries the deposits it did not change over.
+        let later = HashMap::from([
+            (
+                TxId(3),
+                DepositRecord {
+                    state: DisputeState::ChargedBack,
+                    ..history[&TxId(3)].clone()
+                },
+            ),
+            (TxId(1000), DepositRecord::new(ClientId(1), amount("2"))),
+        ]);
+        let merged_path = format!("{}-merged", path);
+        write_deposit_index_file(&merged_path, &later, Some(&index)).unwrap();
+        let merged = DepositIndex::open(&merged_path).unwrap();
+        assert_eq!(merged.len(), 101);
+        let state = |tx| merged.lookup(TxId(tx)).unwrap().unwrap().state;
+        assert_eq!(state(3), DisputeState::ChargedBack);
+        assert_eq!(state(30), DisputeState::Disputed);
+        assert_eq!(state(300), DisputeState::Disputed);
+        assert_eq!(state(1000), DisputeState::Undisputed);
+        std::fs::remove_file(&merged_path).unwrap();
+
+        std::fs::write(path, b"tx,client,amount\n").unwrap();
+        let err = DepositIndex::open(path).unwrap_err();
+        assert_eq!(err.to_string(), format!("Not a deposit index: {}", path));
+        std::fs::remove_file(path).unwrap();
+
+        #[cfg(not(feature = "fixed-point"))]
+        {
+            let mut history = HashMap::new();
+            history.insert(TxId(1), DepositRecord::new(ClientId(1), amount("0.00001")));
+            let err = write_deposit_index(&mut Vec::new(), &history, None).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "Deposit 1 has an amount the index cannot represent: 0.00001"
+            );
+        }
+    }
+}
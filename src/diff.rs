@@ -0,0 +1,231 @@
+//! Comparison of two account outputs.
+//!
+//! [`diff_accounts`] compares the accounts produced by two runs (e.g. by two
+//! versions of the engine on the same input) and returns an [`AccountDelta`] for
+//! every client whose account differs. [`reconcile_accounts`] does the same for
+//! the accounts of a run and an expected account output, with a tolerance for
+//! the balances.
+
+use crate::types::{AccountDetails, Accounts, Amount, ClientId};
+
+/// How the account of a client differs between two outputs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDelta {
+    pub client: ClientId,
+    /// The account in the old output, or `None` if the client is new.
+    pub old: Option<AccountDetails>,
+    /// The account in the new output, or `None` if the client disappeared.
+    pub new: Option<AccountDetails>,
+}
+
+/// The kind of difference described by an [`AccountDelta`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaKind {
+    /// The client only appears in the new output.
+    Added,
+    /// The client only appears in the old output.
+    Removed,
+    /// The client appears in both outputs with different balances or lock status.
+    Changed,
+}
+
+impl DeltaKind {
+    /// Returns the lowercase name of the kind.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeltaKind::Added => "added",
+            DeltaKind::Removed => "removed",
+            DeltaKind::Changed => "changed",
+        }
+    }
+}
+
+impl AccountDelta {
+    /// Returns whether the client was added, removed or changed.
+    pub fn kind(&self) -> DeltaKind {
+        match (&self.old, &self.new) {
+            (None, _) => DeltaKind::Added,
+            (_, None) => DeltaKind::Removed,
+            _ => DeltaKind::Changed,
+        }
+    }
+
+    /// Returns the change of the available balance (new minus old).
+    pub fn available(&self) -> Amount {
+        self.delta(|account| account.available)
+    }
+
+    /// Returns the change of the held balance (new minus old).
+    pub fn held(&self) -> Amount {
+        self.delta(|account| account.held)
+    }
+
+    /// Returns the change of the total balance (new minus old).
+    pub fn total(&self) -> Amount {
+        self.delta(|account| account.total)
+    }
+
+    /// Returns `true` if the account is locked in the new output but was not
+    /// locked (or did not exist) in the old output.
+    pub fn newly_locked(&self) -> bool {
+        let was_locked = self.old.as_ref().is_some_and(|account| account.locked);
+        let is_locked = self.new.as_ref().is_some_and(|account| account.locked);
+        is_locked && !was_locked
+    }
+
+    fn delta(&self, field: impl Fn(&AccountDetails) -> Amount) -> Amount {
+        let old = self.old.as_ref().map_or(Amount::ZERO, &field);
+        let new = self.new.as_ref().map_or(Amount::ZERO, &field);
+        new - old
+    }
+}
+
+/// Returns the differences between `old` and `new`, in ascending client order.
+///
+/// Clients whose accounts are identical in both outputs are omitted, so an empty
+/// result means the outputs are equivalent.
+pub fn diff_accounts(old: &Accounts, new: &Accounts) -> Vec<AccountDelta> {
+    let mut clients: Vec<ClientId> = old.keys().chain(new.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .filter_map(|client| {
+            let old = old.get(&client);
+            let new = new.get(&client);
+            (old != new).then(|| AccountDelta {
+                client,
+                old: old.cloned(),
+                new: new.cloned(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the discrepancies of `actual` from `expected`, in ascending client
+/// order, as deltas from the expected to the actual account.
+///
+/// Unlike [`diff_accounts`], only the balances and the lock status are
+/// compared, and balances differing by at most `tolerance` match, so an
+/// expected output from another system, which lacks the other fields and may
+/// round differently, can be reconciled.
+pub fn reconcile_accounts(
+    expected: &Accounts,
+    actual: &Accounts,
+    tolerance: Amount,
+) -> Vec<AccountDelta> {
+    let within = |delta: Amount| -tolerance <= delta && delta <= tolerance;
+    diff_accounts(expected, actual)
+        .into_iter()
+        .filter(|delta| match (&delta.old, &delta.new) {
+            (Some(expected), Some(actual)) => {
+                expected.locked != actual.locked
+                    || !within(delta.available())
+                    || !within(delta.held())
+                    || !within(delta.total())
+            }
+            _ => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn reports_changed_added_removed_and_locked_accounts() {
+        let old = Accounts::from([
+            (
+                ClientId(1),
+                AccountDetails::new_with_balance(Amount::from_str("10.0").unwrap()),
+            ),
+            (
+                ClientId(2),
+                AccountDetails::new_with_balance(Amount::from_str("5.0").unwrap()),
+            ),
+            (
+                ClientId(3),
+                AccountDetails::new_with_balance(Amount::from_str("1.0").unwrap()),
+            ),
+        ]);
+        let new = Accounts::from([
+            (
+                ClientId(1),
+                AccountDetails::new_with_balance(Amount::from_str("10.0").unwrap()),
+            ),
+            (
+                ClientId(2),
+                AccountDetails {
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                ClientId(4),
+                AccountDetails::new_with_balance(Amount::from_str("2.5").unwrap()),
+            ),
+        ]);
+
+        let deltas = diff_accounts(&old, &new);
+
+        assert_eq!(
+            deltas.iter().map(|delta| delta.client).collect::<Vec<_>>(),
+            vec![ClientId(2), ClientId(3), ClientId(4)]
+        );
+        assert_eq!(deltas[0].kind(), DeltaKind::Changed);
+        assert_eq!(deltas[0].available(), Amount::from_str("-5.0").unwrap());
+        assert_eq!(deltas[0].total(), Amount::from_str("-5.0").unwrap());
+        assert!(deltas[0].newly_locked());
+        assert_eq!(deltas[1].kind(), DeltaKind::Removed);
+        assert_eq!(deltas[1].total(), Amount::from_str("-1.0").unwrap());
+        assert_eq!(deltas[2].kind(), DeltaKind::Added);
+        assert_eq!(deltas[2].available(), Amount::from_str("2.5").unwrap());
+        assert!(!deltas[2].newly_locked());
+    }
+
+    #[test]
+    fn reconciles_balances_within_the_tolerance() {
+        let amount = |value| Amount::from_str(value).unwrap();
+        let expected = Accounts::from([
+            (ClientId(1), AccountDetails::new_with_balance(amount("10"))),
+            (ClientId(2), AccountDetails::new_with_balance(amount("5"))),
+            (ClientId(3), AccountDetails::new_with_balance(amount("1"))),
+        ]);
+        let actual = Accounts::from([
+            (
+                ClientId(1),
+                AccountDetails {
+                    chargebacks: 1,
+                    ..AccountDetails::new_with_balance(amount("10.0001"))
+                },
+            ),
+            (
+                ClientId(2),
+                AccountDetails::new_with_balance(amount("5.01")),
+            ),
+            (ClientId(4), AccountDetails::new_with_balance(amount("1"))),
+        ]);
+
+        let discrepancies = reconcile_accounts(&expected, &actual, amount("0.001"));
+        let kinds: Vec<_> = discrepancies
+            .iter()
+            .map(|delta| (delta.client, delta.kind()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (ClientId(2), DeltaKind::Changed),
+                (ClientId(3), DeltaKind::Removed),
+                (ClientId(4), DeltaKind::Added),
+            ]
+        );
+        assert_eq!(discrepancies[0].total(), amount("0.01"));
+        assert_eq!(
+            reconcile_accounts(&expected, &actual, Amount::ZERO)[0].client,
+            ClientId(1)
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Digest of the final account state.
+//!
+//! [`account_digest`] hashes the accounts in a canonical form that does not
+//! depend on the output options or the amount representation, so two
+//! environments that processed the same transactions can reconcile their results
+//! by comparing one hash. The canonical form is one line per account, in
+//! ascending client order, without header:
+//!
+//! ```text
+//! 1,-1.5,2.0001,0.5001,false
+//! 2,0,0,0,true
+//! ```
+//!
+//! Amounts have no trailing zeros, and every line ends with `\n`. The digest is
+//! the hex-encoded SHA-256 of these lines, so it can be reproduced with
+//! `sha256sum`.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::fs::File;
+use std::path::Path;
+
+use crate::types::{Accounts, Amount, sorted_accounts};
+
+/// Returns the hex-encoded SHA-256 digest of `accounts` in canonical form.
+pub fn account_digest(accounts: &Accounts) -> String {
+    let mut hasher = Sha256::new();
+    let mut line = String::new();
+    for (client, account) in sorted_accounts(accounts) {
+        line.clear();
+        // Writing to a `String` cannot fail.
+        let _ = writeln!(
+            line,
+            "{},{},{},{},{}",
+            client,
+            canonical_amount(account.available),
+            canonical_amount(account.held),
+            canonical_amount(account.total),
+            account.locked
+        );
+        hasher.update(line.as_bytes());
+    }
+    hex(&hasher.finalize())
+}
+
+/// Returns the hex-encoded SHA-256 digest of `bytes`.
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Returns the hex-encoded SHA-256 digest of the contents of the file at `path`.
+pub(crate) fn file_sha256_hex(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash file: {}", path.display()))?;
+    Ok(hex(&hasher.finalize()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Formats `amount` without trailing zeros.
+#[cfg(not(feature = "fixed-point"))]
+fn canonical_amount(amount: Amount) -> Amount {
+    amount.normalize()
+}
+
+/// Formats `amount` without trailing zeros, which `FixedPoint` always does.
+#[cfg(feature = "fixed-point")]
+fn canonical_amount(amount: Amount) -> Amount {
+    amount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountDetails, ClientId};
+    use std::str::FromStr;
+
+    #[test]
+    fn digest_is_independent_of_representation_and_order() {
+        let account = |available: &str, held: &str, total: &str, locked| AccountDetails {
+            available: Amount::from_str(available).unwrap(),
+            held: Amount::from_str(held).unwrap(),
+            total: Amount::from_str(total).unwrap(),
+            locked,
+            ..Default::default()
+        };
+        let mut accounts = Accounts::new();
+        accounts.insert(ClientId(2), account("0", "0", "0", true));
+        accounts.insert(ClientId(1), account("-1.50", "2.0001", "0.5001", false));
+
+        // sha256sum of "1,-1.5,2.0001,0.5001,false\n2,0,0,0,true\n"
+        assert_eq!(
+            account_digest(&accounts),
+            "29c4f62170c2bd8c7d26cf1980d53de9bd91e61c1932c6de0a2c309ff1cbbb4e"
+        );
+
+        accounts.get_mut(&ClientId(2)).unwrap().locked = false;
+        assert_ne!(
+            account_digest(&accounts),
+            "29c4f62170c2bd8c7d26cf1980d53de9bd91e61c1932c6de0a2c309ff1cbbb4e"
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! In-memory processing for embedding the engine.
+//!
+//! The functions here read and write CSV text instead of files, so they run
+//! where there is no file system, such as WebAssembly in a browser (see the
+//! `wasm/` crate). Input columns are recognized by their standard names only;
+//! aliases, value dates and recurrences need the file reader.
+
+use anyhow::{Context, Result};
+
+use crate::engine::{Engine, EngineConfig};
+use crate::io::{self, OutputOptions};
+use crate::types::Transaction;
+
+/// Processes the transactions of the CSV text `input` with a fresh engine and
+/// returns the accounts as CSV text, as the application would print them.
+///
+/// The delimiter is detected from the header line.
+///
+/// # Errors
+///
+/// Returns an error if a record cannot be parsed or the engine fails, e.g. on
+/// an overflowing balance.
+pub fn process_csv(input: &str, config: EngineConfig) -> Result<String> {
+    let header_line = input.lines().next().unwrap_or_default();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .delimiter(io::detect_delimiter(header_line.as_bytes()))
+        .from_reader(input.as_bytes());
+
+    let mut engine = Engine::new(config);
+    for (index, result) in reader.deserialize::<Transaction>().enumerate() {
+        let tx = result.with_context(|| format!("Failed to parse record {}", index + 1))?;
+        engine.process(tx)?;
+    }
+
+    let mut output = Vec::new();
+    io::write_accounts_as_csv(
+        engine.into_accounts(),
+        &mut output,
+        &OutputOptions::default(),
+    )?;
+    String::from_utf8(output).context("Account output is not valid UTF-8")
+}
+
+/// Parses one `type,client,tx,amount` record without a header line, e.g.
+/// `deposit,1,1,10.0` or `dispute,1,1`.
+///
+/// # Errors
+///
+/// Returns an error if the line is not exactly one valid record.
+pub fn parse_csv_line(line: &str) -> Result<Transaction> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .has_headers(false)
+        .from_reader(line.as_bytes());
+    let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+    let mut records = reader.records();
+    let mut record = records
+        .next()
+        .context("Missing record")?
+        .context("Failed to read record")?;
+    anyhow::ensure!(records.next().is_none(), "Expected a single record");
+    // Disputes and their settlements may leave out the amount.
+    if record.len() == 3 {
+        record.push_field("");
+    }
+    record
+        .deserialize(Some(&headers))
+        .with_context(|| format!("Failed to parse record: {}", line.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Amount, ClientId, TxId, TxType};
+
+    #[test]
+    fn processes_csv_text() {
+        let input = "type; client; tx; amount\n\
+                     deposit; 1; 1; 10.0\n\
+                     withdrawal; 1; 2; 2.5\n\
+                     deposit; 2; 3; 1\n\
+                     dispute; 2; 3;\n";
+        assert_eq!(
+            process_csv(input, EngineConfig::default()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,7.5,0,7.5,false\n\
+             2,0,1,1,false\n"
+        );
+
+        let err = process_csv(
+            "type,client,tx,amount\ndeposit,1,1,1\nsend,1,2,1\n",
+            EngineConfig::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("record 2"), "{:#}", err);
+    }
+
+    #[test]
+    fn parses_single_records() {
+        let tx = parse_csv_line(" dispute, 2, 7\n").unwrap();
+        assert_eq!(tx.tx_type, TxType::Dispute);
+        assert_eq!((tx.client, tx.tx), (ClientId(2), TxId(7)));
+        assert_eq!(tx.amount, Amount::ZERO);
+
+        for line in ["", "deposit,1,1,1\ndeposit,1,2,1", "deposit,x,1,1"] {
+            assert!(parse_csv_line(line).is_err(), "{:?}", line);
+        }
+    }
+}
@@ -3,749 +3,4864 @@
 //! This module provides the core business logic for processing financial transactions
 //! and maintaining account state. It handles deposits, withdrawals, disputes, resolves,
 //! and chargebacks according to the transaction processing rules.
+//!
+//! Processing is split into two steps: [`decide`] is a pure function determining the
+//! [`Decision`] for a transaction from the relevant state, and [`apply`] performs the
+//! state mutation. [`Engine`] combines both for the common case. The decision for
+//! each transaction type is made by a [`handler::TxHandler`] of the configuration.
+
+pub mod handler;
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 
+use crate::amount::{AmountOps, PrecisionPolicy};
+use crate::hook::{HookDecision, TransactionHook};
+use crate::lookup::TxLookup;
+use crate::stats::Stats;
 use crate::types::AccountDetails;
 use crate::types::Accounts;
+use crate::types::Amount;
+use crate::types::ClientId;
+use crate::types::DisputeState;
 use crate::types::Transaction;
 use crate::types::TxId;
 use crate::types::TxType;
+use crate::types::sorted_accounts;
 use anyhow::Result;
+use handler::{Handlers, TxContext};
+use serde::{Deserialize, Serialize};
 
-/// Processes transactions from an iterator, maintaining account state.
+/// Configuration options controlling how the engine applies transactions.
 ///
-/// # Arguments
+/// The default configuration matches the original processing rules: available
+/// balances can never go below zero.
+#[derive(Debug, Clone)]
+pub struct EngineConfig<A = Amount> {
+    /// Amount by which a client's available balance may go below zero.
+    ///
+    /// Withdrawals and disputes are accepted as long as the resulting available
+    /// balance does not drop below `-credit_limit`. A limit of zero disables overdrafts.
+    pub credit_limit: A,
+    /// Per-client credit limits that take precedence over `credit_limit`.
+    pub client_credit_limits: BTreeMap<ClientId, A>,
+    /// Per-client attributes. The credit limit of a profile applies unless
+    /// `client_credit_limits` has one for the client.
+    pub client_profiles: BTreeMap<ClientId, ClientProfile<A>>,
+    /// How disputes are handled when available funds do not cover the disputed amount.
+    pub dispute_policy: DisputePolicy,
+    /// Whether a transaction may be disputed again after its dispute was resolved.
+    pub allow_redispute: bool,
+    /// Maximum number of disputes accepted per transaction; `None` means unlimited.
+    pub max_disputes_per_tx: Option<u32>,
+    /// How amounts with more than four decimal places are handled. Rejected
+    /// amounts are ignored with [`IgnoreReason::ExcessPrecision`].
+    pub precision: PrecisionPolicy,
+    /// Cap in bytes on the approximate heap usage of the accounts and the deposit
+    /// history; `None` means unlimited. See [`Engine::memory_usage`].
+    pub max_memory: Option<usize>,
+    /// Number of most recent deposits that stay disputable; `None` keeps every
+    /// deposit. Older deposits are evicted by [`Engine::compact`] unless they are
+    /// currently disputed, and later disputes of them are ignored with
+    /// [`IgnoreReason::UnknownTransaction`].
+    ///
+    /// Windows above [`MAX_HISTORY_RETENTION`] are treated as that maximum.
+    pub history_retention: Option<u32>,
+    /// Whether [`Engine::process`] maintains the
+    /// [`AccountMetrics`](crate::types::AccountMetrics) of each account. Off by
+    /// default, since it reads the clock for every applied transaction.
+    pub account_metrics: bool,
+    /// When a chargeback locks the client's account.
+    pub lock_policy: LockPolicy,
+    /// The dispute lifecycle operations still applied to locked accounts; all
+    /// other transactions of locked accounts are ignored.
+    pub locked_operations: LockedOperations,
+    /// Number of subsequent transactions after which [`Engine::process`] releases
+    /// an authorization that was not captured; `None` keeps authorizations until
+    /// they are captured.
+    pub authorization_expiry: Option<u64>,
+    /// How applied transactions are recognized when they are received again,
+    /// e.g. from overlapping input files; `None` applies every transaction.
+    /// Repeated transactions are ignored with
+    /// [`IgnoreReason::DuplicateTransaction`].
+    pub idempotency: Option<IdempotencyKey>,
+    /// The transaction types that are processed; `None` processes every type.
+    /// Transactions of other types are ignored with
+    /// [`IgnoreReason::TypeNotProcessed`], so they are still counted.
+    pub processed_types: Option<BTreeSet<TxType>>,
+    /// Whether [`Engine::apply`] records withdrawals in the deposit history,
+    /// so a later `reversal` can credit them back. Off by default, since it
+    /// grows the history by one record per withdrawal.
+    pub reversible_withdrawals: bool,
+    /// Whether accounts are only opened by `open` transactions. Deposits and
+    /// authorizations for clients without an account are then ignored with
+    /// [`IgnoreReason::UnknownAccount`].
+    pub require_opening: bool,
+    /// The handlers deciding the effect of each transaction type, see
+    /// [`handler`].
+    pub handlers: Handlers<A>,
+}
+
+impl<A: AmountOps> Default for EngineConfig<A> {
+    fn default() -> Self {
+        EngineConfig {
+            credit_limit: A::ZERO,
+            client_credit_limits: BTreeMap::new(),
+            client_profiles: BTreeMap::new(),
+            dispute_policy: DisputePolicy::default(),
+            allow_redispute: true,
+            max_disputes_per_tx: None,
+            precision: PrecisionPolicy::default(),
+            max_memory: None,
+            history_retention: None,
+            account_metrics: false,
+            lock_policy: LockPolicy::default(),
+            locked_operations: LockedOperations::default(),
+            authorization_expiry: None,
+            idempotency: None,
+            processed_types: None,
+            reversible_withdrawals: false,
+            require_opening: false,
+            handlers: Handlers::standard(),
+        }
+    }
+}
+
+/// Attributes of a client that are configured rather than derived from its
+/// transactions.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientProfile<A = Amount> {
+    /// Largest single withdrawal accepted for the client; larger withdrawals are
+    /// ignored with [`IgnoreReason::WithdrawalLimitExceeded`].
+    pub withdrawal_limit: Option<A>,
+    /// Credit limit of the client, replacing [`EngineConfig::credit_limit`].
+    pub credit_limit: Option<A>,
+    /// Service tier of the client, e.g. `gold`, copied to its account.
+    pub tier: Option<String>,
+    /// Home currency of the client, e.g. `EUR`, copied to its account.
+    pub currency: Option<String>,
+}
+
+/// The key identifying an applied transaction in idempotent processing.
 ///
-/// * `transactions` - An iterator over transactions to process (can be `Result<Transaction>` for error handling)
+/// Only transactions with an ID of their own are recognized: deposits,
+/// withdrawals, authorizations, holds, releases and closes. Disputes, resolves,
+/// chargebacks, refunds and captures reference another transaction, and
+/// repeating them is governed by its dispute lifecycle instead. Interest
+/// entries are numbered anew by every run and are never recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdempotencyKey {
+    /// The transaction ID, which is unique across clients.
+    Tx,
+    /// The client and transaction ID, for inputs numbering transactions per
+    /// client.
+    ClientTx,
+}
+
+impl IdempotencyKey {
+    /// Every key, in declaration order.
+    pub const ALL: [IdempotencyKey; 2] = [IdempotencyKey::Tx, IdempotencyKey::ClientTx];
+
+    /// Returns the name of the key, e.g. `client-tx`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IdempotencyKey::Tx => "tx",
+            IdempotencyKey::ClientTx => "client-tx",
+        }
+    }
+
+    /// Returns the key of `tx`, or `None` if transactions of its type are not
+    /// recognized.
+    fn key<A>(self, tx: &Transaction<A>) -> Option<TransactionKey> {
+        match tx.tx_type {
+            TxType::Deposit
+            | TxType::Withdrawal
+            | TxType::Authorize
+            | TxType::Hold
+            | TxType::Release
+            | TxType::Close
+            | TxType::Open
+            | TxType::OpenLocked => Some(match self {
+                IdempotencyKey::Tx => TransactionKey::Tx(tx.tx),
+                IdempotencyKey::ClientTx => TransactionKey::ClientTx(tx.client, tx.tx),
+            }),
+            TxType::Dispute
+            | TxType::Resolve
+            | TxType::Chargeback
+            | TxType::Refund
+            | TxType::Capture
+            | TxType::Reversal
+            | TxType::Interest => None,
+        }
+    }
+}
+
+impl std::str::FromStr for IdempotencyKey {
+    type Err = String;
+
+    /// Parses the name returned by [`IdempotencyKey::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        IdempotencyKey::ALL
+            .into_iter()
+            .find(|key| key.as_str() == value)
+            .ok_or_else(|| format!("unknown idempotency key '{}'", value))
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An applied transaction, as recorded in [`EngineState::applied`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKey {
+    Tx(TxId),
+    ClientTx(ClientId, TxId),
+}
+
+/// Determines how a dispute is handled when the client's available funds
+/// (including any credit limit) do not cover the disputed amount.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// Ignore the dispute unless the available funds cover the disputed amount.
+    #[default]
+    RequireAvailable,
+    /// Always hold the full disputed amount, letting the available balance go negative.
+    AllowNegativeAvailable,
+    /// Hold only the portion of the disputed amount covered by the available funds.
+    /// Resolves and chargebacks then operate on the portion that was actually held.
+    HoldPartial,
+}
+
+impl DisputePolicy {
+    /// Every policy, in declaration order.
+    pub const ALL: [DisputePolicy; 3] = [
+        DisputePolicy::RequireAvailable,
+        DisputePolicy::AllowNegativeAvailable,
+        DisputePolicy::HoldPartial,
+    ];
+
+    /// Returns the name of the policy, e.g. `hold-partial`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisputePolicy::RequireAvailable => "require-available",
+            DisputePolicy::AllowNegativeAvailable => "allow-negative-available",
+            DisputePolicy::HoldPartial => "hold-partial",
+        }
+    }
+}
+
+impl std::str::FromStr for DisputePolicy {
+    type Err = String;
+
+    /// Parses the name returned by [`DisputePolicy::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        DisputePolicy::ALL
+            .into_iter()
+            .find(|policy| policy.as_str() == value)
+            .ok_or_else(|| format!("unknown dispute policy '{}'", value))
+    }
+}
+
+impl fmt::Display for DisputePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Determines when a chargeback locks the client's account.
 ///
-/// # Returns
+/// Chargebacks are counted per account in
+/// [`AccountDetails::chargebacks`], whether or not they lock it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Lock the account on its first chargeback.
+    #[default]
+    Immediate,
+    /// Lock the account once it has had this many chargebacks.
+    AfterChargebacks(u32),
+    /// Never lock the account.
+    Never,
+}
+
+impl LockPolicy {
+    /// Returns `true` if an account with `chargebacks` chargebacks is locked.
+    pub fn locks_after(self, chargebacks: u32) -> bool {
+        match self {
+            LockPolicy::Immediate => chargebacks >= 1,
+            LockPolicy::AfterChargebacks(threshold) => chargebacks >= threshold,
+            LockPolicy::Never => false,
+        }
+    }
+}
+
+/// The dispute lifecycle operations accepted on locked accounts, since risk
+/// teams differ on whether a lock freezes open disputes too. Deposits and
+/// withdrawals are always ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockedOperations {
+    pub dispute: bool,
+    pub resolve: bool,
+    pub chargeback: bool,
+}
+
+impl LockedOperations {
+    /// Returns `true` if transactions of type `tx_type` are applied to locked
+    /// accounts.
+    pub fn allows(self, tx_type: TxType) -> bool {
+        match tx_type {
+            TxType::Deposit
+            | TxType::Withdrawal
+            | TxType::Refund
+            | TxType::Authorize
+            | TxType::Capture
+            | TxType::Close
+            | TxType::Hold
+            | TxType::Release
+            | TxType::Reversal
+            | TxType::Interest
+            | TxType::Open
+            | TxType::OpenLocked => false,
+            TxType::Dispute => self.dispute,
+            TxType::Resolve => self.resolve,
+            TxType::Chargeback => self.chargeback,
+        }
+    }
+}
+
+impl std::str::FromStr for LockPolicy {
+    type Err = String;
+
+    /// Parses `immediate`, `never` or a chargeback count.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "immediate" => Ok(LockPolicy::Immediate),
+            "never" => Ok(LockPolicy::Never),
+            count => match count.parse::<u32>() {
+                Ok(0) => Err("the chargeback count must be at least 1".to_string()),
+                Ok(threshold) => Ok(LockPolicy::AfterChargebacks(threshold)),
+                Err(_) => Err(format!(
+                    "expected 'immediate', 'never' or a chargeback count, got '{}'",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for LockPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockPolicy::Immediate => f.write_str("immediate"),
+            LockPolicy::AfterChargebacks(threshold) => write!(f, "{}", threshold),
+            LockPolicy::Never => f.write_str("never"),
+        }
+    }
+}
+
+impl<A: AmountOps> EngineConfig<A> {
+    /// Returns `true` if locked accounts never change again, which is the case
+    /// unless [`EngineConfig::locked_operations`] allows an operation on them.
+    pub fn locked_accounts_are_final(&self) -> bool {
+        self.locked_operations == LockedOperations::default()
+    }
+
+    /// Returns `true` if a deposit in the given dispute state may be disputed.
+    /// A deposit whose dispute count cannot grow any further is never disputed
+    /// again, whatever `max_disputes_per_tx` allows.
+    fn may_dispute(&self, deposit: &DepositRecord<A>) -> bool {
+        let state_allows = match deposit.state {
+            DisputeState::Undisputed => true,
+            DisputeState::Resolved => self.allow_redispute,
+            DisputeState::Authorized
+            | DisputeState::Disputed
+            | DisputeState::ChargedBack
+            | DisputeState::Withdrawn
+            | DisputeState::Reversed => false,
+        };
+        state_allows
+            && deposit.dispute_count < u32::MAX
+            && self
+                .max_disputes_per_tx
+                .is_none_or(|max| deposit.dispute_count < max)
+    }
+
+    /// Returns the credit limit that applies to the given client.
+    pub fn credit_limit_for(&self, client: ClientId) -> A {
+        self.client_credit_limits
+            .get(&client)
+            .copied()
+            .or_else(|| self.client_profiles.get(&client)?.credit_limit)
+            .unwrap_or(self.credit_limit)
+    }
+
+    /// Returns the largest single withdrawal accepted for the given client, if
+    /// limited.
+    pub fn withdrawal_limit_for(&self, client: ClientId) -> Option<A> {
+        self.client_profiles.get(&client)?.withdrawal_limit
+    }
+
+    /// Copies the tier and currency of the client's profile to its account,
+    /// clearing them if the client has no profile.
+    fn apply_profile(&self, client: ClientId, account: &mut AccountDetails<A>) {
+        let profile = self.client_profiles.get(&client);
+        account.tier = profile.and_then(|profile| profile.tier.clone());
+        account.currency = profile.and_then(|profile| profile.currency.clone());
+    }
+}
+
+/// The information about a past deposit required to process disputes against it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepositRecord<A = Amount> {
+    /// The client the deposit was credited to.
+    pub client: ClientId,
+    /// The deposited amount.
+    pub amount: A,
+    /// The dispute lifecycle state of the deposit.
+    pub state: DisputeState,
+    /// Amount held by the currently active dispute (zero unless `Disputed`).
+    pub held: A,
+    /// Number of disputes accepted for this deposit so far.
+    pub dispute_count: u32,
+    /// Number of deposits applied before this one, wrapping at `u32::MAX`, which
+    /// orders deposits by age. See [`DepositRecord::age`].
+    pub sequence: u32,
+    /// Amount of the deposit refunded so far.
+    #[serde(default)]
+    pub refunded: A,
+}
+
+impl<A: AmountOps> DepositRecord<A> {
+    /// Creates the record of a fresh, undisputed deposit.
+    pub fn new(client: ClientId, amount: A) -> Self {
+        DepositRecord {
+            client,
+            amount,
+            state: DisputeState::Undisputed,
+            held: A::ZERO,
+            dispute_count: 0,
+            sequence: 0,
+            refunded: A::ZERO,
+        }
+    }
+
+    /// Returns the part of the deposit that was not refunded, which later
+    /// disputes and refunds are limited to.
+    pub fn remaining(&self) -> A {
+        self.amount.checked_sub(self.refunded).unwrap_or(A::ZERO)
+    }
+
+    /// Returns the number of deposits applied after this one, given the number
+    /// of `deposits` applied so far.
+    ///
+    /// Exact for deposits younger than 2^32 deposits; older ones alias, which
+    /// compaction avoids by evicting deposits long before they get that old.
+    pub fn age(&self, deposits: u64) -> u32 {
+        (deposits as u32)
+            .wrapping_sub(self.sequence)
+            .wrapping_sub(1)
+    }
+}
+
+/// The mutable state of the engine: all accounts plus the deposit history
+/// required to process disputes.
+#[derive(Debug, Default)]
+pub struct EngineState<A = Amount> {
+    /// Account details by client.
+    pub accounts: Accounts<A>,
+    /// Past deposits by transaction ID, including their dispute lifecycle.
+    pub deposit_history: HashMap<TxId, DepositRecord<A>>,
+    /// Number of deposits applied so far, including evicted ones.
+    pub deposits: u64,
+    /// Number of decisions applied so far, including ignored transactions.
+    pub transactions: u64,
+    /// Authorizations by the number of decisions applied before them, in the
+    /// order they expire. Captured authorizations stay until they would expire.
+    pub authorizations: BTreeSet<(u64, TxId)>,
+    /// Keys of the applied transactions, recorded with
+    /// [`EngineConfig::idempotency`].
+    pub applied: HashSet<TransactionKey>,
+}
+
+/// The reason a transaction leaves the state unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IgnoreReason {
+    /// The client's account is locked.
+    AccountLocked,
+    /// The client's account is closed.
+    AccountClosed,
+    /// The client has no account.
+    UnknownAccount,
+    /// The available funds (including any credit limit) do not cover the amount.
+    InsufficientFunds,
+    /// The referenced transaction is not a known deposit.
+    UnknownTransaction,
+    /// The referenced deposit belongs to a different client.
+    ClientMismatch,
+    /// The dispute amount exceeds the deposited amount.
+    InvalidDisputeAmount,
+    /// The deposit's dispute lifecycle does not allow another dispute.
+    DisputeNotAllowed,
+    /// The referenced deposit is not currently disputed.
+    NotDisputed,
+    /// The referenced deposit is disputed or charged back and cannot be refunded.
+    RefundNotAllowed,
+    /// The refund amount exceeds the part of the deposit not yet refunded.
+    InvalidRefundAmount,
+    /// The referenced transaction is not a pending authorization.
+    NotAuthorized,
+    /// The capture amount exceeds the authorized amount.
+    InvalidCaptureAmount,
+    /// The release amount exceeds the funds placed on hold by holds.
+    InsufficientEscrow,
+    /// The account to close still holds funds.
+    HeldFundsRemain,
+    /// The account holds less than the disputed amount.
+    InsufficientHeldFunds,
+    /// The amount has more decimal places than the precision policy accepts.
+    ExcessPrecision,
+    /// The amount is negative.
+    NegativeAmount,
+    /// A [`TransactionHook`] rejected the transaction.
+    RejectedByHook,
+    /// The withdrawal exceeds the withdrawal limit of the client's profile.
+    WithdrawalLimitExceeded,
+    /// The transaction was applied before, see [`EngineConfig::idempotency`].
+    DuplicateTransaction,
+    /// The transaction type is not processed, see
+    /// [`EngineConfig::processed_types`].
+    TypeNotProcessed,
+    /// The referenced transaction is not a deposit or recorded withdrawal
+    /// whose effect can still be reversed.
+    ReversalNotAllowed,
+    /// The reversal amount differs from the amount the reversal would move.
+    InvalidReversalAmount,
+    /// The client to open an account for already has one.
+    AccountExists,
+}
+
+impl IgnoreReason {
+    /// Returns the snake_case name of the reason, e.g. `insufficient_funds`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IgnoreReason::AccountLocked => "account_locked",
+            IgnoreReason::AccountClosed => "account_closed",
+            IgnoreReason::UnknownAccount => "unknown_account",
+            IgnoreReason::InsufficientFunds => "insufficient_funds",
+            IgnoreReason::UnknownTransaction => "unknown_transaction",
+            IgnoreReason::ClientMismatch => "client_mismatch",
+            IgnoreReason::InvalidDisputeAmount => "invalid_dispute_amount",
+            IgnoreReason::DisputeNotAllowed => "dispute_not_allowed",
+            IgnoreReason::NotDisputed => "not_disputed",
+            IgnoreReason::RefundNotAllowed => "refund_not_allowed",
+            IgnoreReason::InvalidRefundAmount => "invalid_refund_amount",
+            IgnoreReason::NotAuthorized => "not_authorized",
+            IgnoreReason::InvalidCaptureAmount => "invalid_capture_amount",
+            IgnoreReason::InsufficientEscrow => "insufficient_escrow",
+            IgnoreReason::HeldFundsRemain => "held_funds_remain",
+            IgnoreReason::InsufficientHeldFunds => "insufficient_held_funds",
+            IgnoreReason::ExcessPrecision => "excess_precision",
+            IgnoreReason::NegativeAmount => "negative_amount",
+            IgnoreReason::RejectedByHook => "rejected_by_hook",
+            IgnoreReason::WithdrawalLimitExceeded => "withdrawal_limit_exceeded",
+            IgnoreReason::DuplicateTransaction => "duplicate_transaction",
+            IgnoreReason::TypeNotProcessed => "type_not_processed",
+            IgnoreReason::ReversalNotAllowed => "reversal_not_allowed",
+            IgnoreReason::InvalidReversalAmount => "invalid_reversal_amount",
+            IgnoreReason::AccountExists => "account_exists",
+        }
+    }
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether a transaction was applied or ignored, and why, without the details
+/// of its effect. See [`Decision::outcome`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApplyOutcome {
+    /// The transaction changed the state.
+    Applied,
+    /// The transaction left the state unchanged.
+    Ignored(IgnoreReason),
+}
+
+impl ApplyOutcome {
+    /// Returns `true` if the transaction was applied.
+    pub fn is_applied(self) -> bool {
+        self == ApplyOutcome::Applied
+    }
+
+    /// Returns `applied`, or the name of the reason the transaction was ignored.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApplyOutcome::Applied => "applied",
+            ApplyOutcome::Ignored(reason) => reason.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for ApplyOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The result of [`Engine::apply_batch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOutcome<A = Amount> {
+    /// Every transaction of the batch was applied, with these decisions in
+    /// batch order.
+    Applied(Vec<Decision<A>>),
+    /// The transaction at `index` would have been ignored for `reason`, so
+    /// none of the batch was applied.
+    RolledBack { index: usize, reason: IgnoreReason },
+}
+
+impl<A> BatchOutcome<A> {
+    /// Returns `true` if the batch was applied.
+    pub fn is_applied(&self) -> bool {
+        matches!(self, BatchOutcome::Applied(_))
+    }
+}
+
+/// A transaction the engine ignored, and why.
+#[derive(Debug, Clone)]
+pub struct RejectedTransaction {
+    pub transaction: Transaction,
+    pub reason: IgnoreReason,
+}
+
+/// The effect of a transaction on the engine state, as determined by [`decide`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision<A = Amount> {
+    /// Credit `amount` to the client (opening the account if needed) and record
+    /// deposit `tx` in the history.
+    Deposit {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Debit `amount` from the client's available and total balance.
+    Withdraw {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Move `amount` from available to held funds, disputing deposit `tx`.
+    Hold {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Move `amount` from held back to available funds, resolving the dispute on `tx`.
+    Release {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Remove `amount` from held and total funds, charging back deposit `tx`, and
+    /// lock the account if `lock` is set (see [`LockPolicy`]).
+    Chargeback {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+        lock: bool,
+    },
+    /// Remove `amount` from available and total funds, refunding deposit `tx`
+    /// without a dispute.
+    Refund {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Add `amount` to held and total funds, authorizing `tx` for a later capture.
+    Authorize {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Move `amount` from held to available funds and remove `released` from held
+    /// and total funds, capturing authorization `tx`.
+    Capture {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+        released: A,
+    },
+    /// Move `amount` from available to held funds, placing it in escrow.
+    EscrowHold {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Move `amount` from escrow back to available funds.
+    EscrowRelease {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Credit `amount` of interest to the client's available and total balance.
+    Interest {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+    },
+    /// Undo the effect of transaction `tx` of type `reversed`, a deposit or a
+    /// withdrawal: debit or credit `amount` to the client's available and total
+    /// balance, and mark `tx` as reversed.
+    Reversal {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+        reversed: TxType,
+    },
+    /// Close the client's account.
+    Close { client: ClientId, tx: TxId },
+    /// Open the client's account with `amount` as available and total funds,
+    /// locked if `locked` is set.
+    Open {
+        client: ClientId,
+        tx: TxId,
+        amount: A,
+        locked: bool,
+    },
+    /// Leave the state unchanged.
+    Ignore(IgnoreReason),
+}
+
+impl<A: AmountOps> Decision<A> {
+    /// Returns the client whose account is changed, or `None` for ignored transactions.
+    pub fn client(&self) -> Option<ClientId> {
+        match *self {
+            Decision::Deposit { client, .. }
+            | Decision::Withdraw { client, .. }
+            | Decision::Hold { client, .. }
+            | Decision::Release { client, .. }
+            | Decision::Chargeback { client, .. }
+            | Decision::Refund { client, .. }
+            | Decision::Authorize { client, .. }
+            | Decision::Capture { client, .. }
+            | Decision::EscrowHold { client, .. }
+            | Decision::EscrowRelease { client, .. }
+            | Decision::Interest { client, .. }
+            | Decision::Reversal { client, .. }
+            | Decision::Close { client, .. }
+            | Decision::Open { client, .. } => Some(client),
+            Decision::Ignore(_) => None,
+        }
+    }
+
+    /// Returns the transaction that is applied, or `None` for ignored transactions.
+    ///
+    /// For holds, releases, chargebacks and reversals this is the referenced
+    /// transaction.
+    pub fn tx(&self) -> Option<TxId> {
+        match *self {
+            Decision::Deposit { tx, .. }
+            | Decision::Withdraw { tx, .. }
+            | Decision::Hold { tx, .. }
+            | Decision::Release { tx, .. }
+            | Decision::Chargeback { tx, .. }
+            | Decision::Refund { tx, .. }
+            | Decision::Authorize { tx, .. }
+            | Decision::Capture { tx, .. }
+            | Decision::EscrowHold { tx, .. }
+            | Decision::EscrowRelease { tx, .. }
+            | Decision::Interest { tx, .. }
+            | Decision::Reversal { tx, .. }
+            | Decision::Close { tx, .. }
+            | Decision::Open { tx, .. } => Some(tx),
+            Decision::Ignore(_) => None,
+        }
+    }
+
+    /// Returns the amount of funds moved, or zero for closures and ignored
+    /// transactions.
+    pub fn amount(&self) -> A {
+        match *self {
+            Decision::Deposit { amount, .. }
+            | Decision::Withdraw { amount, .. }
+            | Decision::Hold { amount, .. }
+            | Decision::Release { amount, .. }
+            | Decision::Chargeback { amount, .. }
+            | Decision::Refund { amount, .. }
+            | Decision::Authorize { amount, .. }
+            | Decision::Capture { amount, .. }
+            | Decision::EscrowHold { amount, .. }
+            | Decision::EscrowRelease { amount, .. }
+            | Decision::Interest { amount, .. }
+            | Decision::Reversal { amount, .. }
+            | Decision::Open { amount, .. } => amount,
+            Decision::Close { .. } | Decision::Ignore(_) => A::ZERO,
+        }
+    }
+
+    /// Returns whether the transaction was applied or why it was ignored.
+    pub fn outcome(&self) -> ApplyOutcome {
+        match *self {
+            Decision::Ignore(reason) => ApplyOutcome::Ignored(reason),
+            _ => ApplyOutcome::Applied,
+        }
+    }
+}
+
+/// Decides the effect of a transaction without modifying any state.
 ///
-/// Returns a map of client IDs to their account details after processing all transactions.
-/// If any transaction in the iterator is an error, processing stops and the error is returned.
-pub fn proccess_transactions<I>(transactions: I) -> Result<Accounts>
-where
-    I: IntoIterator<Item = Result<Transaction>>,
-{
-    let mut accounts = Accounts::new();
-    let mut deposit_history: BTreeMap<TxId, Transaction> = BTreeMap::new();
-    let mut disputed_transactions: HashSet<TxId> = HashSet::new();
+/// Only the state relevant to the transaction is required: the client's account
+/// (if any) and, for dispute, resolve and chargeback transactions, the referenced
+/// deposit (if any). This makes the processing rules testable in isolation and
+/// allows embedders to inspect or veto a decision before it is [`apply`]-ed.
+///
+/// # Arguments
+///
+/// * `tx` - The transaction to decide on
+/// * `account` - The account of `tx.client`, if it exists
+/// * `deposit` - The deposit referenced by `tx.tx`, if it exists
+/// * `config` - The processing rules
+pub fn decide<A: AmountOps>(
+    tx: &Transaction<A>,
+    account: Option<&AccountDetails<A>>,
+    deposit: Option<&DepositRecord<A>>,
+    config: &EngineConfig<A>,
+) -> Decision<A> {
+    try_decide(tx, account, deposit, config).unwrap_or_else(Decision::Ignore)
+}
 
-    for tx_result in transactions {
-        let tx = tx_result?;
-        if let Some(acc) = accounts.get(&tx.client) {
-            if acc.locked {
-                continue;
+/// Implements [`decide`], returning the reason as an error if the transaction is ignored.
+fn try_decide<A: AmountOps>(
+    tx: &Transaction<A>,
+    account: Option<&AccountDetails<A>>,
+    deposit: Option<&DepositRecord<A>>,
+    config: &EngineConfig<A>,
+) -> Result<Decision<A>, IgnoreReason> {
+    if account.is_some_and(|account| account.locked) && !config.locked_operations.allows(tx.tx_type)
+    {
+        return Err(IgnoreReason::AccountLocked);
+    }
+    if account.is_some_and(|account| account.closed) {
+        return Err(IgnoreReason::AccountClosed);
+    }
+    let amount = tx
+        .amount
+        .with_precision(config.precision)
+        .ok_or(IgnoreReason::ExcessPrecision)?;
+    if amount < A::ZERO {
+        return Err(IgnoreReason::NegativeAmount);
+    }
+    let context = TxContext {
+        tx,
+        amount,
+        account,
+        deposit,
+        config,
+        credit_limit: config.credit_limit_for(tx.client),
+    };
+    config.handlers.get(tx.tx_type).decide(&context)
+}
+
+/// Applies a decision to the engine state.
+///
+/// # Errors
+///
+/// Returns an error if a balance calculation overflows, or if the decision refers
+/// to an account or deposit that does not exist in `state` (i.e. it was not
+/// produced by [`decide`] for this state).
+pub fn apply<A: AmountOps>(decision: &Decision<A>, state: &mut EngineState<A>) -> Result<()> {
+    match *decision {
+        Decision::Deposit { client, tx, amount } => {
+            match state.accounts.get_mut(&client) {
+                Some(account) => {
+                    account.available = account
+                        .available
+                        .checked_add(amount)
+                        .ok_or_else(|| anyhow::anyhow!("Overflow in deposit available balance"))?;
+                    account.total = account
+                        .total
+                        .checked_add(amount)
+                        .ok_or_else(|| anyhow::anyhow!("Overflow in deposit total balance"))?;
+                }
+                None => {
+                    state
+                        .accounts
+                        .insert(client, AccountDetails::new_with_balance(amount));
+                }
             }
+            let deposit = DepositRecord {
+                // Truncated on purpose, see `DepositRecord::age`.
+                sequence: state.deposits as u32,
+                ..DepositRecord::new(client, amount)
+            };
+            state.deposit_history.insert(tx, deposit);
+            state.deposits += 1;
         }
-        match tx.tx_type {
-            TxType::Deposit => {
-                match accounts.get_mut(&tx.client) {
-                    Some(account) => {
-                        account.available =
-                            account.available.checked_add(tx.amount).ok_or_else(|| {
-                                anyhow::anyhow!("Overflow in deposit available balance")
-                            })?;
-                        account.total = account
-                            .total
-                            .checked_add(tx.amount)
-                            .ok_or_else(|| anyhow::anyhow!("Overflow in deposit total balance"))?;
-                    }
-                    None => {
-                        accounts.insert(tx.client, AccountDetails::new_with_balance(tx.amount));
-                    }
+        Decision::Authorize { client, tx, amount } => {
+            match state.accounts.get_mut(&client) {
+                Some(account) => {
+                    account.held = account
+                        .held
+                        .checked_add(amount)
+                        .ok_or_else(|| anyhow::anyhow!("Overflow in authorize held balance"))?;
+                    account.total = account
+                        .total
+                        .checked_add(amount)
+                        .ok_or_else(|| anyhow::anyhow!("Overflow in authorize total balance"))?;
                 }
-                deposit_history.insert(tx.tx, tx);
-            }
-            TxType::Withdrawal => {
-                if let Some(account) = accounts.get_mut(&tx.client) {
-                    if tx.amount <= account.available {
-                        account.total = account.total.checked_sub(tx.amount).ok_or_else(|| {
-                            anyhow::anyhow!("Underflow in withdrawal total balance")
-                        })?;
-                        account.available =
-                            account.available.checked_sub(tx.amount).ok_or_else(|| {
-                                anyhow::anyhow!("Underflow in withdrawal available balance")
-                            })?;
-                    }
+                None => {
+                    state.accounts.insert(
+                        client,
+                        AccountDetails {
+                            held: amount,
+                            total: amount,
+                            ..Default::default()
+                        },
+                    );
                 }
             }
-            TxType::Dispute => {
-                if let Some(account) = accounts.get_mut(&tx.client) {
-                    if let Some(disputed_tx) = deposit_history.get(&tx.tx) {
-                        if disputed_transactions.contains(&tx.tx) {
-                            continue;
-                        }
-                        if disputed_tx.client == tx.client {
-                            account.available = account
-                                .available
-                                .checked_sub(disputed_tx.amount)
-                                .ok_or_else(|| {
-                                anyhow::anyhow!("Underflow in dispute available balance")
-                            })?;
-                            account.held = account
-                                .held
-                                .checked_add(disputed_tx.amount)
-                                .ok_or_else(|| {
-                                    anyhow::anyhow!("Overflow in dispute held balance")
-                                })?;
-                            disputed_transactions.insert(tx.tx);
-                        }
-                    }
-                }
+            let authorization = DepositRecord {
+                state: DisputeState::Authorized,
+                held: amount,
+                ..DepositRecord::new(client, amount)
+            };
+            state.deposit_history.insert(tx, authorization);
+            state.authorizations.insert((state.transactions, tx));
+        }
+        Decision::Capture {
+            client,
+            tx,
+            amount,
+            released,
+        } => {
+            let account = account_mut(state, client)?;
+            account.held = amount
+                .checked_add(released)
+                .and_then(|settled| account.held.checked_sub(settled))
+                .ok_or_else(|| anyhow::anyhow!("Underflow in capture held balance"))?;
+            account.available = account
+                .available
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in capture available balance"))?;
+            account.total = account
+                .total
+                .checked_sub(released)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in capture total balance"))?;
+            // The captured amount becomes an ordinary deposit that can be disputed.
+            let sequence = state.deposits as u32;
+            let deposit = deposit_mut(state, tx)?;
+            deposit.state = DisputeState::Undisputed;
+            deposit.amount = amount;
+            deposit.held = A::ZERO;
+            deposit.sequence = sequence;
+            state.deposits += 1;
+        }
+        Decision::Withdraw { client, amount, .. } => {
+            let account = account_mut(state, client)?;
+            account.total = account
+                .total
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in withdrawal total balance"))?;
+            account.available = account
+                .available
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in withdrawal available balance"))?;
+        }
+        Decision::Hold { client, tx, amount } => {
+            let account = account_mut(state, client)?;
+            account.available = account
+                .available
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in dispute available balance"))?;
+            account.held = account
+                .held
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in dispute held balance"))?;
+            let deposit = deposit_mut(state, tx)?;
+            deposit.state = DisputeState::Disputed;
+            deposit.held = amount;
+            deposit.dispute_count = deposit
+                .dispute_count
+                .checked_add(1)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in dispute count"))?;
+        }
+        Decision::Release { client, tx, amount } => {
+            let account = account_mut(state, client)?;
+            account.available = account
+                .available
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in resolve available balance"))?;
+            account.held = account
+                .held
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in resolve held balance"))?;
+            let deposit = deposit_mut(state, tx)?;
+            deposit.state = DisputeState::Resolved;
+            deposit.held = A::ZERO;
+        }
+        Decision::Chargeback {
+            client,
+            tx,
+            amount,
+            lock,
+        } => {
+            let account = account_mut(state, client)?;
+            account.total = account
+                .total
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in chargeback total balance"))?;
+            account.held = account
+                .held
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in chargeback held balance"))?;
+            account.chargebacks = account.chargebacks.saturating_add(1);
+            account.locked |= lock;
+            let deposit = deposit_mut(state, tx)?;
+            deposit.state = DisputeState::ChargedBack;
+            deposit.held = A::ZERO;
+        }
+        Decision::Refund { client, tx, amount } => {
+            let account = account_mut(state, client)?;
+            account.available = account
+                .available
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in refund available balance"))?;
+            account.total = account
+                .total
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in refund total balance"))?;
+            let deposit = deposit_mut(state, tx)?;
+            deposit.refunded = deposit
+                .refunded
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in refunded amount"))?;
+        }
+        Decision::EscrowHold { client, amount, .. } => {
+            let account = account_mut(state, client)?;
+            account.available = account
+                .available
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in hold available balance"))?;
+            account.held = account
+                .held
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in hold held balance"))?;
+            account.escrow = account
+                .escrow
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in hold escrow balance"))?;
+        }
+        Decision::EscrowRelease { client, amount, .. } => {
+            let account = account_mut(state, client)?;
+            account.escrow = account
+                .escrow
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in release escrow balance"))?;
+            account.held = account
+                .held
+                .checked_sub(amount)
+                .ok_or_else(|| anyhow::anyhow!("Underflow in release held balance"))?;
+            account.available = account
+                .available
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in release available balance"))?;
+        }
+        Decision::Interest { client, amount, .. } => {
+            let account = account_mut(state, client)?;
+            account.available = account
+                .available
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in interest available balance"))?;
+            account.total = account
+                .total
+                .checked_add(amount)
+                .ok_or_else(|| anyhow::anyhow!("Overflow in interest total balance"))?;
+        }
+        Decision::Reversal {
+            client,
+            tx,
+            amount,
+            reversed,
+        } => {
+            let account = account_mut(state, client)?;
+            if reversed == TxType::Withdrawal {
+                account.available = account
+                    .available
+                    .checked_add(amount)
+                    .ok_or_else(|| anyhow::anyhow!("Overflow in reversal available balance"))?;
+                account.total = account
+                    .total
+                    .checked_add(amount)
+                    .ok_or_else(|| anyhow::anyhow!("Overflow in reversal total balance"))?;
+            } else {
+                account.available = account
+                    .available
+                    .checked_sub(amount)
+                    .ok_or_else(|| anyhow::anyhow!("Underflow in reversal available balance"))?;
+                account.total = account
+                    .total
+                    .checked_sub(amount)
+                    .ok_or_else(|| anyhow::anyhow!("Underflow in reversal total balance"))?;
             }
-            TxType::Resolve => {
-                if let Some(account) = accounts.get_mut(&tx.client) {
-                    if let Some(original) = deposit_history.get(&tx.tx) {
-                        if original.client == tx.client
-                            && disputed_transactions.contains(&tx.tx)
-                            && account.held >= original.amount
-                        {
-                            account.available =
-                                account.available.checked_add(original.amount).ok_or_else(
-                                    || anyhow::anyhow!("Overflow in resolve available balance"),
-                                )?;
-                            account.held =
-                                account.held.checked_sub(original.amount).ok_or_else(|| {
-                                    anyhow::anyhow!("Underflow in resolve held balance")
-                                })?;
-                            disputed_transactions.remove(&tx.tx);
-                        }
-                    }
-                }
+            deposit_mut(state, tx)?.state = DisputeState::Reversed;
+        }
+        Decision::Close { client, .. } => account_mut(state, client)?.closed = true,
+        Decision::Open {
+            client,
+            amount,
+            locked,
+            ..
+        } => {
+            if state.accounts.contains_key(&client) {
+                anyhow::bail!("Decision opens existing account of client {}", client);
             }
-            TxType::Chargeback => {
-                if let Some(account) = accounts.get_mut(&tx.client) {
-                    // Only process if deposit exists, belongs to same client, has an active dispute,
-                    // and sufficient funds are held
-                    if let Some(original) = deposit_history.get(&tx.tx) {
-                        if original.client == tx.client
-                            && disputed_transactions.contains(&tx.tx)
-                            && account.held >= original.amount
-                        {
-                            account.total =
-                                account.total.checked_sub(original.amount).ok_or_else(|| {
-                                    anyhow::anyhow!("Underflow in chargeback total balance")
-                                })?;
-                            account.held =
-                                account.held.checked_sub(original.amount).ok_or_else(|| {
-                                    anyhow::anyhow!("Underflow in chargeback held balance")
-                                })?;
-                            account.locked = true;
-                            disputed_transactions.remove(&tx.tx);
-                        }
-                    }
-                }
+            let account = AccountDetails {
+                locked,
+                opened_locked: locked,
+                ..AccountDetails::new_with_balance(amount)
+            };
+            state.accounts.insert(client, account);
+        }
+        Decision::Ignore(_) => {}
+    }
+    state.transactions += 1;
+
+    Ok(())
+}
+
+fn account_mut<A: AmountOps>(
+    state: &mut EngineState<A>,
+    client: ClientId,
+) -> Result<&mut AccountDetails<A>> {
+    state
+        .accounts
+        .get_mut(&client)
+        .ok_or_else(|| anyhow::anyhow!("Decision references unknown client {}", client))
+}
+
+fn deposit_mut<A: AmountOps>(
+    state: &mut EngineState<A>,
+    tx: TxId,
+) -> Result<&mut DepositRecord<A>> {
+    state
+        .deposit_history
+        .get_mut(&tx)
+        .ok_or_else(|| anyhow::anyhow!("Decision references unknown deposit {}", tx))
+}
+
+/// Stateful transaction processor.
+///
+/// The engine keeps the account state together with the bookkeeping required to
+/// process disputes (the deposit history including the dispute lifecycle of each
+/// deposit). Transactions are applied one at a time via [`Engine::process`], or
+/// in two steps via [`Engine::decide`] and [`Engine::apply`] to inspect or veto
+/// each decision.
+#[derive(Debug)]
+pub struct Engine<A = Amount> {
+    config: EngineConfig<A>,
+    state: EngineState<A>,
+    period: u32,
+    stats: Stats<A>,
+    period_stats: Stats<A>,
+    peak_memory: usize,
+    /// Value of [`EngineState::deposits`] at the last compaction.
+    compacted_at: u64,
+    hooks: Vec<Box<dyn TransactionHook<A>>>,
+    /// Authorizations expired by the last call to [`Engine::decide`].
+    expired: Vec<ExpiredAuthorization<A>>,
+    /// Whether [`Engine::apply_batch`] is running, which postpones compaction.
+    in_batch: bool,
+    /// Provider of deposits missing from the history, see
+    /// [`Engine::set_tx_lookup`].
+    lookup: Option<Box<dyn TxLookup<A>>>,
+    /// The deposit looked up for the transaction being processed.
+    looked_up: Option<(TxId, DepositRecord<A>)>,
+}
+
+/// The records an [`Engine::apply_batch`] changed, as they were before it.
+struct UndoLog<A> {
+    /// Previous accounts by client, `None` for accounts opened by the batch.
+    accounts: HashMap<ClientId, Option<AccountDetails<A>>>,
+    /// Previous deposit history records, `None` for records added by the batch.
+    deposits: HashMap<TxId, Option<DepositRecord<A>>>,
+    /// Keys of applied transactions that were not recorded before the batch.
+    applied: Vec<TransactionKey>,
+    authorizations: BTreeSet<(u64, TxId)>,
+    counters: (u64, u64),
+    stats: Stats<A>,
+    period_stats: Stats<A>,
+    peak_memory: usize,
+}
+
+impl<A: AmountOps> UndoLog<A> {
+    fn new(engine: &Engine<A>) -> Self {
+        UndoLog {
+            accounts: HashMap::new(),
+            deposits: HashMap::new(),
+            applied: Vec::new(),
+            authorizations: engine.state.authorizations.clone(),
+            counters: (engine.state.deposits, engine.state.transactions),
+            stats: engine.stats.clone(),
+            period_stats: engine.period_stats.clone(),
+            peak_memory: engine.peak_memory,
+        }
+    }
+
+    /// Saves the records processing `tx` may change, unless saved before.
+    fn record(&mut self, engine: &Engine<A>, tx: &Transaction<A>) {
+        let state = &engine.state;
+        let mut save_account = |client| {
+            self.accounts
+                .entry(client)
+                .or_insert_with(|| state.accounts.get(&client).cloned());
+        };
+        save_account(tx.client);
+        // Authorizations expiring before `tx` is applied change the
+        // accounts they were taken from.
+        let expiring = engine.config.authorization_expiry.map_or(0, |expiry| {
+            state
+                .authorizations
+                .iter()
+                .take_while(|(position, _)| state.transactions - position > expiry)
+                .count()
+        });
+        let mut txs = vec![tx.tx];
+        for &(_, authorization) in state.authorizations.iter().take(expiring) {
+            if let Some(record) = state.deposit_history.get(&authorization) {
+                save_account(record.client);
             }
+            txs.push(authorization);
+        }
+        for tx in txs {
+            self.deposits
+                .entry(tx)
+                .or_insert_with(|| state.deposit_history.get(&tx).cloned());
+        }
+        if let Some(key) = engine
+            .config
+            .idempotency
+            .and_then(|idempotency| idempotency.key(tx))
+            && !state.applied.contains(&key)
+        {
+            self.applied.push(key);
+        }
+    }
+
+    /// Puts the saved records back into `engine`.
+    fn restore(self, engine: &mut Engine<A>) {
+        let state = &mut engine.state;
+        for (client, account) in self.accounts {
+            match account {
+                Some(account) => state.accounts.insert(client, account),
+                None => state.accounts.remove(&client),
+            };
         }
+        for (tx, deposit) in self.deposits {
+            match deposit {
+                Some(deposit) => state.deposit_history.insert(tx, deposit),
+                None => state.deposit_history.remove(&tx),
+            };
+        }
+        for key in self.applied {
+            state.applied.remove(&key);
+        }
+        state.authorizations = self.authorizations;
+        (state.deposits, state.transactions) = self.counters;
+        engine.stats = self.stats;
+        engine.period_stats = self.period_stats;
+        engine.peak_memory = self.peak_memory;
     }
+}
 
-    Ok(accounts)
+/// An authorization released by [`EngineConfig::authorization_expiry`], which
+/// took `amount` from the held and total balance of `client`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiredAuthorization<A = Amount> {
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: A,
 }
 
-/// Convenience function for tests that processes a vector of transactions.
-#[cfg(test)]
-fn proccess_transactions_vec(transactions: Vec<Transaction>) -> Accounts {
-    proccess_transactions(transactions.into_iter().map(Ok)).unwrap()
+/// Largest effective [`EngineConfig::history_retention`]. Retained deposits are
+/// at most twice as old as the window, which keeps their [`DepositRecord::age`] exact.
+pub const MAX_HISTORY_RETENTION: u32 = u32::MAX / 2;
+
+/// Share (in percent) of [`EngineConfig::max_memory`] above which the engine
+/// compacts the deposit history before growing it.
+const COMPACT_THRESHOLD_PERCENT: usize = 90;
+
+/// The frozen result of a closed processing period.
+#[derive(Debug, Clone)]
+pub struct ClosedPeriod<A = Amount> {
+    /// The number of the closed period, starting at 1.
+    pub period: u32,
+    /// Snapshot of all accounts at the time the period was closed.
+    pub accounts: Accounts<A>,
+    /// Counters of the transactions processed during the period.
+    pub stats: Stats<A>,
+}
+
+impl<A: AmountOps> Default for Engine<A> {
+    fn default() -> Self {
+        Engine::new(EngineConfig::default())
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal::Decimal;
-    use std::str::FromStr;
+impl<A: AmountOps> Engine<A> {
+    /// Creates an engine with no accounts using the given configuration.
+    pub fn new(config: EngineConfig<A>) -> Self {
+        Engine {
+            config,
+            state: EngineState::default(),
+            period: 1,
+            stats: Stats::default(),
+            period_stats: Stats::default(),
+            peak_memory: 0,
+            compacted_at: 0,
+            hooks: Vec::new(),
+            expired: Vec::new(),
+            in_batch: false,
+            lookup: None,
+            looked_up: None,
+        }
+    }
+
+    /// Creates an engine that continues from `state`, e.g. one restored from a
+    /// snapshot. Statistics start from zero.
+    ///
+    /// The tier and currency of the accounts are updated from the client
+    /// profiles of `config`.
+    pub fn with_state(config: EngineConfig<A>, mut state: EngineState<A>) -> Self {
+        for (client, account) in &mut state.accounts {
+            config.apply_profile(*client, account);
+        }
+        Engine {
+            compacted_at: state.deposits,
+            state,
+            ..Engine::new(config)
+        }
+    }
+
+    /// Adds a hook that may veto transactions before they are applied, after the
+    /// hooks added before it.
+    pub fn add_hook(&mut self, hook: impl TransactionHook<A> + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Returns the configuration the engine processes transactions with.
+    pub fn config(&self) -> &EngineConfig<A> {
+        &self.config
+    }
+
+    /// Returns the number of the current (open) processing period, starting at 1.
+    pub fn period(&self) -> u32 {
+        self.period
+    }
+
+    /// Returns the counters of all transactions processed so far.
+    pub fn stats(&self) -> &Stats<A> {
+        &self.stats
+    }
+
+    /// Returns the counters of the current processing period.
+    pub fn period_stats(&self) -> &Stats<A> {
+        &self.period_stats
+    }
+
+    /// Returns the approximate number of heap bytes used by the accounts, the
+    /// deposit history and the keys of applied transactions.
+    ///
+    /// The estimate is derived from the capacity of the hash tables, so it only
+    /// changes when a table grows or shrinks.
+    pub fn memory_usage(&self) -> usize {
+        map_bytes(&self.state.accounts)
+            + map_bytes(&self.state.deposit_history)
+            + table_bytes::<TransactionKey, ()>(self.state.applied.capacity())
+    }
+
+    /// Returns the authorizations that expired before the transaction of the
+    /// last call to [`Engine::process`] was applied.
+    pub fn expired_authorizations(&self) -> &[ExpiredAuthorization<A>] {
+        &self.expired
+    }
+
+    /// Sets where [`Engine::process`] looks up deposits missing from the
+    /// history, e.g. those made before the snapshot the engine continues from.
+    ///
+    /// A dispute, resolve, chargeback, refund or reversal referencing a
+    /// transaction missing from the history is decided against the looked up
+    /// deposit instead of being ignored as unknown, and the deposit then joins
+    /// the history. See [`crate::lookup`].
+    pub fn set_tx_lookup(&mut self, lookup: impl TxLookup<A> + 'static) {
+        self.lookup = Some(Box::new(lookup));
+    }
+
+    /// Looks up the deposit `tx` references if it is missing from the history.
+    fn look_up_reference(&mut self, tx: &Transaction<A>) -> Result<()> {
+        self.looked_up = None;
+        let Some(lookup) = &self.lookup else {
+            return Ok(());
+        };
+        if matches!(
+            tx.tx_type,
+            TxType::Dispute
+                | TxType::Resolve
+                | TxType::Chargeback
+                | TxType::Refund
+                | TxType::Capture
+                | TxType::Reversal
+        ) && !self.state.deposit_history.contains_key(&tx.tx)
+        {
+            self.looked_up = lookup.lookup(tx.tx)?.map(|deposit| (tx.tx, deposit));
+        }
+        Ok(())
+    }
+
+    /// Returns the highest [`Engine::memory_usage`] observed so far.
+    pub fn peak_memory(&self) -> usize {
+        self.peak_memory
+    }
+
+    /// Evicts deposits from the history and shrinks it, returning the number of
+    /// evicted deposits.
+    ///
+    /// Evicted are the deposits of locked accounts (which include every charged
+    /// back deposit), since all transactions of a locked account are ignored
+    /// before the deposit is looked at, unless
+    /// [`EngineConfig::locked_operations`] allows some, the deposits of closed
+    /// accounts, which ignore all transactions, and the deposits outside
+    /// [`EngineConfig::history_retention`] that are not currently disputed.
+    /// [`Engine::dispute_state`] returns `None` for evicted deposits.
+    ///
+    /// With a retention window the engine compacts on its own whenever that many
+    /// deposits were applied since the last compaction.
+    pub fn compact(&mut self) -> usize {
+        let EngineState {
+            accounts,
+            deposit_history,
+            deposits,
+            ..
+        } = &mut self.state;
+        let retention = self
+            .config
+            .history_retention
+            .map(|retention| retention.min(MAX_HISTORY_RETENTION));
+        let locked_are_final = self.config.locked_accounts_are_final();
+        let before = deposit_history.len();
+        deposit_history.retain(|_, deposit| {
+            let account = accounts.get(&deposit.client);
+            let finished = account
+                .is_some_and(|account| account.closed || (locked_are_final && account.locked));
+            let expired = retention.is_some_and(|retention| deposit.age(*deposits) >= retention)
+                && !matches!(
+                    deposit.state,
+                    DisputeState::Authorized | DisputeState::Disputed
+                );
+            !finished && !expired
+        });
+        deposit_history.shrink_to_fit();
+        self.compacted_at = *deposits;
+        let evicted = before - deposit_history.len();
+        tracing::debug!(
+            evicted,
+            retained = deposit_history.len(),
+            "compacted deposit history"
+        );
+        evicted
+    }
+
+    /// Makes room for applying `decision` within [`EngineConfig::max_memory`].
+    ///
+    /// Only deposits grow the state. When applying one would grow a table beyond
+    /// [`COMPACT_THRESHOLD_PERCENT`] of the cap, the history is compacted first.
+    fn reserve_memory(&mut self, decision: &Decision<A>) -> Result<()> {
+        let (Some(max_memory), Decision::Deposit { client, .. }) =
+            (self.config.max_memory, decision)
+        else {
+            return Ok(());
+        };
+        let usage = self.memory_usage();
+        let projected = self.projected_memory_usage(*client);
+        if projected == usage || projected <= max_memory / 100 * COMPACT_THRESHOLD_PERCENT {
+            return Ok(());
+        }
+        // Evicted deposits could not be restored if the batch is rolled back.
+        if !self.in_batch {
+            let evicted = self.compact();
+            tracing::info!(
+                evicted,
+                memory_usage = self.memory_usage(),
+                "compacted deposit history near the memory cap"
+            );
+        }
+        let projected = self.projected_memory_usage(*client);
+        anyhow::ensure!(
+            projected <= max_memory,
+            "Memory usage of about {} bytes would exceed the limit of {} bytes",
+            projected,
+            max_memory
+        );
+        Ok(())
+    }
+
+    /// Returns the memory usage after inserting a deposit of `client`.
+    fn projected_memory_usage(&self, client: ClientId) -> usize {
+        let accounts = if self.state.accounts.contains_key(&client) {
+            map_bytes(&self.state.accounts)
+        } else {
+            map_bytes_after_insert(&self.state.accounts)
+        };
+        accounts + map_bytes_after_insert(&self.state.deposit_history)
+    }
+
+    /// Closes the current processing period.
+    ///
+    /// Freezes a snapshot of the current accounts together with the period's
+    /// counters, resets all period-scoped counters and continues with the next
+    /// period. Account balances and the deposit history carry over unchanged.
+    pub fn close_period(&mut self) -> ClosedPeriod<A> {
+        tracing::info!(
+            period = self.period,
+            applied = self.period_stats.applied,
+            ignored = self.period_stats.ignored,
+            "closing period"
+        );
+        let closed = ClosedPeriod {
+            period: self.period,
+            accounts: self.state.accounts.clone(),
+            stats: std::mem::take(&mut self.period_stats),
+        };
+        self.period += 1;
+        closed
+    }
+
+    /// Returns the current account state.
+    pub fn accounts(&self) -> &Accounts<A> {
+        &self.state.accounts
+    }
+
+    /// Returns the full engine state.
+    pub fn state(&self) -> &EngineState<A> {
+        &self.state
+    }
+
+    /// Returns the full engine state for changing it in place, e.g. to load the
+    /// records a [`StoredEngine`](crate::store::StoredEngine) keeps elsewhere.
+    pub(crate) fn state_mut(&mut self) -> &mut EngineState<A> {
+        &mut self.state
+    }
+
+    /// Returns the dispute lifecycle state of a deposit, or `None` if the
+    /// transaction is not a known deposit.
+    pub fn dispute_state(&self, tx: TxId) -> Option<DisputeState> {
+        self.state
+            .deposit_history
+            .get(&tx)
+            .map(|deposit| deposit.state)
+    }
+
+    /// Consumes the engine and returns the final account state.
+    pub fn into_accounts(self) -> Accounts<A> {
+        self.state.accounts
+    }
+
+    /// Decides the effect of a transaction on the current state without applying it.
+    ///
+    /// Before deciding, authorizations past [`EngineConfig::authorization_expiry`]
+    /// are released and a deposit the transaction references is looked up if it
+    /// is missing from the history (see [`Engine::set_tx_lookup`]); neither
+    /// counts the transaction, so deciding again has no further effect.
+    /// Transactions the processing rules would apply are passed to the hooks,
+    /// and ignored with [`IgnoreReason::RejectedByHook`] if one rejects them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if releasing an authorization underflows a balance or
+    /// the lookup fails.
+    pub fn decide(&mut self, tx: &Transaction<A>) -> Result<Decision<A>> {
+        self.expire_authorizations()?;
+        self.look_up_reference(tx)?;
+        Ok(self.decide_prepared(tx))
+    }
+
+    /// Decides `tx` once [`Engine::decide`] expired authorizations and looked
+    /// up its deposit.
+    fn decide_prepared(&self, tx: &Transaction<A>) -> Decision<A> {
+        if self
+            .config
+            .processed_types
+            .as_ref()
+            .is_some_and(|types| !types.contains(&tx.tx_type))
+        {
+            return Decision::Ignore(IgnoreReason::TypeNotProcessed);
+        }
+        if self
+            .config
+            .idempotency
+            .and_then(|idempotency| idempotency.key(tx))
+            .is_some_and(|key| self.state.applied.contains(&key))
+        {
+            return Decision::Ignore(IgnoreReason::DuplicateTransaction);
+        }
+        let deposit = match tx.tx_type {
+            TxType::Dispute
+            | TxType::Resolve
+            | TxType::Chargeback
+            | TxType::Refund
+            | TxType::Capture
+            | TxType::Reversal => self.state.deposit_history.get(&tx.tx).or_else(|| {
+                self.looked_up
+                    .as_ref()
+                    .filter(|(id, _)| *id == tx.tx)
+                    .map(|(_, deposit)| deposit)
+            }),
+            TxType::Deposit
+            | TxType::Withdrawal
+            | TxType::Authorize
+            | TxType::Close
+            | TxType::Hold
+            | TxType::Release
+            | TxType::Interest
+            | TxType::Open
+            | TxType::OpenLocked => None,
+        };
+        let account = self.state.accounts.get(&tx.client);
+        let decision = decide(tx, account, deposit, &self.config);
+        if self.hooks.is_empty() || matches!(decision, Decision::Ignore(_)) {
+            return decision;
+        }
+
+        let new_account;
+        let account = match account {
+            Some(account) => account,
+            None => {
+                new_account = AccountDetails {
+                    client: tx.client,
+                    ..Default::default()
+                };
+                &new_account
+            }
+        };
+        if self
+            .hooks
+            .iter()
+            .any(|hook| hook.before_apply(tx, account) == HookDecision::Reject)
+        {
+            Decision::Ignore(IgnoreReason::RejectedByHook)
+        } else {
+            decision
+        }
+    }
+
+    /// Applies the decision [`Engine::decide`] returned for `tx`, or an
+    /// [`Decision::Ignore`] of the embedder vetoing it, and counts the
+    /// transaction in the run and period [`Stats`].
+    ///
+    /// Besides the balances, this records the idempotency key, the account
+    /// metrics and the peak memory usage, and compacts the deposit history when
+    /// due, exactly as [`Engine::process`] does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a balance calculation overflows, the decision does
+    /// not match the current state or applying it would exceed
+    /// [`EngineConfig::max_memory`].
+    pub fn apply(&mut self, tx: &Transaction<A>, decision: &Decision<A>) -> Result<()> {
+        match decision {
+            Decision::Ignore(reason) => tracing::debug!(
+                target: crate::logging::IGNORED_TARGET,
+                tx = tx.tx.0,
+                client = tx.client.0,
+                tx_type = tx.tx_type.as_str(),
+                reason = reason.as_str(),
+                "ignored transaction"
+            ),
+            Decision::Chargeback {
+                client, tx, lock, ..
+            } => tracing::info!(
+                tx = tx.0,
+                client = client.0,
+                locked = lock,
+                "chargeback applied"
+            ),
+            decision => tracing::trace!(?decision, "applying transaction"),
+        }
+        self.reserve_memory(decision)?;
+        self.apply_to_state(decision)?;
+        if let Some(key) = self
+            .config
+            .idempotency
+            .and_then(|idempotency| idempotency.key(tx))
+            && !matches!(decision, Decision::Ignore(_))
+        {
+            self.state.applied.insert(key);
+        }
+        if self.config.account_metrics {
+            record_metrics(decision, self.state.transactions, &mut self.state.accounts);
+        }
+        if let Decision::Deposit { .. } = decision {
+            self.peak_memory = self.peak_memory.max(self.memory_usage());
+            if !self.in_batch {
+                self.compact_if_due();
+            }
+        }
+        self.stats.record(tx.tx_type, decision);
+        self.period_stats.record(tx.tx_type, decision);
+        Ok(())
+    }
+
+    /// Applies `decision` to the accounts and the deposit history.
+    fn apply_to_state(&mut self, decision: &Decision<A>) -> Result<()> {
+        let opened = decision.client().filter(|client| {
+            self.config.client_profiles.contains_key(client)
+                && !self.state.accounts.contains_key(client)
+        });
+        if let Decision::Hold { tx, .. }
+        | Decision::Release { tx, .. }
+        | Decision::Chargeback { tx, .. }
+        | Decision::Refund { tx, .. }
+        | Decision::Capture { tx, .. }
+        | Decision::Reversal { tx, .. } = *decision
+            && !self.state.deposit_history.contains_key(&tx)
+            && let Some((_, deposit)) = self.looked_up.take_if(|(id, _)| *id == tx)
+        {
+            // A looked up deposit joins the history once it is referenced.
+            self.state.deposit_history.insert(tx, deposit);
+        }
+        apply(decision, &mut self.state)?;
+        if self.config.reversible_withdrawals
+            && let Decision::Withdraw { client, tx, amount } = *decision
+        {
+            // A reused ID keeps referring to the earlier transaction.
+            let sequence = self.state.deposits as u32;
+            self.state
+                .deposit_history
+                .entry(tx)
+                .or_insert_with(|| DepositRecord {
+                    state: DisputeState::Withdrawn,
+                    sequence,
+                    ..DepositRecord::new(client, amount)
+                });
+        }
+        if let Some(client) = opened
+            && let Some(account) = self.state.accounts.get_mut(&client)
+        {
+            self.config.apply_profile(client, account);
+        }
+        Ok(())
+    }
+
+    /// Applies a single transaction to the account state and returns the decision
+    /// that was applied, i.e. [`Engine::decide`] followed by [`Engine::apply`].
+    /// The transaction is counted in the run and period [`Stats`].
+    ///
+    /// Transactions that violate the processing rules (insufficient funds, unknown
+    /// transaction references, locked accounts, ...) are ignored, which is reported
+    /// as [`Decision::Ignore`]. [`Decision::outcome`] condenses the decision into
+    /// an [`ApplyOutcome`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a balance calculation overflows.
+    pub fn process(&mut self, tx: Transaction<A>) -> Result<Decision<A>> {
+        let decision = self.decide(&tx)?;
+        self.apply(&tx, &decision)?;
+        Ok(decision)
+    }
+
+    /// Compacts the deposit history if [`EngineConfig::history_retention`]
+    /// deposits were applied since the last compaction.
+    fn compact_if_due(&mut self) {
+        if let Some(retention) = self.config.history_retention
+            && self.state.deposits - self.compacted_at
+                >= u64::from(retention.clamp(1, MAX_HISTORY_RETENTION))
+        {
+            self.compact();
+        }
+    }
+
+    /// Processes a batch of transactions with all-or-nothing semantics, e.g.
+    /// the entries of a settlement file.
+    ///
+    /// The transactions are processed in order as by [`Engine::process`]. If
+    /// one of them would be ignored, every change made by the batch is undone,
+    /// including the statistics, and the transaction is reported in
+    /// [`BatchOutcome::RolledBack`]. The changes are undone from a log of the
+    /// records the batch touches, so the cost does not depend on the size of
+    /// the state. Compaction of the deposit history is postponed until the
+    /// batch is applied. Hooks see the transactions up to the one rolling the
+    /// batch back, and what they record is not undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, after undoing the batch, if a transaction fails to
+    /// process, e.g. because a balance calculation overflows.
+    pub fn apply_batch(&mut self, txs: Vec<Transaction<A>>) -> Result<BatchOutcome<A>> {
+        let mut undo = UndoLog::new(self);
+        let mut decisions = Vec::with_capacity(txs.len());
+        self.in_batch = true;
+        let mut result = Ok(None);
+        for (index, tx) in txs.into_iter().enumerate() {
+            undo.record(self, &tx);
+            match self.process(tx) {
+                Ok(Decision::Ignore(reason)) => {
+                    result = Ok(Some(BatchOutcome::RolledBack { index, reason }));
+                    break;
+                }
+                Ok(decision) => decisions.push(decision),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.in_batch = false;
+        match result {
+            Ok(None) => {
+                self.compact_if_due();
+                Ok(BatchOutcome::Applied(decisions))
+            }
+            result => {
+                undo.restore(self);
+                result.map(|outcome| outcome.expect("rolled back batches have an outcome"))
+            }
+        }
+    }
+
+    /// Releases the authorizations that were not captured within
+    /// [`EngineConfig::authorization_expiry`] subsequent transactions, returning
+    /// their funds from held and total balance.
+    fn expire_authorizations(&mut self) -> Result<()> {
+        self.expired.clear();
+        let Some(expiry) = self.config.authorization_expiry else {
+            return Ok(());
+        };
+        let state = &mut self.state;
+        while let Some(&(position, tx)) = state.authorizations.first()
+            && state.transactions - position > expiry
+        {
+            state.authorizations.pop_first();
+            if state
+                .deposit_history
+                .get(&tx)
+                .is_none_or(|record| record.state != DisputeState::Authorized)
+            {
+                continue;
+            }
+            let Some(authorization) = state.deposit_history.remove(&tx) else {
+                continue;
+            };
+            let account = account_mut(state, authorization.client)?;
+            account.held = account
+                .held
+                .checked_sub(authorization.held)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Underflow in expired authorization held balance")
+                })?;
+            account.total = account
+                .total
+                .checked_sub(authorization.held)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Underflow in expired authorization total balance")
+                })?;
+            tracing::debug!(
+                tx = tx.0,
+                client = authorization.client.0,
+                "expired authorization"
+            );
+            self.expired.push(ExpiredAuthorization {
+                client: authorization.client,
+                tx,
+                amount: authorization.held,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Updates the metrics of the account changed by an applied `decision`, the
+/// `sequence`-th transaction processed by the engine.
+fn record_metrics<A: AmountOps>(decision: &Decision<A>, sequence: u64, accounts: &mut Accounts<A>) {
+    let Some(account) = decision
+        .client()
+        .and_then(|client| accounts.get_mut(&client))
+    else {
+        return;
+    };
+    let metrics = &mut account.metrics;
+    match decision {
+        Decision::Deposit { .. } | Decision::Capture { .. } => metrics.deposits += 1,
+        Decision::Withdraw { .. } => {
+            metrics.withdrawals += 1;
+            if metrics.last_was_deposit {
+                metrics.deposit_withdrawal_cycles += 1;
+            }
+        }
+        Decision::Hold { .. } => {
+            metrics.active_disputes += 1;
+            metrics.disputes += 1;
+        }
+        // Saturating, as the dispute may predate a state restored without metrics.
+        Decision::Release { .. } => {
+            metrics.active_disputes = metrics.active_disputes.saturating_sub(1)
+        }
+        Decision::Chargeback { .. } => {
+            metrics.active_disputes = metrics.active_disputes.saturating_sub(1);
+            metrics.chargebacks += 1;
+        }
+        Decision::Refund { .. }
+        | Decision::Authorize { .. }
+        | Decision::EscrowHold { .. }
+        | Decision::EscrowRelease { .. }
+        | Decision::Interest { .. }
+        | Decision::Reversal { .. }
+        | Decision::Close { .. }
+        | Decision::Open { .. }
+        | Decision::Ignore(_) => {}
+    }
+    metrics.last_was_deposit = matches!(
+        decision,
+        Decision::Deposit { .. } | Decision::Capture { .. }
+    );
+    metrics.last_activity = sequence;
+}
+
+/// Returns the approximate heap bytes of a hash table with room for `capacity`
+/// entries: the table keeps at most 7/8 of its power-of-two buckets occupied and
+/// stores one control byte per bucket next to the entry.
+fn table_bytes<K, V>(capacity: usize) -> usize {
+    let buckets = match capacity {
+        0 => 0,
+        1..=3 => 4,
+        _ => (capacity * 8 / 7).next_power_of_two(),
+    };
+    buckets * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+fn map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    table_bytes::<K, V>(map.capacity())
+}
+
+/// Returns the approximate heap bytes of `map` after inserting a new key.
+fn map_bytes_after_insert<K, V>(map: &HashMap<K, V>) -> usize {
+    if map.len() < map.capacity() {
+        map_bytes(map)
+    } else {
+        table_bytes::<K, V>((map.len() + 1).max(map.capacity() * 2))
+    }
+}
+
+/// Processes transactions from an iterator, maintaining account state.
+///
+/// Uses the default [`EngineConfig`]; see [`proccess_transactions_with_config`] to
+/// customize the processing rules.
+///
+/// # Arguments
+///
+/// * `transactions` - An iterator over transactions to process (can be `Result<Transaction>` for error handling)
+///
+/// # Returns
+///
+/// Returns a map of client IDs to their account details after processing all transactions.
+/// If any transaction in the iterator is an error, processing stops and the error is returned.
+pub fn proccess_transactions<A, I>(transactions: I) -> Result<Accounts<A>>
+where
+    A: AmountOps,
+    I: IntoIterator<Item = Result<Transaction<A>>>,
+{
+    proccess_transactions_with_config(transactions, EngineConfig::default())
+}
+
+/// Processes transactions from an iterator using the given engine configuration.
+///
+/// # Returns
+///
+/// Returns a map of client IDs to their account details after processing all transactions.
+/// If any transaction in the iterator is an error, processing stops and the error is returned.
+pub fn proccess_transactions_with_config<A, I>(
+    transactions: I,
+    config: EngineConfig<A>,
+) -> Result<Accounts<A>>
+where
+    A: AmountOps,
+    I: IntoIterator<Item = Result<Transaction<A>>>,
+{
+    let mut engine = Engine::new(config);
+
+    for tx_result in transactions {
+        engine.process(tx_result?)?;
+    }
+
+    Ok(engine.into_accounts())
+}
+
+/// Returns the accounts whose available balance is currently negative.
+pub fn overdrawn_accounts<A: AmountOps>(
+    accounts: &Accounts<A>,
+) -> impl Iterator<Item = (&ClientId, &AccountDetails<A>)> {
+    accounts
+        .iter()
+        .filter(|(_, account)| account.available < A::ZERO)
+}
+
+/// A consistency rule that every account satisfies after any sequence of
+/// transactions, checked by [`check_invariants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+    /// The total balance equals the sum of available and held funds.
+    TotalIsAvailablePlusHeld,
+    /// Held funds are never negative. Available and total balances may be, e.g.
+    /// within a credit limit or after a chargeback.
+    NonNegativeHeld,
+    /// Escrowed funds are part of the held funds.
+    EscrowWithinHeld,
+    /// Only chargebacks lock an account, unless it was opened locked.
+    LockedAfterChargeback,
+    /// An account is only closed without held funds, and stays unchanged after.
+    ClosedWithoutHeld,
+}
+
+impl Invariant {
+    /// Returns the snake_case name of the invariant, e.g. `non_negative_held`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Invariant::TotalIsAvailablePlusHeld => "total_is_available_plus_held",
+            Invariant::NonNegativeHeld => "non_negative_held",
+            Invariant::EscrowWithinHeld => "escrow_within_held",
+            Invariant::LockedAfterChargeback => "locked_after_chargeback",
+            Invariant::ClosedWithoutHeld => "closed_without_held",
+        }
+    }
+}
+
+/// An invariant broken by an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub client: ClientId,
+    pub invariant: Invariant,
+    /// The balances breaking the invariant, e.g. `total 10, available 5, held 4`.
+    pub detail: String,
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {} breaks {}: {}",
+            self.client,
+            self.invariant.as_str(),
+            self.detail
+        )
+    }
+}
+
+/// Returns the [`Invariant`]s broken by `accounts`, in ascending client order.
+///
+/// A correct engine never breaks them, so a violation points to a bug or to
+/// state that was edited or restored from a corrupted snapshot.
+pub fn check_invariants<A: AmountOps + fmt::Display>(
+    accounts: &Accounts<A>,
+) -> Vec<InvariantViolation> {
+    let mut violations = Vec::new();
+    for (client, account) in sorted_accounts(accounts) {
+        let mut violation = |invariant, detail| {
+            violations.push(InvariantViolation {
+                client,
+                invariant,
+                detail,
+            })
+        };
+        if account.available.checked_add(account.held) != Some(account.total) {
+            violation(
+                Invariant::TotalIsAvailablePlusHeld,
+                format!(
+                    "total {}, available {}, held {}",
+                    account.total, account.available, account.held
+                ),
+            );
+        }
+        if account.held < A::ZERO {
+            violation(Invariant::NonNegativeHeld, format!("held {}", account.held));
+        }
+        if account.escrow < A::ZERO || account.escrow > account.held.max(A::ZERO) {
+            violation(
+                Invariant::EscrowWithinHeld,
+                format!("escrow {}, held {}", account.escrow, account.held),
+            );
+        }
+        if account.locked && !account.opened_locked && account.chargebacks == 0 {
+            violation(
+                Invariant::LockedAfterChargeback,
+                "0 chargebacks".to_string(),
+            );
+        }
+        if account.closed && account.held != A::ZERO {
+            violation(
+                Invariant::ClosedWithoutHeld,
+                format!("held {}", account.held),
+            );
+        }
+    }
+    violations
+}
+
+/// Criteria selecting a subset of accounts, e.g. for investigations.
+///
+/// An account matches when it satisfies every criterion that is set; the default
+/// filter matches all accounts.
+#[derive(Debug, Clone, Default)]
+pub struct AccountFilter<A = Amount> {
+    /// Only match these clients; empty means every client.
+    pub clients: BTreeSet<ClientId>,
+    /// Only match locked accounts.
+    pub only_locked: bool,
+    /// Only match accounts whose total balance is at least this amount.
+    pub min_total: Option<A>,
+}
+
+impl<A: AmountOps> AccountFilter<A> {
+    /// Returns `true` if the account of `client` satisfies the filter.
+    pub fn matches(&self, client: ClientId, account: &AccountDetails<A>) -> bool {
+        (self.clients.is_empty() || self.clients.contains(&client))
+            && (!self.only_locked || account.locked)
+            && self
+                .min_total
+                .is_none_or(|min_total| account.total >= min_total)
+    }
+
+    /// Removes all accounts not satisfying the filter.
+    pub fn apply(&self, mut accounts: Accounts<A>) -> Accounts<A> {
+        accounts.retain(|client, account| self.matches(*client, account));
+        accounts
+    }
+}
+
+/// Convenience function for tests that processes a vector of transactions.
+#[cfg(test)]
+fn proccess_transactions_vec(transactions: Vec<Transaction>) -> Accounts {
+    proccess_transactions(transactions.into_iter().map(Ok)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DisputeState;
+    use std::str::FromStr;
+
+    #[test]
+    fn withdraw_succeeds_if_suficent_funds() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("5.0").unwrap(), // Less than available
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+
+        // Verify the account exists
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Verify the withdrawal succeeded - balance should be 5.0 (10.0 - 5.0)
+        assert_eq!(account.available, Amount::from_str("5.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("5.0").unwrap());
+    }
+
+    #[test]
+    fn withdraw_fails_if_insufficent_funds() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("15.0").unwrap(), // More than available
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+
+        // Verify the account exists
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Verify the withdrawal failed - balance should still be 10.0
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_transacion() {
+        // Test successful dispute - funds move from available to held, total unchanged
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),          // Disputes transaction 1
+                amount: Amount::ZERO, // Dispute doesn't have an amount
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Available should decrease by disputed amount (10.0)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        // Held should increase by disputed amount (10.0)
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        // Total should remain unchanged
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_nonexistent_transaction_is_ignored() {
+        // Test that disputing a non-existent transaction is ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(999), // Disputes non-existent transaction
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be unchanged since dispute was ignored
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_partial_funds() {
+        // Test dispute when account has multiple deposits
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("5.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes first deposit
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Available should be 5.0 (only second deposit remains available)
+        assert_eq!(account.available, Amount::from_str("5.0").unwrap());
+        // Held should be 10.0 (first deposit is held)
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        // Total should be 15.0 (sum of both deposits)
+        assert_eq!(account.total, Amount::from_str("15.0").unwrap());
+    }
+
+    #[test]
+    fn resolve_transaction() {
+        // Test successful resolve - funds move from held back to available, total unchanged
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes transaction 1
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1),          // Resolves transaction 1
+                amount: Amount::ZERO, // Resolve doesn't have an amount
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // After resolve, funds should be back in available
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        // Held should be back to zero
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        // Total should remain unchanged
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn resolve_nonexistent_transaction_is_ignored() {
+        // Test that resolving a non-existent transaction is ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(999), // Resolves non-existent transaction
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should still have funds in held (resolve was ignored)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn resolve_transaction_without_dispute_is_ignored() {
+        // Test that resolving a transaction that isn't disputed is ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            // No dispute for transaction 1
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1), // Tries to resolve transaction 1 (but it's not disputed)
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be unchanged (resolve was ignored)
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+    }
+
+    #[test]
+    fn resolve_after_chargeback_is_ignored() {
+        // Test that resolving a transaction that was chargebacked is ignored
+        // (since chargeback withdraws the held funds, there's nothing to resolve)
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1), // Chargebacks the dispute (funds withdrawn, account locked)
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1), // Tries to resolve (but funds already withdrawn, nothing in held)
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be as if resolve never happened (funds withdrawn, account locked)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0.0").unwrap());
+        // Account should still be locked (chargeback happened, resolve was ignored)
+        assert!(
+            account.locked,
+            "Account should be locked after chargeback, resolve was ignored"
+        );
+    }
+
+    #[test]
+    fn resolve_partial_funds() {
+        // Test resolve when account has multiple disputed transactions
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("5.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes first deposit
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(2), // Disputes second deposit
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1), // Resolves first deposit only
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Available should be 10.0 (first deposit resolved)
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        // Held should be 5.0 (second deposit still disputed)
+        assert_eq!(account.held, Amount::from_str("5.0").unwrap());
+        // Total should be 15.0 (sum of both deposits)
+        assert_eq!(account.total, Amount::from_str("15.0").unwrap());
+    }
+
+    #[test]
+    fn chargeback_transacion() {
+        // Test successful chargeback - funds withdrawn from held and total, account locked
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes transaction 1
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1),          // Chargebacks transaction 1
+                amount: Amount::ZERO, // Chargeback doesn't have an amount
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Available should remain 0 (was moved to held, then withdrawn)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        // Held should be 0 (withdrawn)
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        // Total should decrease by disputed amount (10.0 - 10.0 = 0.0)
+        assert_eq!(account.total, Amount::from_str("0.0").unwrap());
+        // Account should be locked
+        assert!(account.locked, "Account should be locked after chargeback");
+    }
+
+    #[test]
+    fn chargeback_nonexistent_transaction_is_ignored() {
+        // Test that chargebacking a non-existent transaction is ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(999), // Chargebacks non-existent transaction
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should still have funds in held (chargeback was ignored)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+        // Account should not be locked
+        assert!(
+            !account.locked,
+            "Account should not be locked when chargeback is ignored"
+        );
+    }
+
+    #[test]
+    fn chargeback_transaction_without_dispute_is_ignored() {
+        // Test that chargebacking a transaction that isn't disputed is ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            // No dispute for transaction 1
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1), // Tries to chargeback transaction 1 (but it's not disputed)
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be unchanged (chargeback was ignored)
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+        // Account should not be locked
+        assert!(
+            !account.locked,
+            "Account should not be locked when chargeback is ignored"
+        );
+    }
+
+    #[test]
+    fn chargeback_partial_funds() {
+        // Test chargeback when account has multiple disputed transactions
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("5.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes first deposit
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(2), // Disputes second deposit
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1), // Chargebacks first deposit only
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Available should be 0 (first deposit was disputed, then chargebacked)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        // Held should be 5.0 (second deposit still disputed)
+        assert_eq!(account.held, Amount::from_str("5.0").unwrap());
+        // Total should be 5.0 (first deposit withdrawn: 15.0 - 10.0 = 5.0)
+        assert_eq!(account.total, Amount::from_str("5.0").unwrap());
+        // Account should be locked
+        assert!(account.locked, "Account should be locked after chargeback");
+    }
+
+    #[test]
+    fn chargeback_after_resolve_is_ignored() {
+        // Test that chargebacking a transaction that was resolved is ignored
+        // (since resolve releases the held funds, there's no active dispute to chargeback)
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1), // Resolves the dispute (funds back to available)
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1), // Tries to chargeback (but dispute was resolved, no funds held)
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be as if chargeback never happened (funds back in available)
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+        // Account should not be locked (chargeback was ignored)
+        assert!(
+            !account.locked,
+            "Account should not be locked when chargeback is ignored"
+        );
+    }
+
+    #[test]
+    fn locked_account_ignores_further_transactions() {
+        // Test that once an account is locked, all further transactions are ignored
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1), // Locks the account
+                amount: Amount::ZERO,
+            },
+            // These should all be ignored because account is locked
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("5.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(3),
+                amount: Amount::from_str("2.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(4),
+                amount: Amount::from_str("100.0").unwrap(),
+            },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Account should be locked
+        assert!(account.locked, "Account should be locked after chargeback");
+
+        // Balances should be as if chargeback was the last processed transaction
+        // (chargeback removed 10.0 from total and held, leaving 0)
+        assert_eq!(account.available, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("0.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("0.0").unwrap());
+
+        // Verify subsequent deposits/withdrawals were ignored
+        // If they weren't ignored, the account would have different balances
+    }
+
+    #[test]
+    fn withdraw_into_overdraft_within_credit_limit() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("15.0").unwrap(), // 5.0 over available
+            },
+        ];
+        let config = EngineConfig {
+            credit_limit: Amount::from_str("5.0").unwrap(),
+            ..Default::default()
+        };
+
+        let accounts =
+            proccess_transactions_with_config(transactions.into_iter().map(Ok), config).unwrap();
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // The withdrawal uses the full credit limit
+        assert_eq!(account.available, Amount::from_str("-5.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("-5.0").unwrap());
+        assert_eq!(overdrawn_accounts(&accounts).count(), 1);
+    }
+
+    #[test]
+    fn withdraw_beyond_credit_limit_is_ignored() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("15.5").unwrap(), // 5.5 over available
+            },
+        ];
+        let config = EngineConfig {
+            credit_limit: Amount::from_str("5.0").unwrap(),
+            ..Default::default()
+        };
+
+        let accounts =
+            proccess_transactions_with_config(transactions.into_iter().map(Ok), config).unwrap();
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+        assert_eq!(overdrawn_accounts(&accounts).count(), 0);
+    }
+
+    #[test]
+    fn client_credit_limit_overrides_default() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(2),
+                tx: TxId(2),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(3),
+                amount: Amount::from_str("30.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(2),
+                tx: TxId(4),
+                amount: Amount::from_str("30.0").unwrap(),
+            },
+        ];
+        let config = EngineConfig {
+            credit_limit: Amount::from_str("5.0").unwrap(),
+            client_credit_limits: BTreeMap::from([(
+                ClientId(2),
+                Amount::from_str("50.0").unwrap(),
+            )]),
+            ..Default::default()
+        };
+
+        let accounts =
+            proccess_transactions_with_config(transactions.into_iter().map(Ok), config).unwrap();
+
+        // Client 1 only has the default limit, so the withdrawal is ignored
+        assert_eq!(
+            accounts[&ClientId(1)].available,
+            Amount::from_str("10.0").unwrap()
+        );
+        // Client 2 has a higher limit and may overdraw
+        assert_eq!(
+            accounts[&ClientId(2)].available,
+            Amount::from_str("-20.0").unwrap()
+        );
+    }
+
+    #[test]
+    fn client_profiles_limit_withdrawals_and_tag_accounts() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let config = EngineConfig {
+            client_credit_limits: BTreeMap::from([(ClientId(2), Amount::ZERO)]),
+            client_profiles: BTreeMap::from([
+                (
+                    ClientId(1),
+                    ClientProfile {
+                        withdrawal_limit: Some(Amount::from_str("50").unwrap()),
+                        credit_limit: Some(Amount::from_str("100").unwrap()),
+                        tier: Some("gold".to_string()),
+                        currency: Some("EUR".to_string()),
+                    },
+                ),
+                (
+                    ClientId(2),
+                    ClientProfile {
+                        credit_limit: Some(Amount::from_str("100").unwrap()),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(config.clone());
+
+        engine.process(tx(TxType::Deposit, 1, 1, "10")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Withdrawal, 1, 2, "60")).unwrap(),
+            Decision::Ignore(IgnoreReason::WithdrawalLimitExceeded)
+        );
+        // Within the withdrawal limit and covered by the profile's credit limit
+        engine.process(tx(TxType::Withdrawal, 1, 3, "50")).unwrap();
+        engine.process(tx(TxType::Deposit, 2, 4, "10")).unwrap();
+        // `client_credit_limits` takes precedence over the profile
+        assert_eq!(
+            engine.process(tx(TxType::Withdrawal, 2, 5, "20")).unwrap(),
+            Decision::Ignore(IgnoreReason::InsufficientFunds)
+        );
+        engine.process(tx(TxType::Deposit, 3, 6, "10")).unwrap();
+
+        let accounts = engine.accounts();
+        assert_eq!(
+            accounts[&ClientId(1)].available,
+            Amount::from_str("-40").unwrap()
+        );
+        assert_eq!(accounts[&ClientId(1)].tier.as_deref(), Some("gold"));
+        assert_eq!(accounts[&ClientId(1)].currency.as_deref(), Some("EUR"));
+        assert_eq!(accounts[&ClientId(2)].tier, None);
+        assert_eq!(accounts[&ClientId(3)].tier, None);
+
+        // Restored accounts get the profiles of the new configuration
+        let mut accounts = engine.into_accounts();
+        for account in accounts.values_mut() {
+            account.tier = Some("silver".to_string());
+        }
+        let state = EngineState {
+            accounts,
+            ..Default::default()
+        };
+        let engine = Engine::with_state(config, state);
+        assert_eq!(
+            engine.accounts()[&ClientId(1)].tier.as_deref(),
+            Some("gold")
+        );
+        assert_eq!(engine.accounts()[&ClientId(3)].tier, None);
+    }
+
+    #[test]
+    fn idempotency_ignores_applied_transactions() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let transactions = [
+            tx(TxType::Withdrawal, 1, 1, "1"),
+            tx(TxType::Deposit, 1, 2, "10"),
+            tx(TxType::Deposit, 1, 2, "10"),
+            // Ignored the first time, so it is applied now
+            tx(TxType::Withdrawal, 1, 1, "1"),
+            tx(TxType::Dispute, 1, 2, "2"),
+            tx(TxType::Resolve, 1, 2, "0"),
+            tx(TxType::Dispute, 1, 2, "2"),
+            tx(TxType::Deposit, 2, 2, "7"),
+        ];
+        let run = |idempotency| {
+            let mut engine = Engine::new(EngineConfig {
+                idempotency,
+                ..Default::default()
+            });
+            let reasons: Vec<_> = transactions
+                .iter()
+                .filter_map(|tx| match engine.process(tx.clone()).unwrap() {
+                    Decision::Ignore(reason) => Some(reason),
+                    _ => None,
+                })
+                .collect();
+            (reasons, engine.into_accounts())
+        };
+
+        let (reasons, accounts) = run(None);
+        assert_eq!(reasons, [IgnoreReason::UnknownAccount]);
+        assert_eq!(
+            accounts[&ClientId(1)].total,
+            Amount::from_str("19").unwrap()
+        );
+
+        // Disputes reference the deposit and are not recognized as repeated
+        let (reasons, accounts) = run(Some(IdempotencyKey::ClientTx));
+        assert_eq!(
+            reasons,
+            [
+                IgnoreReason::UnknownAccount,
+                IgnoreReason::DuplicateTransaction
+            ]
+        );
+        assert_eq!(accounts[&ClientId(1)].total, Amount::from_str("9").unwrap());
+        assert_eq!(accounts[&ClientId(1)].held, Amount::from_str("2").unwrap());
+        assert_eq!(accounts[&ClientId(2)].total, Amount::from_str("7").unwrap());
+
+        let (reasons, accounts) = run(Some(IdempotencyKey::Tx));
+        assert_eq!(reasons[1..], [IgnoreReason::DuplicateTransaction; 2]);
+        assert!(!accounts.contains_key(&ClientId(2)));
+    }
+
+    #[test]
+    fn ignores_types_not_processed() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig {
+            processed_types: Some(BTreeSet::from([TxType::Deposit, TxType::Withdrawal])),
+            ..Default::default()
+        });
+        let transactions = [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Withdrawal, 1, 2, "3"),
+            tx(TxType::Dispute, 1, 1, "0"),
+            tx(TxType::Chargeback, 1, 1, "0"),
+        ];
+        let outcomes: Vec<_> = transactions
+            .into_iter()
+            .map(|tx| engine.process(tx).unwrap().outcome())
+            .collect();
+
+        assert_eq!(
+            outcomes[2..],
+            [ApplyOutcome::Ignored(IgnoreReason::TypeNotProcessed); 2]
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.total, Amount::from_str("7").unwrap());
+        assert!(!account.locked);
+    }
 
     #[test]
-    fn withdraw_succeeds_if_suficent_funds() {
+    fn dispute_respects_credit_limit() {
         let transactions = vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
             },
             Transaction {
                 tx_type: TxType::Withdrawal,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("5.0").unwrap(), // Less than available
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("8.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Would leave available at -8.0
+                amount: Amount::ZERO,
             },
         ];
 
-        let accounts = proccess_transactions_vec(transactions);
-
-        // Verify the account exists
-        let account = accounts.get(&1).expect("Account should exist");
+        // Without credit the dispute cannot be covered and is ignored
+        let accounts = proccess_transactions_vec(transactions.clone());
+        assert_eq!(
+            accounts[&ClientId(1)].available,
+            Amount::from_str("2.0").unwrap()
+        );
+        assert_eq!(accounts[&ClientId(1)].held, Amount::ZERO);
 
-        // Verify the withdrawal succeeded - balance should be 5.0 (10.0 - 5.0)
-        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
+        // With enough credit the disputed funds are held
+        let config = EngineConfig {
+            credit_limit: Amount::from_str("10.0").unwrap(),
+            ..Default::default()
+        };
+        let accounts =
+            proccess_transactions_with_config(transactions.into_iter().map(Ok), config).unwrap();
+        assert_eq!(
+            accounts[&ClientId(1)].available,
+            Amount::from_str("-8.0").unwrap()
+        );
+        assert_eq!(
+            accounts[&ClientId(1)].held,
+            Amount::from_str("10.0").unwrap()
+        );
+        assert_eq!(
+            accounts[&ClientId(1)].total,
+            Amount::from_str("2.0").unwrap()
+        );
     }
 
     #[test]
-    fn withdraw_fails_if_insufficent_funds() {
+    fn partial_dispute_holds_only_disputed_amount() {
         let transactions = vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
             },
             Transaction {
-                tx_type: TxType::Withdrawal,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("15.0").unwrap(), // More than available
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("4.0").unwrap(), // Disputes part of the deposit
             },
         ];
 
         let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
 
-        // Verify the account exists
-        let account = accounts.get(&1).expect("Account should exist");
-
-        // Verify the withdrawal failed - balance should still be 10.0
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.available, Amount::from_str("6.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("4.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
     }
 
     #[test]
-    fn dispute_transacion() {
-        // Test successful dispute - funds move from available to held, total unchanged
+    fn partial_dispute_resolve_releases_disputed_amount() {
         let transactions = vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
             },
             Transaction {
                 tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,                 // Disputes transaction 1
-                amount: Decimal::ZERO, // Dispute doesn't have an amount
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("4.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Resolve,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
             },
         ];
 
         let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
 
-        // Available should decrease by disputed amount (10.0)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        // Held should increase by disputed amount (10.0)
-        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
-        // Total should remain unchanged
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
     }
 
     #[test]
-    fn dispute_nonexistent_transaction_is_ignored() {
-        // Test that disputing a non-existent transaction is ignored
+    fn partial_dispute_chargeback_keeps_remainder_available() {
         let transactions = vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
             },
             Transaction {
                 tx_type: TxType::Dispute,
-                client: 1,
-                tx: 999, // Disputes non-existent transaction
-                amount: Decimal::ZERO,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("4.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Chargeback,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
             },
         ];
 
         let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
 
-        // Account should be unchanged since dispute was ignored
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        // Only the disputed portion is charged back
+        assert_eq!(account.available, Amount::from_str("6.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::from_str("6.0").unwrap());
+        assert!(account.locked, "Account should be locked after chargeback");
     }
 
     #[test]
-    fn dispute_partial_funds() {
-        // Test dispute when account has multiple deposits
+    fn dispute_exceeding_deposit_amount_is_ignored() {
         let transactions = vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.5").unwrap(), // More than was deposited
             },
+        ];
+
+        let accounts = proccess_transactions_vec(transactions);
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+    }
+
+    fn overdrawn_dispute_transactions() -> Vec<Transaction> {
+        vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("5.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("6.0").unwrap(),
             },
             Transaction {
                 tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1, // Disputes first deposit
-                amount: Decimal::ZERO,
+                client: ClientId(1),
+                tx: TxId(1), // Only 4.0 of the disputed 10.0 is still available
+                amount: Amount::ZERO,
             },
-        ];
+        ]
+    }
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+    #[test]
+    fn dispute_policy_require_available_ignores_uncovered_dispute() {
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::RequireAvailable,
+            ..Default::default()
+        };
 
-        // Available should be 5.0 (only second deposit remains available)
-        assert_eq!(account.available, Decimal::from_str("5.0").unwrap());
-        // Held should be 10.0 (first deposit is held)
-        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
-        // Total should be 15.0 (sum of both deposits)
-        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
+        let accounts = proccess_transactions_with_config(
+            overdrawn_dispute_transactions().into_iter().map(Ok),
+            config,
+        )
+        .unwrap();
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        assert_eq!(account.available, Amount::from_str("4.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::from_str("4.0").unwrap());
     }
 
     #[test]
-    fn resolve_transaction() {
-        // Test successful resolve - funds move from held back to available, total unchanged
-        let transactions = vec![
+    fn dispute_policy_allow_negative_available_holds_full_amount() {
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::AllowNegativeAvailable,
+            ..Default::default()
+        };
+
+        let accounts = proccess_transactions_with_config(
+            overdrawn_dispute_transactions().into_iter().map(Ok),
+            config,
+        )
+        .unwrap();
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        assert_eq!(account.available, Amount::from_str("-6.0").unwrap());
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("4.0").unwrap());
+    }
+
+    #[test]
+    fn dispute_policy_hold_partial_holds_available_funds() {
+        let mut transactions = overdrawn_dispute_transactions();
+        transactions.push(Transaction {
+            tx_type: TxType::Chargeback,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Amount::ZERO,
+        });
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::HoldPartial,
+            ..Default::default()
+        };
+
+        let accounts =
+            proccess_transactions_with_config(transactions.into_iter().map(Ok), config).unwrap();
+        let account = accounts.get(&ClientId(1)).expect("Account should exist");
+
+        // Only the 4.0 that was held can be charged back
+        assert_eq!(account.available, Amount::ZERO);
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::ZERO);
+        assert!(account.locked, "Account should be locked after chargeback");
+    }
+
+    #[test]
+    fn interleaved_disputes_settle_their_own_held_amounts() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let amount = |value: &str| Amount::from_str(value).unwrap();
+
+        // Partial disputes hold less than their deposits. The chargeback of the
+        // first deposit settles its 3.0, although its deposit exceeds the held
+        // balance once the second dispute is resolved.
+        let mut engine = Engine::default();
+        for transaction in [
+            tx(TxType::Deposit, 1, "10.0"),
+            tx(TxType::Deposit, 2, "10.0"),
+            tx(TxType::Dispute, 1, "3.0"),
+            tx(TxType::Dispute, 2, "8.0"),
+            tx(TxType::Resolve, 2, "0"),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        assert_eq!(engine.accounts()[&ClientId(1)].held, amount("3.0"));
+        assert_eq!(
+            engine.process(tx(TxType::Chargeback, 1, "0")).unwrap(),
+            Decision::Chargeback {
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: amount("3.0"),
+                lock: true,
+            }
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, amount("17.0"));
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, amount("17.0"));
+
+        // With partial holds, each dispute holds what was available when it was
+        // opened, and settling one leaves the other's bucket intact.
+        let config = EngineConfig {
+            dispute_policy: DisputePolicy::HoldPartial,
+            lock_policy: LockPolicy::Never,
+            ..Default::default()
+        };
+        let mut engine = Engine::new(config);
+        for transaction in [
+            tx(TxType::Deposit, 1, "10.0"),
+            tx(TxType::Deposit, 2, "5.0"),
+            tx(TxType::Withdrawal, 3, "12.0"),
+            tx(TxType::Dispute, 1, "0"),
+            tx(TxType::Deposit, 4, "5.0"),
+            tx(TxType::Dispute, 2, "0"),
+        ] {
+            engine.process(transaction).unwrap();
+        }
+        assert_eq!(engine.accounts()[&ClientId(1)].held, amount("8.0"));
+        engine.process(tx(TxType::Chargeback, 2, "0")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Resolve, 1, "0")).unwrap(),
+            Decision::Release {
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: amount("3.0"),
+            }
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, amount("3.0"));
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, amount("3.0"));
+    }
+
+    fn dispute_resolve_dispute_transactions() -> Vec<Transaction> {
+        vec![
             Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
             },
             Transaction {
                 tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1, // Disputes transaction 1
-                amount: Decimal::ZERO,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
             },
             Transaction {
                 tx_type: TxType::Resolve,
-                client: 1,
-                tx: 1,                 // Resolves transaction 1
-                amount: Decimal::ZERO, // Resolve doesn't have an amount
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1), // Disputes the resolved transaction again
+                amount: Amount::ZERO,
+            },
+        ]
+    }
+
+    #[test]
+    fn redispute_after_resolve_is_allowed_by_default() {
+        let mut engine = Engine::new(EngineConfig::default());
+        for tx in dispute_resolve_dispute_transactions() {
+            engine.process(tx).unwrap();
+        }
+
+        let account = engine
+            .accounts()
+            .get(&ClientId(1))
+            .expect("Account should exist");
+        assert_eq!(account.available, Amount::ZERO);
+        assert_eq!(account.held, Amount::from_str("10.0").unwrap());
+        assert_eq!(engine.dispute_state(TxId(1)), Some(DisputeState::Disputed));
+    }
+
+    #[test]
+    fn redispute_after_resolve_is_ignored_when_disabled() {
+        let mut engine = Engine::new(EngineConfig {
+            allow_redispute: false,
+            ..Default::default()
+        });
+        for tx in dispute_resolve_dispute_transactions() {
+            engine.process(tx).unwrap();
+        }
+
+        let account = engine
+            .accounts()
+            .get(&ClientId(1))
+            .expect("Account should exist");
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(engine.dispute_state(TxId(1)), Some(DisputeState::Resolved));
+    }
+
+    #[test]
+    fn disputes_beyond_max_count_are_ignored() {
+        let mut engine = Engine::new(EngineConfig {
+            max_disputes_per_tx: Some(1),
+            ..Default::default()
+        });
+        for tx in dispute_resolve_dispute_transactions() {
+            engine.process(tx).unwrap();
+        }
+
+        // The second dispute exceeds the limit of one dispute per transaction
+        let account = engine
+            .accounts()
+            .get(&ClientId(1))
+            .expect("Account should exist");
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(engine.dispute_state(TxId(1)), Some(DisputeState::Resolved));
+    }
+
+    #[test]
+    fn disputes_beyond_the_count_range_are_ignored() {
+        let mut engine = Engine::new(EngineConfig::default());
+        let mut transactions = dispute_resolve_dispute_transactions().into_iter();
+        for tx in transactions.by_ref().take(3) {
+            engine.process(tx).unwrap();
+        }
+        engine
+            .state_mut()
+            .deposit_history
+            .get_mut(&TxId(1))
+            .expect("Deposit should exist")
+            .dispute_count = u32::MAX;
+
+        let dispute = transactions.next().expect("Second dispute should exist");
+        let decision = engine.decide(&dispute).unwrap();
+        assert_eq!(
+            decision.outcome(),
+            ApplyOutcome::Ignored(IgnoreReason::DisputeNotAllowed)
+        );
+        engine.apply(&dispute, &decision).unwrap();
+        assert_eq!(engine.dispute_state(TxId(1)), Some(DisputeState::Resolved));
+    }
+
+    #[test]
+    fn lock_policy_decides_which_chargeback_locks() {
+        let tx = |tx_type, tx| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: if tx_type == TxType::Deposit {
+                Amount::from_str("10.0").unwrap()
+            } else {
+                Amount::ZERO
             },
+        };
+        let chargebacks = |lock_policy| {
+            let mut engine = Engine::new(EngineConfig {
+                lock_policy,
+                ..Default::default()
+            });
+            let mut locks = Vec::new();
+            for id in 1..=3 {
+                engine.process(tx(TxType::Deposit, id)).unwrap();
+                engine.process(tx(TxType::Dispute, id)).unwrap();
+                match engine.process(tx(TxType::Chargeback, id)).unwrap() {
+                    Decision::Chargeback { lock, .. } => locks.push(lock),
+                    decision => panic!("Unexpected decision {:?}", decision),
+                }
+                if locks.last() == Some(&true) {
+                    break;
+                }
+            }
+            let account = &engine.accounts()[&ClientId(1)];
+            assert_eq!(account.locked, locks.last() == Some(&true));
+            assert_eq!(account.chargebacks as usize, locks.len());
+            locks
+        };
+
+        assert_eq!(chargebacks(LockPolicy::Immediate), [true]);
+        assert_eq!(chargebacks(LockPolicy::AfterChargebacks(2)), [false, true]);
+        assert_eq!(chargebacks(LockPolicy::Never), [false, false, false]);
+    }
+
+    #[test]
+    fn locked_operations_select_what_locked_accounts_accept() {
+        let tx = |tx_type, tx| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str("10.0").unwrap(),
+        };
+        for operations in 0..8 {
+            let locked_operations = LockedOperations {
+                dispute: operations & 1 != 0,
+                resolve: operations & 2 != 0,
+                chargeback: operations & 4 != 0,
+            };
+            let mut engine = Engine::new(EngineConfig {
+                locked_operations,
+                ..Default::default()
+            });
+            for id in 1..=4 {
+                engine.process(tx(TxType::Deposit, id)).unwrap();
+            }
+            for id in 1..=3 {
+                engine.process(tx(TxType::Dispute, id)).unwrap();
+            }
+            engine.process(tx(TxType::Chargeback, 1)).unwrap();
+            assert!(engine.accounts()[&ClientId(1)].locked);
+
+            for (transaction, allowed) in [
+                (tx(TxType::Deposit, 5), false),
+                (tx(TxType::Withdrawal, 6), false),
+                (tx(TxType::Dispute, 4), locked_operations.dispute),
+                (tx(TxType::Resolve, 2), locked_operations.resolve),
+                (tx(TxType::Chargeback, 3), locked_operations.chargeback),
+            ] {
+                let tx_type = transaction.tx_type;
+                let decision = engine.process(transaction).unwrap();
+                assert_eq!(
+                    decision != Decision::Ignore(IgnoreReason::AccountLocked),
+                    allowed,
+                    "{:?} with {:?}",
+                    tx_type,
+                    locked_operations
+                );
+                assert!(!matches!(decision, Decision::Ignore(_)) || !allowed);
+            }
+            assert!(engine.accounts()[&ClientId(1)].locked);
+            assert_eq!(engine.config().locked_accounts_are_final(), operations == 0);
+        }
+    }
+
+    #[test]
+    fn parses_lock_policies() {
+        assert_eq!("immediate".parse(), Ok(LockPolicy::Immediate));
+        assert_eq!("never".parse(), Ok(LockPolicy::Never));
+        assert_eq!("3".parse(), Ok(LockPolicy::AfterChargebacks(3)));
+        assert!("0".parse::<LockPolicy>().is_err());
+        assert!("later".parse::<LockPolicy>().is_err());
+    }
+
+    #[test]
+    fn dispute_lifecycle_states() {
+        let mut engine = Engine::new(EngineConfig::default());
+        let deposit = Transaction {
+            tx_type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Amount::from_str("10.0").unwrap(),
+        };
+        let dispute = Transaction {
+            tx_type: TxType::Dispute,
+            amount: Amount::ZERO,
+            ..deposit.clone()
+        };
+        let chargeback = Transaction {
+            tx_type: TxType::Chargeback,
+            amount: Amount::ZERO,
+            ..deposit.clone()
+        };
+
+        assert_eq!(engine.dispute_state(TxId(1)), None);
+        engine.process(deposit).unwrap();
+        assert_eq!(
+            engine.dispute_state(TxId(1)),
+            Some(DisputeState::Undisputed)
+        );
+        engine.process(dispute).unwrap();
+        assert_eq!(engine.dispute_state(TxId(1)), Some(DisputeState::Disputed));
+        engine.process(chargeback).unwrap();
+        assert_eq!(
+            engine.dispute_state(TxId(1)),
+            Some(DisputeState::ChargedBack)
+        );
+    }
+
+    #[test]
+    fn decide_withdrawal_without_touching_state() {
+        let account = AccountDetails::new_with_balance(Amount::from_str("10.0").unwrap());
+        let withdrawal = Transaction {
+            tx_type: TxType::Withdrawal,
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Amount::from_str("15.0").unwrap(),
+        };
+
+        assert_eq!(
+            decide(&withdrawal, Some(&account), None, &EngineConfig::default()),
+            Decision::Ignore(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            decide(&withdrawal, None, None, &EngineConfig::default()),
+            Decision::Ignore(IgnoreReason::UnknownAccount)
+        );
+    }
+
+    #[test]
+    fn decide_dispute_reasons() {
+        let account = AccountDetails::new_with_balance(Amount::from_str("10.0").unwrap());
+        let deposit = DepositRecord::new(ClientId(1), Amount::from_str("10.0").unwrap());
+        let dispute = Transaction {
+            tx_type: TxType::Dispute,
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Amount::ZERO,
+        };
+        let config = EngineConfig::default();
+
+        assert_eq!(
+            decide(&dispute, Some(&account), Some(&deposit), &config),
+            Decision::Hold {
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap()
+            }
+        );
+        assert_eq!(
+            decide(&dispute, Some(&account), None, &config),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
+        let foreign_deposit = DepositRecord::new(ClientId(2), Amount::from_str("10.0").unwrap());
+        assert_eq!(
+            decide(&dispute, Some(&account), Some(&foreign_deposit), &config),
+            Decision::Ignore(IgnoreReason::ClientMismatch)
+        );
+        let resolve = Transaction {
+            tx_type: TxType::Resolve,
+            ..dispute.clone()
+        };
+        assert_eq!(
+            decide(&resolve, Some(&account), Some(&deposit), &config),
+            Decision::Ignore(IgnoreReason::NotDisputed)
+        );
+        let locked = AccountDetails {
+            locked: true,
+            ..account.clone()
+        };
+        assert_eq!(
+            decide(&dispute, Some(&locked), Some(&deposit), &config),
+            Decision::Ignore(IgnoreReason::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn vetoed_decision_leaves_state_unchanged() {
+        let mut engine = Engine::new(EngineConfig::default());
+        engine
+            .process(Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            })
+            .unwrap();
+
+        let withdrawal = Transaction {
+            tx_type: TxType::Withdrawal,
+            client: ClientId(1),
+            tx: TxId(2),
+            amount: Amount::from_str("4.0").unwrap(),
+        };
+        let decision = engine.decide(&withdrawal).unwrap();
+        assert!(matches!(decision, Decision::Withdraw { .. }));
+
+        // The embedder decides not to apply the withdrawal
+        let account = engine
+            .accounts()
+            .get(&ClientId(1))
+            .expect("Account should exist");
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+
+        engine.apply(&withdrawal, &decision).unwrap();
+        let account = engine
+            .accounts()
+            .get(&ClientId(1))
+            .expect("Account should exist");
+        assert_eq!(account.available, Amount::from_str("6.0").unwrap());
+    }
+
+    #[test]
+    fn decide_and_apply_match_process() {
+        let config = || EngineConfig {
+            idempotency: Some(IdempotencyKey::Tx),
+            account_metrics: true,
+            authorization_expiry: Some(2),
+            history_retention: Some(2),
+            ..Default::default()
+        };
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let transactions = [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Authorize, 2, 2, "5"),
+            tx(TxType::Withdrawal, 1, 3, "4"),
+            tx(TxType::Dispute, 1, 1, "0"),
+            tx(TxType::Deposit, 2, 4, "1"),
+            tx(TxType::Capture, 2, 2, "0"),
+            tx(TxType::Withdrawal, 2, 5, "100"),
         ];
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let mut processed = Engine::new(config());
+        let mut stepped = Engine::new(config());
+        for tx in transactions {
+            let decision = stepped.decide(&tx).unwrap();
+            // Deciding twice has no further effect.
+            assert_eq!(stepped.decide(&tx).unwrap(), decision);
+            stepped.apply(&tx, &decision).unwrap();
+            assert_eq!(processed.process(tx).unwrap(), decision);
+        }
+
+        assert_eq!(stepped.accounts(), processed.accounts());
+        assert_eq!(stepped.stats(), processed.stats());
+        assert_eq!(stepped.period_stats(), processed.period_stats());
+        assert_eq!(stepped.state().applied, processed.state().applied);
+        assert_eq!(
+            stepped.state().deposit_history,
+            processed.state().deposit_history
+        );
+        assert_eq!(stepped.state().transactions, processed.state().transactions);
+        assert_eq!(stepped.peak_memory(), processed.peak_memory());
+        assert_eq!(stepped.stats().ignored, 4);
+    }
+
+    #[test]
+    fn apply_rejects_decision_for_unknown_account() {
+        let mut state = EngineState::default();
+        let decision = Decision::Withdraw {
+            client: ClientId(1),
+            tx: TxId(1),
+            amount: Amount::ONE,
+        };
+
+        assert!(apply(&decision, &mut state).is_err());
+    }
+
+    #[test]
+    fn refunds_return_deposits_without_dispute_or_lock() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Deposit, 1, "10"),
+            tx(TxType::Deposit, 2, "5"),
+            tx(TxType::Refund, 1, "4"),
+            // More than the remaining 6
+            tx(TxType::Refund, 1, "7"),
+            tx(TxType::Dispute, 2, "0"),
+            tx(TxType::Refund, 2, "0"),
+            tx(TxType::Resolve, 2, "0"),
+            // The rest of deposit 1
+            tx(TxType::Refund, 1, "0"),
+            tx(TxType::Refund, 1, "0"),
+            tx(TxType::Refund, 9, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Applied,
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::InvalidRefundAmount),
+                Applied,
+                Ignored(IgnoreReason::RefundNotAllowed),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::InvalidRefundAmount),
+                Ignored(IgnoreReason::UnknownTransaction),
+            ]
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("5").unwrap());
+        assert_eq!(account.total, Amount::from_str("5").unwrap());
+        assert!(!account.locked);
+        assert_eq!(engine.stats().refunded, Amount::from_str("10").unwrap());
+
+        // A dispute is limited to the part of the deposit that was not refunded.
+        engine.process(tx(TxType::Deposit, 3, "8")).unwrap();
+        engine.process(tx(TxType::Refund, 3, "3")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Dispute, 3, "6")).unwrap(),
+            Decision::Ignore(IgnoreReason::InvalidDisputeAmount)
+        );
+        assert_eq!(
+            engine
+                .process(tx(TxType::Dispute, 3, "0"))
+                .unwrap()
+                .amount(),
+            Amount::from_str("5").unwrap()
+        );
+    }
+
+    #[test]
+    fn captures_move_authorized_funds_to_available() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Authorize, 1, "10"),
+            tx(TxType::Authorize, 2, "5"),
+            tx(TxType::Dispute, 1, "0"),
+            tx(TxType::Withdrawal, 3, "1"),
+            // More than authorized
+            tx(TxType::Capture, 2, "6"),
+            tx(TxType::Capture, 2, "3"),
+            tx(TxType::Capture, 2, "0"),
+            tx(TxType::Capture, 1, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::DisputeNotAllowed),
+                Ignored(IgnoreReason::InsufficientFunds),
+                Ignored(IgnoreReason::InvalidCaptureAmount),
+                Applied,
+                Ignored(IgnoreReason::NotAuthorized),
+                Applied,
+            ]
+        );
+        // Capturing 3 of 5 released the other 2.
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("13").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::from_str("13").unwrap());
+        assert_eq!(engine.stats().authorized, Amount::from_str("15").unwrap());
+        assert_eq!(engine.stats().captured, Amount::from_str("13").unwrap());
+
+        // Captured funds are disputed like a deposit.
+        assert_eq!(
+            engine
+                .process(tx(TxType::Dispute, 2, "0"))
+                .unwrap()
+                .amount(),
+            Amount::from_str("3").unwrap()
+        );
+    }
+
+    #[test]
+    fn uncaptured_authorizations_expire() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig {
+            authorization_expiry: Some(2),
+            ..Default::default()
+        });
+        for tx in [
+            tx(TxType::Authorize, 1, "10"),
+            tx(TxType::Authorize, 2, "5"),
+            tx(TxType::Capture, 2, "0"),
+            tx(TxType::Deposit, 3, "1"),
+        ] {
+            engine.process(tx).unwrap();
+        }
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("6").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        assert_eq!(account.total, Amount::from_str("6").unwrap());
+        assert_eq!(engine.dispute_state(TxId(1)), None);
+        // The capture of authorization 2 kept it from expiring.
+        assert_eq!(
+            engine.dispute_state(TxId(2)),
+            Some(DisputeState::Undisputed)
+        );
+        assert_eq!(
+            engine.process(tx(TxType::Capture, 1, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
+    }
+
+    #[test]
+    fn apply_batch_is_all_or_nothing() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig {
+            authorization_expiry: Some(2),
+            idempotency: Some(IdempotencyKey::Tx),
+            ..Default::default()
+        });
+        for tx in [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Deposit, 2, 2, "20"),
+            tx(TxType::Authorize, 2, 3, "5"),
+        ] {
+            engine.process(tx).unwrap();
+        }
+        let before = (
+            engine.accounts().clone(),
+            engine.state().deposit_history.clone(),
+            engine.state().applied.clone(),
+            engine.state().authorizations.clone(),
+            engine.state().transactions,
+            engine.stats().clone(),
+        );
+
+        // The authorization expires before the withdrawal, which is ignored.
+        let outcome = engine
+            .apply_batch(vec![
+                tx(TxType::Deposit, 3, 4, "1"),
+                tx(TxType::Dispute, 1, 1, "0"),
+                tx(TxType::Withdrawal, 1, 5, "100"),
+            ])
+            .unwrap();
+        assert_eq!(
+            outcome,
+            BatchOutcome::RolledBack {
+                index: 2,
+                reason: IgnoreReason::InsufficientFunds
+            }
+        );
+        assert!(!outcome.is_applied());
+        let after = (
+            engine.accounts().clone(),
+            engine.state().deposit_history.clone(),
+            engine.state().applied.clone(),
+            engine.state().authorizations.clone(),
+            engine.state().transactions,
+            engine.stats().clone(),
+        );
+        assert_eq!(after, before);
+
+        let outcome = engine
+            .apply_batch(vec![
+                tx(TxType::Deposit, 3, 4, "1"),
+                tx(TxType::Withdrawal, 1, 5, "4"),
+            ])
+            .unwrap();
+        assert!(matches!(&outcome, BatchOutcome::Applied(decisions) if decisions.len() == 2));
+        assert_eq!(
+            engine.accounts()[&ClientId(3)].total,
+            Amount::from_str("1").unwrap()
+        );
+        assert_eq!(
+            engine.accounts()[&ClientId(1)].total,
+            Amount::from_str("6").unwrap()
+        );
+        assert_eq!(engine.stats().applied, 5);
+    }
+
+    #[test]
+    fn reversal_undoes_deposits_and_recorded_withdrawals() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let amount = |value| Amount::from_str(value).unwrap();
+
+        // Withdrawals are not kept in the history by default.
+        let mut engine = Engine::default();
+        engine.process(tx(TxType::Deposit, 1, "10")).unwrap();
+        engine.process(tx(TxType::Withdrawal, 2, "4")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 2, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
+
+        let mut engine = Engine::new(EngineConfig {
+            reversible_withdrawals: true,
+            ..Default::default()
+        });
+        engine.process(tx(TxType::Deposit, 1, "10")).unwrap();
+        engine.process(tx(TxType::Deposit, 2, "3")).unwrap();
+        engine.process(tx(TxType::Withdrawal, 3, "4")).unwrap();
+        // A recorded withdrawal still cannot be disputed.
+        assert_eq!(
+            engine.process(tx(TxType::Dispute, 3, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 3, "5")).unwrap(),
+            Decision::Ignore(IgnoreReason::InvalidReversalAmount)
+        );
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 3, "4")).unwrap(),
+            Decision::Reversal {
+                client: ClientId(1),
+                tx: TxId(3),
+                amount: amount("4"),
+                reversed: TxType::Withdrawal,
+            }
+        );
+        assert_eq!(engine.accounts()[&ClientId(1)].total, amount("13"));
+
+        engine.process(tx(TxType::Dispute, 2, "0")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 2, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::ReversalNotAllowed)
+        );
+        engine.process(tx(TxType::Resolve, 2, "0")).unwrap();
+        assert!(matches!(
+            engine.process(tx(TxType::Reversal, 2, "0")).unwrap(),
+            Decision::Reversal {
+                reversed: TxType::Deposit,
+                ..
+            }
+        ));
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(
+            (account.available, account.total),
+            (amount("10"), amount("10"))
+        );
+
+        // A reversed transaction is final.
+        for tx_type in [TxType::Reversal, TxType::Dispute, TxType::Refund] {
+            assert!(matches!(
+                engine.process(tx(tx_type, 2, "0")).unwrap(),
+                Decision::Ignore(_)
+            ));
+        }
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 3, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::ReversalNotAllowed)
+        );
+        assert_eq!(engine.stats().reversed, amount("7"));
+
+        // Reversing a deposit needs the funds.
+        engine.process(tx(TxType::Withdrawal, 4, "8")).unwrap();
+        assert_eq!(
+            engine.process(tx(TxType::Reversal, 1, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn holds_and_releases_move_funds_in_and_out_of_escrow() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Hold, 1, "1"),
+            tx(TxType::Deposit, 2, "10"),
+            tx(TxType::Hold, 3, "11"),
+            tx(TxType::Hold, 4, "6"),
+            tx(TxType::Dispute, 2, "3"),
+            // Funds held by the dispute cannot be released.
+            tx(TxType::Release, 5, "7"),
+            tx(TxType::Release, 6, "2"),
+            tx(TxType::Release, 7, "0"),
+            tx(TxType::Release, 8, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Ignored(IgnoreReason::UnknownAccount),
+                Applied,
+                Ignored(IgnoreReason::InsufficientFunds),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::InsufficientEscrow),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::InsufficientEscrow),
+            ]
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("7").unwrap());
+        assert_eq!(account.held, Amount::from_str("3").unwrap());
+        assert_eq!(account.escrow, Amount::ZERO);
+        assert_eq!(engine.dispute_state(TxId(2)), Some(DisputeState::Disputed));
+    }
+
+    #[test]
+    fn opened_accounts_are_required_in_strict_mode() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig {
+            require_opening: true,
+            ..Default::default()
+        });
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Authorize, 1, 2, "10"),
+            tx(TxType::Open, 1, 3, "5"),
+            tx(TxType::Open, 1, 4, "0"),
+            tx(TxType::Deposit, 1, 5, "10"),
+            // The starting balance is no deposit.
+            tx(TxType::Dispute, 1, 3, "0"),
+            tx(TxType::OpenLocked, 2, 6, "7"),
+            tx(TxType::Deposit, 2, 7, "1"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Ignored(IgnoreReason::UnknownAccount),
+                Ignored(IgnoreReason::UnknownAccount),
+                Applied,
+                Ignored(IgnoreReason::AccountExists),
+                Applied,
+                Ignored(IgnoreReason::UnknownTransaction),
+                Applied,
+                Ignored(IgnoreReason::AccountLocked),
+            ]
+        );
+        let accounts = engine.accounts();
+        assert_eq!(
+            accounts[&ClientId(1)].total,
+            Amount::from_str("15").unwrap()
+        );
+        assert!(!accounts[&ClientId(1)].locked);
+        assert_eq!(
+            accounts[&ClientId(2)].available,
+            Amount::from_str("7").unwrap()
+        );
+        assert!(accounts[&ClientId(2)].locked);
+    }
+
+    #[test]
+    fn accounts_opened_locked_keep_the_invariants() {
+        let mut engine = Engine::new(EngineConfig::default());
+        engine
+            .process(Transaction {
+                tx_type: TxType::OpenLocked,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("5").unwrap(),
+            })
+            .unwrap();
+
+        let account = &engine.accounts()[&ClientId(1)];
+        assert!(account.locked && account.opened_locked);
+        assert_eq!(account.chargebacks, 0);
+        assert_eq!(check_invariants(engine.accounts()), []);
+    }
+
+    #[test]
+    fn closed_accounts_ignore_later_transactions() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Close, 1, "0"),
+            tx(TxType::Deposit, 2, "10"),
+            tx(TxType::Dispute, 2, "0"),
+            tx(TxType::Close, 3, "0"),
+            tx(TxType::Resolve, 2, "0"),
+            tx(TxType::Close, 4, "0"),
+            tx(TxType::Deposit, 5, "1"),
+            tx(TxType::Close, 6, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Ignored(IgnoreReason::UnknownAccount),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::HeldFundsRemain),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::AccountClosed),
+                Ignored(IgnoreReason::AccountClosed),
+            ]
+        );
+        let account = &engine.accounts()[&ClientId(1)];
+        assert!(account.closed);
+        assert_eq!(account.total, Amount::from_str("10").unwrap());
+        assert_eq!(engine.compact(), 1);
+    }
+
+    #[test]
+    fn outcomes_report_why_transactions_were_ignored() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let outcomes: Vec<ApplyOutcome> = [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Withdrawal, 1, 2, "11"),
+            tx(TxType::Dispute, 1, 9, "0"),
+            tx(TxType::Deposit, 2, 3, "5"),
+            tx(TxType::Dispute, 2, 1, "0"),
+            tx(TxType::Dispute, 1, 1, "0"),
+            tx(TxType::Chargeback, 1, 1, "0"),
+            tx(TxType::Deposit, 1, 4, "1"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap().outcome())
+        .collect();
+
+        use ApplyOutcome::{Applied, Ignored};
+        assert_eq!(
+            outcomes,
+            [
+                Applied,
+                Ignored(IgnoreReason::InsufficientFunds),
+                Ignored(IgnoreReason::UnknownTransaction),
+                Applied,
+                Ignored(IgnoreReason::ClientMismatch),
+                Applied,
+                Applied,
+                Ignored(IgnoreReason::AccountLocked),
+            ]
+        );
+        assert_eq!(outcomes[7].as_str(), "account_locked");
+        assert!(outcomes[0].is_applied() && !outcomes[1].is_applied());
+    }
+
+    #[test]
+    fn hooks_veto_transactions_the_rules_would_apply() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Rejects withdrawals of more than half the available balance.
+        struct HalfBalance(Arc<AtomicUsize>);
+
+        impl TransactionHook for HalfBalance {
+            fn before_apply(&self, tx: &Transaction, account: &AccountDetails) -> HookDecision {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                if tx.tx_type == TxType::Withdrawal && tx.amount + tx.amount > account.available {
+                    HookDecision::Reject
+                } else {
+                    HookDecision::Allow
+                }
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut engine = Engine::default();
+        engine.add_hook(HalfBalance(calls.clone()));
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
 
-        // After resolve, funds should be back in available
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        // Held should be back to zero
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        // Total should remain unchanged
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        assert!(matches!(
+            engine.process(tx(TxType::Deposit, 1, "10")).unwrap(),
+            Decision::Deposit { .. }
+        ));
+        assert_eq!(
+            engine.process(tx(TxType::Withdrawal, 2, "6")).unwrap(),
+            Decision::Ignore(IgnoreReason::RejectedByHook)
+        );
+        assert!(matches!(
+            engine.process(tx(TxType::Withdrawal, 3, "5")).unwrap(),
+            Decision::Withdraw { .. }
+        ));
+        // Ignored by the rules before the hook is asked.
+        assert_eq!(
+            engine.process(tx(TxType::Withdrawal, 4, "50")).unwrap(),
+            Decision::Ignore(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            engine.accounts()[&ClientId(1)].available,
+            Amount::from_str("5").unwrap()
+        );
+        assert_eq!(engine.stats().ignored, 2);
     }
 
     #[test]
-    fn resolve_nonexistent_transaction_is_ignored() {
-        // Test that resolving a non-existent transaction is ignored
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Resolve,
-                client: 1,
-                tx: 999, // Resolves non-existent transaction
-                amount: Decimal::ZERO,
-            },
+    fn account_metrics_count_applied_transactions() {
+        let tx = |tx_type, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let transactions = [
+            tx(TxType::Deposit, 1, "10"),
+            tx(TxType::Deposit, 2, "5"),
+            tx(TxType::Withdrawal, 3, "1"),
+            tx(TxType::Withdrawal, 4, "100"), // Ignored
+            tx(TxType::Dispute, 1, "0"),
+            tx(TxType::Dispute, 2, "0"),
+            tx(TxType::Resolve, 2, "0"),
         ];
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let mut untracked = Engine::new(EngineConfig::default());
+        let mut engine = Engine::new(EngineConfig {
+            account_metrics: true,
+            ..Default::default()
+        });
+        for transaction in transactions {
+            untracked.process(transaction.clone()).unwrap();
+            engine.process(transaction).unwrap();
+        }
+        assert!(untracked.accounts()[&ClientId(1)].metrics.is_empty());
+        let metrics = engine.accounts()[&ClientId(1)].metrics;
+        assert_eq!(
+            (
+                metrics.deposits,
+                metrics.withdrawals,
+                metrics.active_disputes,
+                metrics.disputes,
+                metrics.deposit_withdrawal_cycles
+            ),
+            (2, 1, 1, 1, 1)
+        );
+        // The first dispute is the fifth transaction; the ones after it are ignored.
+        assert_eq!(metrics.last_activity, 5);
+        assert_eq!(untracked.accounts()[&ClientId(1)].metrics.last_activity, 0);
 
-        // Account should still have funds in held (resolve was ignored)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        engine.process(tx(TxType::Chargeback, 1, "0")).unwrap();
+        let metrics = engine.accounts()[&ClientId(1)].metrics;
+        assert_eq!((metrics.active_disputes, metrics.chargebacks), (0, 1));
     }
 
     #[test]
-    fn resolve_transaction_without_dispute_is_ignored() {
-        // Test that resolving a transaction that isn't disputed is ignored
-        let transactions = vec![
-            Transaction {
+    fn close_period_snapshots_state_and_resets_counters() {
+        let mut engine = Engine::new(EngineConfig::default());
+        engine
+            .process(Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            // No dispute for transaction 1
-            Transaction {
-                tx_type: TxType::Resolve,
-                client: 1,
-                tx: 1, // Tries to resolve transaction 1 (but it's not disputed)
-                amount: Decimal::ZERO,
-            },
-        ];
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            })
+            .unwrap();
+        engine
+            .process(Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("50.0").unwrap(), // Ignored
+            })
+            .unwrap();
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let first = engine.close_period();
+        assert_eq!(first.period, 1);
+        assert_eq!(first.stats.applied, 1);
+        assert_eq!(first.stats.ignored, 1);
+        assert_eq!(first.stats.deposited, Amount::from_str("10.0").unwrap());
+        assert_eq!(engine.period(), 2);
+        assert_eq!(engine.period_stats(), &Stats::default());
 
-        // Account should be unchanged (resolve was ignored)
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
+        // Processing continues into the next period on top of the carried over state
+        engine
+            .process(Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(3),
+                amount: Amount::from_str("4.0").unwrap(),
+            })
+            .unwrap();
+        let second = engine.close_period();
+        assert_eq!(second.period, 2);
+        assert_eq!(second.stats.applied, 1);
+        assert_eq!(second.stats.withdrawn, Amount::from_str("4.0").unwrap());
+        assert_eq!(
+            first.accounts[&ClientId(1)].available,
+            Amount::from_str("10.0").unwrap()
+        );
+        assert_eq!(
+            second.accounts[&ClientId(1)].available,
+            Amount::from_str("6.0").unwrap()
+        );
     }
 
     #[test]
-    fn resolve_after_chargeback_is_ignored() {
-        // Test that resolving a transaction that was chargebacked is ignored
-        // (since chargeback withdraws the held funds, there's nothing to resolve)
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1, // Chargebacks the dispute (funds withdrawn, account locked)
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Resolve,
-                client: 1,
-                tx: 1, // Tries to resolve (but funds already withdrawn, nothing in held)
-                amount: Decimal::ZERO,
-            },
-        ];
+    fn check_invariants_finds_inconsistent_accounts() {
+        let amount = |value: &str| Amount::from_str(value).unwrap();
+        let tx = |tx_type, client, tx, value: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(value).unwrap_or(Amount::ZERO),
+        };
+        let mut accounts = proccess_transactions_vec(vec![
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Deposit, 1, 2, "5"),
+            tx(TxType::Dispute, 1, 1, ""),
+            tx(TxType::Hold, 1, 3, "2"),
+            tx(TxType::Chargeback, 1, 1, ""),
+            tx(TxType::Deposit, 2, 4, "3"),
+            tx(TxType::Close, 2, 5, ""),
+        ]);
+        assert_eq!(check_invariants(&accounts), []);
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
-
-        // Account should be as if resolve never happened (funds withdrawn, account locked)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
-        // Account should still be locked (chargeback happened, resolve was ignored)
-        assert!(
-            account.locked,
-            "Account should be locked after chargeback, resolve was ignored"
+        let client = accounts.get_mut(&ClientId(1)).unwrap();
+        client.total = amount("4");
+        client.escrow = amount("3");
+        let client = accounts.get_mut(&ClientId(2)).unwrap();
+        client.held = amount("-1");
+        client.available = amount("4");
+        client.locked = true;
+        let violations: Vec<_> = check_invariants(&accounts)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            violations,
+            [
+                "client 1 breaks total_is_available_plus_held: total 4, available 3, held 2",
+                "client 1 breaks escrow_within_held: escrow 3, held 2",
+                "client 2 breaks non_negative_held: held -1",
+                "client 2 breaks locked_after_chargeback: 0 chargebacks",
+                "client 2 breaks closed_without_held: held -1",
+            ]
         );
     }
 
     #[test]
-    fn resolve_partial_funds() {
-        // Test resolve when account has multiple disputed transactions
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("5.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1, // Disputes first deposit
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 2, // Disputes second deposit
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Resolve,
-                client: 1,
-                tx: 1, // Resolves first deposit only
-                amount: Decimal::ZERO,
-            },
-        ];
+    fn account_filter_combines_criteria() {
+        let accounts = Accounts::from([
+            (
+                ClientId(1),
+                AccountDetails::new_with_balance(Amount::from_str("5.0").unwrap()),
+            ),
+            (
+                ClientId(2),
+                AccountDetails {
+                    total: Amount::from_str("20.0").unwrap(),
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                ClientId(3),
+                AccountDetails::new_with_balance(Amount::from_str("50.0").unwrap()),
+            ),
+        ]);
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let all = AccountFilter::default().apply(accounts.clone());
+        assert_eq!(all.len(), 3);
 
-        // Available should be 10.0 (first deposit resolved)
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        // Held should be 5.0 (second deposit still disputed)
-        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
-        // Total should be 15.0 (sum of both deposits)
-        assert_eq!(account.total, Decimal::from_str("15.0").unwrap());
+        let by_client = AccountFilter {
+            clients: BTreeSet::from([ClientId(1), ClientId(2)]),
+            ..Default::default()
+        };
+        assert_eq!(
+            by_client
+                .apply(accounts.clone())
+                .into_keys()
+                .collect::<BTreeSet<_>>(),
+            BTreeSet::from([ClientId(1), ClientId(2)])
+        );
+
+        let locked = AccountFilter {
+            only_locked: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            locked.apply(accounts.clone()).keys().collect::<Vec<_>>(),
+            vec![&ClientId(2)]
+        );
+
+        let rich_clients = AccountFilter {
+            clients: BTreeSet::from([ClientId(1), ClientId(3)]),
+            min_total: Some(Amount::from_str("10.0").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            rich_clients.apply(accounts).keys().collect::<Vec<_>>(),
+            vec![&ClientId(3)]
+        );
     }
 
     #[test]
-    fn chargeback_transacion() {
-        // Test successful chargeback - funds withdrawn from held and total, account locked
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1, // Disputes transaction 1
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1,                 // Chargebacks transaction 1
-                amount: Decimal::ZERO, // Chargeback doesn't have an amount
-            },
-        ];
+    fn negative_amounts_are_ignored() {
+        let mut engine = Engine::default();
+        let tx = |tx_type, tx, amount| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        engine.process(tx(TxType::Deposit, 1, "10.0")).unwrap();
+        for (tx_type, amount) in [
+            (TxType::Deposit, "-5.0"),
+            (TxType::Withdrawal, "-5.0"),
+            (TxType::Dispute, "-1.0"),
+        ] {
+            assert_eq!(
+                engine.process(tx(tx_type, 1, amount)).unwrap(),
+                Decision::Ignore(IgnoreReason::NegativeAmount)
+            );
+        }
 
-        // Available should remain 0 (was moved to held, then withdrawn)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        // Held should be 0 (withdrawn)
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        // Total should decrease by disputed amount (10.0 - 10.0 = 0.0)
-        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
-        // Account should be locked
-        assert!(account.locked, "Account should be locked after chargeback");
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("10.0").unwrap());
+        assert_eq!(account.total, Amount::from_str("10.0").unwrap());
+        assert_eq!(
+            engine.dispute_state(TxId(1)),
+            Some(DisputeState::Undisputed)
+        );
     }
 
     #[test]
-    fn chargeback_nonexistent_transaction_is_ignored() {
-        // Test that chargebacking a non-existent transaction is ignored
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 999, // Chargebacks non-existent transaction
-                amount: Decimal::ZERO,
-            },
-        ];
-
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+    fn engine_applies_precision_policy() {
+        let deposit = |tx, amount| Transaction {
+            tx_type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: rust_decimal::Decimal::from_str(amount).unwrap(),
+        };
 
-        // Account should still have funds in held (chargeback was ignored)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        // Account should not be locked
+        let mut engine = Engine::new(EngineConfig {
+            precision: PrecisionPolicy::Reject,
+            ..Default::default()
+        });
+        assert_eq!(
+            engine.process(deposit(1, "1.23456")).unwrap(),
+            Decision::Ignore(IgnoreReason::ExcessPrecision)
+        );
         assert!(
-            !account.locked,
-            "Account should not be locked when chargeback is ignored"
+            engine
+                .process(deposit(2, "1.2345"))
+                .unwrap()
+                .client()
+                .is_some()
         );
+
+        // Library users get the amounts they pass in unless they opt in to a
+        // policy, which the command line does with `round`.
+        let mut engine = Engine::default();
+        engine.process(deposit(1, "1.23456")).unwrap();
+        assert_eq!(engine.accounts()[&ClientId(1)].total.to_string(), "1.23456");
+
+        let mut engine = Engine::new(EngineConfig {
+            precision: PrecisionPolicy::Round,
+            ..Default::default()
+        });
+        engine.process(deposit(1, "1.23456")).unwrap();
+        assert_eq!(engine.accounts()[&ClientId(1)].total.to_string(), "1.2346");
     }
 
-    #[test]
-    fn chargeback_transaction_without_dispute_is_ignored() {
-        // Test that chargebacking a transaction that isn't disputed is ignored
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            // No dispute for transaction 1
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1, // Tries to chargeback transaction 1 (but it's not disputed)
-                amount: Decimal::ZERO,
-            },
-        ];
+    /// A money type counting whole cents, to check the engine runs on any
+    /// [`AmountOps`] implementation.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+    struct Cents(i32);
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+    impl AmountOps for Cents {
+        const ZERO: Self = Cents(0);
 
-        // Account should be unchanged (chargeback was ignored)
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        // Account should not be locked
-        assert!(
-            !account.locked,
-            "Account should not be locked when chargeback is ignored"
-        );
+        fn checked_add(self, other: Self) -> Option<Self> {
+            self.0.checked_add(other.0).map(Cents)
+        }
+
+        fn checked_sub(self, other: Self) -> Option<Self> {
+            self.0.checked_sub(other.0).map(Cents)
+        }
+
+        fn saturating_add(self, other: Self) -> Self {
+            Cents(self.0.saturating_add(other.0))
+        }
     }
 
     #[test]
-    fn chargeback_partial_funds() {
-        // Test chargeback when account has multiple disputed transactions
-        let transactions = vec![
-            Transaction {
+    fn engine_runs_on_custom_amount_type() {
+        let tx = |tx_type, tx, amount| {
+            Ok(Transaction {
+                tx_type,
+                client: ClientId(1),
+                tx: TxId(tx),
+                amount: Cents(amount),
+            })
+        };
+        let mut engine = Engine::new(EngineConfig {
+            credit_limit: Cents(50),
+            ..Default::default()
+        });
+        for tx in [
+            tx(TxType::Deposit, 1, 1_000),
+            tx(TxType::Withdrawal, 2, 1_040),
+            tx(TxType::Withdrawal, 3, 100),
+            tx(TxType::Dispute, 1, 0),
+        ] {
+            engine.process(tx.unwrap()).unwrap();
+        }
+
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.available, Cents(-40));
+        assert_eq!(account.total, Cents(-40));
+        assert_eq!(engine.stats().funds_moved(), Cents(2_040));
+        assert_eq!(engine.stats().ignored, 2);
+
+        let overflow =
+            proccess_transactions([tx(TxType::Deposit, 1, i32::MAX), tx(TxType::Deposit, 2, 1)]);
+        assert!(overflow.is_err());
+    }
+
+    /// Returns 100 deposits of client 1 followed by a chargeback of the first.
+    fn deposits_of_locked_client() -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = (1..=100)
+            .map(|tx| Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
+                client: ClientId(1),
+                tx: TxId(tx),
+                amount: Amount::from_str("1.5").unwrap(),
+            })
+            .collect();
+        for tx_type in [TxType::Dispute, TxType::Chargeback] {
+            transactions.push(Transaction {
+                tx_type,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            });
+        }
+        transactions
+    }
+
+    #[test]
+    fn compact_evicts_deposits_of_locked_accounts() {
+        let mut engine = Engine::default();
+        for tx in deposits_of_locked_client() {
+            engine.process(tx).unwrap();
+        }
+        engine
+            .process(Transaction {
                 tx_type: TxType::Deposit,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("5.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1, // Disputes first deposit
-                amount: Decimal::ZERO,
-            },
-            Transaction {
+                client: ClientId(2),
+                tx: TxId(101),
+                amount: Amount::from_str("2.5").unwrap(),
+            })
+            .unwrap();
+        let usage = engine.memory_usage();
+
+        assert_eq!(engine.compact(), 100);
+        assert!(engine.memory_usage() < usage);
+        assert_eq!(engine.dispute_state(TxId(2)), None);
+        assert_eq!(
+            engine.dispute_state(TxId(101)),
+            Some(DisputeState::Undisputed)
+        );
+        // Transactions of the locked account are ignored for the same reason as before.
+        let decision = engine
+            .process(Transaction {
                 tx_type: TxType::Dispute,
-                client: 1,
-                tx: 2, // Disputes second deposit
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1, // Chargebacks first deposit only
-                amount: Decimal::ZERO,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::ZERO,
+            })
+            .unwrap();
+        assert_eq!(decision, Decision::Ignore(IgnoreReason::AccountLocked));
+    }
+
+    #[test]
+    fn retention_window_evicts_old_undisputed_deposits() {
+        let tx = |tx_type, tx| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: if tx_type == TxType::Deposit {
+                Amount::from_str("1.5").unwrap()
+            } else {
+                Amount::ZERO
             },
-        ];
+        };
+        let mut engine = Engine::new(EngineConfig {
+            history_retention: Some(2),
+            ..Default::default()
+        });
+        for transaction in [
+            tx(TxType::Deposit, 1),
+            tx(TxType::Dispute, 1),
+            tx(TxType::Deposit, 2),
+            tx(TxType::Deposit, 3),
+            // The fourth deposit compacts the history, evicting deposit 2.
+            tx(TxType::Deposit, 4),
+            tx(TxType::Deposit, 5),
+        ] {
+            engine.process(transaction).unwrap();
+        }
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        assert_eq!(engine.state().deposits, 5);
+        assert_eq!(engine.dispute_state(TxId(2)), None);
+        assert_eq!(
+            engine.dispute_state(TxId(5)),
+            Some(DisputeState::Undisputed)
+        );
+        assert_eq!(
+            engine.process(tx(TxType::Dispute, 2)).unwrap(),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
+        // The disputed deposit is kept until its dispute is settled.
+        assert!(matches!(
+            engine.process(tx(TxType::Resolve, 1)).unwrap(),
+            Decision::Release { .. }
+        ));
+        engine.compact();
+        assert_eq!(engine.dispute_state(TxId(1)), None);
+        assert_eq!(engine.state().deposit_history.len(), 2);
 
-        // Available should be 0 (first deposit was disputed, then chargebacked)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        // Held should be 5.0 (second deposit still disputed)
-        assert_eq!(account.held, Decimal::from_str("5.0").unwrap());
-        // Total should be 5.0 (first deposit withdrawn: 15.0 - 10.0 = 5.0)
-        assert_eq!(account.total, Decimal::from_str("5.0").unwrap());
-        // Account should be locked
-        assert!(account.locked, "Account should be locked after chargeback");
+        let wrapped = DepositRecord {
+            sequence: u32::MAX,
+            ..DepositRecord::new(ClientId(1), Amount::ZERO)
+        };
+        assert_eq!(wrapped.age(u64::from(u32::MAX) + 1), 0);
+        assert_eq!(wrapped.age(u64::from(u32::MAX) + 3), 2);
     }
 
     #[test]
-    fn chargeback_after_resolve_is_ignored() {
-        // Test that chargebacking a transaction that was resolved is ignored
-        // (since resolve releases the held funds, there's no active dispute to chargeback)
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Resolve,
-                client: 1,
-                tx: 1, // Resolves the dispute (funds back to available)
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1, // Tries to chargeback (but dispute was resolved, no funds held)
-                amount: Decimal::ZERO,
-            },
-        ];
+    fn memory_cap_compacts_history_before_growing_it() {
+        let mut reference = Engine::default();
+        for tx in deposits_of_locked_client() {
+            reference.process(tx).unwrap();
+        }
+        let max_memory = reference.memory_usage();
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        let mut engine = Engine::new(EngineConfig {
+            max_memory: Some(max_memory),
+            ..Default::default()
+        });
+        for tx in deposits_of_locked_client() {
+            engine.process(tx).unwrap();
+        }
+        let deposit = |tx| Transaction {
+            tx_type: TxType::Deposit,
+            client: ClientId(2),
+            tx: TxId(tx),
+            amount: Amount::from_str("2.5").unwrap(),
+        };
+        // Growing the history for these deposits would exceed the cap without
+        // evicting the deposits of the locked client.
+        for tx in 101..=200 {
+            engine.process(deposit(tx)).unwrap();
+        }
+        assert_eq!(engine.state().deposit_history.len(), 100);
+        assert!(engine.peak_memory() <= max_memory);
+        assert_eq!(
+            engine.accounts()[&ClientId(2)].total,
+            Amount::from_str("250").unwrap()
+        );
 
-        // Account should be as if chargeback never happened (funds back in available)
-        assert_eq!(account.available, Decimal::from_str("10.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("10.0").unwrap());
-        // Account should not be locked (chargeback was ignored)
+        let err = (201..=300)
+            .map(|tx| engine.process(deposit(tx)))
+            .find_map(Result::err)
+            .unwrap();
         assert!(
-            !account.locked,
-            "Account should not be locked when chargeback is ignored"
+            err.to_string().contains("would exceed the limit"),
+            "{}",
+            err
         );
+        assert!(engine.peak_memory() <= max_memory);
     }
 
     #[test]
-    fn locked_account_ignores_further_transactions() {
-        // Test that once an account is locked, all further transactions are ignored
-        let transactions = vec![
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 1,
-                amount: Decimal::from_str("10.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Dispute,
-                client: 1,
-                tx: 1,
-                amount: Decimal::ZERO,
-            },
-            Transaction {
-                tx_type: TxType::Chargeback,
-                client: 1,
-                tx: 1, // Locks the account
-                amount: Decimal::ZERO,
-            },
-            // These should all be ignored because account is locked
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 2,
-                amount: Decimal::from_str("5.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Withdrawal,
-                client: 1,
-                tx: 3,
-                amount: Decimal::from_str("2.0").unwrap(),
-            },
-            Transaction {
-                tx_type: TxType::Deposit,
-                client: 1,
-                tx: 4,
-                amount: Decimal::from_str("100.0").unwrap(),
-            },
-        ];
+    fn disputes_resolve_against_looked_up_deposits() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let amount = |value| Amount::from_str(value).unwrap();
 
-        let accounts = proccess_transactions_vec(transactions);
-        let account = accounts.get(&1).expect("Account should exist");
+        // The deposit of the account is older than the history, as after
+        // continuing from a snapshot without it.
+        let mut engine = Engine::default();
+        engine.process(tx(TxType::Deposit, 1, 1, "10")).unwrap();
+        engine.process(tx(TxType::Deposit, 2, 3, "5")).unwrap();
+        engine.state_mut().deposit_history.clear();
+        assert_eq!(
+            engine.process(tx(TxType::Dispute, 1, 1, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::UnknownTransaction)
+        );
 
-        // Account should be locked
-        assert!(account.locked, "Account should be locked after chargeback");
+        engine.set_tx_lookup(HashMap::from([(
+            TxId(1),
+            DepositRecord::new(ClientId(1), amount("10")),
+        )]));
+        assert_eq!(
+            engine.process(tx(TxType::Dispute, 2, 1, "0")).unwrap(),
+            Decision::Ignore(IgnoreReason::ClientMismatch)
+        );
+        assert!(engine.state().deposit_history.is_empty());
 
-        // Balances should be as if chargeback was the last processed transaction
-        // (chargeback removed 10.0 from total and held, leaving 0)
-        assert_eq!(account.available, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.held, Decimal::from_str("0.0").unwrap());
-        assert_eq!(account.total, Decimal::from_str("0.0").unwrap());
+        // A rolled back batch leaves the deposit to the lookup.
+        let outcome = engine
+            .apply_batch(vec![
+                tx(TxType::Dispute, 1, 1, "0"),
+                tx(TxType::Withdrawal, 1, 2, "100"),
+            ])
+            .unwrap();
+        assert!(!outcome.is_applied());
+        assert!(engine.state().deposit_history.is_empty());
 
-        // Verify subsequent deposits/withdrawals were ignored
-        // If they weren't ignored, the account would have different balances
+        assert!(matches!(
+            engine.process(tx(TxType::Dispute, 1, 1, "0")).unwrap(),
+            Decision::Hold { .. }
+        ));
+        assert_eq!(engine.accounts()[&ClientId(1)].held, amount("10"));
+        assert_eq!(
+            engine.state().deposit_history[&TxId(1)].state,
+            DisputeState::Disputed
+        );
+        assert!(matches!(
+            engine.process(tx(TxType::Chargeback, 1, 1, "0")).unwrap(),
+            Decision::Chargeback { .. }
+        ));
+        let account = &engine.accounts()[&ClientId(1)];
+        assert_eq!(account.total, amount("0"));
+        assert!(account.locked);
     }
 }
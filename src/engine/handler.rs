@@ -0,0 +1,724 @@
+//! Handlers deciding the effect of each transaction type.
+//!
+//! [`decide`](super::decide) performs the checks common to all transactions,
+//! such as locked accounts and the amount precision, and leaves the rest to
+//! the [`TxHandler`] of the transaction type in
+//! [`EngineConfig::handlers`]. Each handler decides one type from a
+//! [`TxContext`], reading the policies it implements, e.g. the
+//! [`DisputePolicy`], from the configuration. A policy that the configuration
+//! cannot express is added by replacing the handler of a type:
+//!
+//! ```
+//! use project_diamond_hands::engine::handler::{DepositHandler, Handlers, TxContext, TxHandler};
+//! use project_diamond_hands::engine::{Decision, EngineConfig, IgnoreReason};
+//! use project_diamond_hands::types::TxType;
+//!
+//! /// Ignores deposits to clients without an account.
+//! struct KnownClientDeposits;
+//!
+//! impl TxHandler for KnownClientDeposits {
+//!     fn decide(&self, context: &TxContext<'_>) -> Result<Decision, IgnoreReason> {
+//!         context.account.ok_or(IgnoreReason::UnknownAccount)?;
+//!         DepositHandler.decide(context)
+//!     }
+//! }
+//!
+//! let config = EngineConfig {
+//!     handlers: Handlers::standard().with(TxType::Deposit, KnownClientDeposits),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::{Decision, DepositRecord, DisputePolicy, EngineConfig, IgnoreReason};
+use crate::amount::AmountOps;
+use crate::types::{AccountDetails, Amount, DisputeState, Transaction, TxType};
+
+/// What a [`TxHandler`] decides on.
+#[derive(Debug)]
+pub struct TxContext<'a, A = Amount> {
+    pub tx: &'a Transaction<A>,
+    /// The amount of `tx` after applying the precision policy; never negative.
+    pub amount: A,
+    /// The account of `tx.client`, if it exists.
+    pub account: Option<&'a AccountDetails<A>>,
+    /// The deposit referenced by `tx.tx`, if it exists.
+    pub deposit: Option<&'a DepositRecord<A>>,
+    pub config: &'a EngineConfig<A>,
+    /// The credit limit of `tx.client`.
+    pub credit_limit: A,
+}
+
+/// Decides the effect of the transactions of one type.
+pub trait TxHandler<A = Amount>: Send + Sync {
+    /// Returns the decision for the transaction of `context`, or the reason it
+    /// is ignored.
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason>;
+}
+
+/// The handler of every transaction type, see the [module documentation](self).
+#[derive(Clone)]
+pub struct Handlers<A = Amount> {
+    deposit: Arc<dyn TxHandler<A>>,
+    withdrawal: Arc<dyn TxHandler<A>>,
+    dispute: Arc<dyn TxHandler<A>>,
+    resolve: Arc<dyn TxHandler<A>>,
+    chargeback: Arc<dyn TxHandler<A>>,
+    refund: Arc<dyn TxHandler<A>>,
+    authorize: Arc<dyn TxHandler<A>>,
+    capture: Arc<dyn TxHandler<A>>,
+    close: Arc<dyn TxHandler<A>>,
+    hold: Arc<dyn TxHandler<A>>,
+    release: Arc<dyn TxHandler<A>>,
+    interest: Arc<dyn TxHandler<A>>,
+    reversal: Arc<dyn TxHandler<A>>,
+    open: Arc<dyn TxHandler<A>>,
+    open_locked: Arc<dyn TxHandler<A>>,
+}
+
+impl<A: AmountOps> Handlers<A> {
+    /// Returns the handlers implementing the processing rules documented in the
+    /// README.
+    pub fn standard() -> Self {
+        Handlers {
+            deposit: Arc::new(DepositHandler),
+            withdrawal: Arc::new(WithdrawalHandler),
+            dispute: Arc::new(DisputeHandler),
+            resolve: Arc::new(ResolveHandler),
+            chargeback: Arc::new(ChargebackHandler),
+            refund: Arc::new(RefundHandler),
+            authorize: Arc::new(AuthorizeHandler),
+            capture: Arc::new(CaptureHandler),
+            close: Arc::new(CloseHandler),
+            hold: Arc::new(HoldHandler),
+            release: Arc::new(ReleaseHandler),
+            interest: Arc::new(InterestHandler),
+            reversal: Arc::new(ReversalHandler),
+            open: Arc::new(OpenHandler),
+            open_locked: Arc::new(OpenHandler),
+        }
+    }
+}
+
+impl<A> Handlers<A> {
+    /// Returns the handler of `tx_type`.
+    pub fn get(&self, tx_type: TxType) -> &dyn TxHandler<A> {
+        self.slot(tx_type).as_ref()
+    }
+
+    /// Replaces the handler of `tx_type` with `handler`.
+    pub fn with(mut self, tx_type: TxType, handler: impl TxHandler<A> + 'static) -> Self {
+        *self.slot_mut(tx_type) = Arc::new(handler);
+        self
+    }
+
+    fn slot(&self, tx_type: TxType) -> &Arc<dyn TxHandler<A>> {
+        match tx_type {
+            TxType::Deposit => &self.deposit,
+            TxType::Withdrawal => &self.withdrawal,
+            TxType::Dispute => &self.dispute,
+            TxType::Resolve => &self.resolve,
+            TxType::Chargeback => &self.chargeback,
+            TxType::Refund => &self.refund,
+            TxType::Authorize => &self.authorize,
+            TxType::Capture => &self.capture,
+            TxType::Close => &self.close,
+            TxType::Hold => &self.hold,
+            TxType::Release => &self.release,
+            TxType::Interest => &self.interest,
+            TxType::Reversal => &self.reversal,
+            TxType::Open => &self.open,
+            TxType::OpenLocked => &self.open_locked,
+        }
+    }
+
+    fn slot_mut(&mut self, tx_type: TxType) -> &mut Arc<dyn TxHandler<A>> {
+        match tx_type {
+            TxType::Deposit => &mut self.deposit,
+            TxType::Withdrawal => &mut self.withdrawal,
+            TxType::Dispute => &mut self.dispute,
+            TxType::Resolve => &mut self.resolve,
+            TxType::Chargeback => &mut self.chargeback,
+            TxType::Refund => &mut self.refund,
+            TxType::Authorize => &mut self.authorize,
+            TxType::Capture => &mut self.capture,
+            TxType::Close => &mut self.close,
+            TxType::Hold => &mut self.hold,
+            TxType::Release => &mut self.release,
+            TxType::Interest => &mut self.interest,
+            TxType::Reversal => &mut self.reversal,
+            TxType::Open => &mut self.open,
+            TxType::OpenLocked => &mut self.open_locked,
+        }
+    }
+}
+
+impl<A: AmountOps> Default for Handlers<A> {
+    fn default() -> Self {
+        Handlers::standard()
+    }
+}
+
+impl<A> fmt::Debug for Handlers<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handlers").finish_non_exhaustive()
+    }
+}
+
+/// Credits deposits, opening the account of a new client unless
+/// [`EngineConfig::require_opening`] is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DepositHandler;
+
+impl<A: AmountOps> TxHandler<A> for DepositHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        opened_account(context)?;
+        Ok(Decision::Deposit {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: context.amount,
+        })
+    }
+}
+
+/// Debits withdrawals within the credit and withdrawal limits of the client.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WithdrawalHandler;
+
+impl<A: AmountOps> TxHandler<A> for WithdrawalHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let (client, amount) = (context.tx.client, context.amount);
+        let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        if context
+            .config
+            .withdrawal_limit_for(client)
+            .is_some_and(|limit| amount > limit)
+        {
+            return Err(IgnoreReason::WithdrawalLimitExceeded);
+        }
+        if !within_credit_limit(account.available, amount, context.credit_limit) {
+            return Err(IgnoreReason::InsufficientFunds);
+        }
+        Ok(Decision::Withdraw {
+            client,
+            tx: context.tx.tx,
+            amount,
+        })
+    }
+}
+
+/// Holds the disputed amount of a deposit as the [`DisputePolicy`] allows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisputeHandler;
+
+impl<A: AmountOps> TxHandler<A> for DisputeHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let (account, deposit) = referenced_deposit(context)?;
+        if !context.config.may_dispute(deposit) {
+            return Err(IgnoreReason::DisputeNotAllowed);
+        }
+        let amount =
+            disputed_amount(context.amount, deposit).ok_or(IgnoreReason::InvalidDisputeAmount)?;
+        let amount = amount_to_hold(
+            context.config.dispute_policy,
+            account.available,
+            amount,
+            context.credit_limit,
+        )
+        .ok_or(IgnoreReason::InsufficientFunds)?;
+        Ok(Decision::Hold {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount,
+        })
+    }
+}
+
+/// Releases the amount held by the active dispute of a deposit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResolveHandler;
+
+impl<A: AmountOps> TxHandler<A> for ResolveHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let (account, deposit) = referenced_deposit(context)?;
+        Ok(Decision::Release {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: settleable_amount(account, deposit)?,
+        })
+    }
+}
+
+/// Withdraws the amount held by the active dispute of a deposit, locking the
+/// account as the [`LockPolicy`](super::LockPolicy) demands.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChargebackHandler;
+
+impl<A: AmountOps> TxHandler<A> for ChargebackHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        // Only process if deposit exists, belongs to same client, has an active dispute,
+        // and sufficient funds are held
+        let (account, deposit) = referenced_deposit(context)?;
+        let amount = settleable_amount(account, deposit)?;
+        let chargebacks = account.chargebacks.saturating_add(1);
+        Ok(Decision::Chargeback {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount,
+            lock: context.config.lock_policy.locks_after(chargebacks),
+        })
+    }
+}
+
+/// Returns undisputed deposits or parts of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RefundHandler;
+
+impl<A: AmountOps> TxHandler<A> for RefundHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let (account, deposit) = referenced_deposit(context)?;
+        if !matches!(
+            deposit.state,
+            DisputeState::Undisputed | DisputeState::Resolved
+        ) {
+            return Err(IgnoreReason::RefundNotAllowed);
+        }
+        let amount =
+            refunded_amount(context.amount, deposit).ok_or(IgnoreReason::InvalidRefundAmount)?;
+        if !within_credit_limit(account.available, amount, context.credit_limit) {
+            return Err(IgnoreReason::InsufficientFunds);
+        }
+        Ok(Decision::Refund {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount,
+        })
+    }
+}
+
+/// Holds authorized amounts until they are captured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuthorizeHandler;
+
+impl<A: AmountOps> TxHandler<A> for AuthorizeHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        opened_account(context)?;
+        Ok(Decision::Authorize {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: context.amount,
+        })
+    }
+}
+
+/// Captures pending authorizations, releasing the amount not captured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureHandler;
+
+impl<A: AmountOps> TxHandler<A> for CaptureHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let (account, deposit) = referenced_deposit(context)?;
+        if deposit.state != DisputeState::Authorized {
+            return Err(IgnoreReason::NotAuthorized);
+        }
+        let captured = if context.amount == A::ZERO {
+            deposit.held
+        } else {
+            context.amount
+        };
+        let released = deposit
+            .held
+            .checked_sub(captured)
+            .filter(|released| *released >= A::ZERO)
+            .ok_or(IgnoreReason::InvalidCaptureAmount)?;
+        if account.held < deposit.held {
+            return Err(IgnoreReason::InsufficientHeldFunds);
+        }
+        Ok(Decision::Capture {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: captured,
+            released,
+        })
+    }
+}
+
+/// Closes accounts without held funds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CloseHandler;
+
+impl<A: AmountOps> TxHandler<A> for CloseHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        if account.held != A::ZERO {
+            return Err(IgnoreReason::HeldFundsRemain);
+        }
+        Ok(Decision::Close {
+            client: context.tx.client,
+            tx: context.tx.tx,
+        })
+    }
+}
+
+/// Places available funds in escrow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HoldHandler;
+
+impl<A: AmountOps> TxHandler<A> for HoldHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        if !within_credit_limit(account.available, context.amount, context.credit_limit) {
+            return Err(IgnoreReason::InsufficientFunds);
+        }
+        Ok(Decision::EscrowHold {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: context.amount,
+        })
+    }
+}
+
+/// Releases funds from escrow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReleaseHandler;
+
+impl<A: AmountOps> TxHandler<A> for ReleaseHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        let amount = if context.amount == A::ZERO {
+            account.escrow
+        } else {
+            context.amount
+        };
+        if account.escrow == A::ZERO || amount > account.escrow {
+            return Err(IgnoreReason::InsufficientEscrow);
+        }
+        Ok(Decision::EscrowRelease {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount,
+        })
+    }
+}
+
+/// Credits interest to existing accounts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterestHandler;
+
+impl<A: AmountOps> TxHandler<A> for InterestHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        Ok(Decision::Interest {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: context.amount,
+        })
+    }
+}
+
+/// Undoes deposits and recorded withdrawals.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReversalHandler;
+
+impl<A: AmountOps> TxHandler<A> for ReversalHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        let client = context.tx.client;
+        let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+        let record = context.deposit.ok_or(IgnoreReason::UnknownTransaction)?;
+        if record.client != client {
+            return Err(IgnoreReason::ClientMismatch);
+        }
+        let (reversed, reversed_amount) = match record.state {
+            DisputeState::Undisputed | DisputeState::Resolved => {
+                (TxType::Deposit, record.remaining())
+            }
+            DisputeState::Withdrawn => (TxType::Withdrawal, record.amount),
+            DisputeState::Authorized
+            | DisputeState::Disputed
+            | DisputeState::ChargedBack
+            | DisputeState::Reversed => return Err(IgnoreReason::ReversalNotAllowed),
+        };
+        if context.amount != A::ZERO && context.amount != reversed_amount {
+            return Err(IgnoreReason::InvalidReversalAmount);
+        }
+        if reversed == TxType::Deposit
+            && !within_credit_limit(account.available, reversed_amount, context.credit_limit)
+        {
+            return Err(IgnoreReason::InsufficientFunds);
+        }
+        Ok(Decision::Reversal {
+            client,
+            tx: context.tx.tx,
+            amount: reversed_amount,
+            reversed,
+        })
+    }
+}
+
+/// Opens the accounts of new clients, with the amount as starting balance.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenHandler;
+
+impl<A: AmountOps> TxHandler<A> for OpenHandler {
+    fn decide(&self, context: &TxContext<'_, A>) -> Result<Decision<A>, IgnoreReason> {
+        if context.account.is_some() {
+            return Err(IgnoreReason::AccountExists);
+        }
+        Ok(Decision::Open {
+            client: context.tx.client,
+            tx: context.tx.tx,
+            amount: context.amount,
+            locked: context.tx.tx_type == TxType::OpenLocked,
+        })
+    }
+}
+
+/// Checks that the client has an account if
+/// [`EngineConfig::require_opening`] is set, i.e. accounts are only opened by
+/// [`TxType::Open`] transactions.
+fn opened_account<A>(context: &TxContext<'_, A>) -> Result<(), IgnoreReason> {
+    if context.config.require_opening && context.account.is_none() {
+        return Err(IgnoreReason::UnknownAccount);
+    }
+    Ok(())
+}
+
+/// Checks that the client has an account and that the deposit referenced by a
+/// dispute, resolve, chargeback or refund exists and belongs to the same client.
+pub fn referenced_deposit<'a, A: AmountOps>(
+    context: &TxContext<'a, A>,
+) -> Result<(&'a AccountDetails<A>, &'a DepositRecord<A>), IgnoreReason> {
+    let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+    // Recorded withdrawals can only be reversed.
+    let deposit = context
+        .deposit
+        .filter(|deposit| deposit.state != DisputeState::Withdrawn)
+        .ok_or(IgnoreReason::UnknownTransaction)?;
+    if deposit.client != context.tx.client {
+        return Err(IgnoreReason::ClientMismatch);
+    }
+    Ok((account, deposit))
+}
+
+/// Returns the amount held by the active dispute on `deposit` that a resolve or
+/// chargeback settles.
+///
+/// Each dispute settles the amount recorded in its own [`DepositRecord::held`],
+/// never the deposited amount, so interleaved disputes holding partial amounts
+/// do not affect each other. The check against the account's held balance only
+/// guards against inconsistent state, e.g. an edited snapshot.
+pub fn settleable_amount<A: AmountOps>(
+    account: &AccountDetails<A>,
+    deposit: &DepositRecord<A>,
+) -> Result<A, IgnoreReason> {
+    if deposit.state != DisputeState::Disputed {
+        return Err(IgnoreReason::NotDisputed);
+    }
+    if account.held < deposit.held {
+        return Err(IgnoreReason::InsufficientHeldFunds);
+    }
+    Ok(deposit.held)
+}
+
+/// Returns the amount a dispute places on hold.
+///
+/// A dispute without an amount (or with a zero amount) disputes the whole deposit,
+/// less any refunds. A positive amount disputes only that portion of the deposit;
+/// disputes for more than the remaining amount or for negative amounts are invalid
+/// and yield `None`.
+fn disputed_amount<A: AmountOps>(amount: A, deposit: &DepositRecord<A>) -> Option<A> {
+    let remaining = deposit.remaining();
+    if amount == A::ZERO {
+        Some(remaining)
+    } else if amount > A::ZERO && amount <= remaining {
+        Some(amount)
+    } else {
+        None
+    }
+}
+
+/// Returns the amount a refund of `amount` returns: the whole remaining deposit
+/// for zero, or `None` if nothing remains or the amount exceeds it.
+fn refunded_amount<A: AmountOps>(amount: A, deposit: &DepositRecord<A>) -> Option<A> {
+    let remaining = deposit.remaining();
+    if amount == A::ZERO {
+        (remaining > A::ZERO).then_some(remaining)
+    } else if amount <= remaining {
+        Some(amount)
+    } else {
+        None
+    }
+}
+
+/// Returns the amount a dispute for `amount` holds under the given policy, or
+/// `None` if the dispute must be ignored.
+fn amount_to_hold<A: AmountOps>(
+    policy: DisputePolicy,
+    available: A,
+    amount: A,
+    credit_limit: A,
+) -> Option<A> {
+    match policy {
+        DisputePolicy::RequireAvailable => {
+            within_credit_limit(available, amount, credit_limit).then_some(amount)
+        }
+        DisputePolicy::AllowNegativeAvailable => Some(amount),
+        DisputePolicy::HoldPartial => {
+            let spendable = available.checked_add(credit_limit)?;
+            if spendable >= amount {
+                Some(amount)
+            } else if spendable > A::ZERO {
+                Some(spendable)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Returns `true` if `amount` can be taken from `available` without the balance
+/// dropping below `-credit_limit`.
+pub fn within_credit_limit<A: AmountOps>(available: A, amount: A, credit_limit: A) -> bool {
+    available
+        .checked_add(credit_limit)
+        .is_some_and(|spendable| amount <= spendable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::policy::RulesVersion;
+    use crate::types::{ClientId, TxId};
+    use std::str::FromStr;
+
+    /// Ignores every transaction with a reason no standard handler returns.
+    struct Marker;
+
+    impl TxHandler for Marker {
+        fn decide(&self, _: &TxContext<'_>) -> Result<Decision, IgnoreReason> {
+            Err(IgnoreReason::RejectedByHook)
+        }
+    }
+
+    #[test]
+    fn each_type_is_decided_by_its_own_handler() {
+        let account = AccountDetails::new_with_balance(Amount::from_str("10").unwrap());
+        let deposit = DepositRecord::new(ClientId(1), Amount::from_str("10").unwrap());
+        for replaced in TxType::ALL {
+            let config = EngineConfig {
+                handlers: Handlers::standard().with(replaced, Marker),
+                ..Default::default()
+            };
+            for tx_type in TxType::ALL {
+                let tx = Transaction {
+                    tx_type,
+                    client: ClientId(1),
+                    tx: TxId(1),
+                    amount: Amount::from_str("1").unwrap(),
+                };
+                let decision = crate::engine::decide(&tx, Some(&account), Some(&deposit), &config);
+                assert_eq!(
+                    decision == Decision::Ignore(IgnoreReason::RejectedByHook),
+                    tx_type == replaced,
+                    "{:?} with the handler of {:?} replaced",
+                    tx_type,
+                    replaced
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rules_versions_keep_custom_handlers() {
+        let mut config = EngineConfig {
+            handlers: Handlers::standard().with(TxType::Withdrawal, HalfWithdrawals),
+            ..Default::default()
+        };
+        RulesVersion::V2.apply(&mut config);
+        let mut engine = Engine::new(config);
+        let tx = |tx_type, tx, amount| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let decisions: Vec<_> = [
+            tx(TxType::Deposit, 1, "10"),
+            tx(TxType::Withdrawal, 2, "6"),
+            tx(TxType::Withdrawal, 3, "5"),
+            tx(TxType::Dispute, 1, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap())
+        .collect();
+        assert_eq!(
+            decisions[1..],
+            [
+                Decision::Ignore(IgnoreReason::InsufficientFunds),
+                Decision::Withdraw {
+                    client: ClientId(1),
+                    tx: TxId(3),
+                    amount: Amount::from_str("5").unwrap(),
+                },
+                // v2 holds what the available funds cover.
+                Decision::Hold {
+                    client: ClientId(1),
+                    tx: TxId(1),
+                    amount: Amount::from_str("5").unwrap(),
+                },
+            ]
+        );
+    }
+
+    /// Ignores withdrawals of more than half the available funds.
+    struct HalfWithdrawals;
+
+    impl TxHandler for HalfWithdrawals {
+        fn decide(&self, context: &TxContext<'_>) -> Result<Decision, IgnoreReason> {
+            let account = context.account.ok_or(IgnoreReason::UnknownAccount)?;
+            if context.amount.checked_add(context.amount) > Some(account.available) {
+                return Err(IgnoreReason::InsufficientFunds);
+            }
+            WithdrawalHandler.decide(context)
+        }
+    }
+
+    #[test]
+    fn replaced_handlers_decide_their_type() {
+        let mut engine = Engine::new(EngineConfig {
+            handlers: Handlers::standard().with(TxType::Withdrawal, HalfWithdrawals),
+            ..Default::default()
+        });
+        let tx = |tx_type, tx, amount| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let decisions: Vec<_> = [
+            tx(TxType::Deposit, 1, "10"),
+            tx(TxType::Withdrawal, 2, "6"),
+            tx(TxType::Withdrawal, 3, "5"),
+            tx(TxType::Dispute, 1, "0"),
+        ]
+        .into_iter()
+        .map(|tx| engine.process(tx).unwrap())
+        .collect();
+        assert_eq!(
+            decisions[1..],
+            [
+                Decision::Ignore(IgnoreReason::InsufficientFunds),
+                Decision::Withdraw {
+                    client: ClientId(1),
+                    tx: TxId(3),
+                    amount: Amount::from_str("5").unwrap(),
+                },
+                // The standard dispute handler still requires available funds.
+                Decision::Ignore(IgnoreReason::InsufficientFunds),
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Exposure of held funds (`--exposure-report`).
+//!
+//! [`held_exposure`] sums up the funds held on the final accounts, for treasury
+//! to know how much money is tied up. The number of open disputes comes from
+//! the activity counters of [`AccountMetrics`](crate::types::AccountMetrics), so
+//! the engine has to run with
+//! [`EngineConfig::account_metrics`](crate::engine::EngineConfig::account_metrics)
+//! enabled. The held funds include those held by holds and pending
+//! authorizations, not only by disputes.
+
+use crate::types::{Accounts, Amount, ClientId, sorted_accounts};
+
+/// The held funds of a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientExposure {
+    pub client: ClientId,
+    pub held: Amount,
+    /// Number of deposits of the client that are currently disputed.
+    pub open_disputes: u64,
+}
+
+/// The held funds of all clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exposure {
+    /// The clients with held funds or open disputes, in ascending client order.
+    pub clients: Vec<ClientExposure>,
+    /// The held funds of all clients.
+    pub held: Amount,
+    /// The open disputes of all clients.
+    pub open_disputes: u64,
+}
+
+/// Returns the held funds and open disputes of `accounts`.
+pub fn held_exposure(accounts: &Accounts) -> Exposure {
+    let mut exposure = Exposure::default();
+    for (client, account) in sorted_accounts(accounts) {
+        let open_disputes = account.metrics.active_disputes;
+        if account.held == Amount::ZERO && open_disputes == 0 {
+            continue;
+        }
+        exposure.held = exposure.held.saturating_add(account.held);
+        exposure.open_disputes += open_disputes;
+        exposure.clients.push(ClientExposure {
+            client,
+            held: account.held,
+            open_disputes,
+        });
+    }
+    exposure
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::types::{Transaction, TxId, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn sums_up_held_funds_and_open_disputes() {
+        let mut engine = Engine::new(EngineConfig {
+            account_metrics: true,
+            ..Default::default()
+        });
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        for tx in [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Deposit, 1, 2, "2.5"),
+            tx(TxType::Deposit, 2, 3, "4"),
+            tx(TxType::Deposit, 3, 4, "1"),
+            tx(TxType::Dispute, 1, 1, "0"),
+            tx(TxType::Dispute, 1, 2, "0"),
+            tx(TxType::Dispute, 3, 4, "0"),
+            tx(TxType::Hold, 2, 5, "1.5"),
+            tx(TxType::Chargeback, 3, 4, "0"),
+        ] {
+            engine.process(tx).unwrap();
+        }
+
+        let exposure = held_exposure(engine.accounts());
+        let amount = |value| Amount::from_str(value).unwrap();
+        assert_eq!(
+            exposure.clients,
+            [
+                ClientExposure {
+                    client: ClientId(1),
+                    held: amount("12.5"),
+                    open_disputes: 2,
+                },
+                ClientExposure {
+                    client: ClientId(2),
+                    held: amount("1.5"),
+                    open_disputes: 0,
+                },
+            ]
+        );
+        assert_eq!((exposure.held, exposure.open_disputes), (amount("14"), 2));
+    }
+}
@@ -0,0 +1,227 @@
+//! C interface for embedding the engine in C and C++ programs.
+//!
+//! The functions are declared in `include/diamond_hands.h`. An engine is an
+//! opaque handle created by [`dh_engine_new`] and released by
+//! [`dh_engine_free`]; transactions are applied one CSV record at a time and the
+//! accounts are exported as CSV text into a caller-provided buffer:
+//!
+//! ```c
+//! DhEngine *engine = dh_engine_new();
+//! int status = dh_engine_apply_csv_line(engine, "deposit,1,1,10.0");
+//! if (status < 0) {
+//!     fprintf(stderr, "%s\n", dh_engine_last_error(engine));
+//! }
+//! size_t size = 0;
+//! dh_engine_export_accounts_csv(engine, NULL, 0, &size);
+//! char *csv = malloc(size);
+//! dh_engine_export_accounts_csv(engine, csv, size, &size);
+//! dh_engine_free(engine);
+//! ```
+//!
+//! Every function returns one of the `DH_*` status codes. Negative codes are
+//! errors, whose message [`dh_engine_last_error`] returns.
+
+use std::ffi::{CStr, CString, c_char, c_int};
+
+use crate::embed;
+use crate::engine::{Decision, Engine, EngineConfig};
+use crate::io::{self, OutputOptions};
+
+/// The transaction was applied.
+pub const DH_OK: c_int = 0;
+/// The transaction was valid but ignored by the processing rules, e.g. a
+/// withdrawal exceeding the available funds.
+pub const DH_IGNORED: c_int = 1;
+/// A required pointer argument was null.
+pub const DH_NULL_POINTER: c_int = -1;
+/// A string argument was not valid UTF-8.
+pub const DH_INVALID_UTF8: c_int = -2;
+/// The CSV record could not be parsed.
+pub const DH_PARSE_ERROR: c_int = -3;
+/// The engine failed, e.g. on an overflowing balance.
+pub const DH_ENGINE_ERROR: c_int = -4;
+/// The buffer is too small for the output; the required size was stored.
+pub const DH_BUFFER_TOO_SMALL: c_int = -5;
+
+/// An engine with the message of its last error.
+pub struct DhEngine {
+    engine: Engine,
+    last_error: Option<CString>,
+}
+
+impl DhEngine {
+    /// Stores the message of `err` for [`dh_engine_last_error`] and returns
+    /// `status`.
+    fn fail(&mut self, status: c_int, err: impl std::fmt::Display) -> c_int {
+        let message = format!("{:#}", err).replace('\0', " ");
+        self.last_error = CString::new(message).ok();
+        status
+    }
+}
+
+/// Creates an engine with the default configuration. Release it with
+/// [`dh_engine_free`].
+#[unsafe(no_mangle)]
+pub extern "C" fn dh_engine_new() -> *mut DhEngine {
+    Box::into_raw(Box::new(DhEngine {
+        engine: Engine::new(EngineConfig::default()),
+        last_error: None,
+    }))
+}
+
+/// Releases an engine created by [`dh_engine_new`]. Null is ignored.
+///
+/// # Safety
+///
+/// `engine` must be null or a handle returned by [`dh_engine_new`] that was not
+/// released yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dh_engine_free(engine: *mut DhEngine) {
+    if !engine.is_null() {
+        // SAFETY: The caller passes a live handle from `dh_engine_new`.
+        drop(unsafe { Box::from_raw(engine) });
+    }
+}
+
+/// Applies the transaction of one `type,client,tx,amount` CSV record without a
+/// header line, returning [`DH_OK`], [`DH_IGNORED`] or an error code.
+///
+/// # Safety
+///
+/// `engine` must be a live handle from [`dh_engine_new`] and `line` a
+/// NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dh_engine_apply_csv_line(
+    engine: *mut DhEngine,
+    line: *const c_char,
+) -> c_int {
+    // SAFETY: The caller passes a live handle from `dh_engine_new`.
+    let Some(engine) = (unsafe { engine.as_mut() }) else {
+        return DH_NULL_POINTER;
+    };
+    if line.is_null() {
+        return engine.fail(DH_NULL_POINTER, "line is null");
+    }
+    // SAFETY: The caller passes a NUL-terminated string.
+    let line = match unsafe { CStr::from_ptr(line) }.to_str() {
+        Ok(line) => line,
+        Err(err) => return engine.fail(DH_INVALID_UTF8, err),
+    };
+    let tx = match embed::parse_csv_line(line) {
+        Ok(tx) => tx,
+        Err(err) => return engine.fail(DH_PARSE_ERROR, err),
+    };
+    match engine.engine.process(tx) {
+        Ok(Decision::Ignore(_)) => DH_IGNORED,
+        Ok(_) => DH_OK,
+        Err(err) => engine.fail(DH_ENGINE_ERROR, err),
+    }
+}
+
+/// Writes the accounts as NUL-terminated CSV text, in the format of the
+/// application's output, into `buffer` of `capacity` bytes.
+///
+/// The size of the text including the NUL is stored in `size`. If it exceeds
+/// `capacity`, nothing is written and [`DH_BUFFER_TOO_SMALL`] is returned, so
+/// callers can query the size with a null `buffer` and a `capacity` of zero.
+///
+/// # Safety
+///
+/// `engine` must be a live handle from [`dh_engine_new`], `buffer` null or
+/// valid for writes of `capacity` bytes, and `size` valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dh_engine_export_accounts_csv(
+    engine: *mut DhEngine,
+    buffer: *mut c_char,
+    capacity: usize,
+    size: *mut usize,
+) -> c_int {
+    // SAFETY: The caller passes a live handle from `dh_engine_new`.
+    let Some(engine) = (unsafe { engine.as_mut() }) else {
+        return DH_NULL_POINTER;
+    };
+    if size.is_null() {
+        return engine.fail(DH_NULL_POINTER, "size is null");
+    }
+    let mut csv = Vec::new();
+    if let Err(err) = io::write_accounts_as_csv(
+        engine.engine.accounts().clone(),
+        &mut csv,
+        &OutputOptions::default(),
+    ) {
+        return engine.fail(DH_ENGINE_ERROR, err);
+    }
+    csv.push(0);
+
+    // SAFETY: The caller passes a pointer valid for a write.
+    unsafe { size.write(csv.len()) };
+    if buffer.is_null() || csv.len() > capacity {
+        return engine.fail(
+            DH_BUFFER_TOO_SMALL,
+            format!("{} bytes needed, {} available", csv.len(), capacity),
+        );
+    }
+    // SAFETY: The buffer holds at least `capacity >= csv.len()` bytes.
+    unsafe { std::ptr::copy_nonoverlapping(csv.as_ptr().cast(), buffer, csv.len()) };
+    DH_OK
+}
+
+/// Returns the message of the last error of `engine`, or null if there was
+/// none. The string is valid until the next call with `engine`.
+///
+/// # Safety
+///
+/// `engine` must be null or a live handle from [`dh_engine_new`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dh_engine_last_error(engine: *const DhEngine) -> *const c_char {
+    // SAFETY: The caller passes null or a live handle from `dh_engine_new`.
+    match unsafe { engine.as_ref() }.and_then(|engine| engine.last_error.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_lines_and_exports_accounts() {
+        let engine = dh_engine_new();
+        let apply = |line: &str| {
+            let line = CString::new(line).unwrap();
+            unsafe { dh_engine_apply_csv_line(engine, line.as_ptr()) }
+        };
+        let last_error = || unsafe {
+            CStr::from_ptr(dh_engine_last_error(engine))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(apply("deposit,1,1,10.0"), DH_OK);
+        assert_eq!(apply("withdrawal,1,2,20"), DH_IGNORED);
+        assert_eq!(apply("dispute,1,1"), DH_OK);
+        assert_eq!(apply("send,1,3,1"), DH_PARSE_ERROR);
+        assert!(last_error().contains("send,1,3,1"), "{}", last_error());
+        assert_eq!(
+            unsafe { dh_engine_apply_csv_line(engine, std::ptr::null()) },
+            DH_NULL_POINTER
+        );
+
+        let mut size = 0;
+        let status =
+            unsafe { dh_engine_export_accounts_csv(engine, std::ptr::null_mut(), 0, &mut size) };
+        assert_eq!(status, DH_BUFFER_TOO_SMALL);
+        let mut buffer = vec![1 as c_char; size];
+        let status =
+            unsafe { dh_engine_export_accounts_csv(engine, buffer.as_mut_ptr(), size, &mut size) };
+        assert_eq!(status, DH_OK);
+        assert_eq!(
+            unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_str().unwrap(),
+            "client,available,held,total,locked\n1,0,10,10,false\n"
+        );
+
+        unsafe { dh_engine_free(engine) };
+    }
+}
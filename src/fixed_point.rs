@@ -0,0 +1,320 @@
+//! Fixed-point monetary amounts.
+//!
+//! [`FixedPoint`] stores an amount as an `i64` count of 1/10000 units, which
+//! matches the four decimal places of the input format and makes arithmetic a
+//! plain integer operation. With the `fixed-point` feature enabled it replaces
+//! `rust_decimal::Decimal` as [`Amount`](crate::types::Amount).
+//!
+//! Amounts with more than four decimal places cannot be represented and are
+//! rejected when parsing; the transaction reader rounds or truncates them
+//! beforehand according to its [`PrecisionPolicy`](crate::amount::PrecisionPolicy).
+//!
+//! # Examples
+//!
+//! ```
+//! use project_diamond_hands::amount::ParseAmount;
+//! use project_diamond_hands::fixed_point::FixedPoint;
+//!
+//! let amount = FixedPoint::parse_lenient("12.5").unwrap();
+//! assert_eq!(amount.units(), 125_000);
+//! assert_eq!(amount.to_string(), "12.5");
+//! assert!(FixedPoint::parse_lenient("0.00001").is_err());
+//! ```
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+use std::str::FromStr;
+
+use crate::amount::{AmountOps, AmountParseError, AmountParseErrorKind, ParseAmount};
+
+/// A monetary amount with exactly four decimal places, stored as an `i64`
+/// count of 1/10000 units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedPoint(i64);
+
+impl FixedPoint {
+    /// Number of decimal places.
+    pub const SCALE: u32 = 4;
+    /// Number of units in 1.
+    const UNITS_PER_ONE: i64 = 10_i64.pow(Self::SCALE);
+
+    pub const ZERO: FixedPoint = FixedPoint(0);
+    pub const ONE: FixedPoint = FixedPoint(Self::UNITS_PER_ONE);
+    pub const MAX: FixedPoint = FixedPoint(i64::MAX);
+    pub const MIN: FixedPoint = FixedPoint(i64::MIN);
+
+    /// Creates an amount from a count of 1/10000 units.
+    pub const fn from_units(units: i64) -> Self {
+        FixedPoint(units)
+    }
+
+    /// Returns the amount as a count of 1/10000 units.
+    pub const fn units(self) -> i64 {
+        self.0
+    }
+
+    /// Adds two amounts, returning `None` on overflow.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(FixedPoint)
+    }
+
+    /// Subtracts two amounts, returning `None` on overflow.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(FixedPoint)
+    }
+
+    /// Adds two amounts, saturating at the bounds of the representation.
+    pub fn saturating_add(self, other: Self) -> Self {
+        FixedPoint(self.0.saturating_add(other.0))
+    }
+
+    /// Subtracts two amounts, saturating at the bounds of the representation.
+    pub fn saturating_sub(self, other: Self) -> Self {
+        FixedPoint(self.0.saturating_sub(other.0))
+    }
+}
+
+impl AmountOps for FixedPoint {
+    const ZERO: Self = FixedPoint::ZERO;
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        FixedPoint::checked_add(self, other)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        FixedPoint::checked_sub(self, other)
+    }
+
+    fn saturating_add(self, other: Self) -> Self {
+        FixedPoint::saturating_add(self, other)
+    }
+}
+
+impl Add for FixedPoint {
+    type Output = FixedPoint;
+
+    /// # Panics
+    ///
+    /// Panics on overflow; use [`FixedPoint::checked_add`] to handle it.
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other)
+            .expect("FixedPoint addition overflowed")
+    }
+}
+
+impl Sub for FixedPoint {
+    type Output = FixedPoint;
+
+    /// # Panics
+    ///
+    /// Panics on overflow; use [`FixedPoint::checked_sub`] to handle it.
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other)
+            .expect("FixedPoint subtraction overflowed")
+    }
+}
+
+impl Neg for FixedPoint {
+    type Output = FixedPoint;
+
+    fn neg(self) -> Self {
+        FixedPoint(
+            self.0
+                .checked_neg()
+                .expect("FixedPoint negation overflowed"),
+        )
+    }
+}
+
+/// Formats the amount without trailing zeros, e.g. `12.5`, `3` or `-0.0001`.
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let units = self.0.unsigned_abs();
+        let per_one = Self::UNITS_PER_ONE as u64;
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = units / per_one;
+        let fraction = units % per_one;
+
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, whole);
+        }
+        let digits = format!("{:0width$}", fraction, width = Self::SCALE as usize);
+        write!(f, "{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+    }
+}
+
+impl ParseAmount for FixedPoint {
+    fn parse_lenient(input: &str) -> Result<Self, AmountParseError> {
+        let canonical = crate::amount::canonicalize(input)?;
+        if canonical.is_empty() {
+            return Ok(FixedPoint::ZERO);
+        }
+
+        let (negative, digits) = match canonical.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, canonical.as_str()),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let fraction = fraction.trim_end_matches('0');
+        let out_of_range = || {
+            let offset = input.len() - input.trim_start().len();
+            AmountParseError::new(input, offset, AmountParseErrorKind::OutOfRange)
+        };
+
+        if fraction.len() > Self::SCALE as usize {
+            let point = input.find('.').unwrap_or_default();
+            return Err(AmountParseError::new(
+                input,
+                point + 1 + Self::SCALE as usize,
+                AmountParseErrorKind::TooManyDecimalPlaces,
+            ));
+        }
+
+        let whole: i64 = whole.parse().map_err(|_| out_of_range())?;
+        let fraction: i64 = format!("{:0<width$}", fraction, width = Self::SCALE as usize)
+            .parse()
+            .map_err(|_| out_of_range())?;
+        let units = whole
+            .checked_mul(Self::UNITS_PER_ONE)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or_else(out_of_range)?;
+
+        Ok(FixedPoint(if negative { -units } else { units }))
+    }
+
+    fn parse_lenient_bytes(input: &[u8]) -> Result<Self, AmountParseError> {
+        match parse_plain_units(input) {
+            Some(units) => Ok(FixedPoint(units)),
+            None => Self::parse_lenient(&String::from_utf8_lossy(input)),
+        }
+    }
+}
+
+/// Parses the common case of an already trimmed `[+-]digits[.digits]` amount
+/// with at most four decimal places directly into units.
+///
+/// Returns `None` for anything else so the caller can fall back to the full
+/// parser, which also produces the error messages.
+fn parse_plain_units(input: &[u8]) -> Option<i64> {
+    let (negative, digits) = match input.split_first()? {
+        (b'-', rest) => (true, rest),
+        (b'+', rest) => (false, rest),
+        _ => (false, input),
+    };
+
+    let mut units: i64 = 0;
+    let mut decimals: Option<u32> = None;
+    let mut seen_digit = false;
+    for &byte in digits {
+        match byte {
+            b'0'..=b'9' => {
+                seen_digit = true;
+                units = units.checked_mul(10)?.checked_add(i64::from(byte - b'0'))?;
+                if let Some(decimals) = &mut decimals {
+                    *decimals += 1;
+                }
+            }
+            b'.' if decimals.is_none() => decimals = Some(0),
+            _ => return None,
+        }
+    }
+
+    let decimals = decimals.unwrap_or(0);
+    if !seen_digit || decimals > FixedPoint::SCALE {
+        return None;
+    }
+    let units = units.checked_mul(10_i64.pow(FixedPoint::SCALE - decimals))?;
+    Some(if negative { -units } else { units })
+}
+
+impl FromStr for FixedPoint {
+    type Err = AmountParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        FixedPoint::parse_lenient(input)
+    }
+}
+
+impl Serialize for FixedPoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FixedPoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FixedPointVisitor;
+
+        impl Visitor<'_> for FixedPointVisitor {
+            type Value = FixedPoint;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a decimal number with at most four decimal places")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                FixedPoint::parse_lenient(value).map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_str(FixedPointVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_amounts() {
+        for (input, units, formatted) in [
+            ("12.5", 125_000, "12.5"),
+            ("  +3 ", 30_000, "3"),
+            ("-.0001", -1, "-0.0001"),
+            ("7.", 70_000, "7"),
+            ("10.5000", 105_000, "10.5"),
+            ("1.000000", 10_000, "1"),
+            ("", 0, "0"),
+        ] {
+            let amount = FixedPoint::parse_lenient(input).unwrap();
+            assert_eq!(amount.units(), units, "input {:?}", input);
+            assert_eq!(amount.to_string(), formatted, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn parses_bytes_like_strings() {
+        for input in [
+            "12.5", "-.0001", "+3", "7.", " 1 ", "", "1.23456", "1-2", "-",
+        ] {
+            assert_eq!(
+                FixedPoint::parse_lenient_bytes(input.as_bytes()),
+                FixedPoint::parse_lenient(input),
+                "input {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unrepresentable_amounts() {
+        let err = FixedPoint::parse_lenient("1.23456").unwrap_err();
+        assert_eq!(err.kind(), AmountParseErrorKind::TooManyDecimalPlaces);
+        assert_eq!(err.position(), 6);
+
+        let err = FixedPoint::parse_lenient("9223372036854775807").unwrap_err();
+        assert_eq!(err.kind(), AmountParseErrorKind::OutOfRange);
+    }
+
+    #[test]
+    fn checked_math_detects_overflow() {
+        let one = FixedPoint::ONE;
+        assert_eq!(one.checked_add(one), Some(FixedPoint::from_units(20_000)));
+        assert_eq!(FixedPoint::MAX.checked_add(one), None);
+        assert_eq!(FixedPoint::MIN.checked_sub(one), None);
+        assert_eq!(FixedPoint::MAX.saturating_add(one), FixedPoint::MAX);
+        assert_eq!(one - one - one, -one);
+    }
+}
@@ -0,0 +1,177 @@
+//! Flagging of suspicious account behavior.
+//!
+//! [`flag_suspicious_accounts`] is an analysis pass over the final accounts that
+//! reuses the activity counters of [`AccountMetrics`], so the engine has to run
+//! with [`EngineConfig::account_metrics`](crate::engine::EngineConfig::account_metrics)
+//! enabled. It flags three patterns:
+//!
+//! - [`FraudPattern::RapidCycles`]: withdrawals directly following a deposit,
+//!   as when funds are moved through an account
+//! - [`FraudPattern::ChargebackRatio`]: many deposits ending in a chargeback
+//! - [`FraudPattern::ManyDisputes`]: many disputes from one client
+
+use crate::types::{AccountMetrics, Accounts, ClientId, sorted_accounts};
+
+/// A suspicious pattern in the activity of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FraudPattern {
+    RapidCycles,
+    ChargebackRatio,
+    ManyDisputes,
+}
+
+impl FraudPattern {
+    /// Returns the name used in reports.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FraudPattern::RapidCycles => "rapid_cycles",
+            FraudPattern::ChargebackRatio => "chargeback_ratio",
+            FraudPattern::ManyDisputes => "many_disputes",
+        }
+    }
+}
+
+/// The thresholds at which patterns are flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FraudThresholds {
+    /// Minimum number of deposit-withdrawal cycles.
+    pub cycles: u64,
+    /// Minimum share of deposits charged back, in percent.
+    pub chargeback_percent: u64,
+    /// Minimum number of disputes.
+    pub disputes: u64,
+}
+
+impl Default for FraudThresholds {
+    fn default() -> Self {
+        FraudThresholds {
+            cycles: 3,
+            chargeback_percent: 10,
+            disputes: 5,
+        }
+    }
+}
+
+/// A pattern flagged for an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FraudFlag {
+    pub client: ClientId,
+    pub pattern: FraudPattern,
+    /// The counters that triggered the flag, e.g. `2 chargebacks for 4 deposits`.
+    pub detail: String,
+}
+
+/// Returns the patterns flagged in the metrics of `accounts`, in ascending
+/// client order.
+pub fn flag_suspicious_accounts(
+    accounts: &Accounts,
+    thresholds: &FraudThresholds,
+) -> Vec<FraudFlag> {
+    let mut flags = Vec::new();
+    for (client, account) in sorted_accounts(accounts) {
+        for (pattern, detail) in flag_metrics(&account.metrics, thresholds) {
+            flags.push(FraudFlag {
+                client,
+                pattern,
+                detail,
+            });
+        }
+    }
+    flags
+}
+
+fn flag_metrics(
+    metrics: &AccountMetrics,
+    thresholds: &FraudThresholds,
+) -> Vec<(FraudPattern, String)> {
+    let mut flags = Vec::new();
+    if metrics.deposit_withdrawal_cycles >= thresholds.cycles.max(1) {
+        flags.push((
+            FraudPattern::RapidCycles,
+            format!(
+                "{} deposit-withdrawal cycles",
+                metrics.deposit_withdrawal_cycles
+            ),
+        ));
+    }
+    // A chargeback may follow a deposit made before the metrics were tracked.
+    if metrics.chargebacks > 0
+        && u128::from(metrics.chargebacks) * 100
+            >= u128::from(metrics.deposits) * u128::from(thresholds.chargeback_percent)
+    {
+        flags.push((
+            FraudPattern::ChargebackRatio,
+            format!(
+                "{} chargebacks for {} deposits",
+                metrics.chargebacks, metrics.deposits
+            ),
+        ));
+    }
+    if metrics.disputes >= thresholds.disputes.max(1) {
+        flags.push((
+            FraudPattern::ManyDisputes,
+            format!("{} disputes", metrics.disputes),
+        ));
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::AccountDetails;
+
+    #[test]
+    fn flags_patterns_reaching_their_threshold() {
+        let account = |metrics| AccountDetails {
+            metrics,
+            ..Default::default()
+        };
+        let mut accounts = Accounts::new();
+        accounts.insert(
+            ClientId(2),
+            account(AccountMetrics {
+                deposits: 4,
+                chargebacks: 1,
+                disputes: 5,
+                deposit_withdrawal_cycles: 2,
+                ..Default::default()
+            }),
+        );
+        accounts.insert(
+            ClientId(1),
+            account(AccountMetrics {
+                deposits: 11,
+                chargebacks: 1,
+                deposit_withdrawal_cycles: 3,
+                ..Default::default()
+            }),
+        );
+        accounts.insert(ClientId(3), account(AccountMetrics::default()));
+
+        let flags: Vec<_> = flag_suspicious_accounts(&accounts, &FraudThresholds::default())
+            .into_iter()
+            .map(|flag| (flag.client, flag.pattern, flag.detail))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                (
+                    ClientId(1),
+                    FraudPattern::RapidCycles,
+                    "3 deposit-withdrawal cycles".to_string()
+                ),
+                (
+                    ClientId(2),
+                    FraudPattern::ChargebackRatio,
+                    "1 chargebacks for 4 deposits".to_string()
+                ),
+                (
+                    ClientId(2),
+                    FraudPattern::ManyDisputes,
+                    "5 disputes".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Golden-file tests of the engine.
+//!
+//! A golden case is a directory with an `input.csv` of transactions and an
+//! `expected.csv` with the accounts the engine has to produce from them, as the
+//! application prints them. [`check_case`] processes the input with
+//! [`process_csv`](crate::embed::process_csv) and compares the accounts with the
+//! expected file, failing with a line diff of the two. [`check_cases`] checks
+//! every case in a directory, such as the `tests/golden/` directory of this
+//! crate, so a scenario is added by adding a directory.
+//!
+//! With the environment variable `UPDATE_GOLDEN=1`, the expected files are
+//! written from the actual accounts instead, e.g. for new cases or after an
+//! intended change of the output. The changes then show up in `git diff`.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::embed::process_csv;
+use crate::engine::EngineConfig;
+
+/// File name of the transactions of a case.
+pub const INPUT_FILE: &str = "input.csv";
+/// File name of the expected accounts of a case.
+pub const EXPECTED_FILE: &str = "expected.csv";
+
+/// Checks the golden case in `dir` with an engine configured by `config`, see
+/// the [module documentation](self).
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read or written, the input cannot be
+/// processed or the accounts differ from the expected ones.
+pub fn check_case(dir: impl AsRef<Path>, config: EngineConfig) -> Result<()> {
+    let dir = dir.as_ref();
+    let input_path = dir.join(INPUT_FILE);
+    let expected_path = dir.join(EXPECTED_FILE);
+    let input = fs::read_to_string(&input_path)
+        .with_context(|| format!("Failed to read file: {}", input_path.display()))?;
+    let actual = process_csv(&input, config)
+        .with_context(|| format!("Failed to process file: {}", input_path.display()))?;
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some_and(|value| value == "1") {
+        return fs::write(&expected_path, actual)
+            .with_context(|| format!("Failed to write file: {}", expected_path.display()));
+    }
+    let expected = fs::read_to_string(&expected_path).with_context(|| {
+        format!(
+            "Failed to read file: {} (run with UPDATE_GOLDEN=1 to create it)",
+            expected_path.display()
+        )
+    })?;
+    anyhow::ensure!(
+        normalize_newlines(&expected) == normalize_newlines(&actual),
+        "The accounts differ from {} (- expected, + actual):\n{}",
+        expected_path.display(),
+        diff_lines(&expected, &actual)
+    );
+    Ok(())
+}
+
+/// Checks every golden case in the subdirectories of `dir`, in name order, with
+/// the default engine configuration, and returns the number of cases.
+///
+/// All cases are checked even if some fail.
+///
+/// # Errors
+///
+/// Returns an error listing the failed cases if any fails, or if `dir` cannot
+/// be read.
+pub fn check_cases(dir: impl AsRef<Path>) -> Result<usize> {
+    let dir = dir.as_ref();
+    let mut cases = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory: {}", dir.display()))?
+    {
+        let path = entry
+            .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+            .path();
+        if path.join(INPUT_FILE).is_file() {
+            cases.push(path);
+        }
+    }
+    cases.sort();
+
+    let mut failures = String::new();
+    let mut failed = 0;
+    for case in &cases {
+        if let Err(err) = check_case(case, EngineConfig::default()) {
+            failed += 1;
+            let name = case.file_name().unwrap_or_default().to_string_lossy();
+            let _ = writeln!(failures, "golden case '{}' failed: {:#}\n", name, err);
+        }
+    }
+    anyhow::ensure!(
+        failed == 0,
+        "{} of {} golden case(s) failed:\n\n{}",
+        failed,
+        cases.len(),
+        failures.trim_end()
+    );
+    Ok(cases.len())
+}
+
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n")
+}
+
+/// Returns a line diff turning `expected` into `actual`, with the lines only in
+/// `expected` prefixed by `- `, those only in `actual` by `+ ` and the common
+/// ones by two spaces.
+pub fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+    // `common[i][j]` is the length of the longest common subsequence of the
+    // lines from `expected[i]` and `actual[j]` on.
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            let _ = writeln!(diff, "  {}", expected[i]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == actual.len() || (i < expected.len() && common[i + 1][j] >= common[i][j + 1])
+        {
+            let _ = writeln!(diff, "- {}", expected[i]);
+            i += 1;
+        } else {
+            let _ = writeln!(diff, "+ {}", actual[j]);
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_differing_accounts_as_a_line_diff() {
+        assert_eq!(
+            diff_lines("client\n1,a\n2,b\n3,c\n", "client\n1,a\n2,x\n3,c\n4,d\n"),
+            "  client\n  1,a\n- 2,b\n+ 2,x\n  3,c\n+ 4,d\n"
+        );
+
+        let dir = std::env::temp_dir().join(format!("golden-{}", std::process::id()));
+        let case = dir.join("deposit");
+        fs::create_dir_all(&case).unwrap();
+        fs::write(
+            case.join(INPUT_FILE),
+            "type,client,tx,amount\ndeposit,1,1,2\n",
+        )
+        .unwrap();
+        fs::write(
+            case.join(EXPECTED_FILE),
+            "client,available,held,total,locked\n1,2,0,2,false\n",
+        )
+        .unwrap();
+        assert_eq!(check_cases(&dir).unwrap(), 1);
+
+        fs::write(
+            case.join(EXPECTED_FILE),
+            "client,available,held,total,locked\n1,3,0,3,false\n",
+        )
+        .unwrap();
+        let err = check_cases(&dir).unwrap_err();
+        assert!(
+            format!("{:#}", err).ends_with(
+                "  client,available,held,total,locked\n- 1,3,0,3,false\n+ 1,2,0,2,false"
+            ),
+            "{:#}",
+            err
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
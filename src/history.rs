@@ -0,0 +1,124 @@
+//! Per-client transaction history.
+//!
+//! [`History`] collects every applied transaction together with the balances of
+//! the client's account right after it was applied. It is the source for
+//! customer statements, which need the effect of each transaction rather than
+//! only the final account state.
+
+use std::collections::BTreeMap;
+
+use crate::engine::Decision;
+use crate::types::{Accounts, Amount, ClientId, TxId, TxType};
+
+/// An applied transaction and the resulting state of the client's account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub tx: TxId,
+    pub tx_type: TxType,
+    /// Funds moved by the transaction (the held amount for disputes, resolves and chargebacks).
+    pub amount: Amount,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// Applied transactions grouped per client, in processing order.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: BTreeMap<ClientId, Vec<HistoryEntry>>,
+}
+
+impl History {
+    /// Records a transaction of type `tx_type` after `decision` was applied to
+    /// `accounts`. Ignored transactions are not recorded.
+    pub fn record(&mut self, tx_type: TxType, decision: &Decision, accounts: &Accounts) {
+        let (Some(client), Some(tx)) = (decision.client(), decision.tx()) else {
+            return;
+        };
+        let Some(account) = accounts.get(&client) else {
+            return;
+        };
+
+        self.entries.entry(client).or_default().push(HistoryEntry {
+            tx,
+            tx_type,
+            amount: decision.amount(),
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+        });
+    }
+
+    /// Returns the recorded entries of `client`, oldest first.
+    pub fn client(&self, client: ClientId) -> &[HistoryEntry] {
+        self.entries.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns an iterator over all clients with at least one entry, in ascending
+    /// client order.
+    pub fn clients(&self) -> impl Iterator<Item = (ClientId, &[HistoryEntry])> {
+        self.entries
+            .iter()
+            .map(|(client, entries)| (*client, entries.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::types::Transaction;
+    use std::str::FromStr;
+
+    #[test]
+    fn records_applied_transactions_with_resulting_balances() {
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Withdrawal,
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("50.0").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(2),
+                tx: TxId(3),
+                amount: Amount::from_str("1.0").unwrap(),
+            },
+        ];
+
+        let mut engine = Engine::new(EngineConfig::default());
+        let mut history = History::default();
+        for tx in transactions {
+            let tx_type = tx.tx_type;
+            let decision = engine.process(tx).unwrap();
+            history.record(tx_type, &decision, engine.accounts());
+        }
+
+        let entries = history.client(ClientId(1));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tx_type, TxType::Deposit);
+        assert_eq!(entries[0].available, Amount::from_str("10.0").unwrap());
+        assert_eq!(entries[1].tx, TxId(1));
+        assert_eq!(entries[1].tx_type, TxType::Dispute);
+        assert_eq!(entries[1].amount, Amount::from_str("10.0").unwrap());
+        assert_eq!(entries[1].available, Amount::ZERO);
+        assert_eq!(entries[1].held, Amount::from_str("10.0").unwrap());
+        assert_eq!(history.clients().count(), 2);
+        assert!(history.client(ClientId(3)).is_empty());
+    }
+}
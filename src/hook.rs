@@ -0,0 +1,172 @@
+//! Hooks for custom transaction validation.
+//!
+//! A [`TransactionHook`] registered with
+//! [`Engine::add_hook`](crate::engine::Engine::add_hook) sees every transaction
+//! the processing rules would apply, together with the client's account, and may
+//! veto it. This lets library users implement bespoke compliance rules, such as
+//! sanctions screening, without patching the engine:
+//!
+//! ```
+//! use project_diamond_hands::engine::{Decision, Engine, IgnoreReason};
+//! use project_diamond_hands::hook::{HookDecision, TransactionHook};
+//! use project_diamond_hands::types::{AccountDetails, Amount, ClientId, Transaction, TxId, TxType};
+//! use std::collections::HashSet;
+//!
+//! struct Sanctions(HashSet<ClientId>);
+//!
+//! impl TransactionHook for Sanctions {
+//!     fn before_apply(&self, tx: &Transaction, _account: &AccountDetails) -> HookDecision {
+//!         if self.0.contains(&tx.client) {
+//!             HookDecision::Reject
+//!         } else {
+//!             HookDecision::Allow
+//!         }
+//!     }
+//! }
+//!
+//! let mut engine = Engine::default();
+//! engine.add_hook(Sanctions(HashSet::from([ClientId(7)])));
+//! let deposit = Transaction {
+//!     tx_type: TxType::Deposit,
+//!     client: ClientId(7),
+//!     tx: TxId(1),
+//!     amount: Amount::ONE,
+//! };
+//! assert_eq!(
+//!     engine.process(deposit).unwrap(),
+//!     Decision::Ignore(IgnoreReason::RejectedByHook)
+//! );
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::types::{AccountDetails, Amount, Transaction};
+
+/// The verdict of a [`TransactionHook`] on a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookDecision {
+    /// Let the engine apply the transaction.
+    Allow,
+    /// Ignore the transaction with
+    /// [`IgnoreReason::RejectedByHook`](crate::engine::IgnoreReason::RejectedByHook).
+    Reject,
+}
+
+/// Custom validation run before the engine applies a transaction.
+///
+/// Hooks are only consulted for transactions the processing rules would apply;
+/// transactions the engine ignores anyway never reach them. Hooks run in the
+/// order they were added, and the first rejection wins. Hooks that keep state
+/// across calls need interior mutability.
+pub trait TransactionHook<A = Amount>: Send {
+    /// Decides whether `tx` may be applied to `account`, the client's current
+    /// account. For a deposit opening a new account, `account` is empty.
+    fn before_apply(&self, tx: &Transaction<A>, account: &AccountDetails<A>) -> HookDecision;
+}
+
+/// A shared hook, so the caller can keep a handle to read state the hook
+/// collects, such as the violations of a [`RuleSet`](crate::rules::RuleSet).
+impl<A, H: TransactionHook<A> + Sync + ?Sized> TransactionHook<A> for Arc<H> {
+    fn before_apply(&self, tx: &Transaction<A>, account: &AccountDetails<A>) -> HookDecision {
+        (**self).before_apply(tx, account)
+    }
+}
+
+impl<A> fmt::Debug for dyn TransactionHook<A> + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TransactionHook")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Decision, Engine, IgnoreReason};
+    use crate::types::{ClientId, RawTxId, TxId, TxType};
+    use std::str::FromStr;
+    use std::sync::Mutex;
+
+    /// Records the transactions it sees with the available balance, and
+    /// rejects the transaction `reject`.
+    #[derive(Default)]
+    struct Recorder {
+        seen: Mutex<Vec<(TxId, Amount)>>,
+        reject: Option<TxId>,
+    }
+
+    impl TransactionHook for Recorder {
+        fn before_apply(&self, tx: &Transaction, account: &AccountDetails) -> HookDecision {
+            self.seen.lock().unwrap().push((tx.tx, account.available));
+            match self.reject == Some(tx.tx) {
+                true => HookDecision::Reject,
+                false => HookDecision::Allow,
+            }
+        }
+    }
+
+    fn deposit(tx: RawTxId, amount: &str) -> Transaction {
+        Transaction {
+            tx_type: TxType::Deposit,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        }
+    }
+
+    #[test]
+    fn hooks_see_the_current_account() {
+        let recorder = Arc::new(Recorder::default());
+        let mut engine = Engine::default();
+        engine.add_hook(Arc::clone(&recorder));
+        engine.process(deposit(1, "10")).unwrap();
+        engine.process(deposit(2, "2.5")).unwrap();
+
+        // The first deposit opens the account, so the hook gets an empty one.
+        let amount = |value| Amount::from_str(value).unwrap();
+        assert_eq!(
+            *recorder.seen.lock().unwrap(),
+            [(TxId(1), Amount::ZERO), (TxId(2), amount("10"))]
+        );
+    }
+
+    #[test]
+    fn first_rejecting_hook_wins() {
+        let first = Arc::new(Recorder {
+            reject: Some(TxId(2)),
+            ..Default::default()
+        });
+        let second = Arc::new(Recorder::default());
+        let mut engine = Engine::default();
+        engine.add_hook(Arc::clone(&first));
+        engine.add_hook(Arc::clone(&second));
+
+        engine.process(deposit(1, "10")).unwrap();
+        assert_eq!(
+            engine.process(deposit(2, "5")).unwrap(),
+            Decision::Ignore(IgnoreReason::RejectedByHook)
+        );
+        engine.process(deposit(3, "1")).unwrap();
+
+        // The second hook is not asked about the rejected deposit, which is
+        // counted like any other ignored transaction.
+        let seen = |hook: &Recorder| -> Vec<TxId> {
+            hook.seen
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(tx, _)| *tx)
+                .collect()
+        };
+        assert_eq!(seen(&first), [TxId(1), TxId(2), TxId(3)]);
+        assert_eq!(seen(&second), [TxId(1), TxId(3)]);
+        assert_eq!(
+            engine.stats().ignored_by_reason[&IgnoreReason::RejectedByHook],
+            1
+        );
+        assert_eq!(
+            engine.accounts()[&ClientId(1)].total,
+            Amount::from_str("11").unwrap()
+        );
+    }
+}
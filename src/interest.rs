@@ -0,0 +1,316 @@
+//! Interest accrual on available balances.
+//!
+//! A [`RateSchedule`] lists annual interest rates in percent and the dates from
+//! which they apply. [`InterestAccrual`] follows the dates of the processed
+//! transactions: for every day, each account with a positive available balance
+//! accrues `available * rate / 100 / 365`. At the end of each month, the
+//! interest accrued during the month is posted as an `interest` transaction,
+//! which the engine credits to the available and total balance:
+//!
+//! ```
+//! use project_diamond_hands::engine::Engine;
+//! use project_diamond_hands::interest::{InterestAccrual, RateSchedule};
+//! use project_diamond_hands::types::{Amount, ClientId, Date, Transaction, TxId, TxType};
+//! use std::str::FromStr;
+//!
+//! let date = |text: &str| Date::from_str(text).unwrap();
+//! let rates = RateSchedule::new(vec![(date("2024-01-01"), "3.65".parse().unwrap())]);
+//! let mut accrual = InterestAccrual::new(rates);
+//! let mut engine = Engine::default();
+//!
+//! accrual.advance_to(date("2024-01-01"), engine.accounts());
+//! engine
+//!     .process(Transaction {
+//!         tx_type: TxType::Deposit,
+//!         client: ClientId(1),
+//!         tx: TxId(1),
+//!         amount: Amount::from_str("1000").unwrap(),
+//!     })
+//!     .unwrap();
+//! for entry in accrual.advance_to(date("2024-02-01"), engine.accounts()) {
+//!     engine.process(entry).unwrap();
+//! }
+//! // 31 days at 0.01% per day.
+//! assert_eq!(
+//!     engine.accounts()[&ClientId(1)].total,
+//!     Amount::from_str("1003.1").unwrap()
+//! );
+//! ```
+//!
+//! Posted amounts are rounded down to four decimal places; the remainder
+//! carries over to the next month. Interest entries do not reference another
+//! transaction, so their `tx` only numbers the postings of the run, starting
+//! at 1. Locked and closed accounts do not accrue interest.
+
+use anyhow::{Context, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::amount::MAX_DECIMAL_PLACES;
+use crate::io::{Delimiter, open_csv_file};
+use crate::types::{Accounts, Amount, ClientId, Date, RawTxId, Transaction, TxId, TxType};
+
+/// Annual interest rates in percent by the date from which they apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateSchedule {
+    rates: BTreeMap<Date, Decimal>,
+}
+
+impl RateSchedule {
+    /// Creates a schedule from `(date, rate)` pairs. A rate applies from its
+    /// date until the next one; before the first date the rate is zero.
+    pub fn new(rates: impl IntoIterator<Item = (Date, Decimal)>) -> Self {
+        RateSchedule {
+            rates: rates.into_iter().collect(),
+        }
+    }
+
+    /// Returns the annual rate in percent that applies on `date`.
+    pub fn rate_on(&self, date: Date) -> Decimal {
+        self.rates
+            .range(..=date)
+            .next_back()
+            .map_or(Decimal::ZERO, |(_, rate)| *rate)
+    }
+
+    /// Returns the first date after `date` on which the rate changes.
+    fn next_change_after(&self, date: Date) -> Option<Date> {
+        use std::ops::Bound::{Excluded, Unbounded};
+
+        self.rates
+            .range((Excluded(date), Unbounded))
+            .next()
+            .map(|(date, _)| *date)
+    }
+}
+
+/// Reads a rate schedule from a CSV file with `date,rate` columns, where `date`
+/// is written `YYYY-MM-DD` and `rate` is the annual rate in percent.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or a row fails to parse.
+pub fn read_rate_schedule_file(path: &str) -> Result<RateSchedule> {
+    let (file, delimiter) = open_csv_file(path, Delimiter::Auto)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .from_reader(file);
+
+    let mut rates = Vec::new();
+    for (index, result) in reader.records().enumerate() {
+        let rate = result.map_err(anyhow::Error::from).and_then(|record| {
+            let (Some(date), Some(rate), None) = (record.get(0), record.get(1), record.get(2))
+            else {
+                anyhow::bail!("expected the columns date and rate");
+            };
+            let date = date.parse::<Date>().map_err(anyhow::Error::msg)?;
+            let rate = rate
+                .parse::<Decimal>()
+                .with_context(|| format!("invalid rate '{}'", rate))?;
+            Ok((date, rate))
+        });
+        rates.push(rate.with_context(|| {
+            format!("Failed to parse rate at line {} from: {}", index + 2, path)
+        })?);
+    }
+    Ok(RateSchedule::new(rates))
+}
+
+/// Accrues interest on available balances as the processing date advances.
+#[derive(Debug, Clone)]
+pub struct InterestAccrual {
+    rates: RateSchedule,
+    /// The day up to which (exclusive) interest has been accrued, once known.
+    accrued_until: Option<Date>,
+    /// Interest accrued and not posted yet, by client.
+    accrued: BTreeMap<ClientId, Decimal>,
+    /// Number of interest entries posted so far.
+    posted: RawTxId,
+}
+
+impl InterestAccrual {
+    /// Creates an accrual following `rates`.
+    pub fn new(rates: RateSchedule) -> Self {
+        InterestAccrual {
+            rates,
+            accrued_until: None,
+            accrued: BTreeMap::new(),
+            posted: 0,
+        }
+    }
+
+    /// Returns the day up to which interest has been accrued, or `None` before
+    /// the first call to [`advance_to`](Self::advance_to).
+    pub fn accrued_until(&self) -> Option<Date> {
+        self.accrued_until
+    }
+
+    /// Accrues interest on the available balances of `accounts` for the days
+    /// before `date`, and returns the interest entries of the months that ended
+    /// on the way, ordered by month and client.
+    ///
+    /// Call it with the date of each transaction before processing it, so that
+    /// balances are taken as they were on the accrued days. The first call only
+    /// starts the accrual; dates before the current one are ignored.
+    pub fn advance_to(&mut self, date: Date, accounts: &Accounts) -> Vec<Transaction> {
+        let Some(mut day) = self.accrued_until else {
+            self.accrued_until = Some(date);
+            return Vec::new();
+        };
+        let mut entries = Vec::new();
+        // Interest posted during this call, which the accounts do not hold yet.
+        let mut unapplied = HashMap::new();
+        while day < date {
+            let month_end = first_of_next_month(day);
+            let until = [Some(date), month_end, self.rates.next_change_after(day)]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(date);
+            self.accrue(
+                accounts,
+                &unapplied,
+                self.rates.rate_on(day),
+                until.days_since(day),
+            );
+            if Some(until) == month_end {
+                let posted = entries.len();
+                self.post(&mut entries);
+                for entry in &entries[posted..] {
+                    *unapplied.entry(entry.client).or_default() += to_decimal(entry.amount);
+                }
+            }
+            day = until;
+        }
+        self.accrued_until = Some(day);
+        entries
+    }
+
+    /// Accrues `days` days of interest at the annual `rate` in percent, on the
+    /// balances of `accounts` plus the interest posted but not applied yet.
+    fn accrue(
+        &mut self,
+        accounts: &Accounts,
+        unapplied: &HashMap<ClientId, Decimal>,
+        rate: Decimal,
+        days: i64,
+    ) {
+        if rate <= Decimal::ZERO {
+            return;
+        }
+        let factor = rate * Decimal::from(days) / Decimal::from(36500);
+        for (client, account) in accounts {
+            if account.locked || account.closed {
+                continue;
+            }
+            let available =
+                to_decimal(account.available) + unapplied.get(client).copied().unwrap_or_default();
+            if available > Decimal::ZERO {
+                *self.accrued.entry(*client).or_default() += available * factor;
+            }
+        }
+    }
+
+    /// Appends an entry for every client with interest to post.
+    fn post(&mut self, entries: &mut Vec<Transaction>) {
+        for (client, accrued) in &mut self.accrued {
+            let posted =
+                accrued.round_dp_with_strategy(MAX_DECIMAL_PLACES, RoundingStrategy::ToZero);
+            let Ok(amount) = posted.normalize().to_string().parse::<Amount>() else {
+                continue;
+            };
+            if amount <= Amount::ZERO {
+                continue;
+            }
+            *accrued -= posted;
+            self.posted += 1;
+            entries.push(Transaction {
+                tx_type: TxType::Interest,
+                client: *client,
+                tx: TxId(self.posted),
+                amount,
+            });
+        }
+    }
+}
+
+/// Returns the first day of the month after the month of `date`, or `None`
+/// after the year 9999.
+fn first_of_next_month(date: Date) -> Option<Date> {
+    match date.month() {
+        12 => Date::new(date.year().checked_add(1)?, 1, 1).filter(|date| date.year() <= 9999),
+        month => Date::new(date.year(), month + 1, 1),
+    }
+}
+
+/// Converts an amount into a decimal for interest calculations.
+fn to_decimal(amount: Amount) -> Decimal {
+    amount.to_string().parse().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountDetails, RawClientId};
+    use std::str::FromStr;
+
+    fn date(text: &str) -> Date {
+        text.parse().unwrap()
+    }
+
+    fn accounts(balances: &[(RawClientId, &str)]) -> Accounts {
+        balances
+            .iter()
+            .map(|(client, balance)| {
+                let balance = Amount::from_str(balance).unwrap();
+                (ClientId(*client), AccountDetails::new_with_balance(balance))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_days_across_months_and_years() {
+        assert_eq!(date("2024-03-01").days_since(date("2024-02-28")), 2);
+        assert_eq!(date("2025-01-01").days_since(date("2024-01-01")), 366);
+        assert_eq!(date("2024-01-01").days_since(date("2024-01-31")), -30);
+        for days in [0, 1, 59, 365, 1000] {
+            let start = date("2023-12-15");
+            let later = start.add_days(days).unwrap();
+            assert_eq!(later.days_since(start), i64::from(days));
+        }
+        assert_eq!(date("9999-12-31").add_days(1), None);
+    }
+
+    #[test]
+    fn posts_interest_at_month_ends_following_rate_changes() {
+        let rates = RateSchedule::new([
+            (date("2024-01-01"), Decimal::from_str("3.65").unwrap()),
+            (date("2024-01-21"), Decimal::from_str("7.3").unwrap()),
+        ]);
+        assert_eq!(rates.rate_on(date("2023-12-31")), Decimal::ZERO);
+        let mut accrual = InterestAccrual::new(rates);
+        let accounts = accounts(&[(1, "1000"), (2, "-5"), (3, "0.5")]);
+
+        assert!(accrual.advance_to(date("2024-01-01"), &accounts).is_empty());
+        assert!(accrual.advance_to(date("2024-01-31"), &accounts).is_empty());
+        let entries: Vec<_> = accrual
+            .advance_to(date("2024-03-15"), &accounts)
+            .into_iter()
+            .map(|tx| (tx.client, tx.tx, tx.amount.to_string()))
+            .collect();
+        // January: 20 days at 0.01% and 11 days at 0.02% per day; February: 29
+        // days at 0.02%, including on the interest of January. Negative balances
+        // do not accrue.
+        assert_eq!(
+            entries,
+            [
+                (ClientId(1), TxId(1), "4.2".to_string()),
+                (ClientId(3), TxId(2), "0.0021".to_string()),
+                (ClientId(1), TxId(3), "5.8243".to_string()),
+                (ClientId(3), TxId(4), "0.0029".to_string()),
+            ]
+        );
+        assert_eq!(accrual.accrued_until(), Some(date("2024-03-15")));
+    }
+}
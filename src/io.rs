@@ -3,38 +3,793 @@
 //! This module provides functions for reading transaction data from CSV files
 //! and writing account details to standard output in CSV format.
 
+pub mod enrichment;
+pub mod filter;
+pub mod prefix;
+
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
 use std::io;
+use std::sync::{Arc, OnceLock};
 
+use self::enrichment::Enrichment;
+use crate::aging::{AgeThreshold, AgedDispute};
+use crate::amount::{ParseAmount, PrecisionPolicy, deserialize_amount_or_zero};
+use crate::diff::AccountDelta;
+use crate::engine::{ClientProfile, ClosedPeriod, RejectedTransaction};
+use crate::exposure::Exposure;
+use crate::fraud::FraudFlag;
+use crate::history::History;
+use crate::manifest::RunManifest;
+use crate::recurring::Recurrence;
+use crate::rules::TriggeredRule;
+use crate::stats::StatsReport;
+use crate::types::AccountDetails;
 use crate::types::Accounts;
+use crate::types::Amount;
+use crate::types::ClientId;
+use crate::types::Date;
 use crate::types::Transaction;
+use crate::types::TxId;
+use crate::types::TxType;
+use crate::types::sorted_accounts;
 
 /// An iterator over transactions from a CSV file.
 ///
 /// This struct owns the CSV reader and file, allowing transactions to be streamed
 /// one at a time without loading the entire file into memory.
+///
+/// By default records are deserialized with serde. [`TransactionReader::fast_parse`]
+/// switches to a path that parses the fields directly from bytes, avoiding
+/// per-field allocations. Both paths apply the [`PrecisionPolicy`] to the amount
+/// before parsing it.
 pub struct TransactionReader {
     reader: csv::Reader<File>,
     path: String,
-    line_num: usize,
+    fast_parse: bool,
+    precision: PrecisionPolicy,
+    strict: bool,
+    aliases: Vec<(String, TransactionColumn)>,
+    delimiter: u8,
+    record: csv::ByteRecord,
+    /// Position where the csv reader started reading the most recent record.
+    record_start: csv::Position,
+    /// Byte range of the most recently read record within the file.
+    record_span: std::ops::Range<u64>,
+    /// A second handle of the file for [`TransactionReader::raw_record`], opened
+    /// on first use and kept for all later records.
+    raw_file: OnceLock<File>,
+    columns: Option<TransactionColumns>,
+    /// The header row with recognized columns renamed to their canonical names,
+    /// used to deserialize records with serde.
+    headers: csv::ByteRecord,
+    parallel_parse: bool,
+    /// Records parsed in parallel that have not been returned yet.
+    parsed: VecDeque<ChunkRecord<Result<DatedTransaction>>>,
+    /// Raw records read while the previous chunk was being parsed.
+    read_ahead: Vec<ChunkRecord<Result<csv::ByteRecord>>>,
+    /// Effective date of the most recently read transaction.
+    effective_date: Option<Date>,
+    /// Recurrence of the most recently read transaction.
+    recurrence: Option<Recurrence>,
+    /// Number of records still to skip before the first one is returned.
+    skip_rows: u64,
+    /// Maximum number of records to read after the skipped ones.
+    limit_rows: Option<u64>,
+    /// Number of records read after the skipped ones.
+    rows_read: u64,
 }
 
-impl Iterator for TransactionReader {
-    type Item = Result<Transaction, anyhow::Error>;
+/// A parsed transaction with its optional `effective_date`, `interval` and
+/// `count` columns.
+struct DatedTransaction {
+    tx: Transaction,
+    effective_date: Option<Date>,
+    recurrence: Option<Recurrence>,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.reader.deserialize().next().map(|result| {
-            self.line_num += 1;
-            result.with_context(|| {
+/// Number of records parsed per chunk with [`TransactionReader::parallel_parse`].
+const PARALLEL_CHUNK_SIZE: usize = 4096;
+
+/// A record of a chunk read for parallel parsing, with its location in the file.
+struct ChunkRecord<T> {
+    start: csv::Position,
+    span: std::ops::Range<u64>,
+    value: T,
+}
+
+/// A column of the transaction input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionColumn {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    EffectiveDate,
+    Interval,
+    Count,
+}
+
+impl TransactionColumn {
+    const ALL: [TransactionColumn; 7] = [
+        TransactionColumn::Type,
+        TransactionColumn::Client,
+        TransactionColumn::Tx,
+        TransactionColumn::Amount,
+        TransactionColumn::EffectiveDate,
+        TransactionColumn::Interval,
+        TransactionColumn::Count,
+    ];
+
+    /// Returns the canonical header name of the column.
+    pub fn name(self) -> &'static str {
+        match self {
+            TransactionColumn::Type => "type",
+            TransactionColumn::Client => "client",
+            TransactionColumn::Tx => "tx",
+            TransactionColumn::Amount => "amount",
+            TransactionColumn::EffectiveDate => "effective_date",
+            TransactionColumn::Interval => "interval",
+            TransactionColumn::Count => "count",
+        }
+    }
+
+    /// Header names recognized without a user-supplied alias, in the form
+    /// returned by [`normalize_header`].
+    fn builtin_names(self) -> &'static [&'static str] {
+        match self {
+            TransactionColumn::Type => &["type", "txtype", "transactiontype"],
+            TransactionColumn::Client => &["client", "clientid"],
+            TransactionColumn::Tx => &["tx", "txid", "transaction", "transactionid"],
+            TransactionColumn::Amount => &["amount"],
+            TransactionColumn::EffectiveDate => &["effectivedate", "valuedate"],
+            TransactionColumn::Interval => &["interval", "intervaldays"],
+            TransactionColumn::Count => &["count", "occurrences"],
+        }
+    }
+}
+
+impl std::str::FromStr for TransactionColumn {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        TransactionColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| {
                 format!(
-                    "Failed to parse record at line {} from: {}",
-                    self.line_num + 1,
-                    self.path
+                    "unknown column '{}', expected type, client, tx, amount, effective_date, \
+                     interval or count",
+                    name
                 )
             })
+    }
+}
+
+/// Normalizes a header name for matching: ASCII letters are lowercased and
+/// whitespace, `_` and `-` are ignored, so `Client ID` matches `client_id`.
+fn normalize_header(name: &[u8]) -> String {
+    String::from_utf8_lossy(name)
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Positions of the transaction columns within a record.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TransactionColumns {
+    tx_type: usize,
+    client: usize,
+    tx: usize,
+    pub(crate) amount: Option<usize>,
+    effective_date: Option<usize>,
+    interval: Option<usize>,
+    count: Option<usize>,
+}
+
+impl TransactionColumns {
+    /// Locates the columns in the header row.
+    ///
+    /// Headers are matched after [`normalize_header`], first against the
+    /// user-supplied `aliases` and then against the built-in names. Headers that
+    /// match no column are ignored.
+    pub(crate) fn from_headers(
+        headers: &csv::ByteRecord,
+        aliases: &[(String, TransactionColumn)],
+    ) -> Result<Self> {
+        let mut positions = [None; TransactionColumn::ALL.len()];
+        for (index, header) in headers.iter().enumerate() {
+            let name = normalize_header(header);
+            let column = aliases
+                .iter()
+                .find(|(alias, _)| normalize_header(alias.as_bytes()) == name)
+                .map(|(_, column)| *column)
+                .or_else(|| {
+                    TransactionColumn::ALL
+                        .into_iter()
+                        .find(|column| column.builtin_names().contains(&name.as_str()))
+                });
+            let Some(column) = column else {
+                continue;
+            };
+            if let Some(previous) = positions[column as usize].replace(index) {
+                anyhow::bail!(
+                    "Columns '{}' and '{}' both map to column '{}'",
+                    String::from_utf8_lossy(&headers[previous]),
+                    String::from_utf8_lossy(header),
+                    column.name()
+                );
+            }
+        }
+
+        let required = |column: TransactionColumn| {
+            positions[column as usize]
+                .with_context(|| format!("Missing column '{}' in header", column.name()))
+        };
+        Ok(TransactionColumns {
+            tx_type: required(TransactionColumn::Type)?,
+            client: required(TransactionColumn::Client)?,
+            tx: required(TransactionColumn::Tx)?,
+            amount: positions[TransactionColumn::Amount as usize],
+            effective_date: positions[TransactionColumn::EffectiveDate as usize],
+            interval: positions[TransactionColumn::Interval as usize],
+            count: positions[TransactionColumn::Count as usize],
+        })
+    }
+
+    /// Returns the column at `index`, if it is one of the transaction columns.
+    fn column_at(&self, index: usize) -> Option<TransactionColumn> {
+        if index == self.tx_type {
+            Some(TransactionColumn::Type)
+        } else if index == self.client {
+            Some(TransactionColumn::Client)
+        } else if index == self.tx {
+            Some(TransactionColumn::Tx)
+        } else if self.amount == Some(index) {
+            Some(TransactionColumn::Amount)
+        } else if self.effective_date == Some(index) {
+            Some(TransactionColumn::EffectiveDate)
+        } else if self.interval == Some(index) {
+            Some(TransactionColumn::Interval)
+        } else if self.count == Some(index) {
+            Some(TransactionColumn::Count)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `headers` with the located columns renamed to their canonical names.
+    fn canonical_headers(&self, headers: &csv::ByteRecord) -> csv::ByteRecord {
+        headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| match self.column_at(index) {
+                Some(column) => column.name().as_bytes(),
+                None => header,
+            })
+            .collect()
+    }
+}
+
+impl TransactionReader {
+    /// Sets whether records are parsed with the allocation-free fast path instead
+    /// of serde. Both paths accept the same input and produce the same transactions.
+    pub fn fast_parse(mut self, fast_parse: bool) -> Self {
+        self.fast_parse = fast_parse;
+        self
+    }
+
+    /// Sets whether records are parsed on the rayon thread pool.
+    ///
+    /// Raw records are read in chunks on the calling thread, which reads the
+    /// next chunk while the pool parses the current one. Transactions and errors
+    /// are returned in file order, exactly as without parallel parsing.
+    pub fn parallel_parse(mut self, parallel_parse: bool) -> Self {
+        self.parallel_parse = parallel_parse;
+        self
+    }
+
+    /// Sets how amounts with more than four decimal places are handled.
+    /// Rejected amounts fail with an error naming the line.
+    pub fn precision(mut self, precision: PrecisionPolicy) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Sets whether records with a negative amount are rejected with an error
+    /// naming the line. Otherwise they are passed on and the engine ignores them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Adds a header name that is read as `column`, in addition to the built-in
+    /// names. Like those, the alias is matched ignoring case, whitespace, `_`
+    /// and `-`.
+    pub fn column_alias(mut self, alias: impl Into<String>, column: TransactionColumn) -> Self {
+        self.aliases.push((alias.into(), column));
+        self
+    }
+
+    /// Skips the first `rows` records after the header without parsing them.
+    /// Errors still name the line of a record within the whole file.
+    pub fn skip_rows(mut self, rows: u64) -> Self {
+        self.skip_rows = rows;
+        self
+    }
+
+    /// Stops after `rows` records following the skipped ones, or reads to the
+    /// end of the file with `None`.
+    pub fn limit_rows(mut self, rows: Option<u64>) -> Self {
+        self.limit_rows = rows;
+        self
+    }
+
+    /// Reads past the records to skip, returning whether the file has more.
+    fn skip_leading_rows(&mut self) -> Result<bool> {
+        while self.skip_rows > 0 {
+            self.skip_rows -= 1;
+            self.record_start = self.reader.position().clone();
+            if !self.reader.read_byte_record(&mut self.record)? {
+                self.skip_rows = 0;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Locates the columns in the header row on first use.
+    fn columns(&mut self) -> Result<TransactionColumns> {
+        if let Some(columns) = self.columns {
+            return Ok(columns);
+        }
+        let headers = self.reader.byte_headers()?;
+        let columns = TransactionColumns::from_headers(headers, &self.aliases)?;
+        self.headers = columns.canonical_headers(headers);
+        Ok(*self.columns.insert(columns))
+    }
+
+    /// Reads and parses the next record, remembering its effective date and
+    /// recurrence.
+    fn next_record(&mut self) -> Option<Result<Transaction>> {
+        self.effective_date = None;
+        self.recurrence = None;
+        let dated = match self.next_dated_record()? {
+            Ok(dated) => dated,
+            Err(err) => return Some(Err(err)),
+        };
+        self.effective_date = dated.effective_date;
+        self.recurrence = dated.recurrence;
+        Some(Ok(dated.tx))
+    }
+
+    /// Reads and parses the next record together with its effective date and
+    /// recurrence.
+    fn next_dated_record(&mut self) -> Option<Result<DatedTransaction>> {
+        let columns = match self.columns() {
+            Ok(columns) => columns,
+            Err(err) => return Some(Err(err)),
+        };
+        match self.skip_leading_rows() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => return Some(Err(err)),
+        }
+        if self.limit_rows.is_some_and(|limit| self.rows_read >= limit) {
+            return None;
+        }
+        self.rows_read += 1;
+
+        if self.parallel_parse {
+            return self.next_parallel_record(columns);
+        }
+
+        self.record_start = self.reader.position().clone();
+        let read = self.reader.read_byte_record(&mut self.record);
+        self.record_span = self.record_start.byte()..self.reader.position().byte();
+        match read {
+            Ok(false) => None,
+            Ok(true) => Some(
+                RecordParser {
+                    columns,
+                    headers: &self.headers,
+                    fast_parse: self.fast_parse,
+                    precision: self.precision,
+                    strict: self.strict,
+                }
+                .parse(&mut self.record),
+            ),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+
+    /// Returns the next record parsed in parallel, parsing the next chunk if
+    /// none is left.
+    fn next_parallel_record(
+        &mut self,
+        columns: TransactionColumns,
+    ) -> Option<Result<DatedTransaction>> {
+        if self.parsed.is_empty() {
+            let chunk = match std::mem::take(&mut self.read_ahead) {
+                chunk if chunk.is_empty() => read_chunk(&mut self.reader),
+                chunk => chunk,
+            };
+            if chunk.is_empty() {
+                return None;
+            }
+            let parser = RecordParser {
+                columns,
+                headers: &self.headers,
+                fast_parse: self.fast_parse,
+                precision: self.precision,
+                strict: self.strict,
+            };
+            let reader = &mut self.reader;
+            let (read_ahead, parsed) =
+                rayon::join(|| read_chunk(reader), || parser.parse_chunk(chunk));
+            self.read_ahead = read_ahead;
+            self.parsed = parsed.into();
+        }
+
+        let record = self.parsed.pop_front()?;
+        self.record_start = record.start;
+        self.record_span = record.span;
+        Some(record.value)
+    }
+
+    /// Returns the field delimiter of the file.
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    /// Returns the raw bytes of the most recently read record, possibly
+    /// surrounded by line terminators, or `None` if no record was consumed (e.g. the header row was
+    /// invalid).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read again.
+    pub fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        if self.record_span.is_empty() {
+            return Ok(None);
+        }
+        let mut file = match self.raw_file.get() {
+            Some(file) => file,
+            None => {
+                let file = File::open(&self.path)
+                    .with_context(|| format!("Failed to open file: {}", self.path))?;
+                self.raw_file.get_or_init(|| file)
+            }
+        };
+        let mut raw = vec![0; (self.record_span.end - self.record_span.start) as usize];
+        file.seek(SeekFrom::Start(self.record_span.start))
+            .and_then(|_| file.read_exact(&mut raw))
+            .with_context(|| format!("Failed to read file: {}", self.path))?;
+        Ok(Some(raw))
+    }
+}
+
+/// Reads up to [`PARALLEL_CHUNK_SIZE`] raw records. The chunk ends early after
+/// a read error, so the error is returned in order; an empty chunk means the
+/// end of the file.
+fn read_chunk(reader: &mut csv::Reader<File>) -> Vec<ChunkRecord<Result<csv::ByteRecord>>> {
+    let mut chunk = Vec::with_capacity(PARALLEL_CHUNK_SIZE);
+    while chunk.len() < PARALLEL_CHUNK_SIZE {
+        let start = reader.position().clone();
+        let mut record = csv::ByteRecord::new();
+        let read = reader.read_byte_record(&mut record);
+        let span = start.byte()..reader.position().byte();
+        let value = match read {
+            Ok(false) => break,
+            Ok(true) => Ok(record),
+            Err(err) => Err(err.into()),
+        };
+        let failed = value.is_err();
+        chunk.push(ChunkRecord { start, span, value });
+        if failed {
+            break;
+        }
+    }
+    chunk
+}
+
+/// Turns raw records into transactions according to the reader settings.
+#[derive(Clone, Copy)]
+struct RecordParser<'a> {
+    columns: TransactionColumns,
+    headers: &'a csv::ByteRecord,
+    fast_parse: bool,
+    precision: PrecisionPolicy,
+    strict: bool,
+}
+
+impl RecordParser<'_> {
+    /// Parses `record`, adjusting its amount to the precision policy.
+    fn parse(&self, record: &mut csv::ByteRecord) -> Result<DatedTransaction> {
+        if let Some(index) = self.columns.amount
+            && let Some(amount) = record.get(index)
+            && let Some(adjusted) = self.precision.adjust(amount)?
+        {
+            *record = replace_field(record, index, adjusted.as_bytes());
+        }
+
+        let tx: Transaction = if self.fast_parse {
+            parse_transaction_record(record, self.columns)?
+        } else {
+            // The reader reports the accurate position itself, see `describe_record`.
+            record.set_position(None);
+            record.deserialize(Some(self.headers))?
+        };
+        if self.strict && tx.amount < Amount::ZERO {
+            anyhow::bail!("negative amount '{}'", tx.amount);
+        }
+        let effective_date = parse_effective_date(record, self.columns).map_err(|(_, err)| err)?;
+        let recurrence = parse_recurrence(record, self.columns).map_err(|(_, err)| err)?;
+        Ok(DatedTransaction {
+            tx,
+            effective_date,
+            recurrence,
         })
     }
+
+    /// Parses a chunk of raw records on the rayon thread pool, keeping their order.
+    fn parse_chunk(
+        &self,
+        chunk: Vec<ChunkRecord<Result<csv::ByteRecord>>>,
+    ) -> Vec<ChunkRecord<Result<DatedTransaction>>> {
+        use rayon::prelude::*;
+
+        chunk
+            .into_par_iter()
+            .map(|ChunkRecord { start, span, value }| ChunkRecord {
+                start,
+                span,
+                value: value.and_then(|mut record| self.parse(&mut record)),
+            })
+            .collect()
+    }
+}
+
+/// Returns a copy of `record` with the field at `index` replaced by `value`.
+pub(crate) fn replace_field(
+    record: &csv::ByteRecord,
+    index: usize,
+    value: &[u8],
+) -> csv::ByteRecord {
+    let mut replaced: csv::ByteRecord = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == index { value } else { field })
+        .collect();
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+/// Parses a raw record with the fast path after adjusting its amount to
+/// `precision`, rejecting negative amounts if `strict` is set.
+///
+/// Returns the index of the offending field on error.
+pub(crate) fn parse_adjusted_fields(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+    precision: PrecisionPolicy,
+    strict: bool,
+) -> Result<Transaction, (usize, anyhow::Error)> {
+    let adjusted;
+    let mut record = record;
+    if let Some(index) = columns.amount
+        && let Some(amount) = record.get(index)
+        && let Some(value) = precision
+            .adjust(amount)
+            .map_err(|err| (index, err.into()))?
+    {
+        adjusted = replace_field(record, index, value.as_bytes());
+        record = &adjusted;
+    }
+
+    let tx = parse_transaction_fields(record, columns)?;
+    if strict
+        && tx.amount < Amount::ZERO
+        && let Some(index) = columns.amount
+    {
+        return Err((index, anyhow::anyhow!("negative amount '{}'", tx.amount)));
+    }
+    Ok(tx)
+}
+
+/// Parses the `effective_date` column of a raw record, if there is one and it
+/// is not empty.
+///
+/// Returns the index of the field on error.
+pub(crate) fn parse_effective_date(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+) -> Result<Option<Date>, (usize, anyhow::Error)> {
+    let Some(index) = columns.effective_date else {
+        return Ok(None);
+    };
+    match record.get(index) {
+        None | Some(b"") => Ok(None),
+        Some(field) => String::from_utf8_lossy(field)
+            .parse()
+            .map(Some)
+            .map_err(|err: String| (index, anyhow::anyhow!(err))),
+    }
+}
+
+/// Parses the `interval` and `count` columns of a raw record. Records with an
+/// empty or missing interval do not recur.
+///
+/// Returns the index of the offending field on error.
+pub(crate) fn parse_recurrence(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+) -> Result<Option<Recurrence>, (usize, anyhow::Error)> {
+    let field = |index: Option<usize>| {
+        index.and_then(|index| Some((index, record.get(index).filter(|f| !f.is_empty())?)))
+    };
+    let positive = |(index, field): (usize, &[u8]), name: &str| {
+        parse_integer(field, name)
+            .and_then(|value: u32| {
+                anyhow::ensure!(value > 0, "{} must be positive", name);
+                Ok(value)
+            })
+            .map_err(|err| (index, err))
+    };
+    match (field(columns.interval), field(columns.count)) {
+        (None, None) => Ok(None),
+        (Some(interval), Some(count)) => Ok(Some(Recurrence {
+            interval: positive(interval, "interval")?,
+            count: positive(count, "count")?,
+        })),
+        (Some((index, _)), None) => Err((index, anyhow::anyhow!("interval without a count"))),
+        (None, Some((index, _))) => Err((index, anyhow::anyhow!("count without an interval"))),
+    }
+}
+
+/// Fails for a recurring record, for readers that process records one by one
+/// without expanding them.
+pub(crate) fn ensure_not_recurring(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+) -> Result<(), (usize, anyhow::Error)> {
+    match (parse_recurrence(record, columns)?, columns.interval) {
+        (Some(_), Some(index)) => Err((
+            index,
+            anyhow::anyhow!("recurring transactions are not supported in this mode"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the error context naming the record of the file at `path` that
+/// starts at `position`, e.g. `Failed to parse record 2 at line 4 (byte 42) from: day1.csv`.
+///
+/// The csv reader starts a record wherever the previous one ended, which can
+/// be before the `\n` of a `\r\n` terminator or before skipped blank lines.
+/// Those leading line terminators are skipped here so the line and byte point
+/// at the record itself.
+pub(crate) fn describe_record(path: &str, position: &csv::Position) -> String {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut position = position.clone();
+    if let Ok(mut file) = File::open(path)
+        && file.seek(SeekFrom::Start(position.byte())).is_ok()
+    {
+        let (mut leading, mut newlines) = (0, 0);
+        let mut bytes = io::BufReader::new(file).bytes();
+        while let Some(Ok(byte)) = bytes.next() {
+            match byte {
+                b'\n' => newlines += 1,
+                b'\r' => {}
+                _ => {
+                    let (line, byte) = (position.line(), position.byte());
+                    position.set_line(line + newlines).set_byte(byte + leading);
+                    break;
+                }
+            }
+            leading += 1;
+        }
+    }
+    format!(
+        "Failed to parse record {} at line {} (byte {}) from: {}",
+        position.record(),
+        position.line(),
+        position.byte(),
+        path
+    )
+}
+
+/// Parses a transaction from a raw record.
+fn parse_transaction_record(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+) -> Result<Transaction> {
+    parse_transaction_fields(record, columns).map_err(|(_, err)| err)
+}
+
+/// Like [`parse_transaction_record`], but also returns the index of the field
+/// that failed to parse.
+pub(crate) fn parse_transaction_fields(
+    record: &csv::ByteRecord,
+    columns: TransactionColumns,
+) -> Result<Transaction, (usize, anyhow::Error)> {
+    let field = |index: usize, name: &str| {
+        record
+            .get(index)
+            .with_context(|| format!("missing field `{}`", name))
+            .map_err(|err| (index, err))
+    };
+
+    let tx_type = match field(columns.tx_type, "type")? {
+        b"deposit" => TxType::Deposit,
+        b"withdrawal" => TxType::Withdrawal,
+        b"dispute" => TxType::Dispute,
+        b"resolve" => TxType::Resolve,
+        b"chargeback" => TxType::Chargeback,
+        b"refund" => TxType::Refund,
+        b"authorize" => TxType::Authorize,
+        b"capture" => TxType::Capture,
+        b"close" => TxType::Close,
+        b"hold" => TxType::Hold,
+        b"release" => TxType::Release,
+        b"interest" => TxType::Interest,
+        b"reversal" => TxType::Reversal,
+        b"open" => TxType::Open,
+        b"open_locked" => TxType::OpenLocked,
+        other => {
+            let err = anyhow::anyhow!(
+                "unknown transaction type '{}'",
+                String::from_utf8_lossy(other)
+            );
+            return Err((columns.tx_type, err));
+        }
+    };
+    let amount = match columns
+        .amount
+        .and_then(|index| Some((index, record.get(index)?)))
+    {
+        Some((index, amount)) => {
+            Amount::parse_lenient_bytes(amount).map_err(|err| (index, err.into()))?
+        }
+        None => Amount::ZERO,
+    };
+
+    Ok(Transaction {
+        tx_type,
+        client: parse_integer(field(columns.client, "client")?, "client")
+            .map_err(|err| (columns.client, err))?,
+        tx: parse_integer(field(columns.tx, "tx")?, "tx").map_err(|err| (columns.tx, err))?,
+        amount,
+    })
+}
+
+/// Parses an unsigned integer field from raw bytes.
+fn parse_integer<T: std::str::FromStr>(bytes: &[u8], name: &str) -> Result<T> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .with_context(|| format!("invalid {} '{}'", name, String::from_utf8_lossy(bytes)))
+}
+
+impl Iterator for TransactionReader {
+    type Item = Result<Transaction, anyhow::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_record()?;
+
+        Some(result.with_context(|| {
+            if self.columns.is_none() {
+                return format!("Failed to parse header from: {}", self.path);
+            }
+            describe_record(&self.path, &self.record_start)
+        }))
+    }
 }
 
 /// Reads and parses a CSV file, returning an iterator over `Transaction` structs.
@@ -63,106 +818,1930 @@ impl Iterator for TransactionReader {
 ///
 /// Note: Individual record parsing errors will be returned when iterating over the result.
 pub fn read_transactions_from_file(path: &str) -> Result<TransactionReader> {
-    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
+    read_transactions_from_file_with_delimiter(path, Delimiter::Auto)
+}
+
+/// Like [`read_transactions_from_file`], with an explicit field delimiter instead
+/// of detecting it from the header line.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its first line cannot be read.
+pub fn read_transactions_from_file_with_delimiter(
+    path: &str,
+    delimiter: Delimiter,
+) -> Result<TransactionReader> {
+    let (file, delimiter) = open_csv_file(path, delimiter)?;
     let reader = csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
         .flexible(true)
+        .delimiter(delimiter)
         .from_reader(file);
 
     Ok(TransactionReader {
         reader,
         path: path.to_string(),
-        line_num: 0,
+        fast_parse: false,
+        precision: PrecisionPolicy::default(),
+        strict: false,
+        aliases: Vec::new(),
+        delimiter,
+        record: csv::ByteRecord::new(),
+        record_start: csv::Position::new(),
+        record_span: 0..0,
+        raw_file: OnceLock::new(),
+        columns: None,
+        headers: csv::ByteRecord::new(),
+        parallel_parse: false,
+        parsed: VecDeque::new(),
+        read_ahead: Vec::new(),
+        effective_date: None,
+        recurrence: None,
+        skip_rows: 0,
+        limit_rows: None,
+        rows_read: 0,
     })
 }
 
-/// Writes account details to stdout in CSV format.
-///
-/// This function takes a map of accounts, sets the client ID for each account
-/// from the map key, and serializes them to CSV format. The output is written
-/// to standard output.
-///
-/// # Arguments
+/// Collects malformed input records in a file instead of aborting the run.
 ///
-/// * `accounts` - A map of client IDs to their account details
-///
-/// # Returns
+/// Each record is written verbatim, followed by the input delimiter and the
+/// quoted parse error, so the file can be corrected and processed again.
+pub struct Quarantine {
+    writer: io::BufWriter<File>,
+    path: String,
+    count: u64,
+}
+
+impl Quarantine {
+    /// Creates (or truncates) the quarantine file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created.
+    pub fn create(path: &str) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+        Ok(Quarantine {
+            writer: io::BufWriter::new(file),
+            path: path.to_string(),
+            count: 0,
+        })
+    }
+
+    /// Writes a malformed record and the error it failed with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the file fails.
+    pub fn add(&mut self, raw: &[u8], delimiter: u8, error: &anyhow::Error) -> Result<()> {
+        use std::io::Write;
+
+        let is_terminator = |byte: &u8| matches!(byte, b'\r' | b'\n');
+        let start = raw.iter().position(|byte| !is_terminator(byte));
+        let end = raw.iter().rposition(|byte| !is_terminator(byte));
+        let raw = match (start, end) {
+            (Some(start), Some(end)) => &raw[start..=end],
+            _ => &[],
+        };
+        let error = format!("{:#}", error).replace('"', "\"\"");
+        self.writer
+            .write_all(raw)
+            .and_then(|_| self.writer.write_all(&[delimiter, b'"']))
+            .and_then(|_| self.writer.write_all(error.as_bytes()))
+            .and_then(|_| self.writer.write_all(b"\"\n"))
+            .with_context(|| format!("Failed to write to: {}", self.path))?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Returns the path of the quarantine file.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the number of records written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Flushes buffered records to the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the file fails.
+    pub fn flush(&mut self) -> Result<()> {
+        use std::io::Write;
+
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to write to: {}", self.path))
+    }
+}
+
+/// A stream of transactions read from a file, such as a [`TransactionReader`].
+pub trait TransactionSource: Iterator<Item = Result<Transaction>> {
+    /// Returns the raw bytes of the most recently read record, or `None` if no
+    /// record was consumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be recovered.
+    fn raw_record(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Returns the field delimiter of the raw records.
+    fn delimiter(&self) -> u8;
+
+    /// Returns the effective date of the most recently returned transaction, or
+    /// `None` if it has none or the source has no `effective_date` column.
+    fn effective_date(&self) -> Option<Date> {
+        None
+    }
+
+    /// Returns the recurrence of the most recently returned transaction, or
+    /// `None` if it does not recur or the source has no `interval` column.
+    fn recurrence(&self) -> Option<Recurrence> {
+        None
+    }
+
+    /// Returns the number of bytes of the file consumed so far, or `None` if
+    /// the source does not track its position.
+    fn bytes_read(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl TransactionSource for TransactionReader {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        TransactionReader::raw_record(self)
+    }
+
+    fn delimiter(&self) -> u8 {
+        TransactionReader::delimiter(self)
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.effective_date
+    }
+
+    fn recurrence(&self) -> Option<Recurrence> {
+        self.recurrence
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        Some(self.record_span.end)
+    }
+}
+
+impl<S: TransactionSource + ?Sized> TransactionSource for Box<S> {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        (**self).raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        (**self).delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        (**self).effective_date()
+    }
+
+    fn recurrence(&self) -> Option<Recurrence> {
+        (**self).recurrence()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        (**self).bytes_read()
+    }
+}
+
+/// Wraps `reader` so that malformed records are written to `quarantine` and
+/// skipped instead of ending the iteration with an error.
 ///
-/// Returns `Ok(())` on success, or an error if writing to stdout fails.
+/// Without a quarantine, or for errors that do not belong to a single record
+/// (e.g. a missing header column), the error is passed through unchanged.
+pub fn skip_malformed<S: TransactionSource>(
+    reader: S,
+    quarantine: Option<&mut Quarantine>,
+) -> SkipMalformed<'_, S> {
+    SkipMalformed { reader, quarantine }
+}
+
+/// A [`TransactionSource`] skipping malformed records, created by
+/// [`skip_malformed`].
+pub struct SkipMalformed<'a, S> {
+    reader: S,
+    quarantine: Option<&'a mut Quarantine>,
+}
+
+impl<S: TransactionSource> Iterator for SkipMalformed<'_, S> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let err = match self.reader.next()? {
+                Ok(tx) => return Some(Ok(tx)),
+                Err(err) => err,
+            };
+            let Some(quarantine) = self.quarantine.as_deref_mut() else {
+                return Some(Err(err));
+            };
+            let raw = match self.reader.raw_record() {
+                Ok(Some(raw)) => raw,
+                Ok(None) => return Some(Err(err)),
+                Err(read_err) => return Some(Err(read_err)),
+            };
+            tracing::warn!(
+                error = format!("{:#}", err),
+                "quarantining malformed record"
+            );
+            if let Err(write_err) = quarantine.add(&raw, self.reader.delimiter(), &err) {
+                return Some(Err(write_err));
+            }
+        }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for SkipMalformed<'_, S> {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        self.reader.raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        self.reader.delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.reader.effective_date()
+    }
+
+    fn recurrence(&self) -> Option<Recurrence> {
+        self.reader.recurrence()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.reader.bytes_read()
+    }
+}
+
+/// A single row of a per-client credit limit file.
+#[derive(Debug, Deserialize)]
+struct CreditLimitRecord {
+    client: ClientId,
+    #[serde(deserialize_with = "deserialize_amount_or_zero")]
+    limit: Amount,
+}
+
+/// Reads per-client credit limits from a CSV file with `client,limit` columns.
 ///
 /// # Errors
 ///
-/// This function will return an error if:
-/// - Serialization of any account record fails
-/// - Flushing the output buffer fails
-pub fn write_accounts_as_csv_to_stdout(accounts: Accounts) -> Result<()> {
-    let mut writer = csv::Writer::from_writer(io::stdout());
+/// This function will return an error if the file cannot be opened or any row
+/// fails to parse.
+pub fn read_credit_limits_from_file(path: &str) -> Result<BTreeMap<ClientId, Amount>> {
+    let (file, delimiter) = open_csv_file(path, Delimiter::Auto)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .from_reader(file);
 
-    for account in accounts.into_iter().map(|(client_id, mut account)| {
-        account.client = client_id;
-        account
-    }) {
-        writer
-            .serialize(account)
-            .context("Failed to write record to stdout")?;
+    let mut limits = BTreeMap::new();
+    for (index, result) in reader.deserialize::<CreditLimitRecord>().enumerate() {
+        let record = result.with_context(|| {
+            format!(
+                "Failed to parse credit limit at line {} from: {}",
+                index + 2,
+                path
+            )
+        })?;
+        limits.insert(record.client, record.limit);
     }
 
-    writer.flush().context("Failed to flush output to stdout")?;
-
-    Ok(())
+    Ok(limits)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::types::TxType;
-    use rust_decimal::Decimal;
-    use std::str::FromStr;
+/// A single row of a client profile file. All columns but `client` are
+/// optional and may be left empty.
+#[derive(Debug, Deserialize)]
+struct ClientProfileRecord {
+    client: ClientId,
+    #[serde(default)]
+    withdrawal_limit: Option<String>,
+    #[serde(default)]
+    credit_limit: Option<String>,
+    #[serde(default)]
+    tier: Option<String>,
+    #[serde(default)]
+    currency: Option<String>,
+}
 
-    #[test]
-    fn test_input_file_reading() {
-        // Test reading transactions from the test-data.csv file
-        let reader = read_transactions_from_file("test-data.csv").unwrap();
+impl ClientProfileRecord {
+    fn into_profile(self) -> Result<ClientProfile> {
+        let amount = |value: Option<String>| {
+            value
+                .map(|value| Amount::parse_lenient(&value))
+                .transpose()
+                .map_err(anyhow::Error::msg)
+        };
+        if let Some(currency) = &self.currency {
+            anyhow::ensure!(
+                currency.len() == 3 && currency.bytes().all(|byte| byte.is_ascii_uppercase()),
+                "invalid currency '{}', expected a three-letter code such as EUR",
+                currency
+            );
+        }
+        Ok(ClientProfile {
+            withdrawal_limit: amount(self.withdrawal_limit).context("invalid withdrawal_limit")?,
+            credit_limit: amount(self.credit_limit).context("invalid credit_limit")?,
+            tier: self.tier,
+            currency: self.currency,
+        })
+    }
+}
 
-        let transactions: Vec<Transaction> = reader.map(|result| result.unwrap()).collect();
+/// Reads client profiles from a CSV file with a `client` column and any of the
+/// optional `withdrawal_limit`, `credit_limit`, `tier` and `currency` columns.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened or any row
+/// fails to parse.
+pub fn read_client_profiles_from_file(path: &str) -> Result<BTreeMap<ClientId, ClientProfile>> {
+    let (file, delimiter) = open_csv_file(path, Delimiter::Auto)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .from_reader(file);
 
-        // Verify we read all 9 transactions (excluding header)
-        assert_eq!(transactions.len(), 9);
+    let mut profiles = BTreeMap::new();
+    for (index, result) in reader.deserialize::<ClientProfileRecord>().enumerate() {
+        let (client, profile) = result
+            .map_err(anyhow::Error::from)
+            .and_then(|record| Ok((record.client, record.into_profile()?)))
+            .with_context(|| {
+                format!(
+                    "Failed to parse client profile at line {} from: {}",
+                    index + 2,
+                    path
+                )
+            })?;
+        profiles.insert(client, profile);
+    }
 
-        // Verify first deposit transaction
-        assert_eq!(transactions[0].tx_type, TxType::Deposit);
-        assert_eq!(transactions[0].client, 1);
-        assert_eq!(transactions[0].tx, 1);
-        assert_eq!(transactions[0].amount, Decimal::from_str("10.0").unwrap());
+    Ok(profiles)
+}
 
-        // Verify second deposit transaction
-        assert_eq!(transactions[1].tx_type, TxType::Deposit);
-        assert_eq!(transactions[1].client, 2);
-        assert_eq!(transactions[1].tx, 2);
-        assert_eq!(transactions[1].amount, Decimal::from_str("10.0").unwrap());
+/// The field delimiter of an input file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    /// Detect the delimiter from the header line (see [`detect_delimiter`]).
+    #[default]
+    Auto,
+    /// Use the given byte.
+    Byte(u8),
+}
 
-        // Verify dispute transaction (should have amount = 0 for empty/missing amount)
-        assert_eq!(transactions[2].tx_type, TxType::Dispute);
-        assert_eq!(transactions[2].client, 1);
-        assert_eq!(transactions[2].tx, 1);
-        assert_eq!(transactions[2].amount, Decimal::ZERO);
+impl std::str::FromStr for Delimiter {
+    type Err = String;
 
-        // Verify withdrawal transaction
-        assert_eq!(transactions[4].tx_type, TxType::Withdrawal);
-        assert_eq!(transactions[4].client, 1);
-        assert_eq!(transactions[4].tx, 3);
-        assert_eq!(transactions[4].amount, Decimal::from_str("5.0").unwrap());
+    /// Parses `auto`, `tab` or a single ASCII character such as `;`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Delimiter::Auto),
+            _ => parse_delimiter_byte(value).map(Delimiter::Byte),
+        }
+    }
+}
 
-        // Verify resolve transaction
-        assert_eq!(transactions[6].tx_type, TxType::Resolve);
-        assert_eq!(transactions[6].client, 1);
-        assert_eq!(transactions[6].tx, 1);
-        assert_eq!(transactions[6].amount, Decimal::ZERO);
+/// Parses a delimiter given as `tab` (or `\t`) or as a single ASCII character.
+///
+/// # Errors
+///
+/// Returns an error message for any other value.
+pub fn parse_delimiter_byte(value: &str) -> Result<u8, String> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(format!(
+            "invalid delimiter '{}', expected a single ASCII character or 'tab'",
+            value
+        )),
+    }
+}
+
+/// Parses a size in bytes with an optional binary unit suffix, e.g. `2G`,
+/// `512M`, `64KiB` or `1048576`.
+///
+/// # Errors
+///
+/// Returns an error message for unknown units and sizes that overflow `usize`.
+pub fn parse_byte_size(value: &str) -> Result<usize, String> {
+    let invalid = || {
+        format!(
+            "invalid size '{}', expected a number of bytes with an optional K, M or G suffix",
+            value
+        )
+    };
+    let trimmed = value.trim();
+    let digits = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(digits);
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return Err(invalid()),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(invalid)
+}
+
+/// Delimiters recognized by [`detect_delimiter`], in order of preference.
+const DELIMITER_CANDIDATES: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+/// Detects the delimiter of a CSV file from its header line.
+///
+/// Picks the candidate (`,`, tab, `;` or `|`) occurring most often outside
+/// quotes, preferring earlier candidates on ties, and falls back to `,` if none
+/// occurs.
+pub fn detect_delimiter(header_line: &[u8]) -> u8 {
+    let mut counts = [0usize; DELIMITER_CANDIDATES.len()];
+    let mut quoted = false;
+    for &byte in header_line {
+        if byte == b'"' {
+            quoted = !quoted;
+        } else if !quoted && let Some(index) = DELIMITER_CANDIDATES.iter().position(|&c| c == byte)
+        {
+            counts[index] += 1;
+        }
+    }
+
+    // `max_by_key` returns the last maximum, so iterate in reverse to prefer
+    // earlier candidates.
+    counts
+        .into_iter()
+        .enumerate()
+        .rev()
+        .max_by_key(|&(_, count)| count)
+        .filter(|&(_, count)| count > 0)
+        .map_or(b',', |(index, _)| DELIMITER_CANDIDATES[index])
+}
+
+/// Opens a CSV file and resolves its delimiter, detecting it from the first
+/// line if `delimiter` is [`Delimiter::Auto`].
+pub(crate) fn open_csv_file(path: &str, delimiter: Delimiter) -> Result<(File, u8)> {
+    use std::io::{BufRead, Seek};
+
+    let mut file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
+    let delimiter = match delimiter {
+        Delimiter::Byte(byte) => byte,
+        Delimiter::Auto => {
+            let mut header_line = Vec::new();
+            io::BufReader::new(&mut file)
+                .read_until(b'\n', &mut header_line)
+                .with_context(|| format!("Failed to read file: {}", path))?;
+            file.rewind()
+                .with_context(|| format!("Failed to read file: {}", path))?;
+            detect_delimiter(&header_line)
+        }
+    };
+    Ok((file, delimiter))
+}
+
+/// Names of the account output columns, in output order.
+pub const ACCOUNT_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Names of the columns appended with [`OutputOptions::extended`], in output
+/// order. They hold the [`AccountMetrics`](crate::types::AccountMetrics) of each
+/// account, where `last_activity` is empty if unknown, and whether it is closed.
+pub const EXTENDED_ACCOUNT_COLUMNS: [&str; 8] = [
+    "deposits",
+    "withdrawals",
+    "active_disputes",
+    "chargebacks",
+    "last_activity",
+    "closed",
+    "tier",
+    "currency",
+];
+
+/// Encoding used for boolean columns in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoolStyle {
+    /// `true` / `false`
+    #[default]
+    TrueFalse,
+    /// `1` / `0`
+    OneZero,
+    /// `yes` / `no`
+    YesNo,
+}
+
+impl BoolStyle {
+    /// Every style, in declaration order.
+    pub const ALL: [BoolStyle; 3] = [BoolStyle::TrueFalse, BoolStyle::OneZero, BoolStyle::YesNo];
+
+    /// Returns the name of the style, e.g. `one-zero`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BoolStyle::TrueFalse => "true-false",
+            BoolStyle::OneZero => "one-zero",
+            BoolStyle::YesNo => "yes-no",
+        }
+    }
+
+    /// Parses a boolean written in any of the supported styles.
+    pub fn parse(value: &str) -> Option<bool> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" => Some(true),
+            "false" | "0" | "no" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Returns the textual representation of `value` in this style.
+    pub fn format(self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolStyle::TrueFalse, true) => "true",
+            (BoolStyle::TrueFalse, false) => "false",
+            (BoolStyle::OneZero, true) => "1",
+            (BoolStyle::OneZero, false) => "0",
+            (BoolStyle::YesNo, true) => "yes",
+            (BoolStyle::YesNo, false) => "no",
+        }
+    }
+}
+
+impl std::str::FromStr for BoolStyle {
+    type Err = String;
+
+    /// Parses the name returned by [`BoolStyle::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        BoolStyle::ALL
+            .into_iter()
+            .find(|style| style.as_str() == value)
+            .ok_or_else(|| format!("unknown bool style '{}'", value))
+    }
+}
+
+impl std::fmt::Display for BoolStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Options controlling the vocabulary of the account output.
+///
+/// The defaults produce the standard comma-separated
+/// `client,available,held,total,locked` output with `true`/`false` booleans.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Encoding of boolean columns.
+    pub bool_style: BoolStyle,
+    /// Custom header names keyed by the default column name (see [`ACCOUNT_COLUMNS`]).
+    pub header_names: BTreeMap<String, String>,
+    /// Field delimiter of the written files.
+    pub delimiter: u8,
+    /// Whether to append the [`EXTENDED_ACCOUNT_COLUMNS`] to the account output.
+    pub extended: bool,
+    /// Client metadata appended to the account output after all other columns.
+    pub enrichment: Option<Arc<Enrichment>>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            bool_style: BoolStyle::default(),
+            header_names: BTreeMap::new(),
+            delimiter: b',',
+            extended: false,
+            enrichment: None,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Creates a CSV writer using the configured delimiter.
+    fn csv_writer<W: io::Write>(&self, writer: W) -> csv::Writer<W> {
+        csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(writer)
+    }
+
+    /// Returns the header row, applying any custom header names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a custom header name refers to an unknown column.
+    pub(crate) fn headers(&self) -> Result<Vec<&str>> {
+        let mut columns = ACCOUNT_COLUMNS.iter().chain(&EXTENDED_ACCOUNT_COLUMNS);
+        if let Some(unknown) = self
+            .header_names
+            .keys()
+            .find(|column| !columns.clone().any(|known| known == column))
+        {
+            anyhow::bail!(
+                "Unknown output column '{}', expected one of: {}",
+                unknown,
+                columns.by_ref().copied().collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        let count = if self.extended {
+            ACCOUNT_COLUMNS.len() + EXTENDED_ACCOUNT_COLUMNS.len()
+        } else {
+            ACCOUNT_COLUMNS.len()
+        };
+        Ok(columns
+            .take(count)
+            .map(|column| {
+                self.header_names
+                    .get(*column)
+                    .map(String::as_str)
+                    .unwrap_or(column)
+            })
+            .collect())
+    }
+}
+
+/// Writes a processing statistics report as pretty-printed JSON to `path`.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be created or written.
+pub fn write_stats_as_json_to_file(report: &StatsReport, path: &str) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    serde_json::to_writer_pretty(file, report)
+        .with_context(|| format!("Failed to write statistics to: {}", path))
+}
+
+/// Writes `manifest` as pretty-printed JSON to the file at `path`.
+pub fn write_manifest_to_file(manifest: &RunManifest, path: &str) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    serde_json::to_writer_pretty(file, manifest)
+        .with_context(|| format!("Failed to write manifest to: {}", path))
+}
+
+/// Writes account details to stdout in CSV format.
+///
+/// This function takes a map of accounts, sets the client ID for each account
+/// from the map key, and serializes them to CSV format. The output is written
+/// to standard output.
+///
+/// # Arguments
+///
+/// * `accounts` - A map of client IDs to their account details
+/// * `options` - The output vocabulary (boolean encoding and header names)
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or an error if writing to stdout fails.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - Serialization of any account record fails
+/// - Flushing the output buffer fails
+pub fn write_accounts_as_csv_to_stdout(accounts: Accounts, options: &OutputOptions) -> Result<()> {
+    write_accounts_as_csv(accounts, io::stdout(), options)
+        .context("Failed to write accounts to stdout")
+}
+
+/// Writes account details in CSV format to the file at `path`, creating or
+/// truncating it.
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be created or if
+/// writing any record fails.
+pub fn write_accounts_as_csv_to_file(
+    accounts: Accounts,
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    write_accounts_as_csv(accounts, file, options)
+        .with_context(|| format!("Failed to write accounts to: {}", path))
+}
+
+/// Writes account details in CSV format to an arbitrary writer.
+///
+/// Accounts are written in ascending client order. The client ID of each account
+/// is taken from the map key.
+///
+/// # Errors
+///
+/// This function will return an error if:
+/// - A custom header name refers to an unknown column
+/// - Writing any account record fails
+/// - Flushing the output buffer fails
+pub fn write_accounts_as_csv<W: io::Write>(
+    accounts: Accounts,
+    writer: W,
+    options: &OutputOptions,
+) -> Result<()> {
+    let mut writer = AccountWriter::new(writer, options)?;
+    for (client_id, account) in sorted_accounts(&accounts) {
+        writer.write(client_id, account)?;
+    }
+    writer.finish()
+}
+
+/// Writes account summaries one at a time, for output consumed while input is
+/// still being processed.
+///
+/// The header row is written on creation. With [`AccountWriter::flush_each`]
+/// enabled, every record is flushed to the underlying writer as soon as it is
+/// written instead of when the internal buffer fills up.
+pub struct AccountWriter<W: io::Write> {
+    writer: csv::Writer<W>,
+    bool_style: BoolStyle,
+    extended: bool,
+    enrichment: Option<Arc<Enrichment>>,
+    flush_each: bool,
+}
+
+impl<W: io::Write> AccountWriter<W> {
+    /// Creates a writer and writes the header row.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a custom header name refers to an unknown column or
+    /// the header cannot be written.
+    pub fn new(writer: W, options: &OutputOptions) -> Result<Self> {
+        let mut writer = options.csv_writer(writer);
+        let enrichment = options
+            .enrichment
+            .iter()
+            .flat_map(|enrichment| enrichment.columns());
+        writer
+            .write_record(
+                options
+                    .headers()?
+                    .into_iter()
+                    .chain(enrichment.map(String::as_str)),
+            )
+            .context("Failed to write header record")?;
+
+        Ok(AccountWriter {
+            writer,
+            bool_style: options.bool_style,
+            extended: options.extended,
+            enrichment: options.enrichment.clone(),
+            flush_each: false,
+        })
+    }
+
+    /// Sets whether every record is flushed immediately after it is written.
+    pub fn flush_each(mut self, flush_each: bool) -> Self {
+        self.flush_each = flush_each;
+        self
+    }
+
+    /// Writes the summary of the account of `client_id`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written or flushed.
+    pub fn write(&mut self, client_id: ClientId, account: &AccountDetails) -> Result<()> {
+        let fields = [
+            client_id.to_string(),
+            account.available.to_string(),
+            account.held.to_string(),
+            account.total.to_string(),
+        ];
+        let fields = fields
+            .iter()
+            .map(String::as_str)
+            .chain([self.bool_style.format(account.locked)]);
+        let extended = if self.extended {
+            let metrics = &account.metrics;
+            vec![
+                metrics.deposits.to_string(),
+                metrics.withdrawals.to_string(),
+                metrics.active_disputes.to_string(),
+                metrics.chargebacks.to_string(),
+                match metrics.last_activity {
+                    0 => String::new(),
+                    millis => millis.to_string(),
+                },
+                self.bool_style.format(account.closed).to_string(),
+                account.tier.clone().unwrap_or_default(),
+                account.currency.clone().unwrap_or_default(),
+            ]
+        } else {
+            Vec::new()
+        };
+        let enrichment = self
+            .enrichment
+            .iter()
+            .flat_map(|enrichment| enrichment.fields(client_id));
+        self.writer
+            .write_record(
+                fields
+                    .chain(extended.iter().map(String::as_str))
+                    .chain(enrichment),
+            )
+            .context("Failed to write account record")?;
+
+        if self.flush_each {
+            self.writer.flush().context("Failed to flush output")?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes all buffered records.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing the output buffer fails.
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush().context("Failed to flush output")
+    }
+}
+
+/// A row of the period report written when a processing period is closed.
+#[derive(Debug, Serialize)]
+struct PeriodReportRecord {
+    period: u32,
+    applied: u64,
+    ignored: u64,
+    deposited: Amount,
+    withdrawn: Amount,
+    held: Amount,
+    released: Amount,
+    charged_back: Amount,
+    refunded: Amount,
+    authorized: Amount,
+    captured: Amount,
+    interest: Amount,
+    reversed: Amount,
+    opened: Amount,
+}
+
+/// Writes the snapshot and report of a closed period into `dir`.
+///
+/// Creates `period-<N>-accounts.csv` with the account snapshot (using the given
+/// output options) and `period-<N>-report.csv` with the period's counters.
+///
+/// # Errors
+///
+/// This function will return an error if either file cannot be written.
+pub fn write_closed_period_to_dir(
+    closed: ClosedPeriod,
+    dir: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let dir = std::path::Path::new(dir);
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    let report_path = dir.join(format!("period-{}-report.csv", closed.period));
+    let file = File::create(&report_path)
+        .with_context(|| format!("Failed to create file: {}", report_path.display()))?;
+    let mut writer = options.csv_writer(file);
+    writer
+        .serialize(PeriodReportRecord {
+            period: closed.period,
+            applied: closed.stats.applied,
+            ignored: closed.stats.ignored,
+            deposited: closed.stats.deposited,
+            withdrawn: closed.stats.withdrawn,
+            held: closed.stats.held,
+            released: closed.stats.released,
+            charged_back: closed.stats.charged_back,
+            refunded: closed.stats.refunded,
+            authorized: closed.stats.authorized,
+            captured: closed.stats.captured,
+            interest: closed.stats.interest,
+            reversed: closed.stats.reversed,
+            opened: closed.stats.opened,
+        })
+        .and_then(|_| writer.flush().map_err(csv::Error::from))
+        .with_context(|| {
+            format!(
+                "Failed to write period report to: {}",
+                report_path.display()
+            )
+        })?;
+
+    let accounts_path = dir.join(format!("period-{}-accounts.csv", closed.period));
+    write_accounts_as_csv_to_file(closed.accounts, &accounts_path.to_string_lossy(), options)
+}
+
+/// A row of a client history file.
+#[derive(Debug, Serialize)]
+struct HistoryRecord<'a> {
+    tx: TxId,
+    #[serde(rename = "type")]
+    tx_type: TxType,
+    amount: Amount,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: &'a str,
+}
+
+/// Reads account summaries previously written by this application.
+///
+/// Columns are read by position (`client,available,held,total,locked`), so files
+/// written with renamed headers can be read as well. The `locked` column may use
+/// any of the [`BoolStyle`] encodings.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or a row cannot be parsed.
+pub fn read_accounts_from_file(path: &str) -> Result<Accounts> {
+    let (file, delimiter) = open_csv_file(path, Delimiter::Auto)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .from_reader(file);
+
+    let mut accounts = Accounts::new();
+    for (index, result) in reader.records().enumerate() {
+        let line = index + 2;
+        let record = result
+            .with_context(|| format!("Failed to read account at line {} from: {}", line, path))?;
+        let field = |column: usize| {
+            record.get(column).with_context(|| {
+                format!(
+                    "Missing column '{}' at line {} in: {}",
+                    ACCOUNT_COLUMNS[column], line, path
+                )
+            })
+        };
+        let amount = |column: usize| -> Result<Amount> {
+            Amount::parse_lenient(field(column)?).with_context(|| {
+                format!(
+                    "Invalid {} at line {} in: {}",
+                    ACCOUNT_COLUMNS[column], line, path
+                )
+            })
+        };
+
+        let client: ClientId = field(0)?
+            .parse()
+            .with_context(|| format!("Invalid client at line {} in: {}", line, path))?;
+        let locked = BoolStyle::parse(field(4)?)
+            .with_context(|| format!("Invalid locked flag at line {} in: {}", line, path))?;
+        accounts.insert(
+            client,
+            AccountDetails {
+                client,
+                available: amount(1)?,
+                held: amount(2)?,
+                total: amount(3)?,
+                locked,
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok(accounts)
+}
+
+/// Writes account differences to stdout as CSV with the columns
+/// `client,change,available,held,total,newly_locked`, where the balance columns
+/// hold the change from the old to the new output.
+///
+/// # Errors
+///
+/// Returns an error if writing to stdout fails.
+pub fn write_account_deltas_as_csv_to_stdout(
+    deltas: &[AccountDelta],
+    options: &OutputOptions,
+) -> Result<()> {
+    let mut writer = options.csv_writer(io::stdout());
+    write_account_deltas(&mut writer, deltas, options)?;
+    writer.flush().context("Failed to write deltas to stdout")?;
+
+    Ok(())
+}
+
+/// Writes account differences as CSV to the file at `path`, in the format of
+/// [`write_account_deltas_as_csv_to_stdout`].
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_account_deltas_as_csv_to_file(
+    deltas: &[AccountDelta],
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    write_account_deltas(&mut writer, deltas, options)
+        .and_then(|()| Ok(writer.flush()?))
+        .with_context(|| format!("Failed to write deltas to: {}", path))
+}
+
+/// Writes the projected effects of a dry run to stdout as two CSV tables
+/// separated by an empty line.
+///
+/// The first table lists the accounts that would change, in the format of
+/// [`write_account_deltas_as_csv_to_stdout`]. The second lists the transactions
+/// that would be rejected with the columns `type,client,tx,amount,reason`, in
+/// input order.
+///
+/// # Errors
+///
+/// Returns an error if writing to stdout fails.
+pub fn write_dry_run_report_to_stdout(
+    deltas: &[AccountDelta],
+    rejected: &[RejectedTransaction],
+    options: &OutputOptions,
+) -> Result<()> {
+    let mut writer = options.csv_writer(io::stdout().lock());
+    write_account_deltas(&mut writer, deltas, options)?;
+    let mut stdout = writer
+        .into_inner()
+        .map_err(|err| err.into_error())
+        .context("Failed to write deltas to stdout")?;
+    io::Write::write_all(&mut stdout, b"\n").context("Failed to write to stdout")?;
+
+    let mut writer = options.csv_writer(stdout);
+    writer
+        .write_record(["type", "client", "tx", "amount", "reason"])
+        .context("Failed to write header record")?;
+    for RejectedTransaction {
+        transaction: tx,
+        reason,
+    } in rejected
+    {
+        writer
+            .write_record([
+                tx.tx_type.as_str(),
+                tx.client.to_string().as_str(),
+                tx.tx.to_string().as_str(),
+                tx.amount.to_string().as_str(),
+                reason.as_str(),
+            ])
+            .context("Failed to write rejected transaction")?;
+    }
+    writer
+        .flush()
+        .context("Failed to write rejected transactions to stdout")?;
+
+    Ok(())
+}
+
+/// Writes the header and one record per delta.
+fn write_account_deltas<W: io::Write>(
+    writer: &mut csv::Writer<W>,
+    deltas: &[AccountDelta],
+    options: &OutputOptions,
+) -> Result<()> {
+    writer
+        .write_record([
+            "client",
+            "change",
+            "available",
+            "held",
+            "total",
+            "newly_locked",
+        ])
+        .context("Failed to write header record")?;
+
+    for delta in deltas {
+        writer
+            .write_record([
+                delta.client.to_string().as_str(),
+                delta.kind().as_str(),
+                delta.available().to_string().as_str(),
+                delta.held().to_string().as_str(),
+                delta.total().to_string().as_str(),
+                options.bool_style.format(delta.newly_locked()),
+            ])
+            .context("Failed to write account delta")?;
+    }
+
+    Ok(())
+}
+
+/// Writes one `client-<id>.csv` file per client into `dir`, listing every applied
+/// transaction and the client's balances right after it was applied.
+///
+/// The columns are `tx,type,amount,available,held,total,locked`; the `locked`
+/// column uses the boolean encoding of the given output options.
+///
+/// # Errors
+///
+/// Returns an error if the directory or one of the files cannot be created or written.
+pub fn write_history_to_dir(history: &History, dir: &str, options: &OutputOptions) -> Result<()> {
+    let dir = std::path::Path::new(dir);
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+
+    for (client, entries) in history.clients() {
+        let path = dir.join(format!("client-{}.csv", client));
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create file: {}", path.display()))?;
+        let mut writer = options.csv_writer(file);
+
+        for entry in entries {
+            writer
+                .serialize(HistoryRecord {
+                    tx: entry.tx,
+                    tx_type: entry.tx_type,
+                    amount: entry.amount,
+                    available: entry.available,
+                    held: entry.held,
+                    total: entry.total,
+                    locked: options.bool_style.format(entry.locked),
+                })
+                .with_context(|| format!("Failed to write history to: {}", path.display()))?;
+        }
+
+        writer
+            .flush()
+            .with_context(|| format!("Failed to flush file: {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the flagged accounts to a CSV file at `path`, one record per flag with
+/// the columns `client,pattern,detail`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_fraud_report_to_file(
+    flags: &[FraudFlag],
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    writer
+        .write_record(["client", "pattern", "detail"])
+        .context("Failed to write header record")?;
+    for flag in flags {
+        writer
+            .write_record([
+                flag.client.to_string().as_str(),
+                flag.pattern.as_str(),
+                flag.detail.as_str(),
+            ])
+            .with_context(|| format!("Failed to write fraud report to: {}", path))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush file: {}", path))
+}
+
+/// Writes the held funds to a CSV file at `path`, one record per client with
+/// the columns `client,held,open_disputes`, followed by a `total` record.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_exposure_report_to_file(
+    exposure: &Exposure,
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    writer
+        .write_record(["client", "held", "open_disputes"])
+        .context("Failed to write header record")?;
+    let clients = exposure
+        .clients
+        .iter()
+        .map(|client| (client.client.to_string(), client.held, client.open_disputes));
+    let total = ("total".to_string(), exposure.held, exposure.open_disputes);
+    for (client, held, open_disputes) in clients.chain([total]) {
+        writer
+            .write_record([
+                client.as_str(),
+                held.to_string().as_str(),
+                open_disputes.to_string().as_str(),
+            ])
+            .with_context(|| format!("Failed to write exposure report to: {}", path))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush file: {}", path))
+}
+
+/// Writes transactions that are pending after the processing date to a CSV file
+/// at `path` with the columns `type,client,tx,amount,effective_date`, so that the
+/// file can be processed again on a later date.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_pending_transactions_to_file<'a>(
+    pending: impl IntoIterator<Item = (Date, &'a Transaction)>,
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    writer
+        .write_record(["type", "client", "tx", "amount", "effective_date"])
+        .context("Failed to write header record")?;
+    for (date, tx) in pending {
+        writer
+            .write_record([
+                tx.tx_type.as_str(),
+                tx.client.to_string().as_str(),
+                tx.tx.to_string().as_str(),
+                tx.amount.to_string().as_str(),
+                date.to_string().as_str(),
+            ])
+            .with_context(|| format!("Failed to write pending transactions to: {}", path))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush file: {}", path))
+}
+
+/// Writes the overdue disputes to a CSV file at `path` with the columns
+/// `client,tx,amount,opened,age_days` or `...,age_records`, depending on the
+/// unit of `threshold`. The opening date or record number and the age are empty
+/// if unknown.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_dispute_aging_report_to_file(
+    disputes: &[AgedDispute],
+    threshold: AgeThreshold,
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    let age = format!("age_{}", threshold.unit());
+    writer
+        .write_record(["client", "tx", "amount", "opened", age.as_str()])
+        .context("Failed to write header record")?;
+    for dispute in disputes {
+        let opened = dispute.opened.and_then(|opened| match threshold {
+            AgeThreshold::Days(_) => opened.date.map(|date| date.to_string()),
+            AgeThreshold::Records(_) => Some(opened.record.to_string()),
+        });
+        writer
+            .write_record([
+                dispute.client.to_string().as_str(),
+                dispute.tx.to_string().as_str(),
+                dispute.amount.to_string().as_str(),
+                opened.as_deref().unwrap_or_default(),
+                dispute
+                    .age
+                    .map(|age| age.to_string())
+                    .unwrap_or_default()
+                    .as_str(),
+            ])
+            .with_context(|| format!("Failed to write dispute aging report to: {}", path))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush file: {}", path))
+}
+
+/// Writes the rule violations to a CSV file at `path`, one record per violation
+/// with the columns `rule,action,type,client,tx,amount`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be created or written.
+pub fn write_triggered_rules_to_file(
+    triggered: &[TriggeredRule],
+    path: &str,
+    options: &OutputOptions,
+) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create file: {}", path))?;
+    let mut writer = options.csv_writer(file);
+    writer
+        .write_record(["rule", "action", "type", "client", "tx", "amount"])
+        .context("Failed to write header record")?;
+    for TriggeredRule {
+        rule,
+        action,
+        transaction: tx,
+    } in triggered
+    {
+        writer
+            .write_record([
+                rule.as_str(),
+                action.as_str(),
+                tx.tx_type.as_str(),
+                tx.client.to_string().as_str(),
+                tx.tx.to_string().as_str(),
+                tx.amount.to_string().as_str(),
+            ])
+            .with_context(|| format!("Failed to write rule report to: {}", path))?;
+    }
+    writer
+        .flush()
+        .with_context(|| format!("Failed to flush file: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountDetails, AccountMetrics, TxType};
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// The parse paths of [`TransactionReader`]: serde, then
+    /// [`fast_parse`](TransactionReader::fast_parse).
+    const FAST_PARSE: [bool; 2] = [false, true];
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Writes `contents` to a new file in the temporary directory and calls
+    /// `test` with its path. The file is removed afterwards, also if `test`
+    /// panics.
+    pub(crate) fn with_csv<T>(contents: &str, test: impl FnOnce(&str) -> T) -> T {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let file = TempFile(std::env::temp_dir().join(format!(
+            "io-test-{}-{}.csv",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        )));
+        std::fs::write(&file.0, contents).unwrap();
+        test(file.0.to_str().unwrap())
+    }
+
+    #[test]
+    fn test_input_file_reading() {
+        // Test reading transactions from the test-data.csv file
+        let reader = read_transactions_from_file("test-data.csv").unwrap();
+
+        let transactions: Vec<Transaction> = reader.map(|result| result.unwrap()).collect();
+
+        // Verify we read all 9 transactions (excluding header)
+        assert_eq!(transactions.len(), 9);
+
+        // Verify first deposit transaction
+        assert_eq!(transactions[0].tx_type, TxType::Deposit);
+        assert_eq!(transactions[0].client, ClientId(1));
+        assert_eq!(transactions[0].tx, TxId(1));
+        assert_eq!(transactions[0].amount, Amount::from_str("10.0").unwrap());
+
+        // Verify second deposit transaction
+        assert_eq!(transactions[1].tx_type, TxType::Deposit);
+        assert_eq!(transactions[1].client, ClientId(2));
+        assert_eq!(transactions[1].tx, TxId(2));
+        assert_eq!(transactions[1].amount, Amount::from_str("10.0").unwrap());
+
+        // Verify dispute transaction (should have amount = 0 for empty/missing amount)
+        assert_eq!(transactions[2].tx_type, TxType::Dispute);
+        assert_eq!(transactions[2].client, ClientId(1));
+        assert_eq!(transactions[2].tx, TxId(1));
+        assert_eq!(transactions[2].amount, Amount::ZERO);
+
+        // Verify withdrawal transaction
+        assert_eq!(transactions[4].tx_type, TxType::Withdrawal);
+        assert_eq!(transactions[4].client, ClientId(1));
+        assert_eq!(transactions[4].tx, TxId(3));
+        assert_eq!(transactions[4].amount, Amount::from_str("5.0").unwrap());
+
+        // Verify resolve transaction
+        assert_eq!(transactions[6].tx_type, TxType::Resolve);
+        assert_eq!(transactions[6].client, ClientId(1));
+        assert_eq!(transactions[6].tx, TxId(1));
+        assert_eq!(transactions[6].amount, Amount::ZERO);
 
         // Verify chargeback transaction (should have amount = 0 for empty/missing amount)
         assert_eq!(transactions[7].tx_type, TxType::Chargeback);
-        assert_eq!(transactions[7].client, 2);
-        assert_eq!(transactions[7].tx, 2);
-        assert_eq!(transactions[7].amount, Decimal::ZERO);
+        assert_eq!(transactions[7].client, ClientId(2));
+        assert_eq!(transactions[7].tx, TxId(2));
+        assert_eq!(transactions[7].amount, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fast_parse_matches_serde_parse() {
+        for path in ["test-data.csv", "test-data-big.csv"] {
+            let serde: Vec<Transaction> = read_transactions_from_file(path)
+                .unwrap()
+                .map(|result| result.unwrap())
+                .collect();
+            for (fast_parse, parallel_parse) in [(true, false), (false, true), (true, true)] {
+                let other: Vec<Transaction> = read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse)
+                    .parallel_parse(parallel_parse)
+                    .map(|result| result.unwrap())
+                    .collect();
+
+                assert_eq!(serde.len(), other.len());
+                for (expected, actual) in serde.iter().zip(&other) {
+                    assert_eq!(expected.tx_type, actual.tx_type);
+                    assert_eq!(expected.client, actual.client);
+                    assert_eq!(expected.tx, actual.tx);
+                    assert_eq!(expected.amount.to_string(), actual.amount.to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_amounts_are_parsed_without_float_rounding() {
+        with_csv(
+            "type,client,tx,amount\ndeposit,1,1,12345678901234.5678\n",
+            |path| {
+                for fast_parse in FAST_PARSE {
+                    let tx = read_transactions_from_file(path)
+                        .unwrap()
+                        .fast_parse(fast_parse)
+                        .next()
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(tx.amount.to_string(), "12345678901234.5678");
+                }
+            },
+        );
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[test]
+    fn test_wide_ids_round_trip() {
+        with_csv(
+            "type,client,tx,amount\ndeposit,4000000000,10000000000,1.0\n",
+            |path| {
+                for fast_parse in FAST_PARSE {
+                    let tx = read_transactions_from_file(path)
+                        .unwrap()
+                        .fast_parse(fast_parse)
+                        .next()
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(tx.client, ClientId(4_000_000_000));
+                    assert_eq!(tx.tx, TxId(10_000_000_000));
+                }
+            },
+        );
+
+        let accounts = Accounts::from([(ClientId(4_000_000_000), AccountDetails::default())]);
+        let output = write_to_string(accounts, &OutputOptions::default());
+        assert!(output.contains("\n4000000000,"), "{}", output);
+    }
+
+    #[test]
+    fn test_precision_policy_is_applied_when_reading() {
+        let contents = "type,client,tx,amount\ndeposit,1,1,1.00005\ndeposit,1,2,2.5\n";
+        with_csv(contents, |path| {
+            let read = |fast_parse, precision| -> Vec<Result<Transaction>> {
+                read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse)
+                    .precision(precision)
+                    .collect()
+            };
+
+            for fast_parse in FAST_PARSE {
+                let rounded = read(fast_parse, PrecisionPolicy::Round);
+                assert_eq!(rounded[0].as_ref().unwrap().amount.to_string(), "1.0001");
+                let truncated = read(fast_parse, PrecisionPolicy::Truncate);
+                assert_eq!(truncated[0].as_ref().unwrap().amount.to_string(), "1");
+
+                let rejected = read(fast_parse, PrecisionPolicy::Reject);
+                let err = format!("{:#}", rejected[0].as_ref().unwrap_err());
+                assert!(err.contains("line 2"), "{}", err);
+                assert!(err.contains("too many decimal places"), "{}", err);
+                assert_eq!(rejected[1].as_ref().unwrap().amount.to_string(), "2.5");
+            }
+        });
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_negative_amounts() {
+        let contents = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,-1.0\n";
+        with_csv(contents, |path| {
+            let read = |fast_parse, strict| -> Vec<Result<Transaction>> {
+                read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse)
+                    .strict(strict)
+                    .collect()
+            };
+
+            for fast_parse in FAST_PARSE {
+                assert!(read(fast_parse, false).iter().all(Result::is_ok));
+
+                let results = read(fast_parse, true);
+                assert!(results[0].is_ok());
+                let err = format!("{:#}", results[1].as_ref().unwrap_err());
+                assert!(err.contains("line 3"), "{}", err);
+                assert!(err.contains("negative amount '-1'"), "{}", err);
+            }
+        });
+    }
+
+    #[test]
+    fn test_client_profiles_have_optional_columns() {
+        let profiles = with_csv(
+            "client,tier,withdrawal_limit,currency\n1,gold,500,EUR\n2,,,\n",
+            |path| read_client_profiles_from_file(path).unwrap(),
+        );
+        assert_eq!(
+            profiles[&ClientId(1)],
+            ClientProfile {
+                withdrawal_limit: Some(Amount::from_str("500").unwrap()),
+                credit_limit: None,
+                tier: Some("gold".to_string()),
+                currency: Some("EUR".to_string()),
+            }
+        );
+        assert_eq!(profiles[&ClientId(2)], ClientProfile::default());
+
+        let err = with_csv("client,currency\n1,EUR\n2,euro\n", |path| {
+            format!("{:#}", read_client_profiles_from_file(path).unwrap_err())
+        });
+        assert!(err.contains("line 3"), "{}", err);
+        assert!(err.contains("invalid currency 'euro'"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_errors_report_record_line_and_byte() {
+        let error = |contents: &str, (fast_parse, parallel_parse)| {
+            with_csv(contents, |path| {
+                let err = read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse)
+                    .parallel_parse(parallel_parse)
+                    .find_map(Result::err)
+                    .unwrap();
+                format!("{:#}", err)
+            })
+        };
+
+        for mode in [(false, false), (true, false), (true, true)] {
+            // CRLF terminators and a blank line before the bad record.
+            let err = error(
+                "type,client,tx,amount\r\ndeposit,1,1,1.0\r\n\r\ndeposit,x,2,1.0\r\n",
+                mode,
+            );
+            assert!(err.contains("record 2 at line 4 (byte 42)"), "{}", err);
+            assert!(!err.contains("line: "), "{}", err);
+
+            // A quoted field spanning two lines.
+            let err = error(
+                "type,client,tx,amount\ndeposit,1,\"1\n\",1.0\ndeposit,x,2,1.0\n",
+                mode,
+            );
+            assert!(err.contains("record 2 at line 4 (byte 41)"), "{}", err);
+
+            let err = error("type,client\n", mode);
+            assert!(err.contains("Failed to parse header"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_row_window_keeps_line_numbers() {
+        let contents = "type,client,tx,amount\n\
+                        deposit,x,1,1.0\n\
+                        deposit,1,2,1.0\n\
+                        deposit,1,3,1.0\n\
+                        deposit,y,4,1.0\n\
+                        deposit,1,5,1.0\n";
+        with_csv(contents, |path| {
+            let read = |skip, limit, parallel_parse| {
+                read_transactions_from_file(path)
+                    .unwrap()
+                    .parallel_parse(parallel_parse)
+                    .skip_rows(skip)
+                    .limit_rows(limit)
+                    .map(|result| {
+                        result
+                            .map(|tx| tx.tx.0.to_string())
+                            .unwrap_or_else(|err| format!("{:#}", err))
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            for parallel_parse in [false, true] {
+                // The malformed first record is skipped without being parsed.
+                assert_eq!(read(1, Some(2), parallel_parse), ["2", "3"]);
+                let rows = read(2, None, parallel_parse);
+                assert_eq!(rows.len(), 3);
+                assert!(rows[1].contains("record 4 at line 5"), "{}", rows[1]);
+                assert_eq!(read(0, Some(0), parallel_parse), Vec::<String>::new());
+                assert_eq!(read(9, None, parallel_parse), Vec::<String>::new());
+            }
+        });
+    }
+
+    #[test]
+    fn test_malformed_records_are_quarantined() {
+        let contents = "type,client,tx,amount\r\ndeposit,1,1,1.0\r\ndeposit,x,2,1.0\r\nwithdrawal,1,3,\"1,5\"\r\ndeposit,2,4,2.0\r\n";
+        with_csv(contents, |path| {
+            for (fast_parse, parallel_parse) in [(false, false), (true, false), (false, true)] {
+                let contents = with_csv("", |quarantine_path| {
+                    let mut quarantine = Quarantine::create(quarantine_path).unwrap();
+                    let reader = read_transactions_from_file(path)
+                        .unwrap()
+                        .fast_parse(fast_parse)
+                        .parallel_parse(parallel_parse);
+                    let txs: Vec<Transaction> = skip_malformed(reader, Some(&mut quarantine))
+                        .collect::<Result<_>>()
+                        .unwrap();
+                    assert_eq!(
+                        txs.iter().map(|tx| tx.tx).collect::<Vec<_>>(),
+                        vec![TxId(1), TxId(4)]
+                    );
+                    assert_eq!(quarantine.count(), 2);
+                    quarantine.flush().unwrap();
+                    std::fs::read_to_string(quarantine_path).unwrap()
+                });
+
+                let lines: Vec<&str> = contents.lines().collect();
+                assert_eq!(lines.len(), 2, "{}", contents);
+                assert!(lines[0].starts_with("deposit,x,2,1.0,\""), "{}", lines[0]);
+                assert!(lines[0].contains("line 3"), "{}", lines[0]);
+                assert!(
+                    lines[1].starts_with("withdrawal,1,3,\"1,5\",\""),
+                    "{}",
+                    lines[1]
+                );
+                assert!(lines[1].contains("line 4"), "{}", lines[1]);
+            }
+
+            let reader = read_transactions_from_file(path).unwrap();
+            assert!(skip_malformed(reader, None).any(|result| result.is_err()));
+        });
+    }
+
+    #[test]
+    fn test_headers_are_matched_flexibly() {
+        let read = |contents: &str, fast_parse, alias: Option<(&str, TransactionColumn)>| {
+            with_csv(contents, |path| {
+                let mut reader = read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse);
+                if let Some((alias, column)) = alias {
+                    reader = reader.column_alias(alias, column);
+                }
+                reader.collect::<Result<Vec<Transaction>>>()
+            })
+        };
+
+        for fast_parse in FAST_PARSE {
+            let transactions = read(
+                "Amount,Client ID,Note,TX,Type\n2.5,7,hello,3,deposit\n",
+                fast_parse,
+                None,
+            )
+            .unwrap();
+            assert_eq!(transactions[0].tx_type, TxType::Deposit);
+            assert_eq!(transactions[0].client, ClientId(7));
+            assert_eq!(transactions[0].tx, TxId(3));
+            assert_eq!(transactions[0].amount, Amount::from_str("2.5").unwrap());
+
+            let disputes = read("type,client,tx\ndispute,1,1\n", fast_parse, None).unwrap();
+            assert_eq!(disputes[0].amount, Amount::ZERO);
+
+            let aliased = read(
+                "Kind,client,tx,amount\nwithdrawal,1,2,1.0\n",
+                fast_parse,
+                Some(("kind", TransactionColumn::Type)),
+            )
+            .unwrap();
+            assert_eq!(aliased[0].tx_type, TxType::Withdrawal);
+
+            let err = read("Kind,client,tx\ndispute,1,1\n", fast_parse, None).unwrap_err();
+            assert!(
+                format!("{:#}", err).contains("Missing column 'type'"),
+                "{:#}",
+                err
+            );
+
+            let err = read(
+                "client,client_id,type,tx\n1,1,dispute,1\n",
+                fast_parse,
+                None,
+            )
+            .unwrap_err();
+            assert!(
+                format!("{:#}", err)
+                    .contains("'client' and 'client_id' both map to column 'client'"),
+                "{:#}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_delimiter_detection() {
+        assert_eq!(detect_delimiter(b"type,client,tx,amount\n"), b',');
+        assert_eq!(detect_delimiter(b"type\tclient\ttx\tamount\n"), b'\t');
+        assert_eq!(detect_delimiter(b"type;client;tx;\"amount, EUR\"\n"), b';');
+        assert_eq!(detect_delimiter(b"type|client,tx|amount"), b'|');
+        assert_eq!(detect_delimiter(b"type,client;tx"), b',');
+        assert_eq!(detect_delimiter(b"type"), b',');
+
+        assert_eq!("auto".parse(), Ok(Delimiter::Auto));
+        assert_eq!("tab".parse(), Ok(Delimiter::Byte(b'\t')));
+        assert_eq!(";".parse(), Ok(Delimiter::Byte(b';')));
+        assert!("::".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1048576"), Ok(1 << 20));
+        assert_eq!(parse_byte_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_byte_size("512m"), Ok(512 << 20));
+        assert_eq!(parse_byte_size("64 KiB"), Ok(64 << 10));
+        for invalid in ["", "G", "1.5G", "2T", "-1", "99999999999999999999"] {
+            assert!(parse_byte_size(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_delimited_input_and_output() {
+        with_csv("type\tclient\ttx\tamount\ndeposit\t1\t1\t2.5\n", |path| {
+            for fast_parse in FAST_PARSE {
+                let transactions: Vec<Transaction> = read_transactions_from_file(path)
+                    .unwrap()
+                    .fast_parse(fast_parse)
+                    .collect::<Result<_>>()
+                    .unwrap();
+                assert_eq!(transactions[0].amount, Amount::from_str("2.5").unwrap());
+            }
+            let explicit = read_transactions_from_file_with_delimiter(path, Delimiter::Byte(b','))
+                .unwrap()
+                .next()
+                .unwrap();
+            assert!(explicit.is_err());
+        });
+
+        let accounts = Accounts::from([(ClientId(1), AccountDetails::default())]);
+        let options = OutputOptions {
+            delimiter: b';',
+            ..Default::default()
+        };
+        assert_eq!(
+            write_to_string(accounts, &options),
+            "client;available;held;total;locked\n1;0;0;0;false\n"
+        );
+    }
+
+    #[test]
+    fn test_fast_parse_reports_invalid_records() {
+        let contents = "type,client,tx,amount\ndeposit,1,1,1.0\ntransfer,1,2,1.0\n";
+        let results: Vec<Result<Transaction>> = with_csv(contents, |path| {
+            read_transactions_from_file(path)
+                .unwrap()
+                .fast_parse(true)
+                .collect()
+        });
+
+        assert!(results[0].is_ok());
+        let err = format!("{:#}", results[1].as_ref().unwrap_err());
+        assert!(err.contains("line 3"), "{}", err);
+        assert!(
+            err.contains("unknown transaction type 'transfer'"),
+            "{}",
+            err
+        );
+    }
+
+    fn write_to_string(accounts: Accounts, options: &OutputOptions) -> String {
+        let mut output = Vec::new();
+        write_accounts_as_csv(accounts, &mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_default_output_vocabulary() {
+        let mut account = AccountDetails::new_with_balance(Amount::from_str("1.5").unwrap());
+        account.locked = true;
+        let accounts = Accounts::from([(ClientId(1), account)]);
+
+        let output = write_to_string(accounts, &OutputOptions::default());
+
+        assert_eq!(
+            output,
+            "client,available,held,total,locked\n1,1.5,0,1.5,true\n"
+        );
+    }
+
+    #[test]
+    fn test_custom_output_vocabulary() {
+        let accounts = Accounts::from([
+            (ClientId(1), AccountDetails::new_with_balance(Amount::ONE)),
+            (
+                ClientId(2),
+                AccountDetails {
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let options = OutputOptions {
+            bool_style: BoolStyle::YesNo,
+            header_names: BTreeMap::from([
+                ("client".to_string(), "Client ID".to_string()),
+                ("locked".to_string(), "frozen".to_string()),
+            ]),
+            ..Default::default()
+        };
+
+        let output = write_to_string(accounts, &options);
+
+        assert_eq!(
+            output,
+            "Client ID,available,held,total,frozen\n1,1,0,1,no\n2,0,0,0,yes\n"
+        );
+    }
+
+    #[test]
+    fn test_extended_output_columns() {
+        let mut account = AccountDetails::new_with_balance(Amount::ONE);
+        account.metrics = AccountMetrics {
+            deposits: 2,
+            withdrawals: 1,
+            active_disputes: 1,
+            chargebacks: 0,
+            last_activity: 7,
+            ..Default::default()
+        };
+        let closed = AccountDetails {
+            closed: true,
+            tier: Some("gold".to_string()),
+            currency: Some("EUR".to_string()),
+            ..Default::default()
+        };
+        let accounts = Accounts::from([(ClientId(1), account), (ClientId(2), closed)]);
+        let options = OutputOptions {
+            header_names: BTreeMap::from([("last_activity".to_string(), "seen".to_string())]),
+            extended: true,
+            ..Default::default()
+        };
+
+        let output = write_to_string(accounts, &options);
+
+        assert_eq!(
+            output,
+            "client,available,held,total,locked,deposits,withdrawals,active_disputes,chargebacks,seen,closed,tier,currency\n\
+             1,1,0,1,false,2,1,1,0,7,false,,\n\
+             2,0,0,0,false,0,0,0,0,,true,gold,EUR\n"
+        );
+    }
+
+    #[test]
+    fn test_unknown_output_column_is_rejected() {
+        let options = OutputOptions {
+            header_names: BTreeMap::from([("balance".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        let result = write_accounts_as_csv(Accounts::new(), &mut output, &options);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_written_accounts_can_be_read_back() {
+        let accounts = Accounts::from([
+            (
+                ClientId(1),
+                AccountDetails {
+                    client: ClientId(1),
+                    ..AccountDetails::new_with_balance(Amount::from_str("1.5").unwrap())
+                },
+            ),
+            (
+                ClientId(2),
+                AccountDetails {
+                    client: ClientId(2),
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let options = OutputOptions {
+            bool_style: BoolStyle::OneZero,
+            header_names: BTreeMap::from([("locked".to_string(), "frozen".to_string())]),
+            ..Default::default()
+        };
+        let read = with_csv("", |path| {
+            write_accounts_as_csv_to_file(accounts.clone(), path, &options).unwrap();
+            read_accounts_from_file(path).unwrap()
+        });
+
+        assert_eq!(read, accounts);
     }
 }
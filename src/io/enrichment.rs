@@ -0,0 +1,181 @@
+//! Client metadata joined to the account output.
+//!
+//! An enrichment file maps client IDs to metadata such as names or external
+//! references. It has a `client` column and any number of further columns,
+//! which [`AccountWriter`](super::AccountWriter) appends to every account row
+//! in file order:
+//!
+//! ```csv
+//! client,name,external_ref
+//! 1,Alice,CRM-0042
+//! 2,Bob,CRM-0043
+//! ```
+//!
+//! Accounts of clients missing from the file get empty fields, and rows of
+//! clients without an account are not written.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{ACCOUNT_COLUMNS, Delimiter, EXTENDED_ACCOUNT_COLUMNS, open_csv_file};
+use crate::types::ClientId;
+
+/// Metadata columns by client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Enrichment {
+    columns: Vec<String>,
+    rows: HashMap<ClientId, Vec<String>>,
+}
+
+impl Enrichment {
+    /// Returns the names of the metadata columns.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Returns the metadata fields of `client`, which are empty for clients
+    /// missing from the file.
+    pub fn fields(&self, client: ClientId) -> impl Iterator<Item = &str> {
+        let row = self.rows.get(&client);
+        (0..self.columns.len()).map(move |index| row.map_or("", |row| row[index].as_str()))
+    }
+}
+
+/// Reads an enrichment file with a `client` column and the metadata columns to
+/// join.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened, has no `client` column, has
+/// a metadata column named like an account column, or a row fails to parse or
+/// repeats a client.
+pub fn read_enrichment_file(path: &str) -> Result<Enrichment> {
+    let (file, delimiter) = open_csv_file(path, Delimiter::Auto)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(delimiter)
+        .from_reader(file);
+
+    let headers = reader
+        .headers()
+        .with_context(|| format!("Failed to read header from: {}", path))?
+        .clone();
+    let client_column = headers
+        .iter()
+        .position(|name| name == "client")
+        .with_context(|| format!("Missing client column in: {}", path))?;
+    let columns: Vec<String> = headers
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| index != client_column)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if let Some(column) = columns.iter().find(|column| {
+        ACCOUNT_COLUMNS
+            .iter()
+            .chain(&EXTENDED_ACCOUNT_COLUMNS)
+            .any(|known| known == column)
+    }) {
+        anyhow::bail!(
+            "Enrichment column '{}' in {} clashes with an account column",
+            column,
+            path
+        );
+    }
+
+    let mut rows = HashMap::new();
+    for (index, result) in reader.records().enumerate() {
+        let row = result.map_err(anyhow::Error::from).and_then(|record| {
+            let client = record[client_column].parse::<ClientId>().map_err(|err| {
+                anyhow::anyhow!("invalid client '{}': {}", &record[client_column], err)
+            })?;
+            let fields = record
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| index != client_column)
+                .map(|(_, field)| field.to_string())
+                .collect();
+            anyhow::ensure!(
+                rows.insert(client, fields).is_none(),
+                "client {} appears more than once",
+                client
+            );
+            Ok(())
+        });
+        row.with_context(|| {
+            format!(
+                "Failed to parse enrichment at line {} from: {}",
+                index + 2,
+                path
+            )
+        })?;
+    }
+
+    Ok(Enrichment { columns, rows })
+}
+
+/// Reads an enrichment file for a command line argument.
+pub fn parse_enrichment_file(path: &str) -> Result<Arc<Enrichment>, String> {
+    read_enrichment_file(path)
+        .map(Arc::new)
+        .map_err(|err| format!("{:#}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::with_csv;
+    use crate::io::{OutputOptions, write_accounts_as_csv};
+    use crate::types::{AccountDetails, Accounts};
+
+    #[test]
+    fn reads_metadata_columns_around_the_client_column() {
+        let enrichment = with_csv(
+            "name,client,external_ref\nAlice,1,CRM-1\n\"Doe, Bob\",2,\n",
+            |path| read_enrichment_file(path).unwrap(),
+        );
+        assert_eq!(enrichment.columns(), ["name", "external_ref"]);
+        let fields = |client| enrichment.fields(ClientId(client)).collect::<Vec<_>>();
+        assert_eq!(fields(1), ["Alice", "CRM-1"]);
+        assert_eq!(fields(2), ["Doe, Bob", ""]);
+        assert_eq!(fields(3), ["", ""]);
+
+        for (contents, error) in [
+            ("name\nAlice\n", "Missing client column"),
+            ("client,locked\n1,no\n", "'locked' in"),
+            ("client,name\n1,Alice\n1,Bob\n", "line 3"),
+            ("client,name\nx,Alice\n", "invalid client 'x'"),
+        ] {
+            let err = with_csv(contents, |path| {
+                format!("{:#}", read_enrichment_file(path).unwrap_err())
+            });
+            assert!(err.contains(error), "{}", err);
+        }
+    }
+
+    #[test]
+    fn appends_metadata_to_account_rows() {
+        let enrichment = Enrichment {
+            columns: vec!["name".to_string()],
+            rows: HashMap::from([(ClientId(2), vec!["Bob".to_string()])]),
+        };
+        let accounts = Accounts::from([
+            (ClientId(1), AccountDetails::default()),
+            (ClientId(2), AccountDetails::default()),
+        ]);
+        let options = OutputOptions {
+            enrichment: Some(Arc::new(enrichment)),
+            ..Default::default()
+        };
+
+        let mut output = Vec::new();
+        write_accounts_as_csv(accounts, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked,name\n\
+             1,0,0,0,false,\n\
+             2,0,0,0,false,Bob\n"
+        );
+    }
+}
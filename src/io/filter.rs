@@ -0,0 +1,164 @@
+//! Client filters applied to transactions at ingest.
+//!
+//! A [`ClientFilter`] selects the clients whose transactions reach the engine,
+//! so per-business-unit account outputs can be produced from a shared
+//! transaction dump. Transactions of other clients are dropped as if they were
+//! not in the input; malformed records are passed on as errors regardless of
+//! their client.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+
+use super::TransactionSource;
+use crate::recurring::Recurrence;
+use crate::types::{ClientId, Date, Transaction};
+
+/// Clients whose transactions are processed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientFilter {
+    /// Only process these clients; `None` means every client.
+    pub only: Option<BTreeSet<ClientId>>,
+    /// Never process these clients, even if listed in `only`.
+    pub exclude: BTreeSet<ClientId>,
+}
+
+impl ClientFilter {
+    /// Returns `true` if the transactions of `client` are processed.
+    pub fn allows(&self, client: ClientId) -> bool {
+        self.only.as_ref().is_none_or(|only| only.contains(&client))
+            && !self.exclude.contains(&client)
+    }
+
+    /// Returns `true` if the filter lets every client through.
+    pub fn is_empty(&self) -> bool {
+        self.only.is_none() && self.exclude.is_empty()
+    }
+}
+
+/// Parses client IDs given as a comma-separated list such as `1,2,3`, or
+/// otherwise reads them from the file at `value`, separated by commas or line
+/// breaks. Blank lines and lines starting with `#` are ignored.
+///
+/// # Errors
+///
+/// Returns an error if `value` is neither a list of IDs nor a readable file of
+/// them.
+pub fn parse_client_ids(value: &str) -> Result<BTreeSet<ClientId>> {
+    if let Ok(ids) = parse_id_list(value) {
+        return Ok(ids);
+    }
+    let contents = std::fs::read_to_string(value)
+        .with_context(|| format!("Failed to open file: {}", value))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .try_fold(BTreeSet::new(), |mut ids, (index, line)| {
+            ids.extend(parse_id_list(line).with_context(|| {
+                format!(
+                    "Failed to parse line {} of client list: {}",
+                    index + 1,
+                    value
+                )
+            })?);
+            Ok(ids)
+        })
+}
+
+/// Parses a comma-separated list of client IDs.
+fn parse_id_list(list: &str) -> Result<BTreeSet<ClientId>> {
+    list.split(',')
+        .map(|id| {
+            let id = id.trim();
+            id.parse::<ClientId>()
+                .map_err(|_| anyhow::anyhow!("invalid client ID '{}'", id))
+        })
+        .collect()
+}
+
+/// Wraps `source` so that only the transactions of clients allowed by `filter`
+/// are returned.
+pub fn filter_clients<S: TransactionSource>(source: S, filter: ClientFilter) -> FilterClients<S> {
+    FilterClients { source, filter }
+}
+
+/// A [`TransactionSource`] dropping the transactions of filtered clients,
+/// created by [`filter_clients`].
+pub struct FilterClients<S> {
+    source: S,
+    filter: ClientFilter,
+}
+
+impl<S: TransactionSource> Iterator for FilterClients<S> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.source.next()? {
+                Ok(tx) if !self.filter.allows(tx.client) => continue,
+                result => return Some(result),
+            }
+        }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for FilterClients<S> {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        self.source.raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        self.source.delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.source.effective_date()
+    }
+
+    fn recurrence(&self) -> Option<Recurrence> {
+        self.source.recurrence()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.source.bytes_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::read_transactions_from_file;
+    use crate::io::tests::with_csv;
+
+    #[test]
+    fn filters_clients_from_lists_and_files() {
+        assert_eq!(
+            parse_client_ids(" 1, 2").unwrap(),
+            BTreeSet::from([ClientId(1), ClientId(2)])
+        );
+        let exclude = with_csv("# business unit A\n2\n\n3, 4\n", |list| {
+            parse_client_ids(list).unwrap()
+        });
+        assert_eq!(
+            exclude,
+            BTreeSet::from([ClientId(2), ClientId(3), ClientId(4)])
+        );
+        assert!(parse_client_ids("1,x").is_err());
+
+        let filter = ClientFilter {
+            only: Some(BTreeSet::from([ClientId(1), ClientId(2)])),
+            exclude,
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     deposit,2,2,1.0\n\
+                     deposit,x,3,1.0\n\
+                     deposit,3,4,1.0\n";
+        let results: Vec<_> = with_csv(input, |path| {
+            filter_clients(read_transactions_from_file(path).unwrap(), filter).collect()
+        });
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().client, ClientId(1));
+        assert!(results[1].is_err());
+    }
+}
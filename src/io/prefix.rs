@@ -0,0 +1,192 @@
+//! Transaction ID namespaces of merged inputs (`--source-prefix`).
+//!
+//! Inputs from different acquirers may use the same transaction IDs, so a
+//! dispute in one file could resolve against the deposit of another. A
+//! [`SourcePrefix`] maps the IDs of one input into a range of its own by
+//! placing the prefix in the top [`PREFIX_BITS`] bits of every ID:
+//!
+//! | Build | Prefixes | IDs per source |
+//! |-------|----------|----------------|
+//! | default | 0 to 255 | 0 to 16777215 |
+//! | `wide-ids` | 0 to 65535 | 0 to 2^48 - 1 |
+//!
+//! Prefix 0 leaves the IDs unchanged, so a file of the original source can
+//! keep its IDs while files merged into it get prefixes of their own.
+
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+use super::TransactionSource;
+use crate::recurring::Recurrence;
+use crate::types::{Date, RawTxId, Transaction, TxId};
+
+/// Number of bits of a transaction ID holding the prefix.
+pub const PREFIX_BITS: u32 = RawTxId::BITS / 4;
+
+/// Number of bits of a transaction ID left to the IDs of a source.
+const ID_BITS: u32 = RawTxId::BITS - PREFIX_BITS;
+
+/// The namespace of the transaction IDs of one input, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourcePrefix(RawTxId);
+
+impl SourcePrefix {
+    /// The largest prefix.
+    pub const MAX: SourcePrefix = SourcePrefix((1 << PREFIX_BITS) - 1);
+
+    /// The largest transaction ID that can be prefixed.
+    pub const MAX_ID: TxId = TxId((1 << ID_BITS) - 1);
+
+    /// Returns the prefix `value`, or `None` if it exceeds [`SourcePrefix::MAX`].
+    pub fn new(value: RawTxId) -> Option<Self> {
+        (value <= SourcePrefix::MAX.0).then_some(SourcePrefix(value))
+    }
+
+    /// Maps `tx` into the range of this prefix, or returns `None` if it exceeds
+    /// [`SourcePrefix::MAX_ID`].
+    pub fn apply(self, tx: TxId) -> Option<TxId> {
+        (tx <= SourcePrefix::MAX_ID).then_some(TxId(self.0 << ID_BITS | tx.0))
+    }
+
+    /// Splits a mapped transaction ID into its prefix and the ID in its source.
+    pub fn split(tx: TxId) -> (SourcePrefix, TxId) {
+        (
+            SourcePrefix(tx.0 >> ID_BITS),
+            TxId(tx.0 & SourcePrefix::MAX_ID.0),
+        )
+    }
+}
+
+impl FromStr for SourcePrefix {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .trim()
+            .parse()
+            .ok()
+            .and_then(SourcePrefix::new)
+            .ok_or_else(|| {
+                format!(
+                    "expected a prefix from 0 to {}, got '{}'",
+                    SourcePrefix::MAX,
+                    value
+                )
+            })
+    }
+}
+
+impl fmt::Display for SourcePrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Wraps `source` so that the transaction IDs it returns are mapped by
+/// `prefix`.
+pub fn prefix_tx_ids<S: TransactionSource>(source: S, prefix: SourcePrefix) -> PrefixTxIds<S> {
+    PrefixTxIds { source, prefix }
+}
+
+/// A [`TransactionSource`] mapping transaction IDs into the range of a
+/// [`SourcePrefix`], created by [`prefix_tx_ids`]. Transactions with an ID
+/// above [`SourcePrefix::MAX_ID`] are returned as errors.
+pub struct PrefixTxIds<S> {
+    source: S,
+    prefix: SourcePrefix,
+}
+
+impl<S: TransactionSource> Iterator for PrefixTxIds<S> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.source.next()?.and_then(|tx| {
+            let id = self.prefix.apply(tx.tx).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "transaction ID {} exceeds {}, the largest ID of a prefixed source",
+                    tx.tx,
+                    SourcePrefix::MAX_ID
+                )
+            })?;
+            Ok(Transaction { tx: id, ..tx })
+        }))
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for PrefixTxIds<S> {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        self.source.raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        self.source.delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.source.effective_date()
+    }
+
+    fn recurrence(&self) -> Option<Recurrence> {
+        self.source.recurrence()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.source.bytes_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{ApplyOutcome, Engine};
+    use crate::io::read_transactions_from_file;
+    use crate::io::tests::with_csv;
+    use crate::types::{Amount, ClientId};
+
+    #[test]
+    fn prefixed_sources_keep_colliding_ids_apart() {
+        assert_eq!("0".parse(), Ok(SourcePrefix::default()));
+        let max = SourcePrefix::MAX.to_string();
+        assert_eq!(max.parse(), Ok(SourcePrefix::MAX));
+        assert!(format!("{}0", max).parse::<SourcePrefix>().is_err());
+        let prefix = SourcePrefix::new(2).unwrap();
+        let mapped = prefix.apply(TxId(7)).unwrap();
+        assert_eq!(SourcePrefix::split(mapped), (prefix, TxId(7)));
+        assert_eq!(prefix.apply(TxId(SourcePrefix::MAX_ID.0 + 1)), None);
+
+        // Both acquirers use ID 1, and the first one disputes its deposit after
+        // the second one's arrived.
+        let files = [
+            ("deposit,1,1,10.0\n".to_string(), 0),
+            (
+                format!(
+                    "deposit,2,1,5.0\ndeposit,2,{},1.0\n",
+                    SourcePrefix::MAX_ID.0 + 1
+                ),
+                1,
+            ),
+            ("dispute,1,1,\n".to_string(), 0),
+        ];
+        let mut engine = Engine::default();
+        let mut outcomes = Vec::new();
+        for (rows, prefix) in files {
+            with_csv(&format!("type,client,tx,amount\n{}", rows), |path| {
+                let reader = read_transactions_from_file(path).unwrap();
+                for result in prefix_tx_ids(reader, SourcePrefix::new(prefix).unwrap()) {
+                    outcomes.push(result.map(|tx| engine.process(tx).unwrap().outcome()).ok());
+                }
+            });
+        }
+
+        let applied = Some(ApplyOutcome::Applied);
+        assert_eq!(outcomes, [applied, applied, None, applied]);
+        let accounts = engine.accounts();
+        assert_eq!(accounts[&ClientId(1)].held, Amount::from_str("10").unwrap());
+        assert_eq!(
+            accounts[&ClientId(2)].available,
+            Amount::from_str("5").unwrap()
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Event ledger of account changes.
+//!
+//! A ledger is a JSON Lines file with one event per change the engine made to an
+//! account, in the order the changes were applied. Every event states how it
+//! changed the available, held and total balance of the client, and whether it
+//! locked or closed the account:
+//!
+//! ```json
+//! {"seq":1,"event":"deposit","client":1,"tx":1,"available":"10","held":"0","total":"10"}
+//! {"seq":2,"event":"dispute","client":1,"tx":1,"available":"-10","held":"10","total":"0"}
+//! {"seq":3,"event":"chargeback","client":1,"tx":1,"available":"0","held":"-10","total":"-10","locked":true}
+//! ```
+//!
+//! A reversal names the type of the transaction it undid, which shares its
+//! `tx`, so the compensating entry can be traced back to the original one:
+//!
+//! ```json
+//! {"seq":4,"event":"withdrawal","client":2,"tx":7,"available":"-5","held":"0","total":"-5"}
+//! {"seq":5,"event":"reversal","client":2,"tx":7,"available":"5","held":"0","total":"5","reverses":"withdrawal"}
+//! ```
+//!
+//! The balance changes are derived from the engine's [`Decision`]s independently
+//! of [`apply`](crate::engine::apply), so [`replay_ledger`] reconstructing the
+//! accounts from the events alone and comparing them with the account output
+//! catches any divergence between the two. Accounts restored from a snapshot
+//! start with an `opening` event holding their balances.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use crate::engine::{Decision, Engine, ExpiredAuthorization};
+use crate::types::{AccountDetails, Accounts, Amount, ClientId, TxId, TxType};
+
+/// A change of one account, as written to the ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEvent {
+    /// Position of the event in the ledger, starting at 1.
+    pub seq: u64,
+    /// What changed the account, e.g. `deposit` or `authorization_expired`.
+    pub event: String,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// Change of the available balance.
+    pub available: Amount,
+    /// Change of the held balance.
+    pub held: Amount,
+    /// Change of the total balance.
+    pub total: Amount,
+    /// Whether the event locked the account.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+    /// Whether the event closed the account.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub closed: bool,
+    /// The type of the transaction `tx` that a reversal undid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverses: Option<TxType>,
+}
+
+impl LedgerEvent {
+    fn new(event: &str, client: ClientId, tx: TxId) -> Self {
+        LedgerEvent {
+            seq: 0,
+            event: event.to_string(),
+            client,
+            tx,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
+            locked: false,
+            closed: false,
+            reverses: None,
+        }
+    }
+
+    /// Sets the balance changes from their amounts, each of which is either
+    /// added (`1`), subtracted (`-1`) or left out (`0`).
+    fn changes(mut self, amount: Amount, [available, held, total]: [i8; 3]) -> Result<Self> {
+        let change = |sign: i8| match sign {
+            1 => Some(amount),
+            -1 => Amount::ZERO.checked_sub(amount),
+            _ => Some(Amount::ZERO),
+        };
+        let overflow = || anyhow::anyhow!("Overflow in ledger event of tx {}", self.tx);
+        self.available = change(available).ok_or_else(overflow)?;
+        self.held = change(held).ok_or_else(overflow)?;
+        self.total = change(total).ok_or_else(overflow)?;
+        Ok(self)
+    }
+}
+
+/// Returns the ledger event of an applied `decision`, or `None` if it leaves the
+/// accounts unchanged. The `seq` of the event is left at zero.
+///
+/// # Errors
+///
+/// Returns an error if negating an amount overflows.
+pub fn event_for(decision: &Decision) -> Result<Option<LedgerEvent>> {
+    let event = match *decision {
+        Decision::Deposit { client, tx, amount } => {
+            LedgerEvent::new("deposit", client, tx).changes(amount, [1, 0, 1])?
+        }
+        Decision::Withdraw { client, tx, amount } => {
+            LedgerEvent::new("withdrawal", client, tx).changes(amount, [-1, 0, -1])?
+        }
+        Decision::Hold { client, tx, amount } => {
+            LedgerEvent::new("dispute", client, tx).changes(amount, [-1, 1, 0])?
+        }
+        Decision::Release { client, tx, amount } => {
+            LedgerEvent::new("resolve", client, tx).changes(amount, [1, -1, 0])?
+        }
+        Decision::Chargeback {
+            client,
+            tx,
+            amount,
+            lock,
+        } => LedgerEvent {
+            locked: lock,
+            ..LedgerEvent::new("chargeback", client, tx).changes(amount, [0, -1, -1])?
+        },
+        Decision::Refund { client, tx, amount } => {
+            LedgerEvent::new("refund", client, tx).changes(amount, [-1, 0, -1])?
+        }
+        Decision::Authorize { client, tx, amount } => {
+            LedgerEvent::new("authorize", client, tx).changes(amount, [0, 1, 1])?
+        }
+        Decision::Capture {
+            client,
+            tx,
+            amount,
+            released,
+        } => {
+            let settled = amount
+                .checked_add(released)
+                .context("Overflow in ledger event of a capture")?;
+            let released = LedgerEvent::new("capture", client, tx).changes(released, [0, 0, -1])?;
+            let settled = LedgerEvent::new("capture", client, tx).changes(settled, [0, -1, 0])?;
+            LedgerEvent {
+                available: amount,
+                held: settled.held,
+                ..released
+            }
+        }
+        Decision::EscrowHold { client, tx, amount } => {
+            LedgerEvent::new("hold", client, tx).changes(amount, [-1, 1, 0])?
+        }
+        Decision::EscrowRelease { client, tx, amount } => {
+            LedgerEvent::new("release", client, tx).changes(amount, [1, -1, 0])?
+        }
+        Decision::Interest { client, tx, amount } => {
+            LedgerEvent::new("interest", client, tx).changes(amount, [1, 0, 1])?
+        }
+        Decision::Reversal {
+            client,
+            tx,
+            amount,
+            reversed,
+        } => {
+            let sign = if reversed == TxType::Withdrawal {
+                1
+            } else {
+                -1
+            };
+            LedgerEvent {
+                reverses: Some(reversed),
+                ..LedgerEvent::new("reversal", client, tx).changes(amount, [sign, 0, sign])?
+            }
+        }
+        Decision::Close { client, tx } => LedgerEvent {
+            closed: true,
+            ..LedgerEvent::new("close", client, tx)
+        },
+        Decision::Open {
+            client,
+            tx,
+            amount,
+            locked,
+        } => LedgerEvent {
+            locked,
+            ..LedgerEvent::new("open", client, tx).changes(amount, [1, 0, 1])?
+        },
+        Decision::Ignore(_) => return Ok(None),
+    };
+    Ok(Some(event))
+}
+
+/// Returns the ledger event of an authorization released by expiry.
+///
+/// # Errors
+///
+/// Returns an error if negating the amount overflows.
+pub fn expiry_event(expired: &ExpiredAuthorization) -> Result<LedgerEvent> {
+    LedgerEvent::new("authorization_expired", expired.client, expired.tx)
+        .changes(expired.amount, [0, -1, -1])
+}
+
+/// Writes ledger events to a JSON Lines file.
+pub struct LedgerWriter {
+    writer: BufWriter<File>,
+    path: String,
+    /// Number of events written so far.
+    seq: u64,
+}
+
+impl LedgerWriter {
+    /// Creates (or truncates) the ledger at `path` and writes an `opening` event
+    /// for every account `engine` already has, e.g. from a snapshot.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created or written.
+    pub fn create(path: &str, engine: &Engine) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create ledger: {}", path))?;
+        let mut ledger = LedgerWriter {
+            writer: BufWriter::new(file),
+            path: path.to_string(),
+            seq: 0,
+        };
+        for (client, account) in crate::types::sorted_accounts(engine.accounts()) {
+            ledger.write(LedgerEvent {
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.locked,
+                closed: account.closed,
+                ..LedgerEvent::new("opening", client, TxId::default())
+            })?;
+        }
+        Ok(ledger)
+    }
+
+    /// Writes the events of the last call to [`Engine::process`] on `engine`,
+    /// which returned `decision`: the authorizations that expired before it and
+    /// the change made by the decision itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    pub fn record(&mut self, engine: &Engine, decision: &Decision) -> Result<()> {
+        for expired in engine.expired_authorizations() {
+            self.write(expiry_event(expired)?)?;
+        }
+        if let Some(event) = event_for(decision)? {
+            self.write(event)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, event: LedgerEvent) -> Result<()> {
+        self.seq += 1;
+        let event = LedgerEvent {
+            seq: self.seq,
+            ..event
+        };
+        serde_json::to_writer(&mut self.writer, &event)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| Ok(self.writer.write_all(b"\n")?))
+            .with_context(|| format!("Failed to write to ledger: {}", self.path))
+    }
+
+    /// Flushes the buffered events to the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to flush ledger: {}", self.path))
+    }
+}
+
+/// Reconstructs the accounts from the ledger at `path`, summing up the balance
+/// changes of every client.
+///
+/// The accounts hold the columns of the account output: balances and the lock
+/// flag.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read, an event cannot be parsed, an
+/// event is missing or out of order, or a balance overflows.
+pub fn replay_ledger(path: &str) -> Result<Accounts> {
+    let file = File::open(path).with_context(|| format!("Failed to open ledger: {}", path))?;
+    let mut accounts = Accounts::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.with_context(|| format!("Failed to read ledger: {}", path))?;
+        let event: LedgerEvent = serde_json::from_str(&line).with_context(|| {
+            format!(
+                "Failed to parse ledger event at line {} from: {}",
+                line_number, path
+            )
+        })?;
+        anyhow::ensure!(
+            event.seq == line_number as u64,
+            "Ledger event {} found at line {} of {}; events are missing or reordered",
+            event.seq,
+            line_number,
+            path
+        );
+
+        let account = accounts
+            .entry(event.client)
+            .or_insert_with(|| AccountDetails::<Amount> {
+                client: event.client,
+                ..Default::default()
+            });
+        let overflow = || {
+            anyhow::anyhow!(
+                "Balance overflow in ledger event {} from: {}",
+                event.seq,
+                path
+            )
+        };
+        account.available = account
+            .available
+            .checked_add(event.available)
+            .ok_or_else(overflow)?;
+        account.held = account.held.checked_add(event.held).ok_or_else(overflow)?;
+        account.total = account
+            .total
+            .checked_add(event.total)
+            .ok_or_else(overflow)?;
+        account.locked |= event.locked;
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff::diff_accounts;
+    use crate::engine::EngineConfig;
+    use crate::types::{Transaction, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn replayed_ledger_matches_the_engine_state() {
+        let path = std::env::temp_dir().join(format!("ledger-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap_or(Amount::ZERO),
+        };
+
+        let mut engine = Engine::new(EngineConfig {
+            authorization_expiry: Some(3),
+            ..Default::default()
+        });
+        engine.process(tx(TxType::Deposit, 1, 1, "10")).unwrap();
+        let mut ledger = LedgerWriter::create(path, &engine).unwrap();
+        for transaction in [
+            tx(TxType::Authorize, 2, 2, "8"),
+            tx(TxType::Capture, 2, 2, "5"),
+            tx(TxType::Authorize, 2, 3, "4"),
+            tx(TxType::Dispute, 1, 1, "4"),
+            tx(TxType::Withdrawal, 1, 4, "1"),
+            tx(TxType::Hold, 2, 5, "2"),
+            tx(TxType::Chargeback, 1, 1, ""),
+            tx(TxType::Withdrawal, 2, 6, "100"),
+            tx(TxType::Close, 1, 7, ""),
+        ] {
+            let decision = engine.process(transaction).unwrap();
+            ledger.record(&engine, &decision).unwrap();
+        }
+        ledger.flush().unwrap();
+
+        let ledger = std::fs::read_to_string(path).unwrap();
+        assert!(ledger.starts_with(
+            "{\"seq\":1,\"event\":\"opening\",\"client\":1,\"tx\":0,\
+             \"available\":\"10\",\"held\":\"0\",\"total\":\"10\"}\n"
+        ));
+        assert!(ledger.contains("\"event\":\"authorization_expired\",\"client\":2,\"tx\":3"));
+        let mut expected = engine.into_accounts();
+        for (client, account) in expected.iter_mut() {
+            *account = AccountDetails {
+                client: *client,
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.locked,
+                ..Default::default()
+            };
+        }
+        let replayed = replay_ledger(path).unwrap();
+        assert_eq!(diff_accounts(&replayed, &expected), []);
+        assert_eq!(replayed[&ClientId(2)].total, Amount::from_str("5").unwrap());
+        assert!(replayed[&ClientId(1)].locked);
+
+        // A missing event is detected
+        let truncated: Vec<&str> = ledger
+            .lines()
+            .filter(|line| !line.contains("\"seq\":3,"))
+            .collect();
+        std::fs::write(path, truncated.join("\n")).unwrap();
+        let err = format!("{:#}", replay_ledger(path).unwrap_err());
+        assert!(err.contains("missing or reordered"), "{}", err);
+        std::fs::remove_file(path).unwrap();
+    }
+}
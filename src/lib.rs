@@ -0,0 +1,106 @@
+//! Transaction processing library.
+//!
+//! This crate contains the building blocks of the transaction processing
+//! application so they can be embedded in other programs:
+//!
+//! - `xlsx`: Reading transactions from Excel workbooks (`xlsx` feature)
+//! - [`types`]: Core data types (transactions, accounts, identifiers, amounts)
+//! - [`aging`]: Aging of open disputes for chargeback-deadline tracking
+//! - [`amount`]: Parsing and normalization of monetary amounts
+//! - `arrow`: Arrow record batches and IPC output of accounts (`arrow` feature)
+//! - [`cache`]: Cache of the final accounts of batch runs, keyed by the input
+//!   contents and options
+//! - `concurrent`: Engine applying the transactions of different clients on a
+//!   worker pool (`concurrent` feature)
+//! - [`deposit_index`]: Deposit history persisted as a compact index file
+//! - [`diff`]: Comparison of two account outputs
+//! - [`digest`]: Deterministic digest of the final account state
+//! - [`embed`]: In-memory CSV processing for embedding the engine, e.g. in WebAssembly
+//! - [`engine`]: Business logic applying transactions to account state
+//! - `ffi`: C interface for embedding the engine (`ffi` feature)
+//! - [`exposure`]: Exposure of held funds per client and in total
+//! - [`fixed_point`]: Fixed-point amount representation (`fixed-point` feature)
+//! - [`fraud`]: Flagging of suspicious account behavior
+//! - [`golden`]: Golden-file tests comparing the accounts of input fixtures with
+//!   expected outputs
+//! - [`history`]: Per-client history of applied transactions
+//! - [`hook`]: Hooks for custom transaction validation
+//! - [`interest`]: Interest accrual on available balances
+//! - [`io`]: CSV input/output operations, including client metadata joined to
+//!   the account output, client filters applied at ingest and transaction ID
+//!   prefixes of merged inputs
+//! - [`ledger`]: Event ledger of account changes and its replay
+//! - [`logging`]: JSON log format and sampling of ignored-transaction events
+//! - [`lookup`]: Lookup of deposits missing from the engine's history, e.g.
+//!   in a CSV file or over HTTP
+//! - [`manifest`]: Run manifests tracing an output to its inputs and configuration
+//! - [`notify`]: Notifications about account locks and chargebacks
+//! - `pipeline`: Staged asynchronous processing with bounded channels (`async` feature)
+//! - [`policy`]: Versioned rule sets bundling frozen processing policies
+//! - `postgres`: PostgreSQL sink for accounts and rejected transactions (`postgres` feature)
+//! - [`progress`]: Progress bar for long batch runs
+//! - [`rate_limit`]: Per-client and global rate limits of the socket server
+//! - [`recurring`]: Expansion of recurring transactions into their occurrences
+//! - [`rules`]: Velocity limits and other anti-money-laundering rules
+//! - [`schedule`]: Value-dated transactions queued until their effective date
+//! - [`server`]: Socket ingestion of transactions with a control channel
+//! - [`session`]: Recording and replaying received transactions
+//! - [`simulate`]: Seeded workloads with injected faults, checking the
+//!   processing rules
+//! - `sqlite`: SQLite output of accounts and history (`sqlite` feature)
+//! - [`stats`]: Processing statistics
+//! - [`store`]: Persistent state stores for the engine, e.g. a sled database
+//!   (`sled` feature) or a Redis database shared by several servers (`redis`
+//!   feature)
+//! - [`table`]: Human-readable table output of accounts
+//! - [`wal`]: Write-ahead log and snapshots for the socket server
+
+pub mod aging;
+pub mod amount;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod cache;
+#[cfg(feature = "concurrent")]
+pub mod concurrent;
+pub mod deposit_index;
+pub mod diff;
+pub mod digest;
+pub mod embed;
+pub mod engine;
+pub mod exposure;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixed_point;
+pub mod fraud;
+pub mod golden;
+pub mod history;
+pub mod hook;
+pub mod interest;
+pub mod io;
+pub mod ledger;
+pub mod logging;
+pub mod lookup;
+pub mod manifest;
+pub mod notify;
+#[cfg(feature = "async")]
+pub mod pipeline;
+pub mod policy;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod progress;
+pub mod rate_limit;
+pub mod recurring;
+pub mod rules;
+pub mod schedule;
+pub mod server;
+pub mod session;
+pub mod simulate;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod store;
+pub mod table;
+pub mod types;
+pub mod wal;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
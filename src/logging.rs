@@ -0,0 +1,189 @@
+//! Log formats and sampling for the tracing subscriber.
+//!
+//! [`JsonFormat`] writes every log event as one JSON object per line, for log
+//! pipelines that index fields such as `reason` or `client`:
+//!
+//! ```json
+//! {"timestamp":"2024-03-01T12:00:00.000000Z","level":"DEBUG","target":"project_diamond_hands::engine::ignored","message":"ignored transaction","tx":7,"client":2,"tx_type":"withdrawal","reason":"insufficient_funds"}
+//! ```
+//!
+//! [`IgnoredSampler`] thins out the events of ignored transactions, which the
+//! engine logs under the [`IGNORED_TARGET`] target, so that high-volume runs stay
+//! readable. Other events, e.g. of chargebacks and locks, are never sampled.
+
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::field::{Field, Visit};
+use tracing::subscriber::Interest;
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Target of the events logged for ignored transactions.
+pub const IGNORED_TARGET: &str = "project_diamond_hands::engine::ignored";
+
+/// Formats events as JSON Lines with the timestamp, level, target, the names
+/// of the enclosing spans and the fields of the event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let metadata = event.metadata();
+
+        let mut object = Map::new();
+        object.insert("timestamp".to_string(), timestamp.into());
+        object.insert("level".to_string(), metadata.level().as_str().into());
+        object.insert("target".to_string(), metadata.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<Value> = scope.from_root().map(|span| span.name().into()).collect();
+            object.insert("spans".to_string(), spans.into());
+        }
+        event.record(&mut JsonVisitor(&mut object));
+
+        let line = serde_json::to_string(&object).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Collects the fields of an event into a JSON object.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// Lets through the given share of ignored-transaction events, evenly spread:
+/// with a rate of `0.01`, the 100th, 200th, ... event. All other events pass.
+#[derive(Debug)]
+pub struct IgnoredSampler {
+    rate: f64,
+    seen: AtomicU64,
+}
+
+impl IgnoredSampler {
+    /// Creates a sampler keeping `rate` (between 0 and 1) of the events.
+    pub fn new(rate: f64) -> Self {
+        IgnoredSampler {
+            rate: rate.clamp(0.0, 1.0),
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    fn is_sampled(metadata: &Metadata<'_>) -> bool {
+        metadata.target() == IGNORED_TARGET && metadata.is_event()
+    }
+}
+
+impl<S> Filter<S> for IgnoredSampler {
+    fn enabled(&self, metadata: &Metadata<'_>, _: &Context<'_, S>) -> bool {
+        if !Self::is_sampled(metadata) {
+            return true;
+        }
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((seen + 1.0) * self.rate).floor() > (seen * self.rate).floor()
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        // Sampled events must be counted each time they occur.
+        if Self::is_sampled(metadata) {
+            Interest::sometimes()
+        } else {
+            Interest::always()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Captures the output of a subscriber.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn samples_ignored_transactions_as_json() {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            tracing_subscriber::fmt::layer()
+                .event_format(JsonFormat)
+                .with_writer(move || writer.clone())
+                .with_filter(IgnoredSampler::new(0.25)),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("process_file").entered();
+            for tx in 1..=8u32 {
+                tracing::debug!(target: IGNORED_TARGET, tx, reason = "insufficient_funds", "ignored transaction");
+            }
+            tracing::info!(tx = 9u32, locked = true, "chargeback applied");
+        });
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let txs: Vec<_> = events.iter().map(|event| event["tx"].clone()).collect();
+        assert_eq!(txs, [4, 8, 9]);
+        assert_eq!(events[0]["level"], "DEBUG");
+        assert_eq!(events[0]["target"], IGNORED_TARGET);
+        assert_eq!(events[0]["message"], "ignored transaction");
+        assert_eq!(events[0]["reason"], "insufficient_funds");
+        assert_eq!(events[0]["spans"], serde_json::json!(["process_file"]));
+        assert_eq!(events[2]["locked"], true);
+    }
+}
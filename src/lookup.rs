@@ -0,0 +1,277 @@
+//! Lookup of deposits missing from the history of the engine.
+//!
+//! An engine continuing from a snapshot only knows the deposits in the
+//! snapshot's history, and one with a history retention forgets old deposits,
+//! so a dispute of an older deposit would be ignored as referencing an unknown
+//! transaction. A [`TxLookup`] registered with
+//! [`Engine::set_tx_lookup`](crate::engine::Engine::set_tx_lookup) provides such
+//! deposits from elsewhere, so the full history does not have to be kept in
+//! memory:
+//!
+//! - a map of the deposits, for embedding
+//! - [`CsvIndex`]: a CSV file with the columns `tx,client,amount`
+//!   (`--backfill`), of which only the position of every deposit is kept in
+//!   memory
+//! - `HttpLookup`: an HTTP endpoint (`--tx-lookup-url`, `http-lookup`
+//!   feature)
+//! - [`DepositIndex`](crate::deposit_index::DepositIndex): the deposit history
+//!   of an earlier run (`--deposit-index`)
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::fs::File;
+use std::sync::Mutex;
+
+use crate::engine::DepositRecord;
+use crate::types::{Amount, ClientId, TxId};
+
+/// Provides the deposits an engine does not find in its history.
+///
+/// Lookups that keep state across calls need interior mutability.
+pub trait TxLookup<A = Amount>: Send {
+    /// Returns the deposit with the transaction ID `tx` in its last known
+    /// state, or `None` if there is none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the deposit cannot be looked up, e.g. because the
+    /// source is unavailable.
+    fn lookup(&self, tx: TxId) -> Result<Option<DepositRecord<A>>>;
+}
+
+impl<A: Clone + Send> TxLookup<A> for HashMap<TxId, DepositRecord<A>> {
+    fn lookup(&self, tx: TxId) -> Result<Option<DepositRecord<A>>> {
+        Ok(self.get(&tx).cloned())
+    }
+}
+
+impl<A> fmt::Debug for dyn TxLookup<A> + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TxLookup")
+    }
+}
+
+/// A deposit of a lookup source.
+#[derive(Debug, Deserialize)]
+struct LookupRecord {
+    tx: TxId,
+    client: ClientId,
+    amount: Amount,
+}
+
+impl LookupRecord {
+    fn into_deposit(self) -> DepositRecord {
+        DepositRecord::new(self.client, self.amount)
+    }
+}
+
+/// Deposits of a CSV file with the columns `tx,client,amount`, read from the
+/// file when looked up. The deposits are undisputed, as they were made.
+pub struct CsvIndex {
+    path: String,
+    positions: HashMap<TxId, csv::Position>,
+    headers: csv::StringRecord,
+    reader: Mutex<csv::Reader<File>>,
+}
+
+impl CsvIndex {
+    /// Opens a CSV file of deposits and indexes their positions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, a line cannot be parsed, an
+    /// amount is not positive or a transaction is listed twice.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open transaction lookup file: {}", path))?;
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(file);
+        let (headers, positions) = index(&mut reader)
+            .with_context(|| format!("Failed to index transaction lookup file: {}", path))?;
+        Ok(CsvIndex {
+            path: path.to_string(),
+            positions,
+            headers,
+            reader: Mutex::new(reader),
+        })
+    }
+
+    /// Returns the number of deposits in the file.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Returns `true` if the file has no deposits.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+/// Reads every deposit of `reader` and returns the headers and the position of
+/// each deposit.
+fn index<R: std::io::Read>(
+    reader: &mut csv::Reader<R>,
+) -> Result<(csv::StringRecord, HashMap<TxId, csv::Position>)> {
+    let headers = reader.headers()?.clone();
+    let mut positions = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        let position = reader.position().clone();
+        if !reader.read_record(&mut record)? {
+            break;
+        }
+        let line = position.line();
+        let deposit: LookupRecord = record
+            .deserialize(Some(&headers))
+            .with_context(|| format!("Invalid deposit at line {}", line))?;
+        anyhow::ensure!(
+            deposit.amount > Amount::ZERO,
+            "Deposit {} at line {} has no positive amount",
+            deposit.tx,
+            line
+        );
+        match positions.entry(deposit.tx) {
+            Entry::Occupied(_) => {
+                anyhow::bail!("Deposit {} is listed again at line {}", deposit.tx, line)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(position);
+            }
+        }
+    }
+    Ok((headers, positions))
+}
+
+impl TxLookup for CsvIndex {
+    fn lookup(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        let Some(position) = self.positions.get(&tx) else {
+            return Ok(None);
+        };
+        let read = || -> Result<DepositRecord> {
+            let mut reader = self.reader.lock().unwrap_or_else(|err| err.into_inner());
+            reader.seek(position.clone())?;
+            let mut record = csv::StringRecord::new();
+            anyhow::ensure!(reader.read_record(&mut record)?, "Unexpected end of file");
+            let deposit: LookupRecord = record.deserialize(Some(&self.headers))?;
+            anyhow::ensure!(deposit.tx == tx, "The file changed since it was indexed");
+            Ok(deposit.into_deposit())
+        };
+        read().map(Some).with_context(|| {
+            format!(
+                "Failed to read deposit {} at line {} from: {}",
+                tx,
+                position.line(),
+                self.path
+            )
+        })
+    }
+}
+
+/// Deposits looked up with a GET request per transaction (`http-lookup`
+/// feature).
+///
+/// The URL template's `{tx}` is replaced by the transaction ID. The endpoint
+/// answers a deposit with a JSON object like `{"client":1,"amount":"10.5"}`,
+/// and an unknown transaction with the status 404.
+#[cfg(feature = "http-lookup")]
+pub struct HttpLookup {
+    url: String,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "http-lookup")]
+impl HttpLookup {
+    /// Creates a lookup requesting the URL `template` with `{tx}` replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `template` does not contain `{tx}`.
+    pub fn new(template: &str) -> Result<Self> {
+        anyhow::ensure!(
+            template.contains("{tx}"),
+            "The transaction lookup URL must contain {{tx}}: {}",
+            template
+        );
+        Ok(HttpLookup {
+            url: template.to_string(),
+            agent: ureq::AgentBuilder::new()
+                .timeout(std::time::Duration::from_secs(10))
+                .build(),
+        })
+    }
+}
+
+#[cfg(feature = "http-lookup")]
+impl TxLookup for HttpLookup {
+    fn lookup(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        /// The response body of a deposit.
+        #[derive(Deserialize)]
+        struct Deposit {
+            client: ClientId,
+            amount: Amount,
+        }
+
+        let url = self.url.replace("{tx}", &tx.to_string());
+        let response = match self.agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to look up deposit at: {}", url));
+            }
+        };
+        let body = response
+            .into_string()
+            .with_context(|| format!("Failed to read deposit from: {}", url))?;
+        let deposit: Deposit = serde_json::from_str(&body)
+            .with_context(|| format!("Failed to parse deposit from: {}", url))?;
+        anyhow::ensure!(
+            deposit.amount > Amount::ZERO,
+            "Deposit {} from {} has no positive amount",
+            tx,
+            url
+        );
+        Ok(Some(DepositRecord::new(deposit.client, deposit.amount)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn csv_index_reads_deposits_from_the_file() {
+        let path = std::env::temp_dir().join(format!("lookup-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "tx, client, amount\n7, 1, 10.5\n9, 2, 3\n").unwrap();
+        let index = CsvIndex::open(path).unwrap();
+        assert_eq!(index.len(), 2);
+        let deposit = index.lookup(TxId(9)).unwrap().unwrap();
+        assert_eq!(deposit.client, ClientId(2));
+        assert_eq!(deposit.amount, Amount::from_str("3").unwrap());
+        let deposit = index.lookup(TxId(7)).unwrap().unwrap();
+        assert_eq!(deposit.amount, Amount::from_str("10.5").unwrap());
+        assert!(index.lookup(TxId(8)).unwrap().is_none());
+
+        std::fs::write(path, "tx,client,amount\n7,1,10\n7,2,3\n").unwrap();
+        let err = CsvIndex::open(path).err().unwrap();
+        assert_eq!(
+            format!("{:#}", err),
+            format!(
+                "Failed to index transaction lookup file: {}: Deposit 7 is listed again at line 3",
+                path
+            )
+        );
+        std::fs::write(path, "tx,client,amount\n7,1,0\n").unwrap();
+        let err = CsvIndex::open(path).err().unwrap();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Deposit 7 at line 2 has no positive amount"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -12,9 +12,10 @@
 //! # Input Format
 //!
 //! The input CSV file should contain transactions with the following columns:
-//! - `type`: Transaction type (deposit, withdrawal, dispute, resolve, chargeback)
-//! - `client`: Client ID (u16)
-//! - `tx`: Transaction ID (u32)
+//! - `type`: Transaction type (deposit, withdrawal, dispute, resolve, chargeback,
+//!   refund, authorize, capture, close, hold, release)
+//! - `client`: Client ID (u16, or u32 with the `wide-ids` feature)
+//! - `tx`: Transaction ID (u32, or u64 with the `wide-ids` feature)
 //! - `amount`: Transaction amount (decimal, up to 4 decimal places)
 //!
 //! # Output Format
@@ -37,55 +38,3076 @@
 //! ```bash
 //! cargo run -- transactions.csv > accounts.csv
 //! ```
-use anyhow::Result;
-use std::env;
+//!
+//! Read options from a configuration file, overriding some on the command line:
+//! ```bash
+//! cargo run -- transactions.csv --config diamond.toml --bool-style yes-no
+//! ```
+//!
+//! Allow every client to overdraw their account by up to 100 and report overdrawn accounts:
+//! ```bash
+//! cargo run -- transactions.csv --credit-limit 100 --overdraft-report overdrafts.csv
+//! ```
+//!
+//! Limit withdrawals per client and show client tiers in the output:
+//! ```bash
+//! cargo run -- transactions.csv --clients clients.csv --extended-output
+//! ```
+//!
+//! Append client names and external references from a metadata file:
+//! ```bash
+//! cargo run -- transactions.csv --enrich clients-meta.csv
+//! ```
+//!
+//! Reprocess overlapping daily files, skipping transactions applied by an earlier run:
+//! ```bash
+//! cargo run -- day1.csv day2.csv --idempotency tx --snapshot checkpoint.json --save-snapshot checkpoint.json
+//! ```
+//!
+//! Produce a gross flows view of deposits and withdrawals, without dispute effects:
+//! ```bash
+//! cargo run -- transactions.csv --types deposit,withdrawal --stats
+//! ```
+//!
+//! Apply administrative corrections that reverse earlier deposits and withdrawals:
+//! ```bash
+//! cargo run -- transactions.csv corrections.csv --reversible-withdrawals --ledger ledger.jsonl
+//! ```
+//!
+//! Only accept deposits to accounts opened by an `open` or `open_locked` transaction:
+//! ```bash
+//! cargo run -- onboarding.csv transactions.csv --require-opening
+//! ```
+//!
+//! Process today's transactions on top of yesterday's state, writing the new state
+//! and a report of the accounts that changed:
+//! ```bash
+//! cargo run -- today.csv --baseline yesterday.json --save-snapshot today.json --delta-report delta.csv
+//! ```
+//!
+//! Accept disputes of deposits made before the history of a snapshot, looking them up in a file:
+//! ```bash
+//! cargo run -- today.csv --snapshot migrated.json --backfill old-deposits.csv
+//! ```
+//!
+//! Keep the deposit history of all runs in an index file instead of reprocessing old files:
+//! ```bash
+//! cargo run -- today.csv --snapshot yesterday.json --deposit-index deposits.idx --save-deposit-index deposits.idx
+//! ```
+//!
+//! Look up disputed deposits missing from the history in another service (`http-lookup` feature):
+//! ```bash
+//! cargo run --features http-lookup -- today.csv --snapshot migrated.json --tx-lookup-url 'http://archive:8080/deposits/{tx}'
+//! ```
+//!
+//! Process the input again instead of reusing the accounts cached by an earlier run over it:
+//! ```bash
+//! cargo run -- transactions.csv --no-cache
+//! ```
+//!
+//! Process only rows 1000001 to 1500000 of a file, e.g. to bisect a bad record:
+//! ```bash
+//! cargo run -- huge.csv --skip 1000000 --limit 500000
+//! ```
+//!
+//! Produce the accounts of one business unit from a shared transaction dump:
+//! ```bash
+//! cargo run -- dump.csv --only-clients 1,2,3 > unit-a.csv
+//! cargo run -- dump.csv --exclude-clients unit-a-clients.txt > others.csv
+//! ```
+//!
+//! Merge the files of two acquirers whose transaction IDs collide:
+//! ```bash
+//! cargo run -- acquirer-a.csv acquirer-b.csv acquirer-a-disputes.csv --source-prefix 0,1,0
+//! ```
+//!
+//! Branch on the kind of failure, e.g. 3 for a missing and 4 for a malformed input
+//! (see `main` for all exit codes):
+//! ```bash
+//! cargo run -- transactions.csv > accounts.csv || echo "failed with $?"
+//! ```
+//!
+//! Stop a long run with Ctrl+C, keeping the partial results and a checkpoint to
+//! resume from (the run exits with code 130):
+//! ```bash
+//! cargo run --release -- huge.csv --idempotency tx --save-snapshot checkpoint.json > accounts.csv
+//! ```
+//!
+//! Hold or flag transactions that break the velocity limits in a rules file:
+//! ```bash
+//! cargo run -- transactions.csv --rules rules.toml --rules-report violations.csv
+//! ```
+//!
+//! List the disputes open for more than 30 days at the end of the run:
+//! ```bash
+//! cargo run -- transactions.csv --dispute-aging-report aging.csv --max-dispute-age 30d
+//! ```
+//!
+//! Report the funds held and the disputes open per client, and their total:
+//! ```bash
+//! cargo run -- transactions.csv --exposure-report exposure.csv
+//! ```
+//!
+//! Report accounts with suspicious activity, such as many chargebacks:
+//! ```bash
+//! cargo run -- transactions.csv --fraud-report suspicious.csv
+//! ```
+//!
+//! Only lock accounts at their third chargeback:
+//! ```bash
+//! cargo run -- transactions.csv --lock-on-chargeback 3
+//! ```
+//!
+//! Reproduce an output with the frozen policies of a rules version:
+//! ```bash
+//! cargo run -- transactions.csv --rules-version v1
+//! ```
+//!
+//! Still settle disputes of accounts locked by a chargeback:
+//! ```bash
+//! cargo run -- transactions.csv --allow-when-locked resolve,chargeback
+//! ```
+//!
+//! Release card authorizations not captured within 10000 transactions:
+//! ```bash
+//! cargo run -- transactions.csv --authorization-expiry 10000
+//! ```
+//!
+//! Process one file per business day, closing a period after each file:
+//! ```bash
+//! cargo run -- day1.csv day2.csv --period-dir periods/
+//! ```
+//!
+//! Print processing statistics to stderr, or write them as JSON:
+//! ```bash
+//! cargo run -- transactions.csv --stats
+//! cargo run -- transactions.csv --stats stats.json
+//! ```
+//!
+//! Write a manifest tracing the output to its inputs, configuration and counts:
+//! ```bash
+//! cargo run -- transactions.csv --manifest run.json
+//! ```
+//!
+//! Show a progress bar with the throughput and ETA while processing a large file:
+//! ```bash
+//! cargo run --release -- transactions.csv --progress > accounts.csv
+//! ```
+//!
+//! Cap the memory used for accounts and deposit history, reporting the peak:
+//! ```bash
+//! cargo run --release -- transactions.csv --max-memory 2G --stats
+//! ```
+//!
+//! Write one history file per client for customer statements:
+//! ```bash
+//! cargo run -- transactions.csv --history-dir history/
+//! ```
+//!
+//! Notify a webhook whenever a chargeback locks an account:
+//! ```bash
+//! cargo run -- transactions.csv --webhook-url https://alerts.example.com/hooks/fraud
+//! ```
+//!
+//! Print the accounts as an aligned table with a totals row:
+//! ```bash
+//! cargo run -- transactions.csv --output-format table
+//! ```
+//!
+//! Add per-account activity counters and the position of the last activity to the output:
+//! ```bash
+//! cargo run -- transactions.csv --extended-output
+//! ```
+//!
+//! Write the accounts and their history to an SQLite database (`sqlite` feature):
+//! ```bash
+//! cargo run --features sqlite -- transactions.csv --output-format sqlite --output accounts.db --output-history
+//! ```
+//!
+//! Upsert the accounts and ignored transactions into PostgreSQL (`postgres` feature):
+//! ```bash
+//! cargo run --features postgres -- transactions.csv --postgres-url postgres://user@localhost/bank
+//! ```
+//!
+//! Write the accounts as an Arrow IPC stream for pyarrow/pandas (`arrow` feature):
+//! ```bash
+//! cargo run --features arrow -- transactions.csv --output-format arrow --output accounts.arrow
+//! ```
+//!
+//! Read transactions from the first sheet of an Excel workbook (`xlsx` feature):
+//! ```bash
+//! cargo run --features xlsx -- transactions.xlsx
+//! ```
+//!
+//! Process value-dated transactions as of a business day, carrying later ones over:
+//! ```bash
+//! cargo run -- transactions.csv --as-of 2024-03-01 --pending-report pending.csv
+//! ```
+//!
+//! Simulate a year of monthly subscription charges from rows with `interval`
+//! and `count` columns:
+//! ```bash
+//! cargo run -- subscriptions.csv --as-of 2024-12-31
+//! ```
+//!
+//! Accrue interest on available balances, posted at the end of each month:
+//! ```bash
+//! cargo run -- transactions.csv --interest-rates rates.csv --as-of 2024-12-31
+//! ```
+//!
+//! Skip malformed rows instead of aborting, collecting them in a quarantine file:
+//! ```bash
+//! cargo run -- transactions.csv --quarantine rejected.csv
+//! ```
+//!
+//! Process in concurrent stages with bounded memory, applying transactions on four
+//! engines sharded by client (`async` feature):
+//! ```bash
+//! cargo run --release --features async -- transactions.csv --pipeline --shards 4
+//! ```
+//!
+//! Parse large inputs with the allocation-free fast path:
+//! ```bash
+//! cargo run --release -- transactions.csv --fast-parse
+//! ```
+//!
+//! Parse records on all cores while a single engine applies them in order:
+//! ```bash
+//! cargo run --release -- transactions.csv --fast-parse --parallel-parse
+//! ```
+//!
+//! Print only selected accounts, e.g. locked accounts of clients 42 and 99:
+//! ```bash
+//! cargo run -- query transactions.csv --client 42,99 --only-locked
+//! ```
+//!
+//! Accept transactions from other processes over a socket, querying balances on a
+//! control socket and printing the accounts after `shutdown`:
+//! ```bash
+//! cargo run -- listen --ingest unix:/tmp/bank.sock --control 127.0.0.1:7001
+//! ```
+//!
+//! Keep a write-ahead log so a restarted server continues where it stopped:
+//! ```bash
+//! cargo run -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --wal-dir wal/
+//! ```
+//!
+//! Keep the server state in an embedded database instead of memory (`sled` feature):
+//! ```bash
+//! cargo run --features sled -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --state-dir state/
+//! ```
+//!
+//! Share the server state with other instances through Redis (`redis` feature):
+//! ```bash
+//! cargo run --features redis -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --redis-url redis://127.0.0.1/0
+//! ```
+//!
+//! Reject transactions beyond a per-client and a global rate in server mode:
+//! ```bash
+//! cargo run -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --client-rate-limit 100/s --global-rate-limit 5000/s
+//! ```
+//!
+//! Record the transactions a server applies into a session file for replay:
+//! ```bash
+//! cargo run -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --record-session session.csv
+//! ```
+//!
+//! Apply the transactions of different clients in parallel (`concurrent` feature):
+//! ```bash
+//! cargo run --release --features concurrent -- listen --ingest 127.0.0.1:7000 --control 127.0.0.1:7001 --workers 8
+//! ```
+//!
+//! Preview the effect of a correction batch on the state of a server's write-ahead log:
+//! ```bash
+//! cargo run -- corrections.csv --snapshot wal/snapshot.json --dry-run
+//! ```
+//!
+//! Evaluate other engine options on the same input, reporting how the accounts would differ:
+//! ```bash
+//! cargo run -- transactions.csv --shadow candidate.toml --shadow-report shadow.csv
+//! ```
+//!
+//! Fail the run if the final accounts break an invariant, e.g. in CI:
+//! ```bash
+//! cargo run -- transactions.csv --check-invariants
+//! ```
+//!
+//! Log every 100th ignored transaction, and every chargeback, as JSON:
+//! ```bash
+//! cargo run -- transactions.csv --log-level info,project_diamond_hands::engine::ignored=debug --log-format json --log-sample 0.01
+//! ```
+//!
+//! Compare the outputs of two runs, e.g. of two engine versions:
+//! ```bash
+//! cargo run -- diff old_accounts.csv new_accounts.csv
+//! ```
+//!
+//! Reconcile the accounts of a run with the balances expected by another system:
+//! ```bash
+//! cargo run -- reconcile transactions.csv --expected expected_accounts.csv --tolerance 0.0001
+//! ```
+//!
+//! Process a seeded workload with injected faults and verify the invariants:
+//! ```bash
+//! cargo run -- simulate --seed 42 --rows 100000 --dispute-percent 40
+//! ```
+//!
+//! Stream accounts to a downstream consumer as soon as their final state is known:
+//! ```bash
+//! cargo run -- transactions.csv --stream-output | consumer
+//! ```
+//!
+//! Record the received transactions and replay them later against a fresh engine:
+//! ```bash
+//! cargo run -- transactions.csv --record-session session.csv
+//! cargo run -- replay-session session.csv
+//! ```
+//!
+//! Reconstruct the accounts as the engine saw them at a transaction or time of a
+//! recorded session:
+//! ```bash
+//! cargo run -- state-at session.csv --tx 123456
+//! cargo run -- state-at session.csv --timestamp 2024-03-01T12:30:00Z
+//! ```
+//!
+//! Print a digest of the final accounts, or check it against another environment's:
+//! ```bash
+//! cargo run -- transactions.csv --digest
+//! cargo run -- transactions.csv --expect-digest 29c4f621...
+//! ```
+//!
+//! Write an event ledger and check that it reproduces the account output:
+//! ```bash
+//! cargo run -- transactions.csv --ledger ledger.jsonl > accounts.csv
+//! cargo run -- replay ledger.jsonl accounts.csv
+//! ```
+//!
+//! Verify that a recorded session was not edited:
+//! ```bash
+//! cargo run -- verify-audit session.csv
+//! ```
+use anyhow::{Context, Result};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Stderr};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime};
+use tracing_subscriber::filter::FilterExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use project_diamond_hands::aging::{AgeThreshold, DisputeAging};
+use project_diamond_hands::amount::{PrecisionPolicy, parse_amount};
+use project_diamond_hands::cache::{self, AccountCache};
+use project_diamond_hands::deposit_index::{self, DepositIndex};
+use project_diamond_hands::engine::{
+    self, AccountFilter, Decision, DisputePolicy, Engine, EngineConfig, IdempotencyKey, LockPolicy,
+    LockedOperations, RejectedTransaction,
+};
+use project_diamond_hands::exposure;
+use project_diamond_hands::fraud::{self, FraudThresholds};
+use project_diamond_hands::history::History;
+use project_diamond_hands::interest::{self, InterestAccrual};
+use project_diamond_hands::io::enrichment::Enrichment;
+use project_diamond_hands::io::filter::{self, ClientFilter};
+use project_diamond_hands::io::prefix::{self, SourcePrefix};
+use project_diamond_hands::io::{
+    self, AccountWriter, BoolStyle, Delimiter, OutputOptions, Quarantine, TransactionColumn,
+    TransactionSource,
+};
+use project_diamond_hands::ledger::{self, LedgerWriter};
+use project_diamond_hands::logging::{IgnoredSampler, JsonFormat};
+use project_diamond_hands::lookup::CsvIndex;
+use project_diamond_hands::manifest::{self, EngineVersion, RowCounts, RunManifest};
+use project_diamond_hands::notify::{self, Notifier};
+use project_diamond_hands::policy::RulesVersion;
+#[cfg(feature = "postgres")]
+use project_diamond_hands::postgres::{PostgresSink, PostgresTables};
+use project_diamond_hands::progress::Progress;
+use project_diamond_hands::rate_limit::{RateLimit, RateLimiter};
+use project_diamond_hands::rules::{self, RuleAction, RuleSet};
+use project_diamond_hands::schedule::Schedule;
+use project_diamond_hands::server::{ListenAddress, Server};
+use project_diamond_hands::session::{self, SessionRecorder};
+use project_diamond_hands::simulate::{self, SimulationConfig};
+use project_diamond_hands::stats::StatsReport;
+use project_diamond_hands::store::{StateStore, StoredEngine};
+use project_diamond_hands::table::{self, ColorChoice};
+use project_diamond_hands::types::{
+    Accounts, Amount, ClientId, Date, Transaction, TxId, TxType, sorted_accounts,
+};
+use project_diamond_hands::wal::{self, WalConfig, WriteAheadLog};
+use project_diamond_hands::{diff, digest, recurring};
+
+mod config;
+
+/// Command-line arguments of the transaction processing application.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Processes transactions from a CSV file and prints account summaries",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    input: InputArgs,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// Record every received transaction into this session file for later replay
+    #[arg(long, value_name = "PATH")]
+    record_session: Option<String>,
+
+    /// Read options from this TOML file; options given on the command line take
+    /// precedence
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<String>,
+
+    /// Log filter using `RUST_LOG` syntax (e.g. `debug` or `project_diamond_hands=trace`);
+    /// defaults to the `RUST_LOG` environment variable, or `warn` if unset
+    #[arg(long, value_name = "FILTER", global = true)]
+    log_level: Option<String>,
+
+    /// Format of the log events written to stderr
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Share of ignored transactions to log, between 0 and 1, e.g. `0.01` for
+    /// every 100th; chargebacks and locks are always logged
+    #[arg(long, value_name = "RATE", default_value_t = 1.0, value_parser = parse_sample_rate, global = true)]
+    log_sample: f64,
+
+    /// Show a progress bar with the throughput and the estimated time left on
+    /// stderr, if it is a terminal
+    #[arg(long)]
+    progress: bool,
+
+    /// Print processing statistics to stderr, or write them as JSON to PATH
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = "-")]
+    stats: Option<String>,
+
+    /// Write a JSON manifest of the run to PATH, with the input file hashes,
+    /// engine version and configuration, row and rejection counts, output
+    /// digest and timing
+    #[arg(long, value_name = "PATH")]
+    manifest: Option<String>,
+
+    /// The command-line arguments, including the options of a `--config`
+    /// file, set by [`parse_cli`] for the `--manifest`.
+    #[arg(skip)]
+    arguments: Vec<String>,
+
+    /// POST a JSON event to this URL whenever a chargeback is applied and locks an account
+    #[cfg(feature = "webhooks")]
+    #[arg(long, value_name = "URL")]
+    webhook_url: Option<String>,
+
+    /// Number of delivery attempts per webhook event before giving up
+    #[cfg(feature = "webhooks")]
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 5,
+        requires = "webhook_url"
+    )]
+    webhook_attempts: u32,
+
+    /// Upsert the final accounts and the ignored transactions into the PostgreSQL
+    /// database at this URL, e.g. `postgres://user@localhost/bank`
+    #[cfg(feature = "postgres")]
+    #[arg(long, value_name = "URL")]
+    postgres_url: Option<String>,
+
+    /// Table receiving the accounts, optionally schema-qualified
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        value_name = "TABLE",
+        default_value = "accounts",
+        requires = "postgres_url"
+    )]
+    postgres_accounts_table: String,
+
+    /// Table receiving the ignored transactions, optionally schema-qualified
+    #[cfg(feature = "postgres")]
+    #[arg(
+        long,
+        value_name = "TABLE",
+        default_value = "rejected_transactions",
+        requires = "postgres_url"
+    )]
+    postgres_rejected_table: String,
+
+    /// Write one CSV file per client listing every applied transaction and the
+    /// resulting balances into this directory
+    #[arg(long, value_name = "DIR")]
+    history_dir: Option<String>,
+
+    /// Also write the per-client transaction history to the SQLite output
+    #[cfg(feature = "sqlite")]
+    #[arg(long, requires = "output")]
+    output_history: bool,
+
+    /// Write each account as soon as its final state is known and flush every row:
+    /// accounts locked by a chargeback immediately, all others once the input is exhausted
+    #[arg(long, conflicts_with = "output")]
+    stream_output: bool,
+
+    /// Process the input in concurrent stages connected by bounded channels,
+    /// writing the accounts to stdout in client order, or like `--stream-output`
+    #[cfg(feature = "async")]
+    #[arg(long)]
+    pipeline: bool,
+
+    /// Number of engines applying transactions in parallel with `--pipeline`
+    #[cfg(feature = "async")]
+    #[arg(long, value_name = "COUNT", default_value_t = 4, requires = "pipeline")]
+    shards: usize,
+
+    /// Close a processing period after each input file, writing its account
+    /// snapshot and report into this directory
+    #[arg(long, value_name = "DIR")]
+    period_dir: Option<String>,
+
+    /// Continue from the engine state in this snapshot, e.g. the `snapshot.json`
+    /// of a `listen --wal-dir` directory or yesterday's `--save-snapshot`
+    #[arg(long, visible_alias = "baseline", value_name = "PATH")]
+    snapshot: Option<String>,
+
+    /// Resolve disputes, resolves, chargebacks, refunds and reversals of
+    /// deposits missing from the history against this CSV file of
+    /// `tx,client,amount`, e.g. deposits older than the `--snapshot`
+    #[arg(long, value_name = "PATH")]
+    backfill: Option<String>,
+
+    /// Look up deposits missing from the history with a GET request to this
+    /// URL, with `{tx}` replaced by the transaction ID
+    #[cfg(feature = "http-lookup")]
+    #[arg(long, value_name = "URL", conflicts_with_all = ["backfill", "deposit_index"])]
+    tx_lookup_url: Option<String>,
+
+    /// Look up deposits missing from the history in this index file, written
+    /// by an earlier run's `--save-deposit-index`
+    #[arg(long, value_name = "PATH", conflicts_with = "backfill")]
+    deposit_index: Option<String>,
+
+    /// Write the accounts changed since the `--snapshot` state to this CSV file,
+    /// with the change of each balance
+    #[arg(long, value_name = "PATH", requires = "snapshot")]
+    delta_report: Option<String>,
+
+    /// Write the final engine state to this snapshot file, which a later run
+    /// continues from with `--snapshot`
+    #[arg(long, value_name = "PATH")]
+    save_snapshot: Option<String>,
+
+    /// Write the final deposit history to this index file, carrying over the
+    /// deposits of the `--deposit-index`
+    #[arg(long, value_name = "PATH")]
+    save_deposit_index: Option<String>,
+
+    /// Write every change of an account to this JSON Lines event ledger, which
+    /// the `replay` command checks against the account output
+    #[arg(long, value_name = "PATH")]
+    ledger: Option<String>,
+
+    /// Also process the input with the engine options of this TOML file, which
+    /// take precedence over the command line, to evaluate them before rollout
+    #[arg(long, value_name = "PATH", requires = "shadow_report")]
+    shadow: Option<String>,
+
+    /// Write the per-client differences of the `--shadow` accounts from the
+    /// regular ones to this CSV file
+    #[arg(long, value_name = "PATH", requires = "shadow")]
+    shadow_report: Option<String>,
+
+    /// Engine options of the `--shadow` run, set by [`parse_cli`].
+    #[arg(skip)]
+    shadow_engine: Option<EngineArgs>,
+
+    /// Process the input without writing anything, printing the accounts that
+    /// would change and the transactions that would be rejected instead
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Check transactions against the velocity limits and other rules in this
+    /// TOML file before applying them
+    #[arg(long, value_name = "PATH")]
+    rules: Option<String>,
+
+    /// Write every rule violation to this CSV file
+    #[arg(long, value_name = "PATH", requires = "rules")]
+    rules_report: Option<String>,
+
+    /// Write the disputes still open at the end of the run that are older than
+    /// `--max-dispute-age` to this CSV file
+    #[arg(long, value_name = "PATH")]
+    dispute_aging_report: Option<String>,
+
+    /// Age above which open disputes are reported, in days between effective
+    /// dates (e.g. `30d`) or in input records (e.g. `5000`); 0 reports every
+    /// open dispute
+    #[arg(
+        long,
+        value_name = "AGE",
+        default_value = "0",
+        requires = "dispute_aging_report"
+    )]
+    max_dispute_age: AgeThreshold,
+
+    /// Accrue interest on available balances at the annual rates in percent of
+    /// this `date,rate` CSV file, posting it at the end of each month
+    #[arg(long, value_name = "PATH")]
+    interest_rates: Option<String>,
+
+    /// Directory caching the final accounts by the contents of the input files
+    /// and the options; defaults to `project-diamond-hands` in the system temp
+    /// directory
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<String>,
+
+    /// Process the input files even if their accounts are cached, and do not
+    /// cache them
+    #[arg(long, conflicts_with = "cache_dir")]
+    no_cache: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Re-drive the transactions of a recorded session against a fresh engine
+    ReplaySession {
+        /// Path to the session file to replay
+        session: String,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Reconstruct the accounts as of a point in a recorded session by replaying
+    /// its entries up to that point
+    StateAt {
+        /// Path to the session file to replay
+        session: String,
+
+        /// Replay up to and including the first entry of this transaction ID
+        #[arg(
+            long,
+            value_name = "ID",
+            conflicts_with = "timestamp",
+            required_unless_present = "timestamp"
+        )]
+        tx: Option<TxId>,
+
+        /// Replay the entries received up to this time: milliseconds since the
+        /// Unix epoch or a UTC time such as `2024-03-01T12:30:00Z`
+        #[arg(long, value_name = "TIME", value_parser = session::parse_timestamp)]
+        timestamp: Option<u64>,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Verify the hash chain of a recorded session, failing at the first entry
+    /// that was edited, inserted or removed
+    VerifyAudit {
+        /// Path to the session file to verify
+        session: String,
+
+        /// Expected hash of the last entry, which also detects entries removed
+        /// from the end
+        #[arg(long, value_name = "HASH")]
+        expect_last_hash: Option<String>,
+    },
+    /// Compare two account outputs and print per-client balance changes; exits with
+    /// status 1 if the outputs differ
+    Diff {
+        /// Account output of the old run
+        old: String,
+
+        /// Account output of the new run
+        new: String,
+
+        /// Encoding of the `newly_locked` column
+        #[arg(long, value_parser = choice(&BoolStyle::ALL, BoolStyle::as_str), default_value_t = BoolStyle::TrueFalse)]
+        bool_style: BoolStyle,
+    },
+    /// Reconstruct the accounts from an event ledger and compare them with an
+    /// account output, printing per-client differences; exits with status 1 if
+    /// they differ
+    Replay {
+        /// Path to the ledger written with `--ledger`
+        ledger: String,
+
+        /// Account output of the run that wrote the ledger; without it, the
+        /// reconstructed accounts are printed
+        accounts: Option<String>,
+
+        /// Encoding of the `locked` and `newly_locked` columns
+        #[arg(long, value_parser = choice(&BoolStyle::ALL, BoolStyle::as_str), default_value_t = BoolStyle::TrueFalse)]
+        bool_style: BoolStyle,
+    },
+    /// Process transactions and compare the accounts with an expected account
+    /// output, printing per-client discrepancies; exits with status 1 if any
+    /// are found
+    Reconcile {
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Account output with the expected balances, e.g. from another system
+        #[arg(long, value_name = "PATH")]
+        expected: String,
+
+        /// Largest difference of a balance from the expected one that is not a
+        /// discrepancy
+        #[arg(long, value_name = "AMOUNT", default_value_t = Amount::ZERO, value_parser = parse_amount)]
+        tolerance: Amount,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Generate a seeded workload with malformed rows, duplicate transaction IDs
+    /// and out-of-order disputes, process it and verify the invariants, then
+    /// print the accounts; exits with status 5 if a check fails
+    Simulate {
+        /// Seed of the generated workload; the same seed always generates the
+        /// same workload
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Number of generated rows
+        #[arg(long, value_name = "COUNT", default_value_t = 10_000)]
+        rows: u64,
+
+        /// Number of distinct clients
+        #[arg(long, value_name = "COUNT", default_value_t = 100)]
+        client_count: u16,
+
+        /// Share of disputes, resolves and chargebacks among the rows, in percent
+        #[arg(long, value_name = "PERCENT", default_value_t = 10)]
+        dispute_percent: u32,
+
+        /// Share of malformed rows, in percent
+        #[arg(long, value_name = "PERCENT", default_value_t = 2)]
+        malformed_percent: u32,
+
+        /// Share of deposits and withdrawals reusing an earlier transaction ID,
+        /// in percent
+        #[arg(long, value_name = "PERCENT", default_value_t = 2)]
+        duplicate_percent: u32,
+
+        /// Share of disputes, resolves and chargebacks referencing a deposit
+        /// that only comes later, in percent
+        #[arg(long, value_name = "PERCENT", default_value_t = 5)]
+        out_of_order_percent: u32,
+
+        /// Write the generated workload to this CSV file, e.g. to reproduce a
+        /// failure with a regular run
+        #[arg(long, value_name = "PATH")]
+        save_workload: Option<String>,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Apply transactions received over a socket, answering balance queries on a
+    /// control socket, and print the accounts after a `shutdown` command
+    Listen {
+        /// Address receiving newline-delimited CSV or JSON transactions:
+        /// `HOST:PORT` or `unix:PATH`
+        #[arg(long, value_name = "ADDRESS")]
+        ingest: ListenAddress,
+
+        /// Address answering the `balance <client>`, `accounts` and `shutdown`
+        /// commands: `HOST:PORT` or `unix:PATH`
+        #[arg(long, value_name = "ADDRESS")]
+        control: ListenAddress,
+
+        /// Reject transactions with a negative amount instead of ignoring them
+        #[arg(long)]
+        strict: bool,
+
+        /// Persist transactions to a write-ahead log in this directory before
+        /// applying them, and recover the state from it on startup
+        #[arg(long, value_name = "DIR")]
+        wal_dir: Option<String>,
+
+        /// Size after which the write-ahead log continues in a new segment, e.g. `64M`
+        #[arg(long, value_name = "SIZE", default_value = "64M", value_parser = io::parse_byte_size, requires = "wal_dir")]
+        wal_segment_size: usize,
+
+        /// Number of logged transactions after which a snapshot replaces the
+        /// covered log segments
+        #[arg(
+            long,
+            value_name = "COUNT",
+            default_value_t = 100_000,
+            requires = "wal_dir"
+        )]
+        snapshot_interval: u64,
+
+        /// Keep the accounts and deposit history in a sled database in this
+        /// directory instead of memory, continuing from its state on startup
+        #[cfg(feature = "sled")]
+        #[arg(long, value_name = "DIR", conflicts_with = "wal_dir")]
+        #[cfg_attr(feature = "redis", arg(conflicts_with = "redis_url"))]
+        state_dir: Option<String>,
+
+        /// Keep the accounts and deposit history in the Redis database at this
+        /// URL, shared with other servers using it, e.g. `redis://127.0.0.1/0`
+        #[cfg(feature = "redis")]
+        #[arg(long, value_name = "URL", conflicts_with = "wal_dir")]
+        redis_url: Option<String>,
+
+        /// Answer transactions of a client beyond this rate, e.g. `100/s`, with
+        /// `rate_limited client` instead of applying them
+        #[arg(long, value_name = "RATE")]
+        client_rate_limit: Option<RateLimit>,
+
+        /// Answer transactions beyond this rate across all clients, e.g.
+        /// `5000/s`, with `rate_limited global` instead of applying them
+        #[arg(long, value_name = "RATE")]
+        global_rate_limit: Option<RateLimit>,
+
+        /// Apply the transactions of different clients concurrently on this
+        /// many worker threads, keeping the order of each client's transactions
+        #[cfg(feature = "concurrent")]
+        #[arg(long, value_name = "COUNT", conflicts_with = "wal_dir")]
+        #[cfg_attr(feature = "sled", arg(conflicts_with = "state_dir"))]
+        #[cfg_attr(feature = "redis", arg(conflicts_with = "redis_url"))]
+        workers: Option<usize>,
+
+        /// Record every transaction passed to the engine into this session file
+        /// for later replay
+        #[arg(long, value_name = "PATH")]
+        record_session: Option<String>,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Process transactions and print only the accounts matching the filters
+    Query {
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        #[command(flatten)]
+        engine: EngineArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+/// The transaction files to process and how to read them.
+#[derive(Debug, Args)]
+struct InputArgs {
+    /// Paths to the CSV files containing transactions, processed in order
+    #[arg(required = true, value_name = "INPUT")]
+    inputs: Vec<String>,
+
+    /// Parse input records directly from bytes instead of through serde, for
+    /// higher throughput on large files
+    #[arg(long)]
+    fast_parse: bool,
+
+    /// Parse input records on a thread pool while reading ahead, keeping their
+    /// order; transactions are still applied by a single engine
+    #[arg(long)]
+    parallel_parse: bool,
+
+    /// Field delimiter of the input files: `auto` (detect from the header
+    /// line), `tab` or a single character such as `;`
+    #[arg(long, value_name = "DELIMITER", default_value = "auto")]
+    delimiter: Delimiter,
+
+    /// Abort on records with a negative amount instead of ignoring them
+    #[arg(long)]
+    strict: bool,
+
+    /// Read the input header NAME as COLUMN (type, client, tx, amount,
+    /// effective_date, interval or count), e.g. `Kind=type` (can be repeated)
+    #[arg(long, value_name = "NAME=COLUMN", value_parser = parse_column_alias)]
+    column_alias: Vec<(String, TransactionColumn)>,
+
+    /// Skip the first N rows after the header of each input file without
+    /// parsing them
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip: u64,
+
+    /// Process at most N rows of each input file, after the skipped ones
+    #[arg(long, value_name = "N")]
+    limit: Option<u64>,
+
+    /// Only process the transactions of these clients, given as a list such as
+    /// `1,2,3` or a file with one client ID per line
+    #[arg(long, value_name = "IDS|PATH")]
+    only_clients: Option<String>,
+
+    /// Drop the transactions of these clients, given as a list such as `1,2,3`
+    /// or a file with one client ID per line
+    #[arg(long, value_name = "IDS|PATH")]
+    exclude_clients: Option<String>,
+
+    /// Write malformed input records to PATH, each followed by its parse error,
+    /// and continue instead of aborting
+    #[arg(long, value_name = "PATH")]
+    quarantine: Option<String>,
+
+    /// Process as of DATE (`YYYY-MM-DD`), holding back transactions whose
+    /// `effective_date` is later as pending
+    #[arg(long, value_name = "DATE")]
+    as_of: Option<Date>,
+
+    /// Write the transactions still pending after `--as-of` to PATH, in the
+    /// input format
+    #[arg(long, value_name = "PATH", requires = "as_of")]
+    pending_report: Option<String>,
+
+    /// Map the transaction IDs of each input file into the range of its
+    /// source, given as one prefix per input in order, e.g. `0,1`, so colliding
+    /// IDs of different sources stay apart
+    #[arg(long, value_name = "PREFIXES", value_delimiter = ',')]
+    source_prefix: Vec<SourcePrefix>,
+}
+
+impl InputArgs {
+    /// Creates the quarantine file, if one was requested.
+    fn quarantine(&self) -> Result<Option<Quarantine>> {
+        self.quarantine
+            .as_deref()
+            .map(Quarantine::create)
+            .transpose()
+    }
+
+    /// Creates the schedule of value-dated transactions, if `--as-of` was given.
+    fn schedule(&self) -> Option<Schedule> {
+        self.as_of.map(Schedule::new)
+    }
+
+    /// Reads the clients selected by `--only-clients` and `--exclude-clients`.
+    fn client_filter(&self) -> Result<ClientFilter> {
+        Ok(ClientFilter {
+            only: self
+                .only_clients
+                .as_deref()
+                .map(filter::parse_client_ids)
+                .transpose()?,
+            exclude: self
+                .exclude_clients
+                .as_deref()
+                .map(filter::parse_client_ids)
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Returns the `--source-prefix` of the input file at `index`.
+    fn source_prefix(&self, index: usize) -> Result<Option<SourcePrefix>> {
+        if self.source_prefix.is_empty() {
+            return Ok(None);
+        }
+        if self.source_prefix.len() != self.inputs.len() {
+            return Err(anyhow::anyhow!(
+                "--source-prefix needs one prefix per input file, got {} for {} files",
+                self.source_prefix.len(),
+                self.inputs.len()
+            ))
+            .classify(Failure::Usage);
+        }
+        Ok(Some(self.source_prefix[index]))
+    }
+
+    /// Opens the input file at `index` as an Excel workbook if it has an
+    /// `.xlsx` extension and as CSV otherwise, dropping the transactions of
+    /// filtered clients and prefixing the transaction IDs.
+    fn read(&self, index: usize, precision: PrecisionPolicy) -> Result<Box<dyn TransactionSource>> {
+        let mut reader = self.read_file(&self.inputs[index], precision)?;
+        let filter = self.client_filter()?;
+        if !filter.is_empty() {
+            reader = Box::new(filter::filter_clients(reader, filter));
+        }
+        if let Some(source_prefix) = self.source_prefix(index)? {
+            reader = Box::new(prefix::prefix_tx_ids(reader, source_prefix));
+        }
+        Ok(reader)
+    }
+
+    fn read_file(
+        &self,
+        path: &str,
+        precision: PrecisionPolicy,
+    ) -> Result<Box<dyn TransactionSource>> {
+        if path.to_ascii_lowercase().ends_with(".xlsx") {
+            return self.read_xlsx(path, precision);
+        }
+        let reader = io::read_transactions_from_file_with_delimiter(path, self.delimiter)?
+            .fast_parse(self.fast_parse)
+            .parallel_parse(self.parallel_parse)
+            .precision(precision)
+            .strict(self.strict)
+            .skip_rows(self.skip)
+            .limit_rows(self.limit);
+        Ok(Box::new(
+            self.column_alias
+                .iter()
+                .fold(reader, |reader, (alias, column)| {
+                    reader.column_alias(alias.as_str(), *column)
+                }),
+        ))
+    }
+
+    #[cfg(feature = "xlsx")]
+    fn read_xlsx(
+        &self,
+        path: &str,
+        precision: PrecisionPolicy,
+    ) -> Result<Box<dyn TransactionSource>> {
+        let reader = project_diamond_hands::xlsx::read_transactions_from_xlsx(path)?
+            .precision(precision)
+            .strict(self.strict)
+            .skip_rows(self.skip)
+            .limit_rows(self.limit);
+        Ok(Box::new(
+            self.column_alias
+                .iter()
+                .fold(reader, |reader, (alias, column)| {
+                    reader.column_alias(alias.as_str(), *column)
+                }),
+        ))
+    }
+
+    #[cfg(not(feature = "xlsx"))]
+    fn read_xlsx(
+        &self,
+        path: &str,
+        _precision: PrecisionPolicy,
+    ) -> Result<Box<dyn TransactionSource>> {
+        Err(anyhow::anyhow!(
+            "Reading {} requires the `xlsx` feature",
+            path
+        ))
+        .classify(Failure::Usage)
+    }
+}
+
+/// Parses a `NAME=COLUMN` input column alias.
+fn parse_column_alias(value: &str) -> Result<(String, TransactionColumn), String> {
+    let (alias, column) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=COLUMN, got '{}'", value))?;
+    Ok((alias.trim().to_string(), column.trim().parse()?))
+}
+
+/// Criteria selecting the accounts printed by `query`.
+#[derive(Debug, Args)]
+struct FilterArgs {
+    /// Only print these clients, e.g. `--client 42,99` (can be repeated)
+    #[arg(long = "client", value_name = "ID", value_delimiter = ',')]
+    clients: Vec<ClientId>,
+
+    /// Only print locked accounts
+    #[arg(long)]
+    only_locked: bool,
+
+    /// Only print accounts whose total balance is at least AMOUNT
+    #[arg(long, value_name = "AMOUNT", value_parser = parse_amount)]
+    min_total: Option<Amount>,
+}
+
+impl FilterArgs {
+    fn to_filter(&self) -> AccountFilter {
+        AccountFilter {
+            clients: self.clients.iter().copied().collect(),
+            only_locked: self.only_locked,
+            min_total: self.min_total,
+        }
+    }
+}
+
+/// Options controlling the processing rules of the engine.
+#[derive(Debug, Args)]
+struct EngineArgs {
+    /// Amount by which a client's available balance may go below zero
+    #[arg(long, value_name = "AMOUNT", default_value_t = Amount::ZERO, value_parser = parse_amount)]
+    credit_limit: Amount,
+
+    /// CSV file with `client,limit` rows overriding the credit limit per client
+    #[arg(long, value_name = "PATH")]
+    credit_limits: Option<String>,
+
+    /// CSV file with per-client `withdrawal_limit`, `credit_limit`, `tier` and
+    /// `currency` columns
+    #[arg(long = "clients", value_name = "PATH")]
+    client_profiles: Option<String>,
 
-mod engine;
-mod io;
-mod types;
+    /// How to handle disputes when available funds do not cover the disputed amount
+    #[arg(long, value_parser = choice(&DisputePolicy::ALL, DisputePolicy::as_str), default_value_t = DisputePolicy::RequireAvailable)]
+    dispute_policy: DisputePolicy,
+
+    /// When a chargeback locks the account: `immediate`, `never`, or after COUNT
+    /// chargebacks of the client
+    #[arg(long, value_name = "WHEN", default_value_t = LockPolicy::Immediate)]
+    lock_on_chargeback: LockPolicy,
+
+    /// Dispute lifecycle operations still applied to locked accounts, e.g.
+    /// `resolve,chargeback` to settle disputes opened before the lock
+    #[arg(long, value_name = "TYPES", value_enum, value_delimiter = ',')]
+    allow_when_locked: Vec<LockedOperation>,
+
+    /// Ignore disputes on transactions whose previous dispute was resolved
+    #[arg(long)]
+    no_redispute: bool,
+
+    /// Maximum number of disputes accepted per transaction
+    #[arg(long, value_name = "COUNT")]
+    max_disputes_per_tx: Option<u32>,
+
+    /// How to handle amounts with more than four decimal places
+    #[arg(long, value_parser = choice(&PrecisionPolicy::ALL, PrecisionPolicy::as_str), default_value_t = PrecisionPolicy::Round)]
+    precision: PrecisionPolicy,
+
+    /// Apply the frozen dispute, lock and precision policies of this rules
+    /// version, e.g. to reproduce an output produced with it
+    #[arg(
+        long,
+        value_name = "VERSION",
+        value_parser = choice(&RulesVersion::ALL, RulesVersion::as_str),
+        conflicts_with_all = ["dispute_policy", "lock_on_chargeback", "precision"]
+    )]
+    rules_version: Option<RulesVersion>,
+
+    /// Cap on the approximate memory used by accounts and deposit history, e.g. `2G`;
+    /// the history is compacted when the cap is near and processing fails beyond it
+    #[arg(long, value_name = "SIZE", value_parser = io::parse_byte_size)]
+    max_memory: Option<usize>,
+
+    /// Only keep the COUNT most recent deposits disputable, evicting older
+    /// undisputed deposits from the history
+    #[arg(long, value_name = "COUNT")]
+    history_retention: Option<u32>,
+
+    /// Release authorizations not captured within COUNT subsequent transactions
+    #[arg(long, value_name = "COUNT")]
+    authorization_expiry: Option<u64>,
+
+    /// Ignore transactions that were applied before, recognized by their
+    /// transaction ID (`tx`) or their client and transaction ID (`client-tx`)
+    #[arg(long, value_name = "KEY", value_parser = choice(&IdempotencyKey::ALL, IdempotencyKey::as_str))]
+    idempotency: Option<IdempotencyKey>,
+
+    /// Only process these transaction types, e.g. `deposit,withdrawal`; other
+    /// transactions are counted but ignored
+    #[arg(
+        long,
+        value_name = "TYPES",
+        value_parser = choice(&TxType::ALL, TxType::as_str),
+        value_delimiter = ','
+    )]
+    types: Option<Vec<TxType>>,
+
+    /// Record withdrawals so a later `reversal` can credit them back
+    #[arg(long)]
+    reversible_withdrawals: bool,
+
+    /// Only open accounts with `open` transactions, ignoring deposits and
+    /// authorizations for clients without an account
+    #[arg(long)]
+    require_opening: bool,
+}
+
+impl EngineArgs {
+    /// Returns the engine configuration, tracking account metrics if `output`
+    /// includes them.
+    fn to_config(&self, output: &OutputArgs) -> Result<EngineConfig> {
+        let mut config = EngineConfig {
+            credit_limit: self.credit_limit,
+            dispute_policy: self.dispute_policy,
+            allow_redispute: !self.no_redispute,
+            lock_policy: self.lock_on_chargeback,
+            locked_operations: LockedOperations {
+                dispute: self.allow_when_locked.contains(&LockedOperation::Dispute),
+                resolve: self.allow_when_locked.contains(&LockedOperation::Resolve),
+                chargeback: self
+                    .allow_when_locked
+                    .contains(&LockedOperation::Chargeback),
+            },
+            max_disputes_per_tx: self.max_disputes_per_tx,
+            precision: self.precision,
+            max_memory: self.max_memory,
+            history_retention: self.history_retention,
+            authorization_expiry: self.authorization_expiry,
+            idempotency: self.idempotency,
+            processed_types: self
+                .types
+                .as_ref()
+                .map(|types| types.iter().copied().collect()),
+            reversible_withdrawals: self.reversible_withdrawals,
+            require_opening: self.require_opening,
+            account_metrics: output.account_metrics()?,
+            ..Default::default()
+        };
+        if let Some(path) = &self.credit_limits {
+            config.client_credit_limits = io::read_credit_limits_from_file(path)?;
+        }
+        if let Some(path) = &self.client_profiles {
+            config.client_profiles = io::read_client_profiles_from_file(path)?;
+        }
+        if let Some(version) = self.rules_version {
+            version.apply(&mut config);
+        }
+        Ok(config)
+    }
+}
+
+impl Cli {
+    /// Returns whether the run needs to record the per-client history.
+    fn records_history(&self) -> bool {
+        #[cfg(feature = "sqlite")]
+        if self.output_history {
+            return true;
+        }
+        self.history_dir.is_some()
+    }
+
+    /// Creates the engine, continuing from `--snapshot` if given and checking
+    /// `rules` before applying transactions.
+    fn engine(&self, rules: Option<&Arc<RuleSet>>) -> Result<Engine> {
+        self.create_engine(&self.engine, rules)
+    }
+
+    /// Creates the engine of the `--shadow` run, if one was requested, with
+    /// rules of its own.
+    fn shadow(&self) -> Result<Option<Shadow>> {
+        let Some(args) = &self.shadow_engine else {
+            return Ok(None);
+        };
+        Ok(Some(Shadow {
+            engine: self.create_engine(args, self.rules()?.as_ref())?,
+            interest: self.interest()?,
+            diverging: 0,
+        }))
+    }
+
+    fn create_engine(&self, args: &EngineArgs, rules: Option<&Arc<RuleSet>>) -> Result<Engine> {
+        let config = args.to_config(&self.output)?;
+        let mut engine = match &self.snapshot {
+            Some(path) => Engine::with_state(config, wal::read_snapshot_file(path)?),
+            None => Engine::new(config),
+        };
+        if let Some(path) = &self.backfill {
+            engine.set_tx_lookup(CsvIndex::open(path).classify_input()?);
+        }
+        if let Some(path) = &self.deposit_index {
+            engine.set_tx_lookup(DepositIndex::open(path).classify_input()?);
+        }
+        #[cfg(feature = "http-lookup")]
+        if let Some(url) = &self.tx_lookup_url {
+            engine.set_tx_lookup(
+                project_diamond_hands::lookup::HttpLookup::new(url).classify(Failure::Usage)?,
+            );
+        }
+        if let Some(rules) = rules {
+            engine.add_hook(Arc::clone(rules));
+        }
+        Ok(engine)
+    }
+
+    /// Returns the account cache and the key of the run, unless `--no-cache` is
+    /// given or the run has effects beyond the account output and its reports.
+    fn account_cache(&self, engine: &Engine) -> Result<Option<(AccountCache, String)>> {
+        let uncacheable = [
+            self.no_cache,
+            self.record_session.is_some(),
+            self.records_history(),
+            self.period_dir.is_some(),
+            self.snapshot.is_some(),
+            self.backfill.is_some(),
+            #[cfg(feature = "http-lookup")]
+            self.tx_lookup_url.is_some(),
+            self.deposit_index.is_some(),
+            self.save_snapshot.is_some(),
+            self.save_deposit_index.is_some(),
+            self.ledger.is_some(),
+            self.shadow.is_some(),
+            self.rules.is_some(),
+            self.interest_rates.is_some(),
+            self.stream_output,
+            self.stats.is_some(),
+            self.manifest.is_some(),
+            self.input.quarantine.is_some(),
+            self.input.pending_report.is_some(),
+            self.dispute_aging_report.is_some(),
+            // Activity timestamps are taken from the clock.
+            self.output.extended_output,
+            build_notifier(self).is_some(),
+            #[cfg(feature = "postgres")]
+            self.postgres_url.is_some(),
+        ];
+        if uncacheable.contains(&true)
+            || !self
+                .input
+                .inputs
+                .iter()
+                .all(|input| std::fs::metadata(input).is_ok_and(|metadata| metadata.is_file()))
+        {
+            return Ok(None);
+        }
+        let settings = format!(
+            "{:?}\n{:?} {:?} {} {} {:?} {:?} {:?} {:?}",
+            engine.config(),
+            self.input.client_filter()?,
+            self.input.delimiter,
+            self.input.strict,
+            self.input.skip,
+            self.input.limit,
+            self.input.column_alias,
+            self.input.as_of,
+            self.input.source_prefix,
+        );
+        let key = cache::cache_key(&self.input.inputs, &settings)?;
+        let dir = self
+            .cache_dir
+            .as_ref()
+            .map_or_else(AccountCache::default_dir, std::path::PathBuf::from);
+        Ok(Some((AccountCache::new(dir), key)))
+    }
+
+    /// Reads the `--interest-rates` file, if one was given.
+    fn interest(&self) -> Result<Option<InterestAccrual>> {
+        self.interest_rates
+            .as_deref()
+            .map(interest::read_rate_schedule_file)
+            .transpose()
+            .map(|schedule| schedule.map(InterestAccrual::new))
+    }
+
+    /// Creates the `--progress` bar over all input files, unless stderr is not
+    /// a terminal.
+    fn progress(&self) -> Option<Progress<Stderr>> {
+        let stderr = std::io::stderr();
+        if !self.progress || !stderr.is_terminal() {
+            return None;
+        }
+        // Missing files are reported when they are opened.
+        let total = self
+            .input
+            .inputs
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        Some(Progress::new(stderr, total))
+    }
+
+    /// Reads the `--rules` file, if one was given.
+    fn rules(&self) -> Result<Option<Arc<RuleSet>>> {
+        self.rules
+            .as_deref()
+            .map(|path| rules::read_rules_file(path).map(Arc::new))
+            .transpose()
+    }
+}
+
+/// Options controlling the reports written after processing.
+#[derive(Debug, Args)]
+struct OutputArgs {
+    /// Write accounts with a negative available balance to this CSV file
+    #[arg(long, value_name = "PATH")]
+    overdraft_report: Option<String>,
+
+    /// Write the held funds and open disputes of every client, and their total,
+    /// to this CSV file
+    #[arg(long, value_name = "PATH")]
+    exposure_report: Option<String>,
+
+    /// Encoding of boolean columns such as `locked`
+    #[arg(long, value_parser = choice(&BoolStyle::ALL, BoolStyle::as_str), default_value_t = BoolStyle::TrueFalse)]
+    bool_style: BoolStyle,
+
+    /// Rename an output column, e.g. `locked=frozen` (can be repeated)
+    #[arg(long, value_name = "COLUMN=NAME", value_parser = parse_header_name)]
+    header_name: Vec<(String, String)>,
+
+    /// Field delimiter of the written CSV files: `tab` or a single character
+    #[arg(long, value_name = "DELIMITER", default_value = ",", value_parser = io::parse_delimiter_byte)]
+    output_delimiter: u8,
+
+    /// Format of the account output
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    output_format: OutputFormat,
+
+    /// Write the accounts to this file instead of stdout (required for `sqlite`)
+    #[arg(long, value_name = "PATH", required_if_eq("output_format", "sqlite"))]
+    output: Option<String>,
+
+    /// When to color the `table` output
+    #[arg(long, value_name = "WHEN", value_parser = choice(&ColorChoice::ALL, ColorChoice::as_str), default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Append per-account activity columns to the CSV output: deposits,
+    /// withdrawals, active disputes, chargebacks, the position of the last
+    /// activity and whether the account is closed
+    #[arg(long)]
+    extended_output: bool,
+
+    /// CSV file with a `client` column and metadata columns, such as names or
+    /// external references, to append to the CSV output
+    #[arg(long, value_name = "PATH", value_parser = io::enrichment::parse_enrichment_file)]
+    enrich: Option<Arc<Enrichment>>,
+
+    /// Print the SHA-256 digest of the final account state to stderr
+    #[arg(long)]
+    digest: bool,
+
+    /// Fail instead of writing the accounts if their digest differs from DIGEST
+    #[arg(long, value_name = "DIGEST")]
+    expect_digest: Option<String>,
+
+    /// Fail instead of writing the accounts if any account breaks an invariant,
+    /// such as total being available plus held funds
+    #[arg(long)]
+    check_invariants: bool,
+
+    /// Write accounts with suspicious activity, such as rapid deposit-withdrawal
+    /// cycles or many chargebacks, to this CSV file
+    #[arg(long, value_name = "PATH")]
+    fraud_report: Option<String>,
+
+    /// Number of withdrawals directly following a deposit at which an account
+    /// is flagged
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 3,
+        requires = "fraud_report"
+    )]
+    fraud_cycles: u64,
+
+    /// Share of deposits charged back, in percent, at which an account is flagged
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 10,
+        requires = "fraud_report"
+    )]
+    fraud_chargeback_percent: u64,
+
+    /// Number of disputes at which an account is flagged
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 5,
+        requires = "fraud_report"
+    )]
+    fraud_disputes: u64,
+}
+
+/// Parses one of `values` by the name `name` gives it, with the `FromStr`
+/// implementation of the library type, listing the names in the help and in
+/// errors.
+fn choice<T>(values: &[T], name: fn(T) -> &'static str) -> impl TypedValueParser<Value = T>
+where
+    T: std::str::FromStr<Err = String> + Copy + Send + Sync + 'static,
+{
+    PossibleValuesParser::new(values.iter().map(|value| name(*value)))
+        .try_map(|value| value.parse::<T>())
+}
+
+/// A dispute lifecycle operation that may be allowed on locked accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LockedOperation {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/// Format of the account output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// CSV, to stdout or `--output`
+    Csv,
+    /// An aligned table with a totals row, to stdout or `--output`
+    Table,
+    /// An SQLite database at `--output`
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+    /// An Arrow IPC stream, to stdout or `--output`
+    #[cfg(feature = "arrow")]
+    Arrow,
+}
+
+impl OutputArgs {
+    fn to_options(&self) -> OutputOptions {
+        OutputOptions {
+            bool_style: self.bool_style,
+            header_names: self.header_name.iter().cloned().collect(),
+            delimiter: self.output_delimiter,
+            extended: self.extended_output,
+            enrichment: self.enrich.clone(),
+        }
+    }
+
+    /// Returns whether the engine needs to track account metrics for the output,
+    /// the fraud report or the exposure report.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `--extended-output` or `--enrich` is combined with a
+    /// format other than CSV.
+    fn account_metrics(&self) -> Result<bool> {
+        let supported = || {
+            anyhow::ensure!(
+                !self.extended_output || self.output_format == OutputFormat::Csv,
+                "--extended-output is only supported with CSV output"
+            );
+            anyhow::ensure!(
+                self.enrich.is_none() || self.output_format == OutputFormat::Csv,
+                "--enrich is only supported with CSV output"
+            );
+            Ok(())
+        };
+        supported().classify(Failure::Usage)?;
+        Ok(self.extended_output || self.fraud_report.is_some() || self.exposure_report.is_some())
+    }
+}
+
+/// Format of the log events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Parses a `--log-sample` rate between 0 and 1.
+fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!("expected a rate between 0 and 1, got '{}'", value)),
+    }
+}
+
+/// Parses a `COLUMN=NAME` header rename.
+fn parse_header_name(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(column, name)| (column.trim().to_string(), name.trim().to_string()))
+        .ok_or_else(|| format!("expected COLUMN=NAME, got '{}'", value))
+}
 
 /// Main entry point for the transaction processing application.
 ///
 /// This function orchestrates the entire transaction processing pipeline:
-/// 1. Reads the input file path from command-line arguments
-/// 2. Streams and parses transactions from the CSV file
+/// 1. Parses the command-line arguments
+/// 2. Streams and parses transactions from the CSV files
 /// 3. Processes transactions to update account states
 /// 4. Writes account summaries to stdout in CSV format
 ///
-/// # Arguments
-///
-/// The program expects a single command-line argument:
-/// - `file_path`: Path to the CSV file containing transactions
-///
-/// # Returns
+/// # Exit Codes
 ///
-/// Returns `Ok(())` on success, or an error if:
-/// - No input file is provided
-/// - The file cannot be opened or read
-/// - Any transaction fails to parse
-/// - Processing encounters an error
-/// - Writing to stdout fails
-///
-/// # Errors
+/// Errors are printed to stderr and reported through the exit code of their
+/// [`Failure`] class:
+/// - 1: Other failures, e.g. an overflowing balance, or differences found by
+///   `diff`, `replay` and `reconcile`
+/// - 2: Missing or invalid command-line arguments
+/// - 3: An input file was not found
+/// - 4: An input file could not be read or parsed
+/// - 5: `--check-invariants` found violations, or a check of `simulate` failed
+/// - 6: The output could not be written
+/// - 130: The run was interrupted and wrote partial results
+fn main() -> ExitCode {
+    match run_command() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if err.is::<DifferencesFound>() => ExitCode::FAILURE,
+        Err(err) => {
+            let code = Failure::of(&err).map_or(1, |failure| failure as u8);
+            // Print the tagged error itself, which has the backtrace of where it occurred.
+            let outermost = err
+                .chain()
+                .next()
+                .is_some_and(|cause| cause.is::<ClassifiedError>());
+            let err = match outermost {
+                true => err
+                    .downcast::<ClassifiedError>()
+                    .map_or_else(|err| err, |classified| classified.error),
+                false => err,
+            };
+            eprintln!("Error: {:?}", err);
+            ExitCode::from(code)
+        }
+    }
+}
+
+/// Runs the command given on the command line.
+fn run_command() -> Result<()> {
+    let cli = parse_cli().classify(Failure::Usage)?;
+    init_tracing(cli.log_level.as_deref(), cli.log_format, cli.log_sample)
+        .classify(Failure::Usage)?;
+
+    match cli.command {
+        Some(Command::ReplaySession {
+            session,
+            engine,
+            output,
+        }) => {
+            let transactions = session::read_session_from_file(&session)
+                .classify_input()?
+                .map(|result| result.map(|record| record.transaction()).classify_input());
+            let accounts = engine::proccess_transactions_with_config(
+                transactions,
+                engine.to_config(&output).classify_input()?,
+            )?;
+            write_output(accounts, &output, None)
+        }
+        Some(Command::StateAt {
+            session,
+            tx,
+            timestamp,
+            engine,
+            output,
+        }) => {
+            let point = match (tx, timestamp) {
+                (Some(tx), _) => session::SessionPoint::Tx(tx),
+                (None, timestamp) => {
+                    session::SessionPoint::ReceivedAt(timestamp.unwrap_or_default())
+                }
+            };
+            let transactions = session::read_session_until(&session, point)
+                .classify_input()?
+                .map(|result| result.map(|record| record.transaction()).classify_input());
+            let accounts = engine::proccess_transactions_with_config(
+                transactions,
+                engine.to_config(&output).classify_input()?,
+            )?;
+            write_output(accounts, &output, None)
+        }
+        Some(Command::VerifyAudit {
+            session,
+            expect_last_hash,
+        }) => {
+            let summary = session::verify_session_chain(&session).classify_input()?;
+            if let Some(expected) = expect_last_hash {
+                anyhow::ensure!(
+                    summary.last_hash.eq_ignore_ascii_case(expected.trim()),
+                    "Last hash of {} is {}, expected {}",
+                    session,
+                    summary.last_hash,
+                    expected
+                );
+            }
+            println!(
+                "{} entries verified, last hash {}",
+                summary.entries, summary.last_hash
+            );
+            Ok(())
+        }
+        Some(Command::Query {
+            input,
+            filter,
+            engine,
+            output,
+        }) => {
+            let accounts = process_inputs(&input, &engine, &output)?;
+            write_output(filter.to_filter().apply(accounts), &output, None)
+        }
+        Some(Command::Reconcile {
+            input,
+            expected,
+            tolerance,
+            engine,
+            output,
+        }) => {
+            if output.output_format != OutputFormat::Csv {
+                return Err(anyhow::anyhow!("reconcile only supports CSV output"))
+                    .classify(Failure::Usage);
+            }
+            let expected_accounts = io::read_accounts_from_file(&expected).classify_input()?;
+            let accounts = process_inputs(&input, &engine, &output)?;
+            write_reports(&accounts, &output)?;
+            let discrepancies = diff::reconcile_accounts(&expected_accounts, &accounts, tolerance);
+            let options = output.to_options();
+            match &output.output {
+                Some(path) => {
+                    io::write_account_deltas_as_csv_to_file(&discrepancies, path, &options)
+                }
+                None => io::write_account_deltas_as_csv_to_stdout(&discrepancies, &options),
+            }
+            .classify(Failure::Output)?;
+            if !discrepancies.is_empty() {
+                eprintln!(
+                    "{} client(s) do not match {}",
+                    discrepancies.len(),
+                    expected
+                );
+                return Err(DifferencesFound.into());
+            }
+            eprintln!("accounts match {}", expected);
+            Ok(())
+        }
+        Some(Command::Simulate {
+            seed,
+            rows,
+            client_count,
+            dispute_percent,
+            malformed_percent,
+            duplicate_percent,
+            out_of_order_percent,
+            save_workload,
+            engine,
+            output,
+        }) => {
+            let workload = simulate::generate_workload(&SimulationConfig {
+                seed,
+                rows,
+                clients: client_count,
+                dispute_percent,
+                malformed_percent,
+                duplicate_percent,
+                out_of_order_percent,
+            });
+            if let Some(path) = &save_workload {
+                std::fs::write(path, &workload.csv)
+                    .with_context(|| format!("Failed to write file: {}", path))
+                    .classify(Failure::Output)?;
+            }
+            let report =
+                simulate::simulate(&workload.csv, engine.to_config(&output).classify_input()?)?;
+            eprintln!(
+                "seed {}: {} rows with {} malformed, {} duplicate transaction IDs and {} out-of-order references; {} processed, {} ignored, {} accounts",
+                seed,
+                workload.rows,
+                workload.malformed,
+                workload.duplicates,
+                workload.out_of_order,
+                report.processed,
+                report.ignored,
+                report.accounts.len()
+            );
+            for violation in &report.violations {
+                eprintln!("{}", violation);
+            }
+            if !report.violations.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} check(s) failed in the simulation with seed {}",
+                    report.violations.len(),
+                    seed
+                ))
+                .classify(Failure::Invariant);
+            }
+            write_output(report.accounts, &output, None)
+        }
+        Some(Command::Listen {
+            ingest,
+            control,
+            strict,
+            wal_dir,
+            wal_segment_size,
+            snapshot_interval,
+            #[cfg(feature = "sled")]
+            state_dir,
+            #[cfg(feature = "redis")]
+            redis_url,
+            client_rate_limit,
+            global_rate_limit,
+            #[cfg(feature = "concurrent")]
+            workers,
+            record_session,
+            engine,
+            output,
+        }) => {
+            let config = engine.to_config(&output).classify_input()?;
+            #[cfg(feature = "sled")]
+            let state_dir = state_dir.as_deref();
+            #[cfg(not(feature = "sled"))]
+            let state_dir: Option<&str> = None;
+            #[cfg(feature = "redis")]
+            let redis_url = redis_url.as_deref();
+            #[cfg(not(feature = "redis"))]
+            let redis_url: Option<&str> = None;
+            #[cfg(not(feature = "concurrent"))]
+            let workers: Option<usize> = None;
+            let server = match (&wal_dir, open_state_store(state_dir, redis_url)?, workers) {
+                (Some(dir), _, _) => {
+                    let wal_config = WalConfig {
+                        segment_size: wal_segment_size as u64,
+                        snapshot_interval,
+                    };
+                    let (engine, wal) = WriteAheadLog::recover(dir, config, wal_config)?;
+                    Server::bind(engine, &ingest, &control)?.write_ahead_log(wal)
+                }
+                (None, Some(store), _) => {
+                    let engine = StoredEngine::new(config, store).classify(Failure::Usage)?;
+                    Server::bind_stored(engine, &ingest, &control)?
+                }
+                (None, None, Some(workers)) => {
+                    bind_concurrent_server(config, workers, &ingest, &control)?
+                }
+                (None, None, None) => Server::bind(Engine::new(config), &ingest, &control)?,
+            }
+            .strict(strict)
+            .output_options(output.to_options());
+            let server = if client_rate_limit.is_some() || global_rate_limit.is_some() {
+                server.rate_limiter(RateLimiter::new(client_rate_limit, global_rate_limit))
+            } else {
+                server
+            };
+            let server = match record_session {
+                Some(path) => server
+                    .record_session(SessionRecorder::create(&path).classify(Failure::Output)?)?,
+                None => server,
+            };
+            eprintln!(
+                "Listening for transactions on {}, control on {}",
+                server.ingest_address()?,
+                server.control_address()?
+            );
+            write_output(server.run()?, &output, None)
+        }
+        Some(Command::Diff {
+            old,
+            new,
+            bool_style,
+        }) => {
+            let deltas = diff::diff_accounts(
+                &io::read_accounts_from_file(&old).classify_input()?,
+                &io::read_accounts_from_file(&new).classify_input()?,
+            );
+            let options = OutputOptions {
+                bool_style,
+                ..Default::default()
+            };
+            io::write_account_deltas_as_csv_to_stdout(&deltas, &options)
+                .classify(Failure::Output)?;
+            if !deltas.is_empty() {
+                return Err(DifferencesFound.into());
+            }
+            Ok(())
+        }
+        Some(Command::Replay {
+            ledger,
+            accounts,
+            bool_style,
+        }) => {
+            let replayed = ledger::replay_ledger(&ledger).classify_input()?;
+            let options = OutputOptions {
+                bool_style,
+                ..Default::default()
+            };
+            let Some(accounts) = accounts else {
+                return io::write_accounts_as_csv_to_stdout(replayed, &options)
+                    .classify(Failure::Output);
+            };
+            let deltas = diff::diff_accounts(
+                &replayed,
+                &io::read_accounts_from_file(&accounts).classify_input()?,
+            );
+            io::write_account_deltas_as_csv_to_stdout(&deltas, &options)
+                .classify(Failure::Output)?;
+            if !deltas.is_empty() {
+                return Err(DifferencesFound.into());
+            }
+            eprintln!("{} matches {}", accounts, ledger);
+            Ok(())
+        }
+        None => run(&cli),
+    }
+}
+
+/// Processes the input files of a subcommand and returns the final accounts.
+fn process_inputs(input: &InputArgs, engine: &EngineArgs, output: &OutputArgs) -> Result<Accounts> {
+    let mut engine = Engine::new(engine.to_config(output).classify_input()?);
+    let mut quarantine = input.quarantine().classify(Failure::Output)?;
+    let mut schedule = input.schedule();
+    for index in 0..input.inputs.len() {
+        let reader = defer(
+            input
+                .read(index, engine.config().precision)
+                .classify_input()?,
+            schedule.as_mut(),
+        );
+        for tx_result in io::skip_malformed(reader, quarantine.as_mut()) {
+            engine.process(tx_result.classify_input()?)?;
+        }
+    }
+    finish_quarantine(quarantine).classify(Failure::Output)?;
+    finish_schedule(
+        schedule,
+        input.pending_report.as_deref(),
+        &output.to_options(),
+    )
+    .classify(Failure::Output)?;
+    Ok(engine.into_accounts())
+}
+
+/// Parses the command line, adding the options of the `--config` file that are
+/// not given on the command line.
+fn parse_cli() -> Result<Cli> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let command = Cli::command();
+    let matches = command.clone().get_matches_from(&args);
+    let mut cli = match matches.get_one::<String>("config") {
+        Some(path) => {
+            let config_args =
+                config::config_args(&config::read_config_file(path)?, &command, &matches)
+                    .with_context(|| format!("Invalid config file: {}", path))?;
+            insert_options(&mut args, config_args);
+            Cli::parse_from(&args)
+        }
+        None => Cli::from_arg_matches(&matches)?,
+    };
+    cli.arguments = args
+        .iter()
+        .skip(1)
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    if let Some(path) = &cli.shadow {
+        cli.shadow_engine = Some(shadow_engine_args(path, args)?);
+    }
+    Ok(cli)
+}
+
+/// Returns the engine options of a `--shadow` run: those of the command line
+/// `args`, overridden by the engine options in the TOML file at `path`.
+fn shadow_engine_args(path: &str, mut args: Vec<OsString>) -> Result<EngineArgs> {
+    #[derive(Parser)]
+    struct ShadowArgs {
+        #[command(flatten)]
+        engine: EngineArgs,
+    }
+
+    let command = ShadowArgs::command();
+    let matches = command.clone().try_get_matches_from(["shadow"])?;
+    let shadow_args = config::config_args(&config::read_config_file(path)?, &command, &matches)
+        .with_context(|| format!("Invalid shadow config file: {}", path))?;
+    insert_options(&mut args, shadow_args);
+    let matches = Cli::command()
+        .args_override_self(true)
+        .try_get_matches_from(args)?;
+    Ok(Cli::from_arg_matches(&matches)?.engine)
+}
+
+/// Adds `options` to the command line `args`.
+fn insert_options(args: &mut Vec<OsString>, options: Vec<String>) {
+    // Options must precede a `--` that ends them.
+    let end = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    args.splice(end..end, options.into_iter().map(OsString::from));
+}
+
+/// Installs the tracing subscriber writing log events to stderr, keeping
+/// `sample` of the ignored-transaction events.
+fn init_tracing(log_level: Option<&str>, format: LogFormat, sample: f64) -> Result<()> {
+    let filter = match log_level {
+        Some(directives) => EnvFilter::try_new(directives)
+            .with_context(|| format!("Invalid log level: {}", directives))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+
+    let layer = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
+    let layer = match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.event_format(JsonFormat).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter.and(IgnoredSampler::new(sample))))
+        .init();
+
+    Ok(())
+}
+
+/// Processes the input files of a regular run and writes the results.
+fn run(cli: &Cli) -> Result<()> {
+    run_streaming_to(cli, std::io::stdout())
+}
+
+/// Like [`run`], but writes the accounts of `--stream-output` to `stream_to`
+/// instead of stdout.
+fn run_streaming_to(cli: &Cli, stream_to: impl std::io::Write) -> Result<()> {
+    #[cfg(feature = "async")]
+    if cli.pipeline {
+        return run_pipeline(cli);
+    }
+    if cli.dry_run {
+        return run_dry_run(cli);
+    }
+
+    let interrupted = register_interrupt()?;
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let options = cli.output.to_options();
+    let rules = cli.rules().classify_input()?;
+    let mut engine = cli.engine(rules.as_ref()).classify_input()?;
+    let cache = cli.account_cache(&engine).classify_input()?;
+    if let Some((cache, key)) = &cache {
+        match cache.get(key) {
+            Ok(Some(accounts)) => {
+                tracing::info!(key = %key, "using cached accounts");
+                return write_output(accounts, &cli.output, None);
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(
+                error = format!("{:#}", err),
+                "ignoring unreadable cache entry"
+            ),
+        }
+    }
+    let baseline = cli
+        .delta_report
+        .is_some()
+        .then(|| engine.accounts().clone());
+    let mut ledger = cli
+        .ledger
+        .as_deref()
+        .map(|path| LedgerWriter::create(path, &engine))
+        .transpose()
+        .classify(Failure::Output)?;
+    let mut recorder = cli
+        .record_session
+        .as_deref()
+        .map(SessionRecorder::create)
+        .transpose()
+        .classify(Failure::Output)?;
+    let mut notifier = build_notifier(cli);
+    let mut history = cli.records_history().then(History::default);
+    let mut aging = cli
+        .dispute_aging_report
+        .is_some()
+        .then(DisputeAging::default);
+    let mut stream = cli
+        .stream_output
+        .then(|| AccountWriter::new(stream_to, &options))
+        .transpose()
+        .classify(Failure::Output)?
+        .map(|writer| writer.flush_each(true));
+    let mut streamed = BTreeSet::new();
+    let mut quarantine = cli.input.quarantine().classify(Failure::Output)?;
+    let mut schedule = cli.input.schedule();
+    let mut interest = cli.interest().classify_input()?;
+    let mut shadow = cli.shadow().classify_input()?;
+    let mut progress = cli.progress();
+    let mut progress_offset = 0;
+    #[cfg(feature = "postgres")]
+    let mut postgres = connect_postgres(cli)?.map(|sink| (sink, Vec::new()));
+
+    for (index, input) in cli.input.inputs.iter().enumerate() {
+        let _span = tracing::info_span!("process_file", path = %input).entered();
+        tracing::info!("reading transactions");
+        let source = format!("file:{}", input);
+        let reader = defer(
+            cli.input
+                .read(index, engine.config().precision)
+                .classify_input()?,
+            schedule.as_mut(),
+        );
+        let mut reader = io::skip_malformed(reader, quarantine.as_mut());
+        while !interrupted.load(Ordering::Relaxed)
+            && let Some(tx_result) = reader.next()
+        {
+            let tx = tx_result.classify_input()?;
+            if let Some(progress) = &mut progress {
+                progress.update(progress_offset + reader.bytes_read().unwrap_or(0))?;
+            }
+            if let (Some(accrual), Some(date)) = (&mut interest, reader.effective_date()) {
+                post_interest(
+                    accrual,
+                    date,
+                    &mut engine,
+                    &mut ledger,
+                    &mut recorder,
+                    &mut history,
+                )?;
+            }
+            if let (Some(shadow), Some(date)) = (&mut shadow, reader.effective_date()) {
+                shadow.post_interest(date)?;
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&source, &tx).classify(Failure::Output)?;
+            }
+            let tx_type = tx.tx_type;
+            #[cfg(feature = "postgres")]
+            let transaction = postgres.as_ref().map(|_| tx.clone());
+            let shadow_tx = shadow.as_ref().map(|_| tx.clone());
+            let decision = engine.process(tx)?;
+            if let (Some(shadow), Some(tx)) = (&mut shadow, shadow_tx) {
+                shadow.process(tx, &decision)?;
+            }
+            if let Some(ledger) = &mut ledger {
+                ledger
+                    .record(&engine, &decision)
+                    .classify(Failure::Output)?;
+            }
+            #[cfg(feature = "postgres")]
+            if let (Some((_, rejected)), Some(transaction), Decision::Ignore(reason)) =
+                (&mut postgres, transaction, &decision)
+            {
+                rejected.push(RejectedTransaction {
+                    transaction,
+                    reason: *reason,
+                });
+            }
+            if let Some(history) = &mut history {
+                history.record(tx_type, &decision, engine.accounts());
+            }
+            if let Some(aging) = &mut aging {
+                aging.record(&decision, reader.effective_date());
+            }
+            // Locked accounts never change again, so they can be written right away.
+            if let Some(stream) = &mut stream
+                && engine.config().locked_accounts_are_final()
+                && let Decision::Chargeback {
+                    client, lock: true, ..
+                } = decision
+                && let Some(account) = engine.accounts().get(&client)
+            {
+                stream.write(client, account).classify(Failure::Output)?;
+                streamed.insert(client);
+            }
+            if let Some(notifier) = &mut notifier {
+                for event in notify::events_for(&decision) {
+                    if let Err(err) = notifier.notify(&event) {
+                        tracing::error!(
+                            ?event,
+                            error = format!("{:#}", err),
+                            "failed to deliver notification"
+                        );
+                    }
+                }
+            }
+        }
+        if interrupted.load(Ordering::Relaxed) {
+            tracing::warn!(
+                transactions = engine.stats().transactions(),
+                "interrupted, stopped reading transactions"
+            );
+            break;
+        }
+
+        tracing::info!(
+            transactions = engine.stats().transactions(),
+            "finished reading transactions"
+        );
+        progress_offset += std::fs::metadata(input).map_or(0, |metadata| metadata.len());
+
+        if let Some(dir) = &cli.period_dir {
+            io::write_closed_period_to_dir(engine.close_period(), dir, &options)
+                .classify(Failure::Output)?;
+        }
+    }
+    if let Some(progress) = progress {
+        progress.finish(progress_offset)?;
+    }
+    let interrupted = interrupted.load(Ordering::Relaxed);
+
+    // Interest up to `--as-of` is only due once all transactions before it are in.
+    if !interrupted && let (Some(accrual), Some(date)) = (&mut interest, cli.input.as_of) {
+        post_interest(
+            accrual,
+            date,
+            &mut engine,
+            &mut ledger,
+            &mut recorder,
+            &mut history,
+        )?;
+    }
+    if !interrupted && let (Some(shadow), Some(date)) = (&mut shadow, cli.input.as_of) {
+        shadow.post_interest(date)?;
+    }
+
+    if let Some(recorder) = &mut recorder {
+        recorder.flush().classify(Failure::Output)?;
+    }
+    if let Some(ledger) = &mut ledger {
+        ledger.flush().classify(Failure::Output)?;
+    }
+
+    let malformed = quarantine.as_ref().map_or(0, Quarantine::count);
+    finish_quarantine(quarantine).classify(Failure::Output)?;
+    finish_schedule(schedule, cli.input.pending_report.as_deref(), &options)
+        .classify(Failure::Output)?;
+
+    if let Some(rules) = &rules {
+        finish_rules(rules, cli.rules_report.as_deref(), &options).classify(Failure::Output)?;
+    }
+
+    if let (Some(aging), Some(path)) = (&aging, &cli.dispute_aging_report) {
+        let overdue = aging.overdue(
+            &engine.state().deposit_history,
+            cli.max_dispute_age,
+            cli.input.as_of,
+        );
+        io::write_dispute_aging_report_to_file(&overdue, cli.max_dispute_age, path, &options)
+            .classify(Failure::Output)?;
+    }
+
+    #[cfg(feature = "postgres")]
+    if let Some((mut sink, rejected)) = postgres {
+        sink.write(engine.accounts(), &rejected)
+            .classify(Failure::Output)?;
+    }
+
+    if let (Some(history), Some(dir)) = (&history, &cli.history_dir) {
+        io::write_history_to_dir(history, dir, &options).classify(Failure::Output)?;
+    }
+
+    if let Some(target) = &cli.stats {
+        let report = StatsReport::new(
+            engine.stats().clone(),
+            engine.accounts(),
+            started.elapsed(),
+            engine.peak_memory(),
+        );
+        match target.as_str() {
+            "-" => eprintln!("{}", report),
+            path => io::write_stats_as_json_to_file(&report, path).classify(Failure::Output)?,
+        }
+    }
+
+    if let (Some(shadow), Some(report)) = (shadow, &cli.shadow_report) {
+        shadow
+            .finish(engine.accounts(), report, &options)
+            .classify(Failure::Output)?;
+    }
+
+    if let (Some(baseline), Some(path)) = (&baseline, &cli.delta_report) {
+        let deltas = diff::diff_accounts(baseline, engine.accounts());
+        io::write_account_deltas_as_csv_to_file(&deltas, path, &options)
+            .classify(Failure::Output)?;
+    }
+
+    if let Some(path) = &cli.save_snapshot {
+        wal::write_snapshot_file(path, &engine).classify(Failure::Output)?;
+    }
+
+    if let Some(path) = &cli.save_deposit_index {
+        let base = cli
+            .deposit_index
+            .as_deref()
+            .map(DepositIndex::open)
+            .transpose()
+            .classify_input()?;
+        deposit_index::write_deposit_index_file(
+            path,
+            &engine.state().deposit_history,
+            base.as_ref(),
+        )
+        .classify(Failure::Output)?;
+    }
+
+    if !interrupted
+        && let Some((cache, key)) = &cache
+        && let Err(err) = cache.put(key, engine.accounts())
+    {
+        tracing::warn!(error = format!("{:#}", err), "failed to cache accounts");
+    }
+
+    let transactions = engine.stats().transactions();
+    let manifest = cli
+        .manifest
+        .as_ref()
+        .map(|path| {
+            let manifest = RunManifest {
+                engine: EngineVersion::current(),
+                arguments: cli.arguments.clone(),
+                engine_config: format!("{:?}", engine.config()),
+                inputs: manifest::input_files(&cli.input.inputs)?,
+                rows: RowCounts::new(engine.stats(), malformed),
+                rejections: engine.stats().ignored_by_reason.clone(),
+                output_digest: digest::account_digest(engine.accounts()),
+                complete: !interrupted,
+                started_at: manifest::unix_secs(started_at),
+                elapsed_secs: 0.0,
+            };
+            anyhow::Ok((path, manifest))
+        })
+        .transpose()
+        .classify_input()?;
+    match stream {
+        Some(mut stream) => {
+            let accounts = engine.into_accounts();
+            write_reports(&accounts, &cli.output)?;
+            for (client, account) in sorted_accounts(&accounts) {
+                if !streamed.contains(&client) {
+                    stream.write(client, account).classify(Failure::Output)?;
+                }
+            }
+            stream
+                .finish()
+                .context("Failed to write accounts to stdout")
+                .classify(Failure::Output)?;
+        }
+        None => write_output(engine.into_accounts(), &cli.output, history.as_ref())?,
+    }
+
+    // The manifest describes a written output, so it comes last.
+    if let Some((path, mut manifest)) = manifest {
+        manifest.elapsed_secs = started.elapsed().as_secs_f64();
+        io::write_manifest_to_file(&manifest, path).classify(Failure::Output)?;
+    }
+
+    if interrupted {
+        eprintln!(
+            "Interrupted: the results are PARTIAL and cover only the first {} transaction(s)",
+            transactions
+        );
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+    Ok(())
+}
+
+/// Class of a failure, reported as the exit code of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// Missing or invalid command-line arguments or configuration
+    Usage = 2,
+    /// An input file does not exist
+    InputNotFound = 3,
+    /// An input file could not be read or parsed
+    Parse = 4,
+    /// The final accounts violate an invariant
+    Invariant = 5,
+    /// The output could not be written
+    Output = 6,
+}
+
+impl Failure {
+    /// Returns the class `err` was tagged with, if any.
+    fn of(err: &anyhow::Error) -> Option<Failure> {
+        err.downcast_ref::<ClassifiedError>()
+            .map(|classified| classified.failure)
+    }
+}
+
+/// Returned by `diff`, `replay` and `reconcile` when the accounts differ. The
+/// differences were already written, so the process exits with code 1 without
+/// printing an error.
+#[derive(Debug)]
+struct DifferencesFound;
+
+impl std::fmt::Display for DifferencesFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the accounts differ")
+    }
+}
+
+impl std::error::Error for DifferencesFound {}
+
+/// An error tagged with its [`Failure`] class. It displays as the error it
+/// wraps, so the message printed for it does not change.
+#[derive(Debug)]
+struct ClassifiedError {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for ClassifiedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for ClassifiedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Tags the errors of results with their [`Failure`] class. An error keeps the
+/// class it was tagged with first, i.e. closest to where it occurred.
+trait Classify<T> {
+    /// Tags the error with `failure`.
+    fn classify(self, failure: Failure) -> Result<T>;
+
+    /// Tags the error of reading an input file as [`Failure::InputNotFound`]
+    /// if the file does not exist and as [`Failure::Parse`] otherwise.
+    fn classify_input(self) -> Result<T>;
+}
+
+impl<T> Classify<T> for Result<T> {
+    fn classify(self, failure: Failure) -> Result<T> {
+        self.map_err(|error| match Failure::of(&error) {
+            Some(_) => error,
+            None => ClassifiedError { failure, error }.into(),
+        })
+    }
+
+    fn classify_input(self) -> Result<T> {
+        let not_found = self.as_ref().is_err_and(|error| {
+            error.chain().any(|cause| {
+                cause
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == std::io::ErrorKind::NotFound)
+            })
+        });
+        self.classify(if not_found {
+            Failure::InputNotFound
+        } else {
+            Failure::Parse
+        })
+    }
+}
+
+/// Exit code of a run stopped by SIGINT or SIGTERM after writing its partial
+/// results, as a shell reports a process killed by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Lets SIGINT and SIGTERM set the returned flag instead of terminating the
+/// process, so the run can stop after the current record. A second signal
+/// terminates the process right away.
+fn register_interrupt() -> Result<Arc<AtomicBool>> {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+    use signal_hook::flag;
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register_conditional_shutdown(signal, EXIT_INTERRUPTED, Arc::clone(&interrupted))
+            .and_then(|_| flag::register(signal, Arc::clone(&interrupted)))
+            .context("Failed to register the signal handlers")?;
+    }
+    Ok(interrupted)
+}
+
+/// Processes the input files with the staged pipeline and writes the results.
+#[cfg(feature = "async")]
+fn run_pipeline(cli: &Cli) -> Result<()> {
+    use project_diamond_hands::pipeline::{Pipeline, PipelineConfig};
+
+    let unsupported = [
+        ("--record-session", cli.record_session.is_some()),
+        ("--history-dir", cli.history_dir.is_some()),
+        ("--period-dir", cli.period_dir.is_some()),
+        ("--snapshot", cli.snapshot.is_some()),
+        ("--backfill", cli.backfill.is_some()),
+        #[cfg(feature = "http-lookup")]
+        ("--tx-lookup-url", cli.tx_lookup_url.is_some()),
+        ("--deposit-index", cli.deposit_index.is_some()),
+        ("--save-deposit-index", cli.save_deposit_index.is_some()),
+        ("--save-snapshot", cli.save_snapshot.is_some()),
+        ("--ledger", cli.ledger.is_some()),
+        ("--shadow", cli.shadow.is_some()),
+        ("--dispute-aging-report", cli.dispute_aging_report.is_some()),
+        (
+            "--idempotency tx",
+            cli.engine.idempotency == Some(IdempotencyKey::Tx),
+        ),
+        ("--dry-run", cli.dry_run),
+        ("--progress", cli.progress),
+        ("--rules", cli.rules.is_some()),
+        ("--stats", cli.stats.is_some()),
+        ("--manifest", cli.manifest.is_some()),
+        ("--quarantine", cli.input.quarantine.is_some()),
+        ("--skip", cli.input.skip > 0),
+        ("--limit", cli.input.limit.is_some()),
+        ("--only-clients", cli.input.only_clients.is_some()),
+        ("--exclude-clients", cli.input.exclude_clients.is_some()),
+        ("--source-prefix", !cli.input.source_prefix.is_empty()),
+        ("--as-of", cli.input.as_of.is_some()),
+        ("--interest-rates", cli.interest_rates.is_some()),
+        ("--output", cli.output.output.is_some()),
+        (
+            "--output-format",
+            cli.output.output_format != OutputFormat::Csv,
+        ),
+        ("--webhook-url", build_notifier(cli).is_some()),
+        (
+            "Excel input",
+            cli.input
+                .inputs
+                .iter()
+                .any(|path| path.to_ascii_lowercase().ends_with(".xlsx")),
+        ),
+        #[cfg(feature = "postgres")]
+        ("--postgres-url", cli.postgres_url.is_some()),
+    ];
+    if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+        return Err(anyhow::anyhow!("--pipeline does not support {}", option))
+            .classify(Failure::Usage);
+    }
+
+    let config = PipelineConfig {
+        shards: cli.shards,
+        ..Default::default()
+    };
+    let pipeline = Pipeline::new(cli.engine.to_config(&cli.output).classify_input()?, config)
+        .strict(cli.input.strict)
+        .stream_locked(cli.stream_output)
+        .delimiter(cli.input.delimiter);
+    let pipeline = cli
+        .input
+        .column_alias
+        .iter()
+        .fold(pipeline, |pipeline, (alias, column)| {
+            pipeline.column_alias(alias.as_str(), *column)
+        });
+    let writer = AccountWriter::new(std::io::stdout(), &cli.output.to_options())
+        .classify(Failure::Output)?
+        .flush_each(cli.stream_output);
+
+    let accounts = tokio::runtime::Runtime::new()
+        .context("Failed to start the Tokio runtime")?
+        .block_on(pipeline.run_files(cli.input.inputs.clone(), writer))?;
+    write_reports(&accounts, &cli.output)
+}
+
+/// Processes the input files on top of `--snapshot` without writing anything, and
+/// prints the projected effects.
+fn run_dry_run(cli: &Cli) -> Result<()> {
+    let unsupported = [
+        ("--record-session", cli.record_session.is_some()),
+        ("--history-dir", cli.history_dir.is_some()),
+        ("--period-dir", cli.period_dir.is_some()),
+        ("--save-snapshot", cli.save_snapshot.is_some()),
+        ("--save-deposit-index", cli.save_deposit_index.is_some()),
+        ("--delta-report", cli.delta_report.is_some()),
+        ("--ledger", cli.ledger.is_some()),
+        ("--shadow", cli.shadow.is_some()),
+        ("--stream-output", cli.stream_output),
+        ("--stats", cli.stats.is_some()),
+        ("--manifest", cli.manifest.is_some()),
+        ("--quarantine", cli.input.quarantine.is_some()),
+        ("--as-of", cli.input.as_of.is_some()),
+        ("--interest-rates", cli.interest_rates.is_some()),
+        ("--rules-report", cli.rules_report.is_some()),
+        ("--dispute-aging-report", cli.dispute_aging_report.is_some()),
+        ("--output", cli.output.output.is_some()),
+        (
+            "--output-format",
+            cli.output.output_format != OutputFormat::Csv,
+        ),
+        ("--overdraft-report", cli.output.overdraft_report.is_some()),
+        ("--extended-output", cli.output.extended_output),
+        ("--exposure-report", cli.output.exposure_report.is_some()),
+        ("--fraud-report", cli.output.fraud_report.is_some()),
+        ("--digest", cli.output.digest),
+        ("--expect-digest", cli.output.expect_digest.is_some()),
+        ("--check-invariants", cli.output.check_invariants),
+        ("--webhook-url", build_notifier(cli).is_some()),
+        #[cfg(feature = "postgres")]
+        ("--postgres-url", cli.postgres_url.is_some()),
+    ];
+    if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+        return Err(anyhow::anyhow!("--dry-run does not support {}", option))
+            .classify(Failure::Usage);
+    }
+
+    let rules = cli.rules().classify_input()?;
+    let mut engine = cli.engine(rules.as_ref()).classify_input()?;
+    let before = engine.accounts().clone();
+    let mut rejected = Vec::new();
+    let mut progress = cli.progress();
+    let mut progress_offset = 0;
+    for (index, input) in cli.input.inputs.iter().enumerate() {
+        let mut reader = recurring::expand_recurring(
+            cli.input
+                .read(index, engine.config().precision)
+                .classify_input()?,
+        );
+        while let Some(tx_result) = reader.next() {
+            let tx = tx_result.classify_input()?;
+            if let Some(progress) = &mut progress {
+                progress.update(progress_offset + reader.bytes_read().unwrap_or(0))?;
+            }
+            if let Decision::Ignore(reason) = engine.process(tx.clone())? {
+                rejected.push(RejectedTransaction {
+                    transaction: tx,
+                    reason,
+                });
+            }
+        }
+        progress_offset += std::fs::metadata(input).map_or(0, |metadata| metadata.len());
+    }
+    if let Some(progress) = progress {
+        progress.finish(progress_offset)?;
+    }
+
+    let deltas = diff::diff_accounts(&before, engine.accounts());
+    io::write_dry_run_report_to_stdout(&deltas, &rejected, &cli.output.to_options())
+        .classify(Failure::Output)?;
+    eprintln!(
+        "Dry run: {} account(s) would change, {} would become locked and {} transaction(s) \
+         would be rejected; nothing was written",
+        deltas.len(),
+        deltas.iter().filter(|delta| delta.newly_locked()).count(),
+        rejected.len()
+    );
+    Ok(())
+}
+
+/// Writes the `--rules-report` and reports how many rule violations occurred.
+fn finish_rules(rules: &RuleSet, report: Option<&str>, options: &OutputOptions) -> Result<()> {
+    let triggered = rules.triggered();
+    if let Some(path) = report {
+        io::write_triggered_rules_to_file(&triggered, path, options)?;
+    }
+    let held = triggered
+        .iter()
+        .filter(|triggered| triggered.action == RuleAction::Hold)
+        .count();
+    eprint!(
+        "{} rule violation(s): {} flagged, {} held",
+        triggered.len(),
+        triggered.len() - held,
+        held
+    );
+    match report {
+        Some(path) => eprintln!(", see {}", path),
+        None => eprintln!(),
+    }
+    Ok(())
+}
+
+/// Expands the recurring transactions of `reader` and holds back those that are
+/// not due in `schedule`.
+fn defer<'a>(
+    reader: Box<dyn TransactionSource>,
+    schedule: Option<&'a mut Schedule>,
+) -> Box<dyn TransactionSource + 'a> {
+    let expanded = recurring::expand_recurring(reader);
+    match schedule {
+        Some(schedule) => Box::new(schedule.defer(expanded)),
+        None => Box::new(expanded),
+    }
+}
+
+/// Accrues interest up to `date` and processes the interest entries posted on
+/// the way, recording them like input transactions.
+fn post_interest(
+    accrual: &mut InterestAccrual,
+    date: Date,
+    engine: &mut Engine,
+    ledger: &mut Option<LedgerWriter>,
+    recorder: &mut Option<SessionRecorder>,
+    history: &mut Option<History>,
+) -> Result<()> {
+    for entry in accrual.advance_to(date, engine.accounts()) {
+        if let Some(recorder) = recorder {
+            recorder.record("interest", &entry)?;
+        }
+        let decision = engine.process(entry)?;
+        if let Some(ledger) = ledger {
+            ledger.record(engine, &decision)?;
+        }
+        if let Some(history) = history {
+            history.record(TxType::Interest, &decision, engine.accounts());
+        }
+    }
+    Ok(())
+}
+
+/// The engine of a `--shadow` run, which processes the same transactions as the
+/// regular engine.
+struct Shadow {
+    engine: Engine,
+    interest: Option<InterestAccrual>,
+    /// Number of transactions the two engines decided differently.
+    diverging: u64,
+}
+
+impl Shadow {
+    /// Processes `tx`, counting it if the regular engine made another `decision`.
+    fn process(&mut self, tx: Transaction, decision: &Decision) -> Result<()> {
+        if self.engine.process(tx)? != *decision {
+            self.diverging += 1;
+        }
+        Ok(())
+    }
+
+    /// Accrues and posts interest up to `date`, like [`post_interest`].
+    fn post_interest(&mut self, date: Date) -> Result<()> {
+        if let Some(accrual) = &mut self.interest {
+            post_interest(
+                accrual,
+                date,
+                &mut self.engine,
+                &mut None,
+                &mut None,
+                &mut None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the differences of the shadow accounts from `accounts` to `report`
+    /// and prints a summary to stderr.
+    fn finish(self, accounts: &Accounts, report: &str, options: &OutputOptions) -> Result<()> {
+        let deltas = diff::diff_accounts(accounts, self.engine.accounts());
+        io::write_account_deltas_as_csv_to_file(&deltas, report, options)?;
+        eprintln!(
+            "--shadow decided {} transaction(s) differently and changed {} account(s)",
+            self.diverging,
+            deltas.len()
+        );
+        Ok(())
+    }
+}
+
+/// Writes the `--pending-report` and reports how many transactions are pending.
+fn finish_schedule(
+    schedule: Option<Schedule>,
+    report: Option<&str>,
+    options: &OutputOptions,
+) -> Result<()> {
+    let Some(schedule) = schedule else {
+        return Ok(());
+    };
+    if let Some(path) = report {
+        io::write_pending_transactions_to_file(schedule.pending(), path, options)?;
+    }
+    eprint!(
+        "{} transaction(s) pending after {}",
+        schedule.pending().count(),
+        schedule.as_of()
+    );
+    match report {
+        Some(path) => eprintln!(", see {}", path),
+        None => eprintln!(),
+    }
+    Ok(())
+}
+
+/// Flushes the quarantine file and reports how many records were skipped.
+fn finish_quarantine(quarantine: Option<Quarantine>) -> Result<()> {
+    if let Some(mut quarantine) = quarantine {
+        quarantine.flush()?;
+        eprintln!(
+            "Skipped {} malformed record(s), see {}",
+            quarantine.count(),
+            quarantine.path()
+        );
+    }
+    Ok(())
+}
+
+/// Connects to the PostgreSQL database configured on the command line, if any.
+#[cfg(feature = "postgres")]
+fn connect_postgres(cli: &Cli) -> Result<Option<PostgresSink>> {
+    let tables = PostgresTables {
+        accounts: cli.postgres_accounts_table.clone(),
+        rejected: cli.postgres_rejected_table.clone(),
+    };
+    cli.postgres_url
+        .as_deref()
+        .map(|url| PostgresSink::connect(url, &tables))
+        .transpose()
+}
+
+/// Opens the state store of the `listen` server configured on the command
+/// line, if any.
+#[cfg_attr(not(all(feature = "sled", feature = "redis")), allow(unused_variables))]
+fn open_state_store(
+    state_dir: Option<&str>,
+    redis_url: Option<&str>,
+) -> Result<Option<Box<dyn StateStore + Send>>> {
+    #[cfg(feature = "sled")]
+    if let Some(dir) = state_dir {
+        use project_diamond_hands::store::sled::SledStore;
+
+        return Ok(Some(Box::new(SledStore::open(dir).classify_input()?)));
+    }
+    #[cfg(feature = "redis")]
+    if let Some(url) = redis_url {
+        use project_diamond_hands::store::redis::RedisStore;
+
+        return Ok(Some(Box::new(RedisStore::connect(url).classify_input()?)));
+    }
+    Ok(None)
+}
+
+/// Binds a server applying the transactions of different clients on `workers`
+/// threads.
+#[cfg(feature = "concurrent")]
+fn bind_concurrent_server(
+    config: EngineConfig,
+    workers: usize,
+    ingest: &ListenAddress,
+    control: &ListenAddress,
+) -> Result<Server> {
+    use project_diamond_hands::concurrent::ConcurrentEngine;
+
+    let engine = ConcurrentEngine::new(config, workers).classify(Failure::Usage)?;
+    Server::bind_concurrent(engine, ingest, control)
+}
+
+/// Binds a server applying the transactions of different clients on `workers`
+/// threads.
+#[cfg(not(feature = "concurrent"))]
+fn bind_concurrent_server(
+    _config: EngineConfig,
+    _workers: usize,
+    _ingest: &ListenAddress,
+    _control: &ListenAddress,
+) -> Result<Server> {
+    anyhow::bail!("--workers requires the concurrent feature")
+}
+
+/// Returns the notifier configured on the command line, if any.
+#[cfg(feature = "webhooks")]
+fn build_notifier(cli: &Cli) -> Option<Box<dyn Notifier>> {
+    use project_diamond_hands::notify::{RetryPolicy, WebhookNotifier};
+
+    cli.webhook_url.as_deref().map(|url| {
+        let policy = RetryPolicy {
+            max_attempts: cli.webhook_attempts,
+            ..Default::default()
+        };
+        Box::new(WebhookNotifier::new(url, policy)) as Box<dyn Notifier>
+    })
+}
+
+/// Returns the notifier configured on the command line, if any.
+#[cfg(not(feature = "webhooks"))]
+fn build_notifier(_cli: &Cli) -> Option<Box<dyn Notifier>> {
+    None
+}
+
+/// Writes the optional reports followed by the account summaries, to stdout
+/// unless `--output` is given.
 ///
-/// This function will return an error if:
-/// - Missing command-line argument (input file path)
-/// - File I/O errors (file not found, permission denied, etc.)
-/// - CSV parsing errors (invalid format, type conversion errors, etc.)
-/// - Transaction processing errors
-/// - Output writing errors
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+/// `history` is written as well when the output format supports it.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn write_output(accounts: Accounts, output: &OutputArgs, history: Option<&History>) -> Result<()> {
+    let _span = tracing::info_span!("write_output", accounts = accounts.len()).entered();
+    write_reports(&accounts, output)?;
+    let written = match (output.output_format, &output.output) {
+        (OutputFormat::Csv, None) => {
+            io::write_accounts_as_csv_to_stdout(accounts, &output.to_options())
+        }
+        (OutputFormat::Csv, Some(path)) => {
+            io::write_accounts_as_csv_to_file(accounts, path, &output.to_options())
+        }
+        (OutputFormat::Table, None) => {
+            let stdout = std::io::stdout();
+            let color = output.color.enabled(&stdout);
+            table::write_accounts_as_table(&accounts, stdout.lock(), &output.to_options(), color)
+        }
+        (OutputFormat::Table, Some(path)) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create file: {}", path))?;
+            let color = output.color == ColorChoice::Always;
+            table::write_accounts_as_table(
+                &accounts,
+                std::io::BufWriter::new(file),
+                &output.to_options(),
+                color,
+            )
+        }
+        #[cfg(feature = "sqlite")]
+        (OutputFormat::Sqlite, path) => {
+            let path = path
+                .as_deref()
+                .context("--output-format sqlite requires --output")
+                .classify(Failure::Usage)?;
+            project_diamond_hands::sqlite::write_accounts_to_sqlite(&accounts, history, path)
+        }
+        #[cfg(feature = "arrow")]
+        (OutputFormat::Arrow, None) => {
+            project_diamond_hands::arrow::write_accounts_as_arrow_ipc(&accounts, std::io::stdout())
+        }
+        #[cfg(feature = "arrow")]
+        (OutputFormat::Arrow, Some(path)) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create file: {}", path))?;
+            project_diamond_hands::arrow::write_accounts_as_arrow_ipc(
+                &accounts,
+                std::io::BufWriter::new(file),
+            )
+        }
+    };
+    written.classify(Failure::Output)
+}
+
+/// Writes the optional reports derived from the final account states.
+fn write_reports(accounts: &Accounts, output: &OutputArgs) -> Result<()> {
+    if output.check_invariants {
+        let violations = engine::check_invariants(accounts);
+        for violation in &violations {
+            eprintln!("{}", violation);
+        }
+        if !violations.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} invariant violation(s) in the final accounts",
+                violations.len()
+            ))
+            .classify(Failure::Invariant);
+        }
+    }
 
-    if args.len() < 2 {
-        anyhow::bail!("Missing input file!");
+    if output.digest || output.expect_digest.is_some() {
+        let digest = digest::account_digest(accounts);
+        if output.digest {
+            eprintln!("accounts digest: {}", digest);
+        }
+        if let Some(expected) = &output.expect_digest {
+            anyhow::ensure!(
+                digest.eq_ignore_ascii_case(expected.trim()),
+                "Account digest {} does not match the expected {}",
+                digest,
+                expected
+            );
+        }
     }
 
-    let file_path = &args[1];
-    let transactions = io::read_transactions_from_file(file_path)?;
-    let accounts = engine::proccess_transactions(transactions)?;
+    if let Some(path) = &output.overdraft_report {
+        let overdrawn: Accounts = engine::overdrawn_accounts(accounts)
+            .map(|(client, account)| (*client, account.clone()))
+            .collect();
+        io::write_accounts_as_csv_to_file(overdrawn, path, &output.to_options())
+            .classify(Failure::Output)?;
+    }
+
+    if let Some(path) = &output.exposure_report {
+        let exposure = exposure::held_exposure(accounts);
+        io::write_exposure_report_to_file(&exposure, path, &output.to_options())
+            .classify(Failure::Output)?;
+    }
 
-    io::write_accounts_as_csv_to_stdout(accounts)?;
+    if let Some(path) = &output.fraud_report {
+        let thresholds = FraudThresholds {
+            cycles: output.fraud_cycles,
+            chargeback_percent: output.fraud_chargeback_percent,
+            disputes: output.fraud_disputes,
+        };
+        let flags = fraud::flag_suspicious_accounts(accounts, &thresholds);
+        io::write_fraud_report_to_file(&flags, path, &output.to_options())
+            .classify(Failure::Output)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the command line `args`, without the program name.
+    fn run_args(args: &[&str]) -> Result<()> {
+        run(&Cli::parse_from(
+            std::iter::once("project-diamond-hands").chain(args.iter().copied()),
+        ))
+    }
+
+    #[test]
+    fn baseline_runs_report_changes_since_the_previous_day() {
+        let dir = std::env::temp_dir().join(format!("baseline-runs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(
+            path("day1.csv"),
+            "type,client,tx,amount\ndeposit,1,1,10.5\ndeposit,2,2,5.5\n",
+        )
+        .unwrap();
+        std::fs::write(
+            path("day2.csv"),
+            "type,client,tx,amount\nwithdrawal,1,3,4.25\ndeposit,3,4,2.5\n",
+        )
+        .unwrap();
+
+        run_args(&[
+            &path("day1.csv"),
+            "--save-snapshot",
+            &path("day1.json"),
+            "--output",
+            &path("day1-accounts.csv"),
+        ])
+        .unwrap();
+        run_args(&[
+            &path("day2.csv"),
+            "--baseline",
+            &path("day1.json"),
+            "--delta-report",
+            &path("delta.csv"),
+            "--output",
+            &path("day2-accounts.csv"),
+        ])
+        .unwrap();
+
+        // Client 2 is unchanged and has no row, client 3 is new.
+        assert_eq!(
+            std::fs::read_to_string(path("delta.csv")).unwrap(),
+            "client,change,available,held,total,newly_locked\n\
+             1,changed,-4.25,0,-4.25,false\n\
+             3,added,2.5,0,2.5,false\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(path("day2-accounts.csv")).unwrap(),
+            "client,available,held,total,locked\n\
+             1,6.25,0,6.25,false\n\
+             2,5.5,0,5.5,false\n\
+             3,2.5,0,2.5,false\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streamed_output_writes_locked_accounts_first_and_once() {
+        let path = std::env::temp_dir().join(format!("stream-output-{}.csv", std::process::id()));
+        // Client 2 is locked by the chargeback and touched again afterwards.
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\n\
+             deposit,1,1,1.5\n\
+             deposit,2,2,2.5\n\
+             deposit,2,3,3.5\n\
+             dispute,2,2,\n\
+             chargeback,2,2,\n\
+             deposit,2,4,4.5\n\
+             dispute,2,3,\n\
+             withdrawal,2,5,1.5\n\
+             deposit,1,6,0.5\n",
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        let cli = Cli::parse_from([
+            "project-diamond-hands",
+            path.to_str().unwrap(),
+            "--stream-output",
+        ]);
+        run_streaming_to(&cli, &mut output).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The client and the locked column of each row.
+        let output = String::from_utf8(output).unwrap();
+        let rows: Vec<(&str, &str)> = output
+            .lines()
+            .skip(1)
+            .map(|row| {
+                (
+                    row.split(',').next().unwrap(),
+                    row.rsplit(',').next().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(rows, [("2", "true"), ("1", "false")]);
+        assert!(output.starts_with("client,available,held,total,locked\n2,3.5,"));
+    }
+}
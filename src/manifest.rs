@@ -0,0 +1,203 @@
+//! Run manifests (`--manifest`).
+//!
+//! A [`RunManifest`] records how the output of a run was produced, so that a
+//! production output can be traced back to its inputs and configuration: the
+//! version of the engine, the effective command-line arguments, the engine
+//! configuration, the SHA-256 of every input file, the row and rejection
+//! counts, the [digest](crate::digest::account_digest) of the accounts and the
+//! timing. It is written as JSON:
+//!
+//! ```json
+//! {
+//!   "engine": { "name": "project-diamond-hands", "version": "0.1.0", "fixed_point": false, "wide_ids": false },
+//!   "arguments": ["transactions.csv", "--manifest", "run.json"],
+//!   "engine_config": "EngineConfig { credit_limit: 0, ... }",
+//!   "inputs": [{ "path": "transactions.csv", "sha256": "5d41402a...", "bytes": 1024 }],
+//!   "rows": { "transactions": 12, "applied": 10, "ignored": 2, "malformed": 1 },
+//!   "rejections": { "insufficient_funds": 2 },
+//!   "output_digest": "29c4f621...",
+//!   "complete": true,
+//!   "started_at": 1767225600,
+//!   "elapsed_secs": 0.004
+//! }
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::digest::file_sha256_hex;
+use crate::engine::IgnoreReason;
+use crate::stats::Stats;
+
+/// The build of the engine that produced an output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EngineVersion {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Whether amounts are fixed-point numbers (`fixed-point` feature).
+    pub fixed_point: bool,
+    /// Whether client and transaction IDs are widened (`wide-ids` feature).
+    pub wide_ids: bool,
+}
+
+impl EngineVersion {
+    /// Returns the version of this build.
+    pub fn current() -> Self {
+        EngineVersion {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            fixed_point: cfg!(feature = "fixed-point"),
+            wide_ids: cfg!(feature = "wide-ids"),
+        }
+    }
+}
+
+/// An input file and the SHA-256 of its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputFile {
+    pub path: String,
+    /// Hex-encoded SHA-256 of the file.
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// Returns the [`InputFile`]s of `paths`, in order.
+///
+/// # Errors
+///
+/// Returns an error if a file cannot be read.
+pub fn input_files(paths: &[impl AsRef<Path>]) -> Result<Vec<InputFile>> {
+    paths
+        .iter()
+        .map(|path| {
+            let path = path.as_ref();
+            let bytes = std::fs::metadata(path)
+                .with_context(|| format!("Failed to read file: {}", path.display()))?
+                .len();
+            Ok(InputFile {
+                path: path.display().to_string(),
+                sha256: file_sha256_hex(path)?,
+                bytes,
+            })
+        })
+        .collect()
+}
+
+/// The rows a run read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RowCounts {
+    /// Transactions processed by the engine, including posted interest.
+    pub transactions: u64,
+    pub applied: u64,
+    pub ignored: u64,
+    /// Malformed rows skipped with `--quarantine`.
+    pub malformed: u64,
+}
+
+impl RowCounts {
+    /// Returns the counts of a run with `stats` that skipped `malformed` rows.
+    pub fn new(stats: &Stats, malformed: u64) -> Self {
+        RowCounts {
+            transactions: stats.transactions(),
+            applied: stats.applied,
+            ignored: stats.ignored,
+            malformed,
+        }
+    }
+}
+
+/// How the output of a run was produced, see the [module documentation](self).
+#[derive(Debug, Clone, Serialize)]
+pub struct RunManifest {
+    pub engine: EngineVersion,
+    /// The command-line arguments without the program name, including the
+    /// options set by a `--config` file.
+    pub arguments: Vec<String>,
+    /// The engine configuration in its `Debug` form.
+    pub engine_config: String,
+    pub inputs: Vec<InputFile>,
+    pub rows: RowCounts,
+    /// Number of ignored transactions by reason.
+    pub rejections: BTreeMap<IgnoreReason, u64>,
+    /// [`account_digest`](crate::digest::account_digest) of the final accounts.
+    pub output_digest: String,
+    /// Whether all input was processed, i.e. the run was not interrupted.
+    pub complete: bool,
+    /// Start of the run in seconds since the Unix epoch.
+    pub started_at: u64,
+    pub elapsed_secs: f64,
+}
+
+/// Returns `time` in seconds since the Unix epoch, or 0 for earlier times.
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::types::{Amount, ClientId, Transaction, TxId, TxType};
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn records_inputs_counts_and_rejections() {
+        let path = std::env::temp_dir().join(format!("manifest-{}.csv", std::process::id()));
+        std::fs::write(&path, "hello").unwrap();
+        let inputs = input_files(&[&path]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            inputs,
+            [InputFile {
+                path: path.display().to_string(),
+                sha256: "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+                    .to_string(),
+                bytes: 5,
+            }]
+        );
+        assert!(input_files(&[&path]).is_err());
+
+        let mut engine = Engine::new(EngineConfig::default());
+        let tx = |tx_type, tx, amount| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        for tx in [
+            tx(TxType::Deposit, 1, "1"),
+            tx(TxType::Withdrawal, 2, "5"),
+            tx(TxType::Dispute, 3, "0"),
+        ] {
+            engine.process(tx).unwrap();
+        }
+        let manifest = RunManifest {
+            engine: EngineVersion::current(),
+            arguments: vec!["transactions.csv".to_string()],
+            engine_config: format!("{:?}", engine.config()),
+            inputs,
+            rows: RowCounts::new(engine.stats(), 2),
+            rejections: engine.stats().ignored_by_reason.clone(),
+            output_digest: crate::digest::account_digest(engine.accounts()),
+            complete: true,
+            started_at: unix_secs(UNIX_EPOCH + Duration::from_secs(60)),
+            elapsed_secs: 0.5,
+        };
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(
+            json["rows"],
+            serde_json::json!({ "transactions": 3, "applied": 1, "ignored": 2, "malformed": 2 })
+        );
+        assert_eq!(
+            json["rejections"],
+            serde_json::json!({ "insufficient_funds": 1, "unknown_transaction": 1 })
+        );
+        assert_eq!(json["engine"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["started_at"], 60);
+    }
+}
@@ -0,0 +1,267 @@
+//! Notifications about noteworthy account events.
+//!
+//! [`events_for`] derives the [`Event`]s caused by an applied [`Decision`]: a
+//! chargeback and the lock of the client's account it may cause. A [`Notifier`]
+//! delivers those events somewhere; with the `webhooks` feature enabled,
+//! [`WebhookNotifier`] POSTs each event as JSON to a configured URL, retrying
+//! failed deliveries with exponential backoff.
+//!
+//! The JSON payload of an event looks like:
+//!
+//! ```json
+//! {"event":"chargeback","client":1,"tx":7,"amount":"10.5"}
+//! {"event":"account_locked","client":1,"tx":7}
+//! ```
+
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::engine::Decision;
+use crate::types::{Amount, ClientId, TxId};
+
+/// An account event worth notifying about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Deposit `tx` of `client` was charged back.
+    Chargeback {
+        client: ClientId,
+        tx: TxId,
+        amount: Amount,
+    },
+    /// The account of `client` was locked by transaction `tx`.
+    AccountLocked { client: ClientId, tx: TxId },
+}
+
+/// Returns the events caused by applying `decision`, in the order they happened.
+///
+/// Locked accounts ignore all further transactions, so a chargeback that locks the
+/// account produces both events, and the lock is reported only once.
+pub fn events_for(decision: &Decision) -> Vec<Event> {
+    match *decision {
+        Decision::Chargeback {
+            client,
+            tx,
+            amount,
+            lock,
+        } => {
+            let mut events = vec![Event::Chargeback { client, tx, amount }];
+            if lock {
+                events.push(Event::AccountLocked { client, tx });
+            }
+            events
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Delivers events to an external system.
+pub trait Notifier {
+    /// Delivers a single event.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event could not be delivered.
+    fn notify(&mut self, event: &Event) -> Result<()>;
+}
+
+/// How often and how patiently failed deliveries are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of delivery attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after every further failure.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait after the given failed attempt (starting at 1).
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Runs `attempt` until it succeeds or `max_attempts` is reached, calling
+    /// `sleep` with the backoff delay between two attempts.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the last attempt if all attempts fail.
+    pub fn run<F, S>(&self, mut attempt: F, mut sleep: S) -> Result<()>
+    where
+        F: FnMut() -> Result<()>,
+        S: FnMut(Duration),
+    {
+        let mut failed = 0;
+        loop {
+            match attempt() {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    failed += 1;
+                    if failed >= self.max_attempts.max(1) {
+                        return Err(
+                            err.context(format!("Giving up after {} delivery attempts", failed))
+                        );
+                    }
+                    tracing::warn!(attempt = failed, error = %err, "delivery failed, retrying");
+                    sleep(self.backoff(failed));
+                }
+            }
+        }
+    }
+}
+
+/// Notifier POSTing every event as JSON to a webhook URL.
+#[cfg(feature = "webhooks")]
+pub struct WebhookNotifier {
+    url: String,
+    policy: RetryPolicy,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "webhooks")]
+impl WebhookNotifier {
+    /// Creates a notifier POSTing to `url`, retrying failed deliveries according to `policy`.
+    pub fn new(url: &str, policy: RetryPolicy) -> Self {
+        WebhookNotifier {
+            url: url.to_string(),
+            policy,
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build(),
+        }
+    }
+}
+
+#[cfg(feature = "webhooks")]
+impl Notifier for WebhookNotifier {
+    fn notify(&mut self, event: &Event) -> Result<()> {
+        use anyhow::Context;
+
+        let payload = serde_json::to_string(event).context("Failed to serialize event")?;
+        self.policy.run(
+            || {
+                self.agent
+                    .post(&self.url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&payload)
+                    .map(drop)
+                    .with_context(|| format!("Failed to POST event to: {}", self.url))
+            },
+            std::thread::sleep,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::str::FromStr;
+
+    #[test]
+    fn chargeback_produces_chargeback_and_lock_events() {
+        let decision = Decision::Chargeback {
+            client: ClientId(1),
+            tx: TxId(7),
+            amount: Amount::from_str("10.5").unwrap(),
+            lock: true,
+        };
+
+        let events = events_for(&decision);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            serde_json::to_string(&events[0]).unwrap(),
+            r#"{"event":"chargeback","client":1,"tx":7,"amount":"10.5"}"#
+        );
+        assert_eq!(
+            events[1],
+            Event::AccountLocked {
+                client: ClientId(1),
+                tx: TxId(7)
+            }
+        );
+
+        let unlocking = Decision::Chargeback {
+            client: ClientId(1),
+            tx: TxId(7),
+            amount: Amount::from_str("10.5").unwrap(),
+            lock: false,
+        };
+        assert_eq!(events_for(&unlocking), events[..1]);
+
+        let deposit = Decision::Deposit {
+            client: ClientId(1),
+            tx: TxId(8),
+            amount: Amount::from_str("1.0").unwrap(),
+        };
+        assert!(events_for(&deposit).is_empty());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_maximum() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn retries_until_success_or_attempts_exhausted() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            ..Default::default()
+        };
+
+        let mut calls = 0;
+        let mut sleeps = Vec::new();
+        let result = policy.run(
+            || {
+                calls += 1;
+                if calls < 2 {
+                    Err(anyhow!("unavailable"))
+                } else {
+                    Ok(())
+                }
+            },
+            |delay| sleeps.push(delay),
+        );
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+        assert_eq!(sleeps, vec![policy.initial_backoff]);
+
+        let mut calls = 0;
+        let result = policy.run(
+            || {
+                calls += 1;
+                Err(anyhow!("unavailable"))
+            },
+            |_| {},
+        );
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,574 @@
+//! Asynchronous processing pipeline (`async` feature).
+//!
+//! [`Pipeline`] processes transactions in stages that run concurrently on a
+//! Tokio runtime and are connected by bounded channels:
+//!
+//! ```text
+//! read ─▶ parse ─▶ shard ─┬─▶ apply (shard 0) ─┬─▶ write
+//!                         └─▶ apply (shard N) ─┘
+//! ```
+//!
+//! - **read** reads raw CSV records in batches on a blocking thread.
+//! - **parse** turns the records into transactions, applying the precision policy.
+//! - **shard** routes every transaction to the engine owning its client
+//!   (`client % shards`), so the transactions of a client stay in order.
+//! - **apply** runs one [`Engine`] per shard.
+//! - **write** writes the accounts once the input is exhausted, merging the
+//!   client-ordered accounts of the shards, so the output is sorted by client
+//!   however the shards were scheduled. With [`Pipeline::stream_locked`],
+//!   accounts locked by a chargeback are written right away instead.
+//!
+//! Every channel holds at most [`PipelineConfig::capacity`] batches, so a slow
+//! stage (typically the output) stalls the stages before it instead of letting
+//! input pile up in memory. Producers other than CSV files, such as sockets,
+//! send [`RecordBatch`]es to [`Pipeline::run`] and share the remaining stages.
+//!
+//! A shard only knows the deposits of its own clients, so transaction IDs must
+//! be unique across clients, as the input format requires.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::{self, JoinError};
+
+use crate::amount::PrecisionPolicy;
+use crate::engine::{Decision, Engine, EngineConfig};
+use crate::io::{
+    AccountWriter, Delimiter, TransactionColumn, TransactionColumns, describe_record,
+    ensure_not_recurring, open_csv_file, parse_adjusted_fields,
+};
+use crate::types::{AccountDetails, Accounts, ClientId, Transaction};
+
+/// Sizes of the pipeline stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// Number of engines applying transactions in parallel.
+    pub shards: usize,
+    /// Maximum number of batches waiting in each channel between stages.
+    pub capacity: usize,
+    /// Number of records read per batch.
+    pub batch_size: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            shards: 4,
+            capacity: 16,
+            batch_size: 1024,
+        }
+    }
+}
+
+/// A batch of raw records from one source, the input of the parse stage.
+#[derive(Debug, Clone)]
+pub struct RecordBatch {
+    /// Where the records come from, e.g. a file path, named in parse errors.
+    pub source: Arc<str>,
+    /// The header row of the source.
+    pub headers: Arc<csv::ByteRecord>,
+    /// The records, each with its position within the source.
+    pub records: Vec<csv::ByteRecord>,
+}
+
+/// Processes transactions in concurrent stages, see the [module documentation](self).
+pub struct Pipeline {
+    engine: EngineConfig,
+    config: PipelineConfig,
+    strict: bool,
+    stream_locked: bool,
+    delimiter: Delimiter,
+    aliases: Vec<(String, TransactionColumn)>,
+}
+
+impl Pipeline {
+    /// Creates a pipeline whose shards apply the rules of `engine`.
+    pub fn new(engine: EngineConfig, config: PipelineConfig) -> Self {
+        Pipeline {
+            engine,
+            config,
+            strict: false,
+            stream_locked: false,
+            delimiter: Delimiter::Auto,
+            aliases: Vec::new(),
+        }
+    }
+
+    /// Rejects negative amounts with an error instead of passing them on.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Writes accounts locked by a chargeback as soon as they are locked,
+    /// ahead of the others, so the output is no longer sorted by client.
+    pub fn stream_locked(mut self, stream_locked: bool) -> Self {
+        self.stream_locked = stream_locked;
+        self
+    }
+
+    /// Sets the field delimiter of the files read by [`Pipeline::run_files`].
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Reads header names matching `alias` as `column`.
+    pub fn column_alias(mut self, alias: impl Into<String>, column: TransactionColumn) -> Self {
+        self.aliases.push((alias.into(), column));
+        self
+    }
+
+    /// Processes the CSV files at `paths` in order, streaming the accounts to
+    /// `output`, and returns the final account states.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of any stage. Accounts locked by a chargeback
+    /// may have been written already; the remaining accounts are not.
+    pub async fn run_files<W: Write + Send + 'static>(
+        self,
+        paths: Vec<String>,
+        output: AccountWriter<W>,
+    ) -> Result<Accounts> {
+        let (sender, receiver) = mpsc::channel(self.config.capacity.max(1));
+        let (delimiter, batch_size) = (self.delimiter, self.config.batch_size.max(1));
+        let read = task::spawn_blocking(move || {
+            for path in &paths {
+                if let Err(err) = read_csv_file(path, delimiter, batch_size, &sender) {
+                    // Fails only if the pipeline stopped, which it reports itself.
+                    let _ = sender.blocking_send(Err(err));
+                    break;
+                }
+            }
+        });
+        let result = self.run(receiver, output).await;
+        read.await.context("Read stage failed")?;
+        result
+    }
+
+    /// Processes the record batches received from `input` until it is closed,
+    /// streaming the accounts to `output`, and returns the final account states.
+    ///
+    /// A producer reports a failure by sending an error, which stops the
+    /// pipeline without writing the remaining accounts.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of any stage. Accounts locked by a chargeback
+    /// may have been written already; the remaining accounts are not.
+    pub async fn run<W: Write + Send + 'static>(
+        self,
+        input: mpsc::Receiver<Result<RecordBatch>>,
+        output: AccountWriter<W>,
+    ) -> Result<Accounts> {
+        let capacity = self.config.capacity.max(1);
+        let (parsed, parsed_receiver) = mpsc::channel(capacity);
+        let parse = tokio::spawn(parse_stage(
+            input,
+            parsed,
+            self.aliases,
+            self.engine.precision,
+            self.strict,
+        ));
+
+        let shard_count = self.config.shards.max(1);
+        let (shards, shard_receivers): (Vec<_>, Vec<_>) =
+            (0..shard_count).map(|_| mpsc::channel(capacity)).unzip();
+        // The memory cap is shared evenly by the shard engines.
+        let mut engine_config = self.engine.clone();
+        engine_config.max_memory = engine_config.max_memory.map(|max| max / shard_count);
+        let shard = tokio::spawn(shard_stage(parsed_receiver, shards));
+
+        let (written, written_receiver) = mpsc::channel(capacity);
+        let applies: Vec<_> = shard_receivers
+            .into_iter()
+            .map(|receiver| {
+                let engine = Engine::new(engine_config.clone());
+                let written = self.stream_locked.then(|| written.clone());
+                task::spawn_blocking(move || apply_stage(receiver, engine, written))
+            })
+            .collect();
+        let write = task::spawn_blocking(move || write_stage(written_receiver, output));
+
+        let mut results = vec![joined(parse.await), joined(shard.await)];
+        let mut shards = Vec::with_capacity(shard_count);
+        for apply in applies {
+            match joined(apply.await) {
+                Ok(engine) => shards.push(engine.into_accounts().into_iter().collect()),
+                Err(err) => results.push(Err(err)),
+            }
+        }
+        let upstream = first_error(results);
+        if upstream.is_ok() {
+            // Fails only if the write stage stopped, which it reports itself.
+            let _ = written.send(Output::Remaining(shards)).await;
+        }
+        drop(written);
+        let accounts = match joined(write.await) {
+            Ok(accounts) => upstream.map(|()| accounts),
+            Err(err) => first_error([upstream, Err(err)]).map(|()| None),
+        }?;
+        accounts.context("Pipeline stopped before writing the accounts")
+    }
+}
+
+/// Error of a stage whose neighbour stopped; the neighbour reports the cause.
+#[derive(Debug)]
+struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pipeline stage stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Flattens the result of a joined stage.
+fn joined<T>(result: Result<Result<T>, JoinError>) -> Result<T> {
+    result.context("Pipeline stage failed")?
+}
+
+/// Returns the first error that is not [`Stopped`], falling back to [`Stopped`].
+fn first_error(results: impl IntoIterator<Item = Result<()>>) -> Result<()> {
+    let mut stopped = None;
+    for err in results.into_iter().filter_map(Result::err) {
+        if !err.is::<Stopped>() {
+            return Err(err);
+        }
+        stopped.get_or_insert(err);
+    }
+    stopped.map_or(Ok(()), Err)
+}
+
+/// Reads the records of the CSV file at `path` into batches of `batch_size`.
+fn read_csv_file(
+    path: &str,
+    delimiter: Delimiter,
+    batch_size: usize,
+    output: &mpsc::Sender<Result<RecordBatch>>,
+) -> Result<()> {
+    let (file, delimiter) = open_csv_file(path, delimiter)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(file);
+    let source: Arc<str> = path.into();
+    let headers = Arc::new(
+        reader
+            .byte_headers()
+            .with_context(|| format!("Failed to parse header from: {}", path))?
+            .clone(),
+    );
+
+    let send = |records| {
+        output
+            .blocking_send(Ok(RecordBatch {
+                source: source.clone(),
+                headers: headers.clone(),
+                records,
+            }))
+            .map_err(|_| Stopped)
+    };
+    let mut records = Vec::with_capacity(batch_size);
+    let mut record = csv::ByteRecord::new();
+    loop {
+        let position = reader.position().clone();
+        if !reader
+            .read_byte_record(&mut record)
+            .with_context(|| describe_record(path, &position))?
+        {
+            break;
+        }
+        records.push(std::mem::take(&mut record));
+        if records.len() == batch_size {
+            send(std::mem::replace(
+                &mut records,
+                Vec::with_capacity(batch_size),
+            ))?;
+        }
+    }
+    if !records.is_empty() {
+        send(records)?;
+    }
+    Ok(())
+}
+
+/// Parses record batches into transaction batches.
+async fn parse_stage(
+    mut input: mpsc::Receiver<Result<RecordBatch>>,
+    output: mpsc::Sender<Vec<Transaction>>,
+    aliases: Vec<(String, TransactionColumn)>,
+    precision: PrecisionPolicy,
+    strict: bool,
+) -> Result<()> {
+    let mut columns: Option<(Arc<csv::ByteRecord>, TransactionColumns)> = None;
+    while let Some(batch) = input.recv().await {
+        let batch = batch?;
+        let batch_columns = match &columns {
+            Some((headers, columns)) if Arc::ptr_eq(headers, &batch.headers) => *columns,
+            _ => {
+                let parsed = TransactionColumns::from_headers(&batch.headers, &aliases)
+                    .with_context(|| format!("Failed to parse header from: {}", batch.source))?;
+                columns.insert((batch.headers.clone(), parsed)).1
+            }
+        };
+        let transactions = batch
+            .records
+            .iter()
+            .map(|record| {
+                ensure_not_recurring(record, batch_columns)
+                    .and_then(|()| parse_adjusted_fields(record, batch_columns, precision, strict))
+                    .map_err(|(_, err)| match record.position() {
+                        Some(position) => err.context(describe_record(&batch.source, position)),
+                        None => {
+                            err.context(format!("Failed to parse record from: {}", batch.source))
+                        }
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        output.send(transactions).await.map_err(|_| Stopped)?;
+    }
+    Ok(())
+}
+
+/// Splits transaction batches by the shard owning their clients.
+async fn shard_stage(
+    mut input: mpsc::Receiver<Vec<Transaction>>,
+    shards: Vec<mpsc::Sender<Vec<Transaction>>>,
+) -> Result<()> {
+    while let Some(batch) = input.recv().await {
+        let mut routed = vec![Vec::new(); shards.len()];
+        for tx in batch {
+            routed[tx.client.0 as usize % shards.len()].push(tx);
+        }
+        for (shard, batch) in shards.iter().zip(routed) {
+            if !batch.is_empty() {
+                shard.send(batch).await.map_err(|_| Stopped)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A message to the write stage.
+enum Output {
+    /// An account locked by a chargeback, which never changes again.
+    Locked(ClientId, AccountDetails),
+    /// The final states of the accounts of every shard, sent once the input is
+    /// exhausted.
+    Remaining(Vec<BTreeMap<ClientId, AccountDetails>>),
+}
+
+/// Applies the transactions of one shard to `engine`, sending the accounts it
+/// locks to `output` if given.
+fn apply_stage(
+    mut input: mpsc::Receiver<Vec<Transaction>>,
+    mut engine: Engine,
+    output: Option<mpsc::Sender<Output>>,
+) -> Result<Engine> {
+    while let Some(batch) = input.blocking_recv() {
+        for tx in batch {
+            if let Decision::Chargeback {
+                client, lock: true, ..
+            } = engine.process(tx)?
+                && let Some(output) = &output
+                && engine.config().locked_accounts_are_final()
+                && let Some(account) = engine.accounts().get(&client)
+            {
+                output
+                    .blocking_send(Output::Locked(client, account.clone()))
+                    .map_err(|_| Stopped)?;
+            }
+        }
+    }
+    Ok(engine)
+}
+
+/// Writes locked accounts as they arrive and the remaining ones at the end in
+/// client order, returning the final account states if they were received.
+fn write_stage<W: Write>(
+    mut input: mpsc::Receiver<Output>,
+    mut writer: AccountWriter<W>,
+) -> Result<Option<Accounts>> {
+    let mut written = HashSet::new();
+    let mut remaining = None;
+    while let Some(output) = input.blocking_recv() {
+        match output {
+            Output::Locked(client, account) => {
+                writer.write(client, &account)?;
+                written.insert(client);
+            }
+            Output::Remaining(shards) => {
+                let mut accounts = Accounts::new();
+                for (client, account) in merge_shards(shards) {
+                    if !written.contains(&client) {
+                        writer.write(client, &account)?;
+                    }
+                    accounts.insert(client, account);
+                }
+                remaining = Some(accounts);
+            }
+        }
+    }
+    writer.finish()?;
+    Ok(remaining)
+}
+
+/// Merges the accounts of the shards, each ordered by client, into one
+/// sequence ordered by client.
+fn merge_shards(
+    shards: Vec<BTreeMap<ClientId, AccountDetails>>,
+) -> impl Iterator<Item = (ClientId, AccountDetails)> {
+    let mut shards: Vec<_> = shards
+        .into_iter()
+        .map(|accounts| accounts.into_iter().peekable())
+        .collect();
+    std::iter::from_fn(move || {
+        let (next, _) = shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, accounts)| Some((index, accounts.peek()?.0)))
+            .min_by_key(|(_, client)| *client)?;
+        shards[next].next()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine;
+    use crate::io::{self, OutputOptions};
+    use std::sync::Mutex;
+
+    /// A writer that can be inspected after the pipeline took ownership of it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn run_files(config: PipelineConfig, paths: &[&str]) -> (Result<Accounts>, String) {
+        let buffer = SharedBuffer::default();
+        let writer = AccountWriter::new(buffer.clone(), &OutputOptions::default()).unwrap();
+        let pipeline = Pipeline::new(EngineConfig::default(), config);
+        let result = tokio::runtime::Runtime::new().unwrap().block_on(
+            pipeline.run_files(paths.iter().map(|path| path.to_string()).collect(), writer),
+        );
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn matches_sequential_processing() {
+        let sequential = engine::proccess_transactions(
+            io::read_transactions_from_file("test-data.csv").unwrap(),
+        )
+        .unwrap();
+        // Tiny batches and channels exercise the backpressure between stages.
+        for config in [
+            PipelineConfig::default(),
+            PipelineConfig {
+                shards: 3,
+                capacity: 1,
+                batch_size: 2,
+            },
+        ] {
+            let (accounts, output) = run_files(config, &["test-data.csv"]);
+            assert_eq!(accounts.unwrap(), sequential);
+
+            let mut expected = Vec::new();
+            io::write_accounts_as_csv(sequential.clone(), &mut expected, &OutputOptions::default())
+                .unwrap();
+            let mut lines: Vec<&str> = output.lines().collect();
+            lines[1..].sort_unstable();
+            let mut expected: Vec<&str> = std::str::from_utf8(&expected).unwrap().lines().collect();
+            expected[1..].sort_unstable();
+            assert_eq!(lines, expected);
+        }
+    }
+
+    #[test]
+    fn output_is_identical_to_serial_processing() {
+        let path = std::env::temp_dir().join(format!("pipeline-order-{}.csv", std::process::id()));
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=600 {
+            let client = (tx * 7919) % 97;
+            input.push_str(&format!("deposit,{},{},10\n", client, tx));
+            if tx % 5 == 0 {
+                input.push_str(&format!("withdrawal,{},{},3\n", client, 1000 + tx));
+            }
+            if tx % 11 == 0 {
+                input.push_str(&format!(
+                    "dispute,{},{},\nchargeback,{},{},\n",
+                    client, tx, client, tx
+                ));
+            }
+        }
+        std::fs::write(&path, input).unwrap();
+        let path = path.to_str().unwrap();
+
+        let sequential =
+            engine::proccess_transactions(io::read_transactions_from_file(path).unwrap()).unwrap();
+        let mut expected = Vec::new();
+        io::write_accounts_as_csv(sequential, &mut expected, &OutputOptions::default()).unwrap();
+        for shards in [1, 3, 8] {
+            let config = PipelineConfig {
+                shards,
+                capacity: 1,
+                batch_size: 7,
+            };
+            let (accounts, output) = run_files(config, &[path]);
+            accounts.unwrap();
+            assert_eq!(output.as_bytes(), expected, "{} shards", shards);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reports_parse_errors_like_the_reader() {
+        let path = std::env::temp_dir().join(format!("pipeline-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount\r\ndeposit,1,1,1\r\ndeposit,x,2,1\r\n",
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+
+        let (result, output) = run_files(PipelineConfig::default(), &[path]);
+        let expected = io::read_transactions_from_file(path)
+            .unwrap()
+            .fast_parse(true)
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(
+            format!("{:#}", result.unwrap_err()),
+            format!("{:#}", expected)
+        );
+        // The accounts are not written after an error.
+        assert_eq!(output, "client,available,held,total,locked\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_input_stops_the_pipeline() {
+        let (result, _) = run_files(PipelineConfig::default(), &["test-data.csv", "missing.csv"]);
+        assert!(
+            format!("{:#}", result.unwrap_err()).contains("missing.csv"),
+            "missing file not reported"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! Versioned rule sets (`--rules-version`).
+//!
+//! A rules version is a frozen bundle of the policies that decide how
+//! transactions are applied: the [`DisputePolicy`], the [`LockPolicy`] and the
+//! [`PrecisionPolicy`] rounding excess decimal places. Each version is its own
+//! [`PolicyBundle`] implementation, and a version never changes once released,
+//! so historical outputs can be reproduced after the defaults moved on by
+//! processing their input with the version they were produced with.
+//!
+//! | Version | Dispute policy | Lock policy | Precision |
+//! |---------|----------------|-------------|-----------|
+//! | `v1` | `require-available` | `immediate` | `round` |
+//! | `v2` | `hold-partial` | after 2 chargebacks | `truncate` |
+//!
+//! `v1` is the original behavior and matches the defaults of the command line.
+//! [`EngineConfig`] differs only in keeping excess decimal places.
+
+use crate::amount::{AmountOps, PrecisionPolicy};
+use crate::engine::{DisputePolicy, EngineConfig, LockPolicy};
+
+/// A frozen bundle of processing policies.
+pub trait PolicyBundle {
+    /// Returns how disputes not covered by the available funds are handled.
+    fn dispute_policy(&self) -> DisputePolicy;
+
+    /// Returns when chargebacks lock accounts.
+    fn lock_policy(&self) -> LockPolicy;
+
+    /// Returns how amounts with excess decimal places are handled.
+    fn precision(&self) -> PrecisionPolicy;
+}
+
+/// The original rules: disputes require available funds, the first chargeback
+/// locks the account and excess decimal places are rounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct V1;
+
+impl PolicyBundle for V1 {
+    fn dispute_policy(&self) -> DisputePolicy {
+        DisputePolicy::RequireAvailable
+    }
+
+    fn lock_policy(&self) -> LockPolicy {
+        LockPolicy::Immediate
+    }
+
+    fn precision(&self) -> PrecisionPolicy {
+        PrecisionPolicy::Round
+    }
+}
+
+/// Disputes hold what the available funds cover, accounts are locked on their
+/// second chargeback and excess decimal places are truncated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct V2;
+
+impl PolicyBundle for V2 {
+    fn dispute_policy(&self) -> DisputePolicy {
+        DisputePolicy::HoldPartial
+    }
+
+    fn lock_policy(&self) -> LockPolicy {
+        LockPolicy::AfterChargebacks(2)
+    }
+
+    fn precision(&self) -> PrecisionPolicy {
+        PrecisionPolicy::Truncate
+    }
+}
+
+/// The released rules versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulesVersion {
+    V1,
+    V2,
+}
+
+impl RulesVersion {
+    /// Every version, oldest first.
+    pub const ALL: [RulesVersion; 2] = [RulesVersion::V1, RulesVersion::V2];
+
+    /// Returns the name of the version, e.g. `v2`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RulesVersion::V1 => "v1",
+            RulesVersion::V2 => "v2",
+        }
+    }
+
+    /// Returns the policies of this version.
+    pub fn bundle(self) -> &'static dyn PolicyBundle {
+        match self {
+            RulesVersion::V1 => &V1,
+            RulesVersion::V2 => &V2,
+        }
+    }
+
+    /// Sets the policies of `config` to those of this version. The
+    /// [handlers](crate::engine::handler) are kept: the standard ones read the
+    /// policies from the configuration, and custom ones stay in place.
+    pub fn apply<A: AmountOps>(self, config: &mut EngineConfig<A>) {
+        let bundle = self.bundle();
+        config.dispute_policy = bundle.dispute_policy();
+        config.lock_policy = bundle.lock_policy();
+        config.precision = bundle.precision();
+    }
+}
+
+impl std::str::FromStr for RulesVersion {
+    type Err = String;
+
+    /// Parses the name returned by [`RulesVersion::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        RulesVersion::ALL
+            .into_iter()
+            .find(|version| version.as_str() == value)
+            .ok_or_else(|| format!("unknown rules version '{}'", value))
+    }
+}
+
+impl std::fmt::Display for RulesVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Decision, Engine, IgnoreReason};
+    use crate::types::{Accounts, Amount, ClientId, Transaction, TxId, TxType};
+    use std::str::FromStr;
+
+    fn process(config: EngineConfig) -> (Accounts, Vec<Option<IgnoreReason>>) {
+        let mut engine = Engine::new(config);
+        let tx = |tx_type, tx, amount| Transaction {
+            tx_type,
+            client: ClientId(1),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut ignored = Vec::new();
+        for tx in [
+            tx(TxType::Deposit, 1, "10"),
+            tx(TxType::Withdrawal, 2, "8"),
+            tx(TxType::Dispute, 1, "0"),
+            tx(TxType::Chargeback, 1, "0"),
+            tx(TxType::Deposit, 3, "1"),
+        ] {
+            ignored.push(match engine.process(tx).unwrap() {
+                Decision::Ignore(reason) => Some(reason),
+                _ => None,
+            });
+        }
+        (engine.into_accounts(), ignored)
+    }
+
+    #[test]
+    fn versions_bundle_frozen_policies() {
+        // Version 1 froze the defaults of the command line, which rounds.
+        let defaults = EngineConfig {
+            precision: PrecisionPolicy::Round,
+            ..Default::default()
+        };
+        let mut v1 = EngineConfig::default();
+        RulesVersion::V1.apply(&mut v1);
+        assert_eq!(
+            (v1.dispute_policy, v1.lock_policy, v1.precision),
+            (
+                defaults.dispute_policy,
+                defaults.lock_policy,
+                defaults.precision
+            )
+        );
+        let (accounts, ignored) = process(v1);
+        assert_eq!((accounts, ignored), process(defaults));
+
+        let mut v2 = EngineConfig::default();
+        RulesVersion::V2.apply(&mut v2);
+        assert_eq!(v2.precision, PrecisionPolicy::Truncate);
+        let (accounts, ignored) = process(v2);
+        // The partial dispute holds the remaining 2 and the account stays
+        // open after its first chargeback.
+        assert_eq!(ignored, [None; 5]);
+        let account = &accounts[&ClientId(1)];
+        let amount = |value| Amount::from_str(value).unwrap();
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (amount("1"), amount("0"), amount("1"))
+        );
+        assert!(!account.locked);
+    }
+}
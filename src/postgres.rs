@@ -0,0 +1,282 @@
+//! PostgreSQL sink (`postgres` feature).
+//!
+//! [`PostgresSink`] writes the final account states and the transactions the
+//! engine ignored into two tables. Rows are upserted, so running the processor
+//! every day against the same database keeps one row per client (and per
+//! rejected transaction) that reflects the latest run.
+//!
+//! The tables are created if they do not exist:
+//!
+//! ```sql
+//! CREATE TABLE accounts (
+//!     client BIGINT PRIMARY KEY,
+//!     available NUMERIC NOT NULL,
+//!     held NUMERIC NOT NULL,
+//!     total NUMERIC NOT NULL,
+//!     locked BOOLEAN NOT NULL,
+//!     updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+//! );
+//! CREATE TABLE rejected_transactions (
+//!     tx BIGINT NOT NULL,
+//!     type TEXT NOT NULL,
+//!     client BIGINT NOT NULL,
+//!     amount NUMERIC NOT NULL,
+//!     reason TEXT NOT NULL,
+//!     updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+//!     PRIMARY KEY (tx, type)
+//! );
+//! ```
+
+use anyhow::{Context, Result};
+use postgres::{Client, NoTls};
+
+use crate::engine::RejectedTransaction;
+use crate::types::{Accounts, sorted_accounts};
+
+/// Names of the tables written by [`PostgresSink`].
+///
+/// A name may be qualified with a schema, e.g. `reporting.accounts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostgresTables {
+    pub accounts: String,
+    pub rejected: String,
+}
+
+impl Default for PostgresTables {
+    fn default() -> Self {
+        PostgresTables {
+            accounts: "accounts".to_string(),
+            rejected: "rejected_transactions".to_string(),
+        }
+    }
+}
+
+/// Upserts accounts and rejected transactions into PostgreSQL.
+pub struct PostgresSink {
+    client: Client,
+    /// Quoted table names, safe to splice into statements.
+    accounts: String,
+    rejected: String,
+}
+
+impl PostgresSink {
+    /// Connects to the database at `url`, e.g. `postgres://user@localhost/bank`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a table name is not a valid identifier or the
+    /// connection fails.
+    pub fn connect(url: &str, tables: &PostgresTables) -> Result<Self> {
+        let accounts = quote_table_name(&tables.accounts)?;
+        let rejected = quote_table_name(&tables.rejected)?;
+        let client = Client::connect(url, NoTls).context("Failed to connect to PostgreSQL")?;
+        Ok(PostgresSink {
+            client,
+            accounts,
+            rejected,
+        })
+    }
+
+    /// Creates the tables if needed and upserts `accounts` and `rejected` in a
+    /// single database transaction.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any statement fails; nothing is written in that case.
+    pub fn write(&mut self, accounts: &Accounts, rejected: &[RejectedTransaction]) -> Result<()> {
+        let mut transaction = self.client.transaction()?;
+        transaction
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {accounts} (
+                    client BIGINT PRIMARY KEY,
+                    available NUMERIC NOT NULL,
+                    held NUMERIC NOT NULL,
+                    total NUMERIC NOT NULL,
+                    locked BOOLEAN NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+                );
+                CREATE TABLE IF NOT EXISTS {rejected} (
+                    tx BIGINT NOT NULL,
+                    type TEXT NOT NULL,
+                    client BIGINT NOT NULL,
+                    amount NUMERIC NOT NULL,
+                    reason TEXT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                    PRIMARY KEY (tx, type)
+                );",
+                accounts = self.accounts,
+                rejected = self.rejected,
+            ))
+            .context("Failed to create PostgreSQL tables")?;
+
+        // Amounts are sent as text and cast, which keeps every digit for both
+        // amount representations.
+        let upsert = transaction.prepare(&format!(
+            "INSERT INTO {} (client, available, held, total, locked)
+             VALUES ($1, $2::text::numeric, $3::text::numeric, $4::text::numeric, $5)
+             ON CONFLICT (client) DO UPDATE SET
+                 available = EXCLUDED.available,
+                 held = EXCLUDED.held,
+                 total = EXCLUDED.total,
+                 locked = EXCLUDED.locked,
+                 updated_at = now()",
+            self.accounts
+        ))?;
+        for (client, account) in sorted_accounts(accounts) {
+            transaction
+                .execute(
+                    &upsert,
+                    &[
+                        &i64::from(client.0),
+                        &account.available.to_string(),
+                        &account.held.to_string(),
+                        &account.total.to_string(),
+                        &account.locked,
+                    ],
+                )
+                .with_context(|| format!("Failed to write account of client {}", client))?;
+        }
+
+        let upsert = transaction.prepare(&format!(
+            "INSERT INTO {} (tx, type, client, amount, reason)
+             VALUES ($1, $2, $3, $4::text::numeric, $5)
+             ON CONFLICT (tx, type) DO UPDATE SET
+                 client = EXCLUDED.client,
+                 amount = EXCLUDED.amount,
+                 reason = EXCLUDED.reason,
+                 updated_at = now()",
+            self.rejected
+        ))?;
+        for RejectedTransaction {
+            transaction: tx,
+            reason,
+        } in rejected
+        {
+            // Infallible unless transaction IDs are widened by the `wide-ids` feature.
+            #[allow(clippy::unnecessary_fallible_conversions)]
+            let id = i64::try_from(tx.tx.0)
+                .with_context(|| format!("Transaction ID {} does not fit into BIGINT", tx.tx))?;
+            transaction
+                .execute(
+                    &upsert,
+                    &[
+                        &id,
+                        &tx.tx_type.as_str(),
+                        &i64::from(tx.client.0),
+                        &tx.amount.to_string(),
+                        &reason.as_str(),
+                    ],
+                )
+                .with_context(|| format!("Failed to write rejected transaction {}", tx.tx))?;
+        }
+
+        transaction
+            .commit()
+            .context("Failed to commit PostgreSQL transaction")
+    }
+}
+
+/// Validates a possibly schema-qualified table name and quotes each part.
+fn quote_table_name(name: &str) -> Result<String> {
+    let parts = name
+        .split('.')
+        .map(|part| {
+            let mut chars = part.chars();
+            let valid = chars
+                .next()
+                .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            anyhow::ensure!(valid, "Invalid table name '{}'", name);
+            Ok(format!("\"{}\"", part))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    anyhow::ensure!(parts.len() <= 2, "Invalid table name '{}'", name);
+    Ok(parts.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Decision, Engine, EngineConfig};
+    use crate::types::{Amount, ClientId, RawClientId, RawTxId, Transaction, TxId, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn quotes_valid_table_names_only() {
+        assert_eq!(quote_table_name("accounts").unwrap(), "\"accounts\"");
+        assert_eq!(
+            quote_table_name("reporting.daily_2024").unwrap(),
+            "\"reporting\".\"daily_2024\""
+        );
+        for invalid in ["", "1st", "a.b.c", "accounts; DROP TABLE x", "a\"b", "a."] {
+            assert!(quote_table_name(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    /// Runs against the database in `DIAMOND_HANDS_POSTGRES_URL`, and is skipped
+    /// when it is not set.
+    #[test]
+    fn upserts_accounts_and_rejected_transactions() {
+        let Ok(url) = std::env::var("DIAMOND_HANDS_POSTGRES_URL") else {
+            return;
+        };
+        let tables = PostgresTables {
+            accounts: format!("test_accounts_{}", std::process::id()),
+            rejected: format!("test_rejected_{}", std::process::id()),
+        };
+        let mut engine = Engine::new(EngineConfig::default());
+        let mut rejected = Vec::new();
+        let mut run = |transactions: &[(TxType, RawClientId, RawTxId, &str)]| {
+            for &(tx_type, client, tx, amount) in transactions {
+                let transaction = Transaction {
+                    tx_type,
+                    client: ClientId(client),
+                    tx: TxId(tx),
+                    amount: Amount::from_str(amount).unwrap(),
+                };
+                if let Decision::Ignore(reason) = engine.process(transaction.clone()).unwrap() {
+                    rejected.push(RejectedTransaction {
+                        transaction,
+                        reason,
+                    });
+                }
+            }
+            let mut sink = PostgresSink::connect(&url, &tables).unwrap();
+            sink.write(engine.accounts(), &rejected).unwrap();
+        };
+
+        run(&[
+            (TxType::Deposit, 1, 1, "10.1234"),
+            (TxType::Withdrawal, 1, 2, "20"),
+        ]);
+        run(&[
+            (TxType::Deposit, 1, 3, "5"),
+            (TxType::Withdrawal, 1, 2, "20"),
+        ]);
+
+        let mut client = Client::connect(&url, NoTls).unwrap();
+        let accounts = client
+            .query(
+                &format!("SELECT client, total::text FROM {}", tables.accounts),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].get::<_, i64>(0), 1);
+        assert_eq!(accounts[0].get::<_, String>(1), "15.1234");
+
+        let rejected = client
+            .query(&format!("SELECT tx, reason FROM {}", tables.rejected), &[])
+            .unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].get::<_, i64>(0), 2);
+        assert_eq!(rejected[0].get::<_, String>(1), "insufficient_funds");
+
+        client
+            .batch_execute(&format!(
+                "DROP TABLE {}; DROP TABLE {};",
+                tables.accounts, tables.rejected
+            ))
+            .unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! Progress bar for long batch runs.
+//!
+//! [`Progress`] redraws a single line on a terminal with the share of the input
+//! read, the throughput and the estimated time left:
+//!
+//! ```text
+//! [#########...........]  45.2%  1.2 GiB / 2.7 GiB  812345 rows/s  ETA 0:01:05
+//! ```
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Width of the bar in characters.
+const BAR_WIDTH: usize = 20;
+
+/// Minimum time between two redraws, so drawing does not slow down processing.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A progress bar over `total` bytes of input, drawn to `writer`.
+pub struct Progress<W: Write> {
+    writer: W,
+    total: u64,
+    rows: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+impl<W: Write> Progress<W> {
+    /// Creates a progress bar for an input of `total` bytes.
+    pub fn new(writer: W, total: u64) -> Self {
+        Progress {
+            writer,
+            total,
+            rows: 0,
+            started: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    /// Counts a processed row, `bytes` into the input, and redraws the bar if
+    /// it was not drawn recently.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bar cannot be written.
+    pub fn update(&mut self, bytes: u64) -> std::io::Result<()> {
+        self.rows += 1;
+        let now = Instant::now();
+        if self
+            .drawn
+            .is_some_and(|drawn| now.duration_since(drawn) < REDRAW_INTERVAL)
+        {
+            return Ok(());
+        }
+        self.drawn = Some(now);
+        self.draw(bytes, now.duration_since(self.started))
+    }
+
+    /// Draws the bar at `bytes` a last time and ends its line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bar cannot be written.
+    pub fn finish(mut self, bytes: u64) -> std::io::Result<()> {
+        self.draw(bytes, self.started.elapsed())?;
+        writeln!(self.writer)
+    }
+
+    fn draw(&mut self, bytes: u64, elapsed: Duration) -> std::io::Result<()> {
+        let line = render(bytes, self.total, self.rows, elapsed);
+        write!(self.writer, "\r{}\x1b[K", line)?;
+        self.writer.flush()
+    }
+}
+
+/// Renders the progress line after `rows` rows and `bytes` of `total` bytes
+/// in `elapsed` time.
+fn render(bytes: u64, total: u64, rows: u64, elapsed: Duration) -> String {
+    let bytes = bytes.min(total);
+    let fraction = if total == 0 {
+        1.0
+    } else {
+        bytes as f64 / total as f64
+    };
+    let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+    let seconds = elapsed.as_secs_f64();
+    let rate = if seconds > 0.0 {
+        rows as f64 / seconds
+    } else {
+        0.0
+    };
+    let eta = if bytes > 0 && bytes < total {
+        let left = seconds * (total - bytes) as f64 / bytes as f64;
+        format_duration(Duration::from_secs_f64(left))
+    } else if bytes == total {
+        format_duration(Duration::ZERO)
+    } else {
+        "--:--:--".to_string()
+    };
+    format!(
+        "[{}{}] {:>5.1}%  {} / {}  {:.0} rows/s  ETA {}",
+        "#".repeat(filled),
+        ".".repeat(BAR_WIDTH - filled),
+        fraction * 100.0,
+        format_bytes(bytes),
+        format_bytes(total),
+        rate,
+        eta
+    )
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats a duration as `H:MM:SS`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_share_throughput_and_eta() {
+        let gib = 1 << 30;
+        assert_eq!(
+            render(gib, 4 * gib, 2_000_000, Duration::from_secs(10)),
+            "[#####...............]  25.0%  1.0 GiB / 4.0 GiB  200000 rows/s  ETA 0:00:30"
+        );
+        assert_eq!(
+            render(0, 1536, 0, Duration::ZERO),
+            "[....................]   0.0%  0 B / 1.5 KiB  0 rows/s  ETA --:--:--"
+        );
+        assert_eq!(
+            render(1536, 1536, 3, Duration::from_secs(3700)),
+            "[####################] 100.0%  1.5 KiB / 1.5 KiB  0 rows/s  ETA 0:00:00"
+        );
+
+        let mut output = Vec::new();
+        let mut progress = Progress::new(&mut output, 100);
+        progress.update(50).unwrap();
+        progress.update(60).unwrap();
+        progress.finish(100).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches('\r').count(), 2, "{:?}", output);
+        assert!(output.ends_with("ETA 0:00:00\x1b[K\n"), "{:?}", output);
+    }
+}
@@ -0,0 +1,238 @@
+//! Rate limits of the socket server (`listen --client-rate-limit` and
+//! `--global-rate-limit`).
+//!
+//! A [`RateLimiter`] admits transactions with token buckets: a bucket holds up
+//! to [`RateLimit::count`] tokens, is refilled at `count` tokens per
+//! [`RateLimit::per`], and every admitted transaction takes one token. A client
+//! may therefore send a burst of `count` transactions at once, but no more than
+//! `count` per period on average. A transaction is admitted only if both its
+//! client's bucket and the global bucket have a token, and takes a token from
+//! neither otherwise.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::types::ClientId;
+
+/// Number of client buckets above which full ones are dropped, since a full
+/// bucket admits the same as a new one.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A maximum number of transactions per period, written `COUNT/UNIT` with the
+/// unit `s`, `m` or `h`, e.g. `100/s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Transactions admitted per period, and the size of a burst.
+    pub count: u32,
+    /// Length of the period.
+    pub per: Duration,
+}
+
+impl std::str::FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid rate limit '{}', expected COUNT/UNIT with the unit s, m or h, e.g. 100/s",
+                value
+            )
+        };
+        let (count, unit) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let count = count
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&count| count > 0)
+            .ok_or_else(invalid)?;
+        let per = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+        Ok(RateLimit { count, per })
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.per.as_secs() {
+            3600 => "h",
+            60 => "m",
+            _ => "s",
+        };
+        write!(f, "{}/{}", self.count, unit)
+    }
+}
+
+/// Which limit rejected a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    /// The limit of the transaction's client.
+    Client,
+    /// The limit of all transactions together.
+    Global,
+}
+
+impl LimitScope {
+    /// Returns the name of the scope, as sent in `rate_limited` replies.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LimitScope::Client => "client",
+            LimitScope::Global => "global",
+        }
+    }
+}
+
+/// The error of a transaction rejected by a [`RateLimiter`]. The transaction
+/// was not applied and may be sent again later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    pub scope: LimitScope,
+    pub client: ClientId,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.scope {
+            LimitScope::Client => write!(f, "Rate limit of client {} exceeded", self.client),
+            LimitScope::Global => write!(f, "Global rate limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// A token bucket.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit, now: Instant) -> Self {
+        Bucket {
+            tokens: f64::from(limit.count),
+            updated: now,
+        }
+    }
+
+    /// Refills the bucket up to `now` and returns whether it has a token.
+    fn refill(&mut self, limit: RateLimit, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let refill = elapsed.as_secs_f64() / limit.per.as_secs_f64() * f64::from(limit.count);
+        self.tokens = (self.tokens + refill).min(f64::from(limit.count));
+        self.updated = now;
+        self.tokens >= 1.0
+    }
+}
+
+/// Admits transactions within a per-client and a global [`RateLimit`], see the
+/// [module documentation](self).
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    client_limit: Option<RateLimit>,
+    global_limit: Option<RateLimit>,
+    clients: HashMap<ClientId, Bucket>,
+    global: Option<Bucket>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter admitting up to `client_limit` transactions of every
+    /// client and up to `global_limit` transactions in total. `None` leaves
+    /// the respective rate unlimited.
+    pub fn new(client_limit: Option<RateLimit>, global_limit: Option<RateLimit>) -> Self {
+        RateLimiter {
+            client_limit,
+            global_limit,
+            ..Default::default()
+        }
+    }
+
+    /// Takes a token for a transaction of `client` received at `now`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the limit that is exhausted; no token is taken then.
+    pub fn admit(&mut self, client: ClientId, now: Instant) -> Result<(), RateLimited> {
+        let limited = |scope| RateLimited { scope, client };
+        if let Some(limit) = self.client_limit {
+            if self.clients.len() >= PRUNE_THRESHOLD {
+                self.clients.retain(|_, bucket| {
+                    bucket.refill(limit, now);
+                    bucket.tokens < f64::from(limit.count)
+                });
+            }
+            let bucket = self
+                .clients
+                .entry(client)
+                .or_insert_with(|| Bucket::full(limit, now));
+            if !bucket.refill(limit, now) {
+                return Err(limited(LimitScope::Client));
+            }
+        }
+        if let Some(limit) = self.global_limit {
+            let bucket = self.global.get_or_insert_with(|| Bucket::full(limit, now));
+            if !bucket.refill(limit, now) {
+                return Err(limited(LimitScope::Global));
+            }
+            bucket.tokens -= 1.0;
+        }
+        if let Some(bucket) = self.clients.get_mut(&client) {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admits_bursts_and_refills_over_time() {
+        assert_eq!(
+            "100/s".parse(),
+            Ok(RateLimit {
+                count: 100,
+                per: Duration::from_secs(1)
+            })
+        );
+        assert_eq!("6000/m".parse::<RateLimit>().unwrap().to_string(), "6000/m");
+        for invalid in ["0/s", "100", "100/d", "-1/s"] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{}", invalid);
+        }
+
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new("2/s".parse().ok(), "3/s".parse().ok());
+        assert_eq!(limiter.admit(ClientId(1), start), Ok(()));
+        assert_eq!(limiter.admit(ClientId(1), start), Ok(()));
+        let limited = limiter.admit(ClientId(1), start).unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Client);
+        assert_eq!(limited.to_string(), "Rate limit of client 1 exceeded");
+        assert_eq!(limiter.admit(ClientId(2), start), Ok(()));
+        // The global bucket is empty, so client 3 is rejected without taking
+        // a token of its own.
+        let limited = limiter.admit(ClientId(3), start).unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Global);
+        assert_eq!(limiter.clients[&ClientId(3)].tokens, 2.0);
+
+        // Half a second refills one token of every client and 1.5 global ones.
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.admit(ClientId(1), later), Ok(()));
+        assert!(limiter.admit(ClientId(1), later).is_err());
+        assert!(limiter.admit(ClientId(3), later).is_err());
+        assert_eq!(
+            limiter.admit(ClientId(3), start + Duration::from_secs(1)),
+            Ok(())
+        );
+
+        let mut unlimited = RateLimiter::new(None, None);
+        for _ in 0..1000 {
+            assert_eq!(unlimited.admit(ClientId(1), start), Ok(()));
+        }
+    }
+}
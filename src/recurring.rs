@@ -0,0 +1,263 @@
+//! Expansion of recurring transactions.
+//!
+//! A deposit or withdrawal with `interval` and `count` columns recurs: it stands
+//! for `count` transactions, the first on its `effective_date` and each further
+//! one `interval` days after the previous one. Occurrence `n` (counting from
+//! zero) gets the transaction ID `tx + n`, so a recurring row reserves the IDs
+//! up to `tx + count - 1`. This is meant for simulations such as subscription
+//! billing:
+//!
+//! ```csv
+//! type,client,tx,amount,effective_date,interval,count
+//! withdrawal,1,100,9.99,2024-01-15,30,12
+//! ```
+//!
+//! [`expand_recurring`] is a preprocessing stage between a [`TransactionSource`]
+//! and the engine; combined with a [`Schedule`](crate::schedule::Schedule), the
+//! later occurrences are pending until their date.
+
+use anyhow::Result;
+
+use crate::io::TransactionSource;
+use crate::types::{Date, RawTxId, Transaction, TxId, TxType};
+
+/// How often a transaction recurs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recurrence {
+    /// Days between two occurrences.
+    pub interval: u32,
+    /// Number of occurrences, including the first one.
+    pub count: u32,
+}
+
+/// A recurring transaction with the occurrences not returned yet.
+struct Occurrences {
+    tx: Transaction,
+    first_date: Date,
+    recurrence: Recurrence,
+    next: u32,
+}
+
+/// A [`TransactionSource`] returning every occurrence of the recurring
+/// transactions of another source, created by [`expand_recurring`].
+pub struct Expanded<S> {
+    source: S,
+    current: Option<Occurrences>,
+    effective_date: Option<Date>,
+}
+
+/// Wraps `source` so that each recurring transaction is replaced by its
+/// occurrences. Transactions that do not recur are passed on unchanged.
+///
+/// A recurring transaction that is not a deposit or withdrawal, has no
+/// effective date, or whose last occurrence would overflow the transaction ID
+/// or the date fails with an error before any of its occurrences is returned.
+pub fn expand_recurring<S: TransactionSource>(source: S) -> Expanded<S> {
+    Expanded {
+        source,
+        current: None,
+        effective_date: None,
+    }
+}
+
+impl<S: TransactionSource> Expanded<S> {
+    /// Returns the next occurrence of the current recurring transaction, if any.
+    fn next_occurrence(&mut self) -> Option<Transaction> {
+        let current = self.current.as_mut()?;
+        let n = current.next;
+        if n == current.recurrence.count {
+            self.current = None;
+            return None;
+        }
+        current.next += 1;
+        // Both were checked when the transaction was read.
+        self.effective_date = current.first_date.add_days(n * current.recurrence.interval);
+        Some(Transaction {
+            tx: TxId(current.tx.tx.0 + RawTxId::from(n)),
+            ..current.tx.clone()
+        })
+    }
+
+    /// Checks a recurring transaction and starts returning its occurrences.
+    fn start(&mut self, tx: Transaction, recurrence: Recurrence) -> Result<()> {
+        anyhow::ensure!(
+            matches!(tx.tx_type, TxType::Deposit | TxType::Withdrawal),
+            "only deposits and withdrawals can recur, not {}",
+            tx.tx_type.as_str()
+        );
+        let first_date = self
+            .source
+            .effective_date()
+            .ok_or_else(|| anyhow::anyhow!("recurring transaction without an effective_date"))?;
+        let last = recurrence.count - 1;
+        anyhow::ensure!(
+            tx.tx.0.checked_add(RawTxId::from(last)).is_some(),
+            "transaction IDs of {} occurrences starting at {} overflow",
+            recurrence.count,
+            tx.tx.0
+        );
+        anyhow::ensure!(
+            last.checked_mul(recurrence.interval)
+                .and_then(|days| first_date.add_days(days))
+                .is_some(),
+            "last of {} occurrences every {} days after {} is out of range",
+            recurrence.count,
+            recurrence.interval,
+            first_date
+        );
+        self.current = Some(Occurrences {
+            tx,
+            first_date,
+            recurrence,
+            next: 0,
+        });
+        Ok(())
+    }
+}
+
+impl<S: TransactionSource> Iterator for Expanded<S> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(tx) = self.next_occurrence() {
+            return Some(Ok(tx));
+        }
+        self.effective_date = None;
+        let tx = match self.source.next()? {
+            Ok(tx) => tx,
+            Err(err) => return Some(Err(err)),
+        };
+        let Some(recurrence) = self.source.recurrence() else {
+            self.effective_date = self.source.effective_date();
+            return Some(Ok(tx));
+        };
+        if let Err(err) = self.start(tx, recurrence) {
+            return Some(Err(err));
+        }
+        self.next_occurrence().map(Ok)
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Expanded<S> {
+    /// Returns the raw record of the recurring transaction for all of its
+    /// occurrences.
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        self.source.raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        self.source.delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.effective_date
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.source.bytes_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::read_transactions_from_file;
+    use crate::schedule::Schedule;
+
+    #[test]
+    fn expands_recurring_rows_into_dated_occurrences() {
+        let path = std::env::temp_dir().join(format!("recurring-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,effective_date,interval,count\n\
+             deposit,1,1,100.0,2024-01-01,,\n\
+             withdrawal,1,10,9.99,2024-01-15,30,3\n\
+             deposit,2,20,5.0,,7,2\n\
+             dispute,1,1,,2024-01-01,1,2\n\
+             deposit,2,30,5.0,2024-01-01,0,2\n\
+             deposit,2,40,5.0,2024-12-31,1,2\n",
+        )
+        .unwrap();
+
+        for parallel_parse in [false, true] {
+            let reader = read_transactions_from_file(path.to_str().unwrap())
+                .unwrap()
+                .parallel_parse(parallel_parse);
+            let mut expanded = expand_recurring(reader);
+            let mut results = Vec::new();
+            while let Some(result) = expanded.next() {
+                results.push(
+                    result
+                        .map(|tx| (tx.tx_type, tx.tx, expanded.effective_date()))
+                        .map_err(|err| format!("{:#}", err)),
+                );
+            }
+
+            let date = |text: &str| Some(text.parse::<Date>().unwrap());
+            assert_eq!(
+                results[0],
+                Ok((TxType::Deposit, TxId(1), date("2024-01-01")))
+            );
+            assert_eq!(
+                results[1..4],
+                [
+                    Ok((TxType::Withdrawal, TxId(10), date("2024-01-15"))),
+                    Ok((TxType::Withdrawal, TxId(11), date("2024-02-14"))),
+                    Ok((TxType::Withdrawal, TxId(12), date("2024-03-15"))),
+                ]
+            );
+            let errors: Vec<&str> = results[4..7]
+                .iter()
+                .map(|result| result.as_ref().unwrap_err().as_str())
+                .collect();
+            assert!(
+                errors[0].contains("without an effective_date"),
+                "{}",
+                errors[0]
+            );
+            assert!(
+                errors[1].contains("can recur, not dispute"),
+                "{}",
+                errors[1]
+            );
+            assert!(
+                errors[2].contains("interval must be positive"),
+                "{}",
+                errors[2]
+            );
+            assert_eq!(
+                results[7..],
+                [
+                    Ok((TxType::Deposit, TxId(40), date("2024-12-31"))),
+                    Ok((TxType::Deposit, TxId(41), date("2025-01-01"))),
+                ]
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn later_occurrences_are_pending_in_a_schedule() {
+        let path = std::env::temp_dir().join(format!("recurring-due-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,Value Date,Interval Days,Occurrences\n\
+             deposit,1,1,10.0,2024-01-01,14,4\n",
+        )
+        .unwrap();
+
+        let reader = read_transactions_from_file(path.to_str().unwrap()).unwrap();
+        let mut schedule = Schedule::new("2024-01-20".parse().unwrap());
+        let due: Vec<TxId> = schedule
+            .defer(expand_recurring(reader))
+            .map(|tx| tx.unwrap().tx)
+            .collect();
+        assert_eq!(due, [TxId(1), TxId(2)]);
+        let pending: Vec<String> = schedule
+            .pending()
+            .map(|(date, tx)| format!("{} {}", date, tx.tx.0))
+            .collect();
+        assert_eq!(pending, ["2024-01-29 3", "2024-02-12 4"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+//! Velocity limits and other anti-money-laundering rules.
+//!
+//! Operators describe the limits they want enforced in a TOML rules file, one
+//! `[[rule]]` table per rule:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "deposit-velocity"
+//! type = "deposit"
+//! max-count = 10
+//! window = 1000
+//! action = "hold"
+//!
+//! [[rule]]
+//! name = "large-withdrawal"
+//! type = "withdrawal"
+//! max-amount = "10000"
+//! action = "flag"
+//! ```
+//!
+//! A rule applies to the transactions of its `type`, or to all transactions if
+//! the type is omitted, and sets exactly one limit:
+//!
+//! - `max-amount`: the transaction amount may not exceed this value.
+//! - `max-count`: a client may have at most this many such transactions within
+//!   the last `window` transactions, or within the whole run without a window.
+//!
+//! A transaction that violates a `flag` rule is applied but reported; one that
+//! violates a `hold` rule is not applied. A [`RuleSet`] is a
+//! [`TransactionHook`], so rules only see, and windows only count, transactions
+//! the processing rules would apply. Every violation is recorded and available
+//! from [`RuleSet::triggered`].
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::amount::parse_amount;
+use crate::hook::{HookDecision, TransactionHook};
+use crate::types::{AccountDetails, Amount, ClientId, Transaction, TxType};
+
+/// What happens to a transaction that violates a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Apply the transaction and report the violation.
+    Flag,
+    /// Do not apply the transaction.
+    Hold,
+}
+
+impl RuleAction {
+    /// Returns the name used in rules files and reports.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RuleAction::Flag => "flag",
+            RuleAction::Hold => "hold",
+        }
+    }
+}
+
+/// The limit a rule enforces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Limit {
+    /// The transaction amount may not exceed this value.
+    Amount(Amount),
+    /// A client may have at most `count` matching transactions within the last
+    /// `window` transactions, or at all without a window.
+    Velocity { count: usize, window: Option<u64> },
+}
+
+/// A limit on the transactions of one type, or of all types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    /// The transaction type the rule applies to, or `None` for all types.
+    pub tx_type: Option<TxType>,
+    pub limit: Limit,
+    pub action: RuleAction,
+}
+
+/// A violation of a rule by a transaction.
+#[derive(Debug, Clone)]
+pub struct TriggeredRule {
+    pub rule: String,
+    pub action: RuleAction,
+    pub transaction: Transaction,
+}
+
+/// A `[[rule]]` table as written in a rules file.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct RuleSpec {
+    name: String,
+    #[serde(rename = "type")]
+    tx_type: Option<TxType>,
+    max_amount: Option<String>,
+    max_count: Option<usize>,
+    window: Option<u64>,
+    action: RuleAction,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleSpec>,
+}
+
+impl TryFrom<RuleSpec> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: RuleSpec) -> Result<Rule> {
+        let limit = match (spec.max_amount, spec.max_count) {
+            (Some(amount), None) => {
+                anyhow::ensure!(
+                    spec.window.is_none(),
+                    "Rule '{}' sets a window without max-count",
+                    spec.name
+                );
+                let amount = parse_amount(&amount)
+                    .with_context(|| format!("Invalid max-amount in rule '{}'", spec.name))?;
+                Limit::Amount(amount)
+            }
+            (None, Some(count)) => {
+                anyhow::ensure!(
+                    spec.window != Some(0),
+                    "Rule '{}' has an empty window",
+                    spec.name
+                );
+                Limit::Velocity {
+                    count,
+                    window: spec.window,
+                }
+            }
+            _ => anyhow::bail!(
+                "Rule '{}' must set exactly one of max-amount and max-count",
+                spec.name
+            ),
+        };
+        Ok(Rule {
+            name: spec.name,
+            tx_type: spec.tx_type,
+            limit,
+            action: spec.action,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    /// Number of transactions checked so far.
+    seen: u64,
+    /// Positions of the latest transactions counted by each velocity rule, by
+    /// rule index and client.
+    recent: HashMap<(usize, ClientId), VecDeque<u64>>,
+    triggered: Vec<TriggeredRule>,
+}
+
+/// A set of rules checked before each transaction is applied.
+#[derive(Debug, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    state: Mutex<RuleState>,
+}
+
+impl RuleSet {
+    /// Creates a rule set checking `rules`.
+    pub fn new(rules: Vec<Rule>) -> Self {
+        RuleSet {
+            rules,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Parses a rule set from the contents of a TOML rules file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text is not valid TOML or a rule is invalid.
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let file: RulesFile = toml::from_str(text)?;
+        let rules = file
+            .rule
+            .into_iter()
+            .map(Rule::try_from)
+            .collect::<Result<_>>()?;
+        Ok(RuleSet::new(rules))
+    }
+
+    /// Returns the rules of this set.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns the violations recorded so far, in the order they occurred.
+    pub fn triggered(&self) -> Vec<TriggeredRule> {
+        self.state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .triggered
+            .clone()
+    }
+
+    /// Checks `tx` against every rule, records the violations and returns
+    /// whether the transaction may be applied.
+    pub fn check(&self, tx: &Transaction) -> HookDecision {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        let state = &mut *state;
+        state.seen += 1;
+        let position = state.seen;
+
+        let matching = || {
+            self.rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.tx_type.is_none_or(|tx_type| tx_type == tx.tx_type))
+        };
+
+        let mut decision = HookDecision::Allow;
+        for (index, rule) in matching() {
+            let violated = match rule.limit {
+                Limit::Amount(max) => tx.amount > max,
+                Limit::Velocity { count, window } => {
+                    let recent = state.recent.entry((index, tx.client)).or_default();
+                    if let Some(window) = window {
+                        while recent
+                            .front()
+                            .is_some_and(|&seen| position - seen >= window)
+                        {
+                            recent.pop_front();
+                        }
+                    }
+                    recent.len() >= count
+                }
+            };
+            if violated {
+                state.triggered.push(TriggeredRule {
+                    rule: rule.name.clone(),
+                    action: rule.action,
+                    transaction: tx.clone(),
+                });
+                if rule.action == RuleAction::Hold {
+                    decision = HookDecision::Reject;
+                }
+            }
+        }
+
+        // Held transactions are not applied, so they do not count towards limits.
+        if decision == HookDecision::Allow {
+            for (index, rule) in matching() {
+                if let Limit::Velocity { count, .. } = rule.limit
+                    && let Some(recent) = state.recent.get_mut(&(index, tx.client))
+                {
+                    recent.push_back(position);
+                    // Only the latest `count` positions decide later violations.
+                    if recent.len() > count {
+                        recent.pop_front();
+                    }
+                }
+            }
+        }
+        decision
+    }
+}
+
+impl TransactionHook for RuleSet {
+    fn before_apply(&self, tx: &Transaction, _account: &AccountDetails) -> HookDecision {
+        self.check(tx)
+    }
+}
+
+/// Reads and parses the TOML rules file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or does not describe valid rules.
+pub fn read_rules_file(path: &str) -> Result<RuleSet> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read rules file: {}", path))?;
+    RuleSet::from_toml(&contents).with_context(|| format!("Failed to parse rules file: {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{RawClientId, RawTxId, TxId};
+    use std::str::FromStr;
+
+    fn tx(tx_type: TxType, client: RawClientId, tx: RawTxId, amount: &str) -> Transaction {
+        Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        }
+    }
+
+    #[test]
+    fn flags_large_amounts_and_holds_velocity_violations() {
+        let rules = RuleSet::from_toml(
+            "
+            [[rule]]
+            name = \"deposit-velocity\"
+            type = \"deposit\"
+            max-count = 2
+            window = 4
+            action = \"hold\"
+
+            [[rule]]
+            name = \"large-withdrawal\"
+            type = \"withdrawal\"
+            max-amount = \"100\"
+            action = \"flag\"
+            ",
+        )
+        .unwrap();
+
+        let decisions: Vec<_> = [
+            tx(TxType::Deposit, 1, 1, "10"),
+            tx(TxType::Deposit, 1, 2, "10"),
+            tx(TxType::Deposit, 2, 3, "10"),
+            // Third deposit of client 1 within four transactions.
+            tx(TxType::Deposit, 1, 4, "10"),
+            tx(TxType::Withdrawal, 1, 5, "100.01"),
+            // The earlier deposits have left the window.
+            tx(TxType::Deposit, 1, 6, "10"),
+        ]
+        .iter()
+        .map(|tx| rules.check(tx))
+        .collect();
+
+        use HookDecision::{Allow, Reject};
+        assert_eq!(decisions, [Allow, Allow, Allow, Reject, Allow, Allow]);
+        let triggered: Vec<_> = rules
+            .triggered()
+            .into_iter()
+            .map(|triggered| (triggered.rule, triggered.action, triggered.transaction.tx))
+            .collect();
+        assert_eq!(
+            triggered,
+            [
+                ("deposit-velocity".to_string(), RuleAction::Hold, TxId(4)),
+                ("large-withdrawal".to_string(), RuleAction::Flag, TxId(5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for (rules, message) in [
+            (
+                "[[rule]]\nname = \"a\"\naction = \"flag\"",
+                "Rule 'a' must set exactly one of max-amount and max-count",
+            ),
+            (
+                "[[rule]]\nname = \"a\"\nmax-count = 1\nwindow = 0\naction = \"hold\"",
+                "Rule 'a' has an empty window",
+            ),
+            (
+                "[[rule]]\nname = \"a\"\nmax-amount = \"1\"\nwindow = 5\naction = \"hold\"",
+                "Rule 'a' sets a window without max-count",
+            ),
+        ] {
+            assert_eq!(RuleSet::from_toml(rules).unwrap_err().to_string(), message);
+        }
+        assert!(
+            RuleSet::from_toml("[[rule]]\nname = \"a\"\nmax-amount = \"x\"\naction = \"flag\"")
+                .is_err()
+        );
+        assert!(
+            RuleSet::from_toml("[[rule]]\nname = \"a\"\nmax-count = 1\naction = \"block\"")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Value-dated transactions.
+//!
+//! Inputs may carry an `effective_date` column (`YYYY-MM-DD`). A [`Schedule`]
+//! processes them as of a date: transactions dated on or before it, and
+//! undated ones, are due right away, while later ones are queued until the
+//! processing date reaches their effective date. Whatever is still queued at the
+//! end of a run is pending and can be carried over into the next run, which
+//! enables end-of-day cutoffs:
+//!
+//! ```
+//! use project_diamond_hands::schedule::Schedule;
+//! use project_diamond_hands::types::{Amount, ClientId, Date, Transaction, TxId, TxType};
+//!
+//! let deposit = |tx| Transaction {
+//!     tx_type: TxType::Deposit,
+//!     client: ClientId(1),
+//!     tx: TxId(tx),
+//!     amount: Amount::ONE,
+//! };
+//! let today: Date = "2024-03-01".parse().unwrap();
+//! let tomorrow: Date = "2024-03-02".parse().unwrap();
+//!
+//! let mut schedule = Schedule::new(today);
+//! assert!(schedule.submit(deposit(1), Some(today)).is_some());
+//! assert!(schedule.submit(deposit(2), Some(tomorrow)).is_none());
+//! assert_eq!(schedule.pending().count(), 1);
+//!
+//! let due = schedule.advance_to(tomorrow);
+//! assert_eq!(due[0].tx, TxId(2));
+//! ```
+//!
+//! [`Schedule::defer`] applies a schedule to a [`TransactionSource`], so that
+//! reading the source only yields due transactions.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::io::TransactionSource;
+use crate::types::{Date, Transaction};
+
+/// Transactions queued until their effective date.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    as_of: Date,
+    /// Queued transactions by effective date, in the order they were submitted.
+    queued: BTreeMap<Date, Vec<Transaction>>,
+}
+
+impl Schedule {
+    /// Creates an empty schedule processing as of `as_of`.
+    pub fn new(as_of: Date) -> Self {
+        Schedule {
+            as_of,
+            queued: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the processing date.
+    pub fn as_of(&self) -> Date {
+        self.as_of
+    }
+
+    /// Returns `tx` if it is due as of the processing date, and queues it
+    /// otherwise. Transactions without an effective date are always due.
+    pub fn submit(&mut self, tx: Transaction, effective_date: Option<Date>) -> Option<Transaction> {
+        match effective_date {
+            Some(date) if date > self.as_of => {
+                self.queued.entry(date).or_default().push(tx);
+                None
+            }
+            _ => Some(tx),
+        }
+    }
+
+    /// Moves the processing date forward to `date` and returns the queued
+    /// transactions that became due, ordered by effective date and then in the
+    /// order they were submitted. An earlier `date` leaves the schedule unchanged.
+    pub fn advance_to(&mut self, date: Date) -> Vec<Transaction> {
+        if date <= self.as_of {
+            return Vec::new();
+        }
+        self.as_of = date;
+        let mut due = std::mem::take(&mut self.queued);
+        self.queued = due.split_off(&date);
+        if let Some(queued) = self.queued.remove(&date) {
+            due.insert(date, queued);
+        }
+        due.into_values().flatten().collect()
+    }
+
+    /// Returns the queued transactions with their effective dates, in the order
+    /// they become due.
+    pub fn pending(&self) -> impl Iterator<Item = (Date, &Transaction)> {
+        self.queued
+            .iter()
+            .flat_map(|(date, queued)| queued.iter().map(move |tx| (*date, tx)))
+    }
+
+    /// Wraps `source` so that it only yields the transactions that are due and
+    /// queues the others in this schedule.
+    pub fn defer<S: TransactionSource>(&mut self, source: S) -> Deferred<'_, S> {
+        Deferred {
+            source,
+            schedule: self,
+        }
+    }
+}
+
+/// A [`TransactionSource`] yielding only the due transactions of another
+/// source, created by [`Schedule::defer`].
+pub struct Deferred<'a, S> {
+    source: S,
+    schedule: &'a mut Schedule,
+}
+
+impl<S: TransactionSource> Iterator for Deferred<'_, S> {
+    type Item = Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let tx = match self.source.next()? {
+                Ok(tx) => tx,
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(tx) = self.schedule.submit(tx, self.source.effective_date()) {
+                return Some(Ok(tx));
+            }
+        }
+    }
+}
+
+impl<S: TransactionSource> TransactionSource for Deferred<'_, S> {
+    fn raw_record(&self) -> Result<Option<Vec<u8>>> {
+        self.source.raw_record()
+    }
+
+    fn delimiter(&self) -> u8 {
+        self.source.delimiter()
+    }
+
+    fn effective_date(&self) -> Option<Date> {
+        self.source.effective_date()
+    }
+
+    fn bytes_read(&self) -> Option<u64> {
+        self.source.bytes_read()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::read_transactions_from_file;
+    use crate::types::TxId;
+
+    fn date(text: &str) -> Date {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_calendar_dates_only() {
+        assert_eq!(date("2024-02-29").to_string(), "2024-02-29");
+        assert!(date("2024-03-01") > date("2024-02-29"));
+        for invalid in [
+            "2023-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "24-01-01",
+            "2024-1-01",
+            "+024-01-01",
+            "",
+        ] {
+            assert!(invalid.parse::<Date>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn defers_transactions_dated_after_the_processing_date() {
+        let path = std::env::temp_dir().join(format!("value-dated-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "type,client,tx,amount,Value Date\n\
+             deposit,1,1,1.0,2024-03-03\n\
+             deposit,1,2,1.0,2024-03-01\n\
+             deposit,1,3,1.0,\n\
+             deposit,1,4,1.0,2024-03-02\n\
+             deposit,1,5,1.0,2024-03-03\n\
+             deposit,1,6,1.0,2024-02-30\n",
+        )
+        .unwrap();
+
+        for parallel_parse in [false, true] {
+            let reader = read_transactions_from_file(path.to_str().unwrap())
+                .unwrap()
+                .parallel_parse(parallel_parse);
+            let mut schedule = Schedule::new(date("2024-03-01"));
+            let results: Vec<_> = schedule.defer(reader).collect();
+            let due: Vec<TxId> = results[..2]
+                .iter()
+                .map(|result| result.as_ref().unwrap().tx)
+                .collect();
+            assert_eq!(due, [TxId(2), TxId(3)]);
+            let err = format!("{:#}", results[2].as_ref().unwrap_err());
+            assert!(err.contains("invalid date '2024-02-30'"), "{}", err);
+
+            let pending: Vec<(Date, TxId)> =
+                schedule.pending().map(|(date, tx)| (date, tx.tx)).collect();
+            assert_eq!(
+                pending,
+                [
+                    (date("2024-03-02"), TxId(4)),
+                    (date("2024-03-03"), TxId(1)),
+                    (date("2024-03-03"), TxId(5)),
+                ]
+            );
+            let due: Vec<TxId> = schedule
+                .advance_to(date("2024-03-03"))
+                .iter()
+                .map(|tx| tx.tx)
+                .collect();
+            assert_eq!(due, [TxId(4), TxId(1), TxId(5)]);
+            assert_eq!(schedule.pending().count(), 0);
+            assert_eq!(schedule.as_of(), date("2024-03-03"));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
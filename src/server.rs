@@ -0,0 +1,984 @@
+//! Socket ingestion (`listen` subcommand).
+//!
+//! A [`Server`] applies transactions received over a TCP or Unix domain socket
+//! to a shared [`Engine`], so other processes on the host can feed it without
+//! writing files. It listens on two addresses:
+//!
+//! - The **ingest** address accepts newline-delimited transactions, either as
+//!   CSV (`deposit,1,1,10.5`, optionally preceded by a header line naming the
+//!   columns) or as JSON objects (`{"type":"deposit","client":1,"tx":1,"amount":"10.5"}`).
+//!   Every line is answered with `ok`, `ignored <reason>` (e.g.
+//!   `ignored insufficient_funds`), `rate_limited <scope>` or `error <message>`;
+//!   a malformed line does not close the connection.
+//! - The **control** address accepts the commands `balance <client>`,
+//!   `accounts` and `shutdown`. Balance queries are answered with the account
+//!   CSV (header included) followed by an empty line; `shutdown` stops the
+//!   server once the connected clients are idle.
+//!
+//! Addresses are `HOST:PORT` for TCP and `unix:PATH` for Unix domain sockets.
+//! Each connection is served by its own thread, which applies and answers its
+//! lines in order, so the transactions a connection sends for a client are
+//! applied in the order they were sent.
+//!
+//! With a [`RateLimiter`], transactions exceeding the rate of their client or
+//! the global rate are answered with `rate_limited client` or
+//! `rate_limited global` without being applied, so the sender can back off and
+//! send them again.
+//!
+//! With a [`SessionRecorder`] every transaction that is passed to the engine is
+//! recorded with the source `listen:<ingest address>`, in the order the
+//! transactions are applied, so the session replays to the same accounts.
+//!
+//! With a [`WriteAheadLog`] every transaction is persisted before it is applied
+//! and answered, snapshots are taken periodically and on shutdown, and a
+//! restarted server continues from the recovered state. A server created with
+//! [`Server::bind_stored`] keeps the accounts in a [`StateStore`] instead of
+//! memory, which persists every transaction by itself. One created with
+//! `Server::bind_concurrent` (`concurrent` feature) applies the transactions of
+//! different connections in parallel unless they are for the same client.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread::{self, Scope};
+use std::time::{Duration, Instant};
+
+use crate::amount::PrecisionPolicy;
+#[cfg(feature = "concurrent")]
+use crate::concurrent::ConcurrentEngine;
+use crate::engine::{Decision, Engine};
+use crate::io::{
+    AccountWriter, OutputOptions, TransactionColumns, ensure_not_recurring, parse_adjusted_fields,
+};
+use crate::rate_limit::{RateLimited, RateLimiter};
+use crate::session::SessionRecorder;
+use crate::store::{StateStore, StoredEngine};
+use crate::types::{Accounts, ClientId, Transaction, sorted_accounts};
+use crate::wal::WriteAheadLog;
+
+/// How often idle accept loops and connections check for a shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Column order of CSV lines received before any header line.
+const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// An address to listen on: `HOST:PORT` or `unix:PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "Unix domain sockets are not supported on this platform: {}",
+                path
+            ));
+        }
+        if value.is_empty() {
+            return Err("expected HOST:PORT or unix:PATH".to_string());
+        }
+        Ok(ListenAddress::Tcp(value.to_string()))
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A bound listening socket.
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    fn bind(address: &ListenAddress) -> Result<Self> {
+        let listener = match address {
+            ListenAddress::Tcp(address) => Listener::Tcp(
+                TcpListener::bind(address)
+                    .with_context(|| format!("Failed to listen on: {}", address))?,
+            ),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("Failed to listen on: unix:{}", path.display()))?;
+                Listener::Unix(listener, path.clone())
+            }
+        };
+        // Accepting without blocking lets the accept loop notice a shutdown.
+        match &listener {
+            Listener::Tcp(listener) => listener.set_nonblocking(true)?,
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(true)?,
+        }
+        Ok(listener)
+    }
+
+    /// Returns the bound address, with the actual port if port 0 was requested.
+    fn address(&self) -> Result<ListenAddress> {
+        Ok(match self {
+            Listener::Tcp(listener) => ListenAddress::Tcp(listener.local_addr()?.to_string()),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => ListenAddress::Unix(path.clone()),
+        })
+    }
+
+    /// Returns the next connection, or `None` if none is pending.
+    fn accept(&self) -> io::Result<Option<Connection>> {
+        let result = match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener
+                .accept()
+                .map(|(stream, _)| Connection::Unix(stream)),
+        };
+        match result {
+            Ok(connection) => Ok(Some(connection)),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Removes a socket file left behind by a previous run, but nothing else.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path)
+        && metadata.file_type().is_socket()
+    {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// An accepted connection.
+enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Connection {
+    /// Switches the connection to blocking reads that time out after `timeout`.
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))
+            }
+            #[cfg(unix)]
+            Connection::Unix(stream) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(timeout))
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(match self {
+            Connection::Tcp(stream) => Connection::Tcp(stream.try_clone()?),
+            #[cfg(unix)]
+            Connection::Unix(stream) => Connection::Unix(stream.try_clone()?),
+        })
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// The engine a [`Server`] applies transactions to.
+// A server holds a single engine, so the size of the variants does not matter.
+#[allow(clippy::large_enum_variant)]
+enum ServerEngine {
+    /// All accounts in memory, optionally persisted by a write-ahead log.
+    Memory(Mutex<Engine>),
+    /// The accounts in a state store.
+    Stored(Mutex<StoredEngine<Box<dyn StateStore + Send>>>),
+    /// All accounts in memory, applied by a worker pool that needs no lock.
+    #[cfg(feature = "concurrent")]
+    Concurrent(ConcurrentEngine),
+}
+
+impl ServerEngine {
+    /// Returns the precision policy amounts are parsed with.
+    fn precision(&self) -> PrecisionPolicy {
+        match self {
+            ServerEngine::Memory(engine) => lock(engine).config().precision,
+            ServerEngine::Stored(engine) => lock(engine).config().precision,
+            #[cfg(feature = "concurrent")]
+            ServerEngine::Concurrent(engine) => engine.config().precision,
+        }
+    }
+}
+
+/// Locks `mutex`, ignoring whether a connection thread panicked holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Applies transactions received over sockets to a shared engine.
+pub struct Server {
+    /// The session recorder and the source of its entries. Locked before
+    /// `engine` and held until a transaction is applied, so transactions are
+    /// recorded in the order they are applied.
+    recorder: Option<(Mutex<SessionRecorder>, String)>,
+    engine: ServerEngine,
+    /// Locked after `engine`, so transactions are logged in the order they are applied.
+    wal: Option<Mutex<WriteAheadLog>>,
+    ingest: Listener,
+    control: Listener,
+    strict: bool,
+    limiter: Option<Mutex<RateLimiter>>,
+    options: OutputOptions,
+    shutdown: AtomicBool,
+}
+
+impl Server {
+    /// Binds the ingest and control addresses.
+    ///
+    /// A stale Unix domain socket file at either address is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an address cannot be bound.
+    pub fn bind(engine: Engine, ingest: &ListenAddress, control: &ListenAddress) -> Result<Self> {
+        Self::bind_engine(ServerEngine::Memory(Mutex::new(engine)), ingest, control)
+    }
+
+    /// Binds the ingest and control addresses of a server keeping the accounts
+    /// in the store of `engine`, see [`Server::bind`].
+    ///
+    /// The store persists every transaction before it is answered, so the
+    /// server needs no write-ahead log.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an address cannot be bound.
+    pub fn bind_stored(
+        engine: StoredEngine<Box<dyn StateStore + Send>>,
+        ingest: &ListenAddress,
+        control: &ListenAddress,
+    ) -> Result<Self> {
+        Self::bind_engine(ServerEngine::Stored(Mutex::new(engine)), ingest, control)
+    }
+
+    /// Binds the ingest and control addresses of a server applying the
+    /// transactions of different clients concurrently with `engine`, see
+    /// [`Server::bind`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an address cannot be bound.
+    #[cfg(feature = "concurrent")]
+    pub fn bind_concurrent(
+        engine: ConcurrentEngine,
+        ingest: &ListenAddress,
+        control: &ListenAddress,
+    ) -> Result<Self> {
+        Self::bind_engine(ServerEngine::Concurrent(engine), ingest, control)
+    }
+
+    fn bind_engine(
+        engine: ServerEngine,
+        ingest: &ListenAddress,
+        control: &ListenAddress,
+    ) -> Result<Self> {
+        Ok(Server {
+            recorder: None,
+            engine,
+            wal: None,
+            ingest: Listener::bind(ingest)?,
+            control: Listener::bind(control)?,
+            strict: false,
+            limiter: None,
+            options: OutputOptions::default(),
+            shutdown: AtomicBool::new(false),
+        })
+    }
+
+    /// Rejects negative amounts with an error instead of passing them on.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Rejects transactions exceeding the rate limits of `limiter` instead of
+    /// applying them.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(Mutex::new(limiter));
+        self
+    }
+
+    /// Persists every transaction to `wal` before applying it.
+    ///
+    /// The engine passed to [`Server::bind`] must be the one recovered from the
+    /// same log. Servers created otherwise ignore the log.
+    pub fn write_ahead_log(mut self, wal: WriteAheadLog) -> Self {
+        self.wal = Some(Mutex::new(wal));
+        self
+    }
+
+    /// Records every transaction passed to the engine into `recorder`, flushing
+    /// it after each entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address of a TCP socket cannot be determined.
+    pub fn record_session(mut self, recorder: SessionRecorder) -> Result<Self> {
+        let source = format!("listen:{}", self.ingest_address()?);
+        self.recorder = Some((Mutex::new(recorder), source));
+        Ok(self)
+    }
+
+    /// Sets the vocabulary of the account CSV sent on the control channel.
+    pub fn output_options(mut self, options: OutputOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the bound ingest address, with the actual port if port 0 was requested.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address of a TCP socket cannot be determined.
+    pub fn ingest_address(&self) -> Result<ListenAddress> {
+        self.ingest.address()
+    }
+
+    /// Returns the bound control address, with the actual port if port 0 was requested.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address of a TCP socket cannot be determined.
+    pub fn control_address(&self) -> Result<ListenAddress> {
+        self.control.address()
+    }
+
+    /// Serves connections until a `shutdown` command is received and every
+    /// connection has gone idle, then returns the final accounts.
+    ///
+    /// With a write-ahead log, a final snapshot is taken before returning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if accepting connections, writing the final snapshot or
+    /// reading the accounts from the state store fails.
+    pub fn run(self) -> Result<Accounts> {
+        let server = &self;
+        thread::scope(|scope| {
+            let ingest = scope.spawn(move || {
+                server.accept_loop(&server.ingest, scope, |server, connection| {
+                    server.serve_ingest(connection)
+                })
+            });
+            let control = server.accept_loop(&server.control, scope, |server, connection| {
+                server.serve_control(connection)
+            });
+            // Stop the ingest loop as well if the control loop failed.
+            server.shutdown.store(true, Ordering::Relaxed);
+            let ingest = ingest
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("Ingest listener panicked")));
+            control.and(ingest)
+        })?;
+        let engine = match self.engine {
+            ServerEngine::Memory(engine) => engine
+                .into_inner()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            ServerEngine::Stored(engine) => {
+                return engine
+                    .into_inner()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .accounts();
+            }
+            #[cfg(feature = "concurrent")]
+            ServerEngine::Concurrent(engine) => return Ok(engine.into_accounts()),
+        };
+        if let Some(wal) = self.wal {
+            wal.into_inner()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .snapshot(&engine)?;
+        }
+        Ok(engine.into_accounts())
+    }
+
+    /// Accepts connections on `listener` until shutdown, serving each with
+    /// `serve` on its own thread.
+    fn accept_loop<'scope>(
+        &'scope self,
+        listener: &'scope Listener,
+        scope: &'scope Scope<'scope, '_>,
+        serve: fn(&Server, Connection) -> Result<()>,
+    ) -> Result<()> {
+        while !self.shutdown.load(Ordering::Relaxed) {
+            let Some(connection) = listener.accept().context("Failed to accept connection")? else {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+            scope.spawn(move || {
+                if let Err(err) = serve(self, connection) {
+                    tracing::warn!(error = format!("{:#}", err), "connection failed");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the lines of `connection` until it is closed, or until it is idle
+    /// after a shutdown, calling `handle` with each line and the reply writer.
+    fn serve_lines(
+        &self,
+        connection: Connection,
+        mut handle: impl FnMut(&str, &mut BufWriter<Connection>) -> Result<()>,
+    ) -> Result<()> {
+        connection.set_read_timeout(POLL_INTERVAL)?;
+        let mut writer = BufWriter::new(connection.try_clone()?);
+        let mut reader = BufReader::new(connection);
+        let mut line = Vec::new();
+        loop {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) if !line.ends_with(b"\n") => {}
+                Ok(_) => {
+                    let text = String::from_utf8_lossy(&line);
+                    let text = text.trim();
+                    if !text.is_empty() {
+                        handle(text, &mut writer)?;
+                    }
+                    line.clear();
+                }
+                // Bytes read before the timeout stay in `line`.
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if self.shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+            // Replies are batched while more input is already buffered.
+            if reader.buffer().is_empty() {
+                writer.flush()?;
+            }
+        }
+        // A final line without a newline is still a line.
+        let text = String::from_utf8_lossy(&line);
+        if !text.trim().is_empty() {
+            handle(text.trim(), &mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn serve_ingest(&self, connection: Connection) -> Result<()> {
+        let mut columns = None;
+        self.serve_lines(connection, |line, writer| {
+            let reply = match self.ingest_line(line, &mut columns) {
+                Ok(None) => return Ok(()),
+                Ok(Some(Decision::Ignore(reason))) => format!("ignored {}", reason.as_str()),
+                Ok(Some(_)) => "ok".to_string(),
+                Err(err) => match err.downcast_ref::<RateLimited>() {
+                    Some(limited) => format!("rate_limited {}", limited.scope.as_str()),
+                    None => format!("error {:#}", err).replace('\n', " "),
+                },
+            };
+            writeln!(writer, "{}", reply)?;
+            Ok(())
+        })
+    }
+
+    /// Parses and applies one ingest line, returning `None` for a CSV header line.
+    fn ingest_line(
+        &self,
+        line: &str,
+        columns: &mut Option<TransactionColumns>,
+    ) -> Result<Option<Decision>> {
+        let tx = if line.starts_with('{') {
+            let (headers, record) = json_record(line)?;
+            self.parse_record(record, TransactionColumns::from_headers(&headers, &[])?)?
+        } else {
+            let record = csv_record(line)?;
+            if is_header(&record) {
+                *columns = Some(TransactionColumns::from_headers(&record, &[])?);
+                return Ok(None);
+            }
+            let columns = match columns {
+                Some(columns) => *columns,
+                None => *columns.insert(TransactionColumns::from_headers(
+                    &csv::ByteRecord::from(DEFAULT_COLUMNS.to_vec()),
+                    &[],
+                )?),
+            };
+            self.parse_record(record, columns)?
+        };
+        if let Some(limiter) = &self.limiter {
+            lock(limiter).admit(tx.client, Instant::now())?;
+        }
+        let _recorder = self.record(&tx)?;
+        let mut engine = match &self.engine {
+            ServerEngine::Memory(engine) => lock(engine),
+            ServerEngine::Stored(engine) => return lock(engine).process(tx).map(Some),
+            #[cfg(feature = "concurrent")]
+            ServerEngine::Concurrent(engine) => return engine.process(tx).map(Some),
+        };
+        let Some(wal) = &self.wal else {
+            return engine.process(tx).map(Some);
+        };
+        let mut wal = lock(wal);
+        wal.append(&tx)?;
+        let decision = engine.process(tx)?;
+        if wal.snapshot_due()
+            && let Err(err) = wal.snapshot(&engine)
+        {
+            tracing::error!(error = format!("{:#}", err), "failed to write snapshot");
+        }
+        Ok(Some(decision))
+    }
+
+    /// Records `tx` into the session, if any, and returns the locked recorder.
+    fn record(&self, tx: &Transaction) -> Result<Option<MutexGuard<'_, SessionRecorder>>> {
+        let Some((recorder, source)) = &self.recorder else {
+            return Ok(None);
+        };
+        let mut recorder = lock(recorder);
+        recorder.record(source, tx)?;
+        recorder.flush()?;
+        Ok(Some(recorder))
+    }
+
+    /// Parses `record` with the precision policy of the engine.
+    fn parse_record(
+        &self,
+        record: csv::ByteRecord,
+        columns: TransactionColumns,
+    ) -> Result<Transaction> {
+        let precision = self.engine.precision();
+        ensure_not_recurring(&record, columns)
+            .and_then(|()| parse_adjusted_fields(&record, columns, precision, self.strict))
+            .map_err(|(_, err)| err)
+    }
+
+    fn serve_control(&self, connection: Connection) -> Result<()> {
+        self.serve_lines(connection, |line, writer| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("balance"), Some(client), None) => match client.parse::<ClientId>() {
+                    Ok(client) => self.write_accounts(writer, Some(client))?,
+                    Err(_) => writeln!(writer, "error invalid client '{}'", client)?,
+                },
+                (Some("accounts"), None, None) => self.write_accounts(writer, None)?,
+                (Some("shutdown"), None, None) => {
+                    self.shutdown.store(true, Ordering::Relaxed);
+                    writeln!(writer, "ok")?;
+                }
+                _ => writeln!(writer, "error unknown command '{}'", line)?,
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes the account of `client`, or all accounts, as CSV, followed by an
+    /// empty line.
+    fn write_accounts(&self, writer: &mut impl Write, client: Option<ClientId>) -> Result<()> {
+        let mut accounts = AccountWriter::new(&mut *writer, &self.options)?;
+        match &self.engine {
+            ServerEngine::Memory(engine) => {
+                let engine = lock(engine);
+                for (id, account) in sorted_accounts(engine.accounts()) {
+                    if client.is_none_or(|client| client == id) {
+                        accounts.write(id, account)?;
+                    }
+                }
+            }
+            ServerEngine::Stored(engine) => {
+                let engine = lock(engine);
+                match client {
+                    Some(client) => {
+                        if let Some(account) = engine.account(client)? {
+                            accounts.write(client, &account)?;
+                        }
+                    }
+                    None => engine.store().for_each_account(&mut |client, account| {
+                        accounts.write(client, &account)
+                    })?,
+                }
+            }
+            #[cfg(feature = "concurrent")]
+            ServerEngine::Concurrent(engine) => match client {
+                Some(client) => {
+                    if let Some(account) = engine.account(client) {
+                        accounts.write(client, &account)?;
+                    }
+                }
+                None => {
+                    for (id, account) in sorted_accounts(&engine.accounts()) {
+                        accounts.write(id, account)?;
+                    }
+                }
+            },
+        }
+        accounts.finish()?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// Parses a CSV ingest line into a record.
+fn csv_record(line: &str) -> Result<csv::ByteRecord> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+    let mut record = csv::ByteRecord::new();
+    reader.read_byte_record(&mut record)?;
+    Ok(record)
+}
+
+/// Returns whether a CSV ingest line is a header line, i.e. has no numeric
+/// field where a transaction has its client and transaction IDs.
+fn is_header(record: &csv::ByteRecord) -> bool {
+    record
+        .iter()
+        .all(|field| !field.first().is_some_and(u8::is_ascii_digit))
+}
+
+/// Converts a JSON ingest line into a header row and a record.
+///
+/// Numbers are accepted for every field and `null` means an empty field.
+fn json_record(line: &str) -> Result<(csv::ByteRecord, csv::ByteRecord)> {
+    let object: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(line).context("invalid JSON object")?;
+    let mut headers = csv::ByteRecord::new();
+    let mut record = csv::ByteRecord::new();
+    for (name, value) in &object {
+        let field = match value {
+            serde_json::Value::String(text) => text.trim().to_string(),
+            serde_json::Value::Number(number) => number.to_string(),
+            serde_json::Value::Null => String::new(),
+            other => anyhow::bail!("unsupported value {} for field `{}`", other, name),
+        };
+        headers.push_field(name.as_bytes());
+        record.push_field(field.as_bytes());
+    }
+    Ok((headers, record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineConfig;
+    use crate::types::{Amount, TxId};
+    use std::str::FromStr;
+
+    /// Sends `lines` over a new connection to `address` and returns the reply lines.
+    fn send(address: &ListenAddress, lines: &[&str]) -> Vec<String> {
+        let mut connection = match address {
+            ListenAddress::Tcp(address) => Connection::Tcp(TcpStream::connect(address).unwrap()),
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => Connection::Unix(UnixStream::connect(path).unwrap()),
+        };
+        for line in lines {
+            writeln!(connection, "{}", line).unwrap();
+        }
+        match &connection {
+            Connection::Tcp(stream) => stream.shutdown(std::net::Shutdown::Write).unwrap(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.shutdown(std::net::Shutdown::Write).unwrap(),
+        }
+        BufReader::new(connection)
+            .lines()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!(
+            ListenAddress::from_str("127.0.0.1:7000").unwrap(),
+            ListenAddress::Tcp("127.0.0.1:7000".to_string())
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            ListenAddress::from_str("unix:/tmp/bank.sock").unwrap(),
+            ListenAddress::Unix(PathBuf::from("/tmp/bank.sock"))
+        );
+        assert!(ListenAddress::from_str("").is_err());
+    }
+
+    #[test]
+    fn applies_csv_and_json_lines_and_answers_queries() {
+        let any_port = ListenAddress::Tcp("127.0.0.1:0".to_string());
+        let server = Server::bind(Engine::new(EngineConfig::default()), &any_port, &any_port)
+            .unwrap()
+            .strict(true);
+        let ingest = server.ingest_address().unwrap();
+        let control = server.control_address().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        assert_eq!(
+            send(
+                &ingest,
+                &[
+                    "deposit,1,1,10.5",
+                    "withdrawal,1,2,20",
+                    r#"{"type":"deposit","client":2,"tx":3,"amount":1.25}"#,
+                    "deposit,x,4,1",
+                    "deposit,1,5,-1",
+                    r#"{"type":"deposit","client":2,"tx":7,"amount":1,"interval":30,"count":2}"#,
+                ]
+            ),
+            vec![
+                "ok",
+                "ignored insufficient_funds",
+                "ok",
+                "error invalid client 'x'",
+                "error negative amount '-1'",
+                "error recurring transactions are not supported in this mode",
+            ]
+        );
+        // A header line changes the column order for the rest of the connection.
+        assert_eq!(
+            send(&ingest, &["client,type,amount,tx", "1,withdrawal,0.25,6"]),
+            vec!["ok"]
+        );
+
+        assert_eq!(
+            send(&control, &["balance 1", "balance 9", "frobnicate"]),
+            vec![
+                "client,available,held,total,locked",
+                "1,10.25,0,10.25,false",
+                "",
+                "client,available,held,total,locked",
+                "",
+                "error unknown command 'frobnicate'",
+            ]
+        );
+        assert_eq!(send(&control, &["shutdown"]), vec!["ok"]);
+
+        let accounts = running.join().unwrap().unwrap();
+        assert_eq!(
+            accounts[&ClientId(2)].total,
+            Amount::from_str("1.25").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_transactions_over_the_rate_limits() {
+        let any_port = ListenAddress::Tcp("127.0.0.1:0".to_string());
+        let limiter = RateLimiter::new("2/h".parse().ok(), "3/h".parse().ok());
+        let server = Server::bind(Engine::new(EngineConfig::default()), &any_port, &any_port)
+            .unwrap()
+            .rate_limiter(limiter);
+        let ingest = server.ingest_address().unwrap();
+        let control = server.control_address().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        assert_eq!(
+            send(
+                &ingest,
+                &[
+                    "deposit,1,1,1",
+                    "deposit,1,2,1",
+                    "deposit,1,3,1",
+                    "deposit,2,4,1",
+                    "deposit,2,5,1",
+                    // Malformed lines take no token.
+                    "deposit,x,6,1",
+                ]
+            ),
+            vec![
+                "ok",
+                "ok",
+                "rate_limited client",
+                "ok",
+                "rate_limited global",
+                "error invalid client 'x'",
+            ]
+        );
+        send(&control, &["shutdown"]);
+        let accounts = running.join().unwrap().unwrap();
+        assert_eq!(accounts[&ClientId(1)].total, Amount::from_str("2").unwrap());
+        assert_eq!(accounts[&ClientId(2)].total, Amount::from_str("1").unwrap());
+    }
+
+    #[test]
+    fn restarts_from_the_write_ahead_log() {
+        use crate::wal::WalConfig;
+
+        let dir = std::env::temp_dir().join(format!("server-wal-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let any_port = ListenAddress::Tcp("127.0.0.1:0".to_string());
+        let start = || {
+            let (engine, wal) =
+                WriteAheadLog::recover(&dir, EngineConfig::default(), WalConfig::default())
+                    .unwrap();
+            let server = Server::bind(engine, &any_port, &any_port)
+                .unwrap()
+                .write_ahead_log(wal);
+            let ingest = server.ingest_address().unwrap();
+            let control = server.control_address().unwrap();
+            (ingest, control, thread::spawn(move || server.run()))
+        };
+
+        let (ingest, control, running) = start();
+        assert_eq!(
+            send(&ingest, &["deposit,1,1,10.5", "dispute,1,1,"]),
+            vec!["ok", "ok"]
+        );
+        send(&control, &["shutdown"]);
+        running.join().unwrap().unwrap();
+
+        let (ingest, control, running) = start();
+        assert_eq!(send(&ingest, &["resolve,1,1,"]), vec!["ok"]);
+        send(&control, &["shutdown"]);
+        let accounts = running.join().unwrap().unwrap();
+        let account = &accounts[&ClientId(1)];
+        assert_eq!(account.available, Amount::from_str("10.5").unwrap());
+        assert_eq!(account.held, Amount::ZERO);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_applied_transactions_into_a_session() {
+        use crate::session::read_session_from_file;
+
+        let path = std::env::temp_dir().join(format!("server-session-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let any_port = ListenAddress::Tcp("127.0.0.1:0".to_string());
+        let limiter = RateLimiter::new(None, "2/h".parse().ok());
+        let server = Server::bind(Engine::new(EngineConfig::default()), &any_port, &any_port)
+            .unwrap()
+            .rate_limiter(limiter)
+            .record_session(SessionRecorder::create(path).unwrap())
+            .unwrap();
+        let ingest = server.ingest_address().unwrap();
+        let control = server.control_address().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        assert_eq!(
+            send(
+                &ingest,
+                &[
+                    "deposit,1,1,10",
+                    "deposit,x,2,1",
+                    "withdrawal,1,3,20",
+                    "deposit,1,4,1"
+                ]
+            ),
+            vec![
+                "ok",
+                "error invalid client 'x'",
+                "ignored insufficient_funds",
+                "rate_limited global"
+            ]
+        );
+        send(&control, &["shutdown"]);
+        running.join().unwrap().unwrap();
+
+        // Ignored transactions are recorded, malformed and rate-limited ones
+        // are not.
+        let records: Vec<_> = read_session_from_file(path)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        let recorded: Vec<_> = records.iter().map(|record| record.tx).collect();
+        assert_eq!(recorded, [TxId(1), TxId(3)]);
+        assert_eq!(records[0].source, format!("listen:{}", ingest));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listens_on_unix_domain_sockets() {
+        let dir = std::env::temp_dir();
+        let ingest = ListenAddress::Unix(dir.join(format!("ingest-{}.sock", std::process::id())));
+        let control = ListenAddress::Unix(dir.join(format!("control-{}.sock", std::process::id())));
+        let server = Server::bind(Engine::new(EngineConfig::default()), &ingest, &control).unwrap();
+        let running = thread::spawn(move || server.run());
+
+        assert_eq!(
+            send(&ingest, &["type,client,tx,amount", "deposit,7,1,3"]),
+            vec!["ok"]
+        );
+        assert_eq!(
+            send(&control, &["accounts", "shutdown"]),
+            vec![
+                "client,available,held,total,locked",
+                "7,3,0,3,false",
+                "",
+                "ok"
+            ]
+        );
+
+        running.join().unwrap().unwrap();
+        let ListenAddress::Unix(path) = ingest else {
+            unreachable!()
+        };
+        assert!(!path.exists());
+    }
+}
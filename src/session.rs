@@ -0,0 +1,534 @@
+//! Session recording and replay.
+//!
+//! A session file captures every transaction received by the application, in
+//! arrival order, together with the time it was received and where it came from.
+//! Replaying a session re-drives the exact same transactions against a fresh
+//! engine, which makes it possible to reproduce production incidents locally.
+//!
+//! Session files are CSV with the columns
+//! `received_at,source,type,client,tx,amount,prev_hash,hash`, where
+//! `received_at` is the number of milliseconds since the Unix epoch.
+//!
+//! The session file doubles as a tamper-evident audit trail: the entries form a
+//! hash chain. `prev_hash` is the `hash` of the previous entry (64 zeros for the
+//! first), and `hash` is the hex-encoded SHA-256 of the entry's other columns,
+//! `prev_hash` included, encoded as one CSV line without line terminator.
+//! Editing, inserting or removing an entry breaks the chain for every later
+//! entry, which [`verify_session_chain`] detects. Removing entries from the end
+//! is only detected by comparing the hash of the last entry with one kept
+//! elsewhere. Session files recorded before the chain was introduced have no
+//! hash columns and still replay.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::amount::deserialize_amount_or_zero;
+use crate::digest::sha256_hex;
+use crate::types::{Amount, ClientId, Date, Transaction, TxId, TxType};
+
+/// A single recorded transaction together with its arrival metadata.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionRecord {
+    /// Milliseconds since the Unix epoch at which the transaction was received.
+    pub received_at: u64,
+    /// Where the transaction came from (e.g. `file:transactions.csv`).
+    pub source: String,
+    #[serde(rename = "type")]
+    pub tx_type: TxType,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(deserialize_with = "deserialize_amount_or_zero")]
+    pub amount: Amount,
+    /// Hash of the previous entry; `None` in sessions recorded without a chain.
+    pub prev_hash: Option<String>,
+    /// Hash of this entry; `None` in sessions recorded without a chain.
+    pub hash: Option<String>,
+}
+
+/// `prev_hash` of the first entry of a session.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Header of session files.
+const COLUMNS: [&str; 8] = [
+    "received_at",
+    "source",
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "prev_hash",
+    "hash",
+];
+
+/// Number of columns covered by the hash of an entry: every column but `hash`.
+const HASHED_COLUMNS: usize = COLUMNS.len() - 1;
+
+/// Returns the hash of an entry from its hashed columns, i.e. the hex-encoded
+/// SHA-256 of their CSV encoding.
+fn entry_hash<'a>(columns: impl IntoIterator<Item = &'a [u8]>) -> Result<String> {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(Vec::new());
+    writer.write_record(columns)?;
+    let mut line = writer.into_inner().map_err(|err| err.into_error())?;
+    line.pop();
+    Ok(sha256_hex(&line))
+}
+
+impl SessionRecord {
+    /// Returns the recorded transaction.
+    pub fn transaction(&self) -> Transaction {
+        Transaction {
+            tx_type: self.tx_type,
+            client: self.client,
+            tx: self.tx,
+            amount: self.amount,
+        }
+    }
+}
+
+/// Records received transactions into a session file.
+pub struct SessionRecorder {
+    writer: csv::Writer<File>,
+    path: String,
+    /// Hash of the last recorded entry.
+    last_hash: String,
+}
+
+impl SessionRecorder {
+    /// Creates (or truncates) the session file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be created.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create session file: {}", path))?;
+
+        let mut writer = csv::Writer::from_writer(file);
+        writer
+            .write_record(COLUMNS)
+            .with_context(|| format!("Failed to write session header to: {}", path))?;
+
+        Ok(SessionRecorder {
+            writer,
+            path: path.to_string(),
+            last_hash: GENESIS_HASH.to_string(),
+        })
+    }
+
+    /// Appends a transaction received from `source` to the session, stamped with
+    /// the current time and chained to the previous entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record cannot be written.
+    pub fn record(&mut self, source: &str, tx: &Transaction) -> Result<()> {
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+
+        let columns = [
+            received_at.to_string(),
+            source.to_string(),
+            tx.tx_type.as_str().to_string(),
+            tx.client.to_string(),
+            tx.tx.to_string(),
+            tx.amount.to_string(),
+            std::mem::take(&mut self.last_hash),
+        ];
+        self.last_hash = entry_hash(columns.iter().map(|column| column.as_bytes()))?;
+        self.writer
+            .write_record(columns.iter().chain([&self.last_hash]))
+            .with_context(|| format!("Failed to write session record to: {}", self.path))
+    }
+
+    /// Returns the hash of the last recorded entry, or [`GENESIS_HASH`] if the
+    /// session is empty.
+    pub fn last_hash(&self) -> &str {
+        &self.last_hash
+    }
+
+    /// Flushes all buffered records to the session file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if flushing fails.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to flush session file: {}", self.path))
+    }
+}
+
+/// Reads a session file, returning an iterator over the recorded entries in
+/// arrival order.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened. Individual record parsing errors
+/// are returned when iterating over the result.
+pub fn read_session_from_file(path: &str) -> Result<impl Iterator<Item = Result<SessionRecord>>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open session file: {}", path))?;
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(file);
+    let path = path.to_string();
+
+    Ok(reader
+        .into_deserialize::<SessionRecord>()
+        .enumerate()
+        .map(move |(index, result)| {
+            result.with_context(|| {
+                format!(
+                    "Failed to parse session record at line {} from: {}",
+                    index + 2,
+                    path
+                )
+            })
+        }))
+}
+
+/// A point in a session up to which its entries are replayed, see
+/// [`read_session_until`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionPoint {
+    /// Up to and including the first entry with this transaction ID, i.e.
+    /// right after the original transaction was applied.
+    Tx(TxId),
+    /// Up to the last entry received at or before this many milliseconds since
+    /// the Unix epoch.
+    ReceivedAt(u64),
+}
+
+/// Parses a point in time as milliseconds since the Unix epoch, either given
+/// as such or as a UTC date and time such as `2024-03-01T12:30:00Z`.
+///
+/// # Errors
+///
+/// Returns an error message if the input is neither.
+pub fn parse_timestamp(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    if let Ok(millis) = input.parse() {
+        return Ok(millis);
+    }
+    let invalid = || {
+        format!(
+            "invalid timestamp '{}', expected milliseconds since the Unix epoch or \
+             YYYY-MM-DDTHH:MM:SSZ",
+            input
+        )
+    };
+    let (date, time) = input
+        .strip_suffix('Z')
+        .and_then(|input| input.split_once('T'))
+        .ok_or_else(invalid)?;
+    let date: Date = date.parse().map_err(|_| invalid())?;
+    let (time, millis) = time.split_once('.').unwrap_or((time, "0"));
+    let mut parts = time.split(':').map(|part| {
+        (part.len() == 2)
+            .then(|| part.parse::<u64>().ok())
+            .flatten()
+    });
+    let (Some(Some(hour)), Some(Some(minute)), Some(Some(second)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let millis = match millis.len() {
+        1..=3 if millis.bytes().all(|b| b.is_ascii_digit()) => {
+            millis.parse::<u64>().unwrap_or_default() * 10u64.pow(3 - millis.len() as u32)
+        }
+        _ => return Err(invalid()),
+    };
+    let epoch = Date::new(1970, 1, 1).expect("valid date");
+    let days = u64::try_from(date.days_since(epoch)).map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+    Ok((((days * 24 + hour) * 60 + minute) * 60 + second) * 1000 + millis)
+}
+
+/// Reads the entries of the session file at `path` up to `point`, in arrival
+/// order, to reconstruct the state the engine had at that point.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened. Parsing errors, and a
+/// [`SessionPoint::Tx`] that is not in the session, are returned when iterating
+/// over the result.
+pub fn read_session_until(
+    path: &str,
+    point: SessionPoint,
+) -> Result<impl Iterator<Item = Result<SessionRecord>>> {
+    let mut records = read_session_from_file(path)?;
+    let path = path.to_string();
+    let mut done = false;
+    Ok(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let Some(result) = records.next() else {
+            done = true;
+            return match point {
+                SessionPoint::Tx(tx) => Some(Err(anyhow::anyhow!(
+                    "Transaction {} is not in the session: {}",
+                    tx,
+                    path
+                ))),
+                SessionPoint::ReceivedAt(_) => None,
+            };
+        };
+        if let Ok(record) = &result {
+            match point {
+                SessionPoint::Tx(tx) => done = record.tx == tx,
+                SessionPoint::ReceivedAt(at) if record.received_at > at => {
+                    done = true;
+                    return None;
+                }
+                SessionPoint::ReceivedAt(_) => {}
+            }
+        }
+        Some(result)
+    }))
+}
+
+/// The result of verifying the hash chain of a session file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSummary {
+    /// Number of entries in the session.
+    pub entries: u64,
+    /// Hash of the last entry, or [`GENESIS_HASH`] for an empty session.
+    pub last_hash: String,
+}
+
+/// Verifies the hash chain of the session file at `path`.
+///
+/// # Errors
+///
+/// Returns an error naming the first entry whose `prev_hash` does not match the
+/// previous entry or whose `hash` does not match its content, or if the file
+/// cannot be read or has no hash columns.
+pub fn verify_session_chain(path: &str) -> Result<ChainSummary> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open session file: {}", path))?;
+    let mut reader = csv::Reader::from_reader(file);
+    let headers = reader
+        .byte_headers()
+        .with_context(|| format!("Failed to read session header from: {}", path))?;
+    anyhow::ensure!(
+        headers
+            .iter()
+            .eq(COLUMNS.iter().map(|column| column.as_bytes())),
+        "Session file has no hash chain: {}",
+        path
+    );
+
+    let mut summary = ChainSummary {
+        entries: 0,
+        last_hash: GENESIS_HASH.to_string(),
+    };
+    for result in reader.byte_records() {
+        let record =
+            result.with_context(|| format!("Failed to read session record from: {}", path))?;
+        summary.entries += 1;
+        let broken = |reason: &str| {
+            anyhow::anyhow!(
+                "Audit chain broken at entry {} (line {}) of {}: {}",
+                summary.entries,
+                record.position().map_or(0, |position| position.line()),
+                path,
+                reason
+            )
+        };
+        if &record[HASHED_COLUMNS - 1] != summary.last_hash.as_bytes() {
+            return Err(broken("prev_hash does not match the previous entry"));
+        }
+        let hash = entry_hash(record.iter().take(HASHED_COLUMNS))?;
+        if &record[HASHED_COLUMNS] != hash.as_bytes() {
+            return Err(broken("hash does not match the entry"));
+        }
+        summary.last_hash = hash;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn recorded_session_replays_same_transactions() {
+        let path = std::env::temp_dir().join(format!("session-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let transactions = vec![
+            Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.5").unwrap(),
+            },
+            Transaction {
+                tx_type: TxType::Dispute,
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::ZERO,
+            },
+        ];
+
+        let mut recorder = SessionRecorder::create(path).unwrap();
+        for tx in &transactions {
+            recorder.record("file:test.csv", tx).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        let records: Vec<SessionRecord> = read_session_from_file(path)
+            .unwrap()
+            .map(|result| result.unwrap())
+            .collect();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].prev_hash.as_deref(), Some(GENESIS_HASH));
+        assert_eq!(records[1].prev_hash, records[0].hash);
+        assert_eq!(records[1].hash.as_deref(), Some(recorder.last_hash()));
+        assert_eq!(records[0].source, "file:test.csv");
+        assert!(records[0].received_at <= records[1].received_at);
+        for (record, tx) in records.iter().zip(&transactions) {
+            let replayed = record.transaction();
+            assert_eq!(replayed.tx_type, tx.tx_type);
+            assert_eq!(replayed.client, tx.client);
+            assert_eq!(replayed.tx, tx.tx);
+            assert_eq!(replayed.amount, tx.amount);
+        }
+    }
+
+    #[test]
+    fn verifies_the_hash_chain_and_detects_edits() {
+        let path = std::env::temp_dir().join(format!("audit-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = SessionRecorder::create(path).unwrap();
+        for (tx, amount) in [(1, "10.5"), (2, "2.25"), (3, "1")] {
+            let tx = Transaction {
+                tx_type: TxType::Deposit,
+                client: ClientId(1),
+                tx: TxId(tx),
+                amount: Amount::from_str(amount).unwrap(),
+            };
+            recorder.record("socket:ingest, 1", &tx).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        let summary = verify_session_chain(path).unwrap();
+        assert_eq!(summary.entries, 3);
+        assert_eq!(summary.last_hash, recorder.last_hash());
+
+        let original = std::fs::read_to_string(path).unwrap();
+        std::fs::write(path, original.replacen(",2.25,", ",22.5,", 1)).unwrap();
+        let err = verify_session_chain(path).unwrap_err().to_string();
+        assert!(
+            err.starts_with("Audit chain broken at entry 2 (line 3)"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("hash does not match the entry"), "{}", err);
+
+        // Dropping an entry breaks the link of the next one.
+        let lines: Vec<&str> = original.lines().collect();
+        std::fs::write(path, [lines[0], lines[1], lines[3]].join("\n")).unwrap();
+        let err = verify_session_chain(path).unwrap_err().to_string();
+        assert!(
+            err.ends_with("prev_hash does not match the previous entry"),
+            "{}",
+            err
+        );
+
+        std::fs::write(path, "received_at,source,type,client,tx,amount\n").unwrap();
+        assert!(verify_session_chain(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replays_sessions_recorded_without_a_chain() {
+        let path = std::env::temp_dir().join(format!("legacy-session-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "received_at,source,type,client,tx,amount\n1,file:a.csv,deposit,1,1,2.5\n",
+        )
+        .unwrap();
+        let records: Vec<SessionRecord> = read_session_from_file(path.to_str().unwrap())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hash, None);
+        assert_eq!(records[0].amount, Amount::from_str("2.5").unwrap());
+    }
+
+    #[test]
+    fn reads_entries_up_to_a_transaction_or_time() {
+        let path = std::env::temp_dir().join(format!("state-at-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "received_at,source,type,client,tx,amount\n\
+             1000,file:a.csv,deposit,1,1,10\n\
+             2000,file:a.csv,deposit,1,2,5\n\
+             2000,file:a.csv,dispute,1,1,\n\
+             3000,file:a.csv,resolve,1,1,\n",
+        )
+        .unwrap();
+        let read = |point| -> Result<Vec<(TxType, TxId)>> {
+            read_session_until(path, point)
+                .unwrap()
+                .map(|result| result.map(|record| (record.tx_type, record.tx)))
+                .collect()
+        };
+
+        assert_eq!(
+            read(SessionPoint::Tx(TxId(1))).unwrap(),
+            [(TxType::Deposit, TxId(1))]
+        );
+        assert_eq!(read(SessionPoint::Tx(TxId(2))).unwrap().len(), 2);
+        assert_eq!(read(SessionPoint::ReceivedAt(999)).unwrap(), []);
+        assert_eq!(
+            read(SessionPoint::ReceivedAt(2999)).unwrap().last(),
+            Some(&(TxType::Dispute, TxId(1)))
+        );
+        assert_eq!(read(SessionPoint::ReceivedAt(u64::MAX)).unwrap().len(), 4);
+        let err = read(SessionPoint::Tx(TxId(3))).unwrap_err().to_string();
+        assert!(
+            err.starts_with("Transaction 3 is not in the session"),
+            "{}",
+            err
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1709296200000"), Ok(1_709_296_200_000));
+        assert_eq!(
+            parse_timestamp("2024-03-01T12:30:00Z"),
+            Ok(1_709_296_200_000)
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00.25Z"), Ok(250));
+        for invalid in [
+            "2024-03-01",
+            "2024-03-01T12:30:00",
+            "2024-03-01T24:00:00Z",
+            "2024-02-30T00:00:00Z",
+            "1969-12-31T23:59:59Z",
+            "2024-03-01T12:30:00.1234Z",
+        ] {
+            assert!(parse_timestamp(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
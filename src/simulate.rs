@@ -0,0 +1,271 @@
+//! Deterministic simulation with failure injection (`simulate`).
+//!
+//! [`generate_workload`] generates a CSV workload of deposits, withdrawals and
+//! dispute lifecycles from a seed, injecting faults a real input may contain:
+//! malformed rows, transaction IDs used twice, and disputes, resolves and
+//! chargebacks arriving before the deposit they reference. [`simulate`] runs
+//! the engine on it and verifies that the processing rules held up:
+//!
+//! - every account satisfies the [`Invariant`](crate::engine::Invariant)s
+//! - the balances of all accounts add up to the deposits minus the withdrawals
+//!   and chargebacks that were applied
+//! - processing the workload again yields the same accounts
+//!
+//! The same seed always generates the same workload, so a failing simulation
+//! can be reproduced from its seed alone.
+
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+
+use crate::engine::{self, Decision, Engine, EngineConfig};
+use crate::types::{Accounts, Amount, Transaction};
+
+/// The shape of a generated workload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulationConfig {
+    /// Seed of the generator.
+    pub seed: u64,
+    /// Number of generated rows, including malformed ones.
+    pub rows: u64,
+    /// Number of distinct clients.
+    pub clients: u16,
+    /// Share of rows that are disputes, resolves or chargebacks, in percent.
+    pub dispute_percent: u32,
+    /// Share of malformed rows, in percent.
+    pub malformed_percent: u32,
+    /// Share of deposits and withdrawals reusing an earlier transaction ID, in
+    /// percent.
+    pub duplicate_percent: u32,
+    /// Share of disputes, resolves and chargebacks referencing a deposit that
+    /// only comes later, in percent.
+    pub out_of_order_percent: u32,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        SimulationConfig {
+            seed: 0,
+            rows: 10_000,
+            clients: 100,
+            dispute_percent: 10,
+            malformed_percent: 2,
+            duplicate_percent: 2,
+            out_of_order_percent: 5,
+        }
+    }
+}
+
+/// A generated workload and the number of faults injected into it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Workload {
+    /// The rows as CSV text with a `type,client,tx,amount` header.
+    pub csv: String,
+    pub rows: u64,
+    pub malformed: u64,
+    pub duplicates: u64,
+    pub out_of_order: u64,
+}
+
+/// A deterministic xorshift generator.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves the all-zero state.
+        Rng((seed ^ 0x2545_f491_4f6c_dd1d).max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+
+    /// Returns `true` with a probability of `percent` percent.
+    fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < u64::from(percent)
+    }
+}
+
+/// Malformed rows, with `{client}` and `{tx}` replaced.
+const MALFORMED_ROWS: [&str; 5] = [
+    "deposit,{client},{tx},1.2.3",
+    "withdrawal,client{client},{tx},5",
+    "transfer,{client},{tx},1",
+    "deposit,{client}",
+    "dispute,{client},-{tx},",
+];
+
+/// Generates the workload of `config`, see the [module documentation](self).
+pub fn generate_workload(config: &SimulationConfig) -> Workload {
+    let mut rng = Rng::new(config.seed);
+    let clients = u64::from(config.clients.max(1));
+    let mut workload = Workload {
+        csv: "type,client,tx,amount\n".to_string(),
+        rows: config.rows,
+        ..Default::default()
+    };
+    let mut deposits: Vec<(u64, u64)> = Vec::new();
+    for tx in 1..=config.rows {
+        let client = rng.below(clients) + 1;
+        if rng.chance(config.malformed_percent) {
+            let row = MALFORMED_ROWS[rng.below(MALFORMED_ROWS.len() as u64) as usize]
+                .replace("{client}", &client.to_string())
+                .replace("{tx}", &tx.to_string());
+            workload.malformed += 1;
+            let _ = writeln!(workload.csv, "{}", row);
+            continue;
+        }
+        if rng.chance(config.dispute_percent) {
+            let kind = match rng.below(10) {
+                0..=5 => "dispute",
+                6..=8 => "resolve",
+                _ => "chargeback",
+            };
+            let (client, deposit) =
+                if deposits.is_empty() || rng.chance(config.out_of_order_percent) {
+                    // Possibly the ID of a later deposit of the client.
+                    workload.out_of_order += 1;
+                    (client, tx + 1 + rng.below(20))
+                } else {
+                    deposits[rng.below(deposits.len() as u64) as usize]
+                };
+            let _ = writeln!(workload.csv, "{},{},{},", kind, client, deposit);
+            continue;
+        }
+        let id = if tx > 1 && rng.chance(config.duplicate_percent) {
+            workload.duplicates += 1;
+            rng.below(tx - 1) + 1
+        } else {
+            tx
+        };
+        let amount = format!("{}.{:04}", rng.below(1_000), rng.below(10_000));
+        if rng.below(4) == 0 {
+            let _ = writeln!(workload.csv, "withdrawal,{},{},{}", client, id, amount);
+        } else {
+            let _ = writeln!(workload.csv, "deposit,{},{},{}", client, id, amount);
+            deposits.push((client, id));
+        }
+    }
+    workload
+}
+
+/// The outcome of a simulation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SimulationReport {
+    /// Number of well-formed rows processed by the engine.
+    pub processed: u64,
+    /// Number of malformed rows skipped.
+    pub skipped: u64,
+    /// Number of processed transactions the engine ignored.
+    pub ignored: u64,
+    /// The final accounts.
+    pub accounts: Accounts,
+    /// Descriptions of the broken checks; empty if the simulation passed.
+    pub violations: Vec<String>,
+}
+
+/// Processes the CSV text `workload` with an engine configured by `config` and
+/// verifies the result, see the [module documentation](self).
+///
+/// # Errors
+///
+/// Returns an error if the workload is no CSV text or the engine fails, e.g. on
+/// an overflowing balance.
+pub fn simulate(workload: &str, config: EngineConfig) -> Result<SimulationReport> {
+    let mut report = run(workload, config.clone())?;
+    for violation in engine::check_invariants(&report.accounts) {
+        report.violations.push(violation.to_string());
+    }
+    if run(workload, config)?.accounts != report.accounts {
+        report
+            .violations
+            .push("processing the workload again yields different accounts".to_string());
+    }
+    Ok(report)
+}
+
+/// Processes `workload` and checks that the balances add up to the applied
+/// deposits, withdrawals and chargebacks.
+fn run(workload: &str, config: EngineConfig) -> Result<SimulationReport> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(workload.as_bytes());
+    let mut engine = Engine::new(config);
+    let mut report = SimulationReport::default();
+    let mut expected_total = Amount::ZERO;
+    for result in reader.deserialize::<Transaction>() {
+        let tx = match result {
+            Ok(tx) => tx,
+            Err(err) if err.is_io_error() => return Err(err).context("Failed to read workload"),
+            Err(_) => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+        report.processed += 1;
+        let change = match engine.process(tx)? {
+            Decision::Deposit { amount, .. } => expected_total.checked_add(amount),
+            Decision::Withdraw { amount, .. } | Decision::Chargeback { amount, .. } => {
+                expected_total.checked_sub(amount)
+            }
+            Decision::Ignore(_) => {
+                report.ignored += 1;
+                Some(expected_total)
+            }
+            _ => Some(expected_total),
+        };
+        expected_total = change.context("Overflow in the expected total balance")?;
+    }
+    report.accounts = engine.into_accounts();
+    let total = report
+        .accounts
+        .values()
+        .try_fold(Amount::ZERO, |total, account| {
+            total.checked_add(account.total)
+        })
+        .context("Overflow in the total balance")?;
+    if total != expected_total {
+        report.violations.push(format!(
+            "the accounts total {}, but the applied transactions add up to {}",
+            total, expected_total
+        ));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulates_seeded_workloads_with_injected_faults() {
+        let config = SimulationConfig {
+            seed: 7,
+            rows: 2_000,
+            clients: 20,
+            ..Default::default()
+        };
+        let workload = generate_workload(&config);
+        assert_eq!(workload, generate_workload(&config));
+        assert_ne!(
+            workload.csv,
+            generate_workload(&SimulationConfig { seed: 8, ..config }).csv
+        );
+        assert_eq!(workload.csv.lines().count(), 2_001);
+        assert!(workload.malformed > 0 && workload.duplicates > 0 && workload.out_of_order > 0);
+
+        let report = simulate(&workload.csv, EngineConfig::default()).unwrap();
+        assert_eq!(report.violations, Vec::<String>::new());
+        assert_eq!(report.skipped, workload.malformed);
+        assert_eq!(report.processed, workload.rows - workload.malformed);
+        assert!(report.ignored > 0);
+        assert!(!report.accounts.is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! SQLite output (`sqlite` feature).
+//!
+//! [`write_accounts_to_sqlite`] stores the final account states, and optionally
+//! the per-client [`History`], as tables of an SQLite database so results can be
+//! queried directly instead of importing the CSV output. Existing tables are
+//! replaced, so writing to the same database again leaves only the latest run.
+//!
+//! Amounts are stored as `TEXT` in the same notation as the CSV output, since
+//! SQLite has no exact decimal type and `REAL` would round them. Casting is
+//! fine for ad-hoc queries, e.g. `SELECT * FROM accounts WHERE CAST(total AS REAL) < 0`.
+//!
+//! ```sql
+//! CREATE TABLE accounts (
+//!     client INTEGER PRIMARY KEY,
+//!     available TEXT NOT NULL,
+//!     held TEXT NOT NULL,
+//!     total TEXT NOT NULL,
+//!     locked INTEGER NOT NULL
+//! );
+//! CREATE TABLE history (
+//!     client INTEGER NOT NULL,
+//!     seq INTEGER NOT NULL,
+//!     tx INTEGER NOT NULL,
+//!     type TEXT NOT NULL,
+//!     amount TEXT NOT NULL,
+//!     available TEXT NOT NULL,
+//!     held TEXT NOT NULL,
+//!     total TEXT NOT NULL,
+//!     locked INTEGER NOT NULL,
+//!     PRIMARY KEY (client, seq)
+//! );
+//! ```
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, params};
+
+use crate::history::History;
+use crate::types::{Accounts, sorted_accounts};
+
+const SCHEMA: &str = "
+    DROP TABLE IF EXISTS accounts;
+    DROP TABLE IF EXISTS history;
+    CREATE TABLE accounts (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE INDEX accounts_locked ON accounts (locked);
+";
+
+const HISTORY_SCHEMA: &str = "
+    CREATE TABLE history (
+        client INTEGER NOT NULL,
+        seq INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL,
+        PRIMARY KEY (client, seq)
+    );
+    CREATE INDEX history_tx ON history (tx);
+";
+
+/// Writes `accounts`, and `history` if given, to the SQLite database at `path`.
+///
+/// The database is created if it does not exist. All tables are written in a
+/// single transaction, so readers never see a partially written run.
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be opened or written.
+pub fn write_accounts_to_sqlite(
+    accounts: &Accounts,
+    history: Option<&History>,
+    path: &str,
+) -> Result<()> {
+    let mut conn =
+        Connection::open(path).with_context(|| format!("Failed to open database: {}", path))?;
+    write_tables(&mut conn, accounts, history)
+        .with_context(|| format!("Failed to write accounts to: {}", path))
+}
+
+fn write_tables(
+    conn: &mut Connection,
+    accounts: &Accounts,
+    history: Option<&History>,
+) -> Result<()> {
+    let transaction = conn.transaction()?;
+    transaction.execute_batch(SCHEMA)?;
+
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO accounts (client, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for (client, account) in sorted_accounts(accounts) {
+            insert.execute(params![
+                client.0,
+                account.available.to_string(),
+                account.held.to_string(),
+                account.total.to_string(),
+                account.locked,
+            ])?;
+        }
+    }
+
+    if let Some(history) = history {
+        transaction.execute_batch(HISTORY_SCHEMA)?;
+        let mut insert = transaction.prepare(
+            "INSERT INTO history (client, seq, tx, type, amount, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for (client, entries) in history.clients() {
+            for (seq, entry) in entries.iter().enumerate() {
+                insert.execute(params![
+                    client.0,
+                    seq as i64,
+                    entry.tx.0,
+                    entry.tx_type.as_str(),
+                    entry.amount.to_string(),
+                    entry.available.to_string(),
+                    entry.held.to_string(),
+                    entry.total.to_string(),
+                    entry.locked,
+                ])?;
+            }
+        }
+    }
+
+    transaction.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::types::{Amount, ClientId, Transaction, TxId, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn writes_accounts_and_history_tables() {
+        let path = std::env::temp_dir().join(format!("accounts-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut engine = Engine::new(EngineConfig::default());
+        let mut history = History::default();
+        for (tx_type, client, tx, amount) in [
+            (TxType::Deposit, 1, 1, "10.5"),
+            (TxType::Deposit, 2, 2, "3"),
+            (TxType::Dispute, 2, 2, "0"),
+            (TxType::Chargeback, 2, 2, "0"),
+        ] {
+            let decision = engine
+                .process(Transaction {
+                    tx_type,
+                    client: ClientId(client),
+                    tx: TxId(tx),
+                    amount: Amount::from_str(amount).unwrap(),
+                })
+                .unwrap();
+            history.record(tx_type, &decision, engine.accounts());
+        }
+
+        // Writing twice replaces the tables instead of appending to them.
+        write_accounts_to_sqlite(engine.accounts(), None, path).unwrap();
+        write_accounts_to_sqlite(engine.accounts(), Some(&history), path).unwrap();
+
+        let conn = Connection::open(path).unwrap();
+        let accounts: Vec<(i64, String, String, bool)> = conn
+            .prepare("SELECT client, available, total, locked FROM accounts ORDER BY client")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            accounts,
+            vec![
+                (1, "10.5".to_string(), "10.5".to_string(), false),
+                (2, "0".to_string(), "0".to_string(), true),
+            ]
+        );
+
+        let types: Vec<String> = conn
+            .prepare("SELECT type FROM history WHERE client = 2 ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(types, vec!["deposit", "dispute", "chargeback"]);
+
+        drop(conn);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Processing statistics.
+//!
+//! [`Stats`] counts the transactions processed by the engine per transaction type
+//! and sums the funds moved. The engine keeps one instance for the whole run and
+//! one for the current processing period. [`StatsReport`] combines the counters
+//! with information only known at the end of a run (locked accounts, throughput,
+//! peak memory usage).
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::amount::AmountOps;
+use crate::engine::{Decision, IgnoreReason};
+use crate::types::{Accounts, Amount, TxType};
+
+/// Applied and ignored counts for one transaction type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TypeStats {
+    /// Number of transactions that changed the state.
+    pub applied: u64,
+    /// Number of transactions that were ignored.
+    pub ignored: u64,
+}
+
+/// Counters describing processed transactions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Stats<A = Amount> {
+    /// Number of transactions that changed the state.
+    pub applied: u64,
+    /// Number of transactions that were ignored.
+    pub ignored: u64,
+    /// Applied and ignored counts per transaction type.
+    pub by_type: BTreeMap<TxType, TypeStats>,
+    /// Ignored counts per reason.
+    pub ignored_by_reason: BTreeMap<IgnoreReason, u64>,
+    /// Sum of all applied deposits.
+    pub deposited: A,
+    /// Sum of all applied withdrawals.
+    pub withdrawn: A,
+    /// Sum of all funds placed on hold by disputes and holds.
+    pub held: A,
+    /// Sum of all held funds released by resolves and releases.
+    pub released: A,
+    /// Sum of all funds charged back.
+    pub charged_back: A,
+    /// Sum of all refunded funds.
+    pub refunded: A,
+    /// Sum of all authorized funds.
+    pub authorized: A,
+    /// Sum of all captured funds.
+    pub captured: A,
+    /// Sum of all credited interest.
+    pub interest: A,
+    /// Sum of all funds moved by reversals, in either direction.
+    pub reversed: A,
+    /// Sum of all starting balances of opened accounts.
+    pub opened: A,
+}
+
+impl<A: AmountOps> Stats<A> {
+    /// Updates the counters with a processed transaction of type `tx_type` and
+    /// the decision that was applied for it.
+    pub fn record(&mut self, tx_type: TxType, decision: &Decision<A>) {
+        let type_stats = self.by_type.entry(tx_type).or_default();
+        let moved = match decision {
+            Decision::Ignore(reason) => {
+                type_stats.ignored += 1;
+                self.ignored += 1;
+                *self.ignored_by_reason.entry(*reason).or_default() += 1;
+                return;
+            }
+            Decision::Deposit { .. } => Some(&mut self.deposited),
+            Decision::Withdraw { .. } => Some(&mut self.withdrawn),
+            Decision::Hold { .. } | Decision::EscrowHold { .. } => Some(&mut self.held),
+            Decision::Release { .. } | Decision::EscrowRelease { .. } => Some(&mut self.released),
+            Decision::Chargeback { .. } => Some(&mut self.charged_back),
+            Decision::Refund { .. } => Some(&mut self.refunded),
+            Decision::Authorize { .. } => Some(&mut self.authorized),
+            Decision::Capture { .. } => Some(&mut self.captured),
+            Decision::Interest { .. } => Some(&mut self.interest),
+            Decision::Reversal { .. } => Some(&mut self.reversed),
+            Decision::Open { .. } => Some(&mut self.opened),
+            Decision::Close { .. } => None,
+        };
+        if let Some(moved) = moved {
+            *moved = moved.saturating_add(decision.amount());
+        }
+        type_stats.applied += 1;
+        self.applied += 1;
+    }
+
+    /// Returns the total number of processed transactions.
+    pub fn transactions(&self) -> u64 {
+        self.applied + self.ignored
+    }
+
+    /// Returns the sum of all funds moved by applied transactions.
+    pub fn funds_moved(&self) -> A {
+        [
+            self.deposited,
+            self.withdrawn,
+            self.held,
+            self.released,
+            self.charged_back,
+            self.refunded,
+            self.authorized,
+            self.captured,
+            self.interest,
+            self.reversed,
+            self.opened,
+        ]
+        .into_iter()
+        .fold(A::ZERO, A::saturating_add)
+    }
+}
+
+/// Summary of a processing run.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsReport {
+    /// Total number of processed transactions.
+    pub transactions: u64,
+    /// Counters of the processed transactions.
+    #[serde(flatten)]
+    pub stats: Stats,
+    /// Sum of all funds moved by applied transactions.
+    pub funds_moved: Amount,
+    /// Number of accounts locked at the end of the run.
+    pub locked_accounts: usize,
+    /// Wall-clock processing time in seconds.
+    pub elapsed_secs: f64,
+    /// Processed transactions per second.
+    pub rows_per_sec: f64,
+    /// Approximate peak heap usage of the accounts and the deposit history in bytes.
+    pub peak_memory_bytes: usize,
+}
+
+impl StatsReport {
+    /// Builds the report for a run that produced `accounts` in `elapsed` time
+    /// with the engine using at most `peak_memory_bytes`.
+    pub fn new(
+        stats: Stats,
+        accounts: &Accounts,
+        elapsed: Duration,
+        peak_memory_bytes: usize,
+    ) -> Self {
+        let elapsed_secs = elapsed.as_secs_f64();
+        let transactions = stats.transactions();
+        let rows_per_sec = if elapsed_secs > 0.0 {
+            transactions as f64 / elapsed_secs
+        } else {
+            0.0
+        };
+
+        StatsReport {
+            transactions,
+            funds_moved: stats.funds_moved(),
+            locked_accounts: accounts.values().filter(|account| account.locked).count(),
+            elapsed_secs,
+            rows_per_sec,
+            peak_memory_bytes,
+            stats,
+        }
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "transactions: {} ({} applied, {} ignored)",
+            self.transactions, self.stats.applied, self.stats.ignored
+        )?;
+        for (tx_type, counts) in &self.stats.by_type {
+            writeln!(
+                f,
+                "  {:?}: {} applied, {} ignored",
+                tx_type, counts.applied, counts.ignored
+            )?;
+        }
+        if !self.stats.ignored_by_reason.is_empty() {
+            let reasons: Vec<String> = self
+                .stats
+                .ignored_by_reason
+                .iter()
+                .map(|(reason, count)| format!("{} {}", reason, count))
+                .collect();
+            writeln!(f, "ignored by reason: {}", reasons.join(", "))?;
+        }
+        writeln!(
+            f,
+            "funds moved: {} (deposited {}, withdrawn {}, held {}, released {}, charged back {}, \
+             refunded {}, authorized {}, captured {}, interest {}, reversed {}, opened {})",
+            self.funds_moved,
+            self.stats.deposited,
+            self.stats.withdrawn,
+            self.stats.held,
+            self.stats.released,
+            self.stats.charged_back,
+            self.stats.refunded,
+            self.stats.authorized,
+            self.stats.captured,
+            self.stats.interest,
+            self.stats.reversed,
+            self.stats.opened
+        )?;
+        writeln!(f, "locked accounts: {}", self.locked_accounts)?;
+        writeln!(
+            f,
+            "peak memory: {:.1} MiB (accounts and deposit history, approximate)",
+            self.peak_memory_bytes as f64 / (1024.0 * 1024.0)
+        )?;
+        write!(
+            f,
+            "elapsed: {:.3}s ({:.0} rows/sec)",
+            self.elapsed_secs, self.rows_per_sec
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountDetails, ClientId, TxId};
+    use std::str::FromStr;
+
+    #[test]
+    fn records_counts_per_type_and_funds_moved() {
+        let mut stats = Stats::default();
+        stats.record(
+            TxType::Deposit,
+            &Decision::Deposit {
+                client: ClientId(1),
+                tx: TxId(1),
+                amount: Amount::from_str("10.0").unwrap(),
+            },
+        );
+        stats.record(
+            TxType::Withdrawal,
+            &Decision::Withdraw {
+                client: ClientId(1),
+                tx: TxId(2),
+                amount: Amount::from_str("2.5").unwrap(),
+            },
+        );
+        stats.record(
+            TxType::Withdrawal,
+            &Decision::Ignore(IgnoreReason::InsufficientFunds),
+        );
+
+        assert_eq!(stats.transactions(), 3);
+        assert_eq!(stats.applied, 2);
+        assert_eq!(stats.ignored, 1);
+        assert_eq!(
+            stats.by_type[&TxType::Withdrawal],
+            TypeStats {
+                applied: 1,
+                ignored: 1
+            }
+        );
+        assert_eq!(
+            stats.ignored_by_reason,
+            BTreeMap::from([(IgnoreReason::InsufficientFunds, 1)])
+        );
+        assert_eq!(stats.funds_moved(), Amount::from_str("12.5").unwrap());
+    }
+
+    #[test]
+    fn report_counts_locked_accounts_and_throughput() {
+        let mut stats = Stats::default();
+        stats.record(
+            TxType::Dispute,
+            &Decision::Ignore(IgnoreReason::UnknownTransaction),
+        );
+        let accounts = Accounts::from([
+            (ClientId(1), AccountDetails::default()),
+            (
+                ClientId(2),
+                AccountDetails {
+                    locked: true,
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let report = StatsReport::new(stats, &accounts, Duration::from_millis(500), 3 << 19);
+
+        assert_eq!(report.transactions, 1);
+        assert_eq!(report.locked_accounts, 1);
+        assert_eq!(report.rows_per_sec, 2.0);
+        assert!(
+            report.to_string().contains("peak memory: 1.5 MiB"),
+            "{}",
+            report
+        );
+        assert!(
+            report
+                .to_string()
+                .contains("ignored by reason: unknown_transaction 1"),
+            "{}",
+            report
+        );
+    }
+}
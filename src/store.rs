@@ -0,0 +1,522 @@
+//! Persistent state stores for the engine.
+//!
+//! An [`Engine`] keeps every account and deposit in memory. A [`StoredEngine`]
+//! keeps them in a [`StateStore`] instead, such as the embedded database of
+//! `sled::SledStore` (`sled` feature), so the number of clients is bounded by
+//! disk rather than memory and the state survives restarts.
+//!
+//! [`EngineState`], the in-memory maps of an engine, is the default store, and
+//! [`InstrumentedStore`] wraps any store to count the operations on it.
+//!
+//! For every transaction, the stored engine loads the account of the client and
+//! the deposit with the transaction's ID into an engine, processes the
+//! transaction there and writes the changed records back, committing them
+//! together. A store shared by several processes, such as the Redis database of
+//! `redis::RedisStore` (`redis` feature), fails the commit with [`Conflict`] if
+//! another process changed the records in between, and the transaction is
+//! processed again on the new state. The engine therefore applies the same rules, hooks and client
+//! profiles as an in-memory one. Options that look at the state as a whole are
+//! not supported: idempotency keys, authorization expiry, the history retention
+//! window and the memory cap.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+use crate::engine::{Decision, DepositRecord, Engine, EngineConfig, EngineState};
+use crate::hook::TransactionHook;
+use crate::stats::Stats;
+use crate::types::{AccountDetails, Accounts, ClientId, Transaction, TxId};
+
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "sled")]
+pub mod sled;
+
+/// Number of times [`StoredEngine::process`] processes a transaction again
+/// after a [`Conflict`] before giving up.
+pub const MAX_CONFLICT_RETRIES: u32 = 16;
+
+/// Error of [`StateStore::commit`] when another process changed the records
+/// read since the last commit, so nothing was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conflict;
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the stored state was changed concurrently")
+    }
+}
+
+impl Error for Conflict {}
+
+/// Counters of the engine state that are kept next to the records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateCounters {
+    /// Number of deposits applied so far, see
+    /// [`EngineState::deposits`](crate::engine::EngineState::deposits).
+    pub deposits: u64,
+    /// Number of decisions applied so far, see
+    /// [`EngineState::transactions`](crate::engine::EngineState::transactions).
+    pub transactions: u64,
+}
+
+/// Storage of accounts and the deposit history, including the dispute state of
+/// every deposit.
+///
+/// Changes made with the `put_*` methods take effect together on
+/// [`StateStore::commit`]; reads may not see them before. A store shared with
+/// other processes fails the commit with [`Conflict`] if the records read since
+/// the last commit have changed.
+pub trait StateStore {
+    /// Returns the account of `client`, or `None` if it has none.
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>>;
+
+    /// Stores the account of `client`, replacing the previous one.
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()>;
+
+    /// Returns the deposit history record of `tx`, or `None` if there is none.
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>>;
+
+    /// Stores the deposit history record of `tx`, replacing the previous one.
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()>;
+
+    /// Returns the counters, all zero for an empty store.
+    fn get_counters(&self) -> Result<StateCounters>;
+
+    /// Stores the counters.
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()>;
+
+    /// Calls `f` with every account, in client order.
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()>;
+
+    /// Makes the changes since the last commit durable, all or none of them.
+    fn commit(&mut self) -> Result<()>;
+
+    /// Discards the changes since the last commit that have not taken effect
+    /// yet, after a failed read, write or commit.
+    fn rollback(&mut self) -> Result<()>;
+}
+
+impl<S: StateStore + ?Sized> StateStore for Box<S> {
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        (**self).get_account(client)
+    }
+
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()> {
+        (**self).put_account(client, account)
+    }
+
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        (**self).get_deposit(tx)
+    }
+
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()> {
+        (**self).put_deposit(tx, deposit)
+    }
+
+    fn get_counters(&self) -> Result<StateCounters> {
+        (**self).get_counters()
+    }
+
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()> {
+        (**self).put_counters(counters)
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()> {
+        (**self).for_each_account(f)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        (**self).commit()
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        (**self).rollback()
+    }
+}
+
+/// Keeps the records in memory; commits have nothing to do.
+impl StateStore for EngineState {
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        Ok(self.accounts.get(&client).cloned())
+    }
+
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()> {
+        self.accounts.insert(client, account.clone());
+        Ok(())
+    }
+
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        Ok(self.deposit_history.get(&tx).cloned())
+    }
+
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()> {
+        self.deposit_history.insert(tx, deposit.clone());
+        Ok(())
+    }
+
+    fn get_counters(&self) -> Result<StateCounters> {
+        Ok(StateCounters {
+            deposits: self.deposits,
+            transactions: self.transactions,
+        })
+    }
+
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()> {
+        self.deposits = counters.deposits;
+        self.transactions = counters.transactions;
+        Ok(())
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()> {
+        let mut clients: Vec<_> = self.accounts.keys().copied().collect();
+        clients.sort_unstable();
+        for client in clients {
+            f(client, self.accounts[&client].clone())?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Numbers of operations on an [`InstrumentedStore`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StoreMetrics {
+    /// Calls of [`StateStore::get_account`], [`StateStore::get_deposit`] and
+    /// [`StateStore::get_counters`].
+    pub reads: u64,
+    /// Calls of [`StateStore::put_account`], [`StateStore::put_deposit`] and
+    /// [`StateStore::put_counters`].
+    pub writes: u64,
+    /// Accounts passed to the callback of [`StateStore::for_each_account`].
+    pub scanned: u64,
+    /// Successful calls of [`StateStore::commit`].
+    pub commits: u64,
+    /// Calls of [`StateStore::commit`] that failed with a [`Conflict`].
+    pub conflicts: u64,
+    /// Calls of any other method that returned an error.
+    pub errors: u64,
+}
+
+/// A [`StateStore`] forwarding to another one and counting the operations on
+/// it, e.g. to see how many reads a workload costs before choosing a backend.
+#[derive(Debug, Default)]
+pub struct InstrumentedStore<S> {
+    inner: S,
+    metrics: std::cell::Cell<StoreMetrics>,
+}
+
+impl<S> InstrumentedStore<S> {
+    /// Wraps `inner` with all counters at zero.
+    pub fn new(inner: S) -> Self {
+        InstrumentedStore {
+            inner,
+            metrics: Default::default(),
+        }
+    }
+
+    /// Returns the counters so far.
+    pub fn metrics(&self) -> StoreMetrics {
+        self.metrics.get()
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Unwraps the wrapped store.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Counts an operation with `count` and its failure, passing the result on.
+    fn record<T>(&self, result: Result<T>, count: impl FnOnce(&mut StoreMetrics)) -> Result<T> {
+        let mut metrics = self.metrics.get();
+        match &result {
+            Ok(_) => count(&mut metrics),
+            Err(err) if err.is::<Conflict>() => metrics.conflicts += 1,
+            Err(_) => metrics.errors += 1,
+        }
+        self.metrics.set(metrics);
+        result
+    }
+}
+
+impl<S: StateStore> StateStore for InstrumentedStore<S> {
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        self.record(self.inner.get_account(client), |m| m.reads += 1)
+    }
+
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()> {
+        let result = self.inner.put_account(client, account);
+        self.record(result, |m| m.writes += 1)
+    }
+
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        self.record(self.inner.get_deposit(tx), |m| m.reads += 1)
+    }
+
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()> {
+        let result = self.inner.put_deposit(tx, deposit);
+        self.record(result, |m| m.writes += 1)
+    }
+
+    fn get_counters(&self) -> Result<StateCounters> {
+        self.record(self.inner.get_counters(), |m| m.reads += 1)
+    }
+
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()> {
+        let result = self.inner.put_counters(counters);
+        self.record(result, |m| m.writes += 1)
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()> {
+        let mut scanned = 0;
+        let result = self.inner.for_each_account(&mut |client, account| {
+            scanned += 1;
+            f(client, account)
+        });
+        self.record(result, |m| m.scanned += scanned)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let result = self.inner.commit();
+        self.record(result, |m| m.commits += 1)
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        let result = self.inner.rollback();
+        self.record(result, |_| {})
+    }
+}
+
+/// An engine keeping its accounts and deposit history in a [`StateStore`], see
+/// the [module documentation](self).
+pub struct StoredEngine<S = EngineState> {
+    engine: Engine,
+    store: S,
+    stats: Stats,
+}
+
+impl<S: StateStore> StoredEngine<S> {
+    /// Creates an engine continuing from the state in `store`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `config` enables an option the stored engine does
+    /// not support.
+    pub fn new(config: EngineConfig, store: S) -> Result<Self> {
+        let unsupported = [
+            ("idempotency keys", config.idempotency.is_some()),
+            (
+                "authorization expiry",
+                config.authorization_expiry.is_some(),
+            ),
+            ("a history retention", config.history_retention.is_some()),
+            ("a memory cap", config.max_memory.is_some()),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, used)| *used) {
+            anyhow::bail!("A state store does not support {}", option);
+        }
+        Ok(StoredEngine {
+            engine: Engine::new(config),
+            store,
+            stats: Stats::default(),
+        })
+    }
+
+    /// Adds a hook that may veto transactions before they are applied, see
+    /// [`Engine::add_hook`].
+    pub fn add_hook(&mut self, hook: impl TransactionHook + 'static) {
+        self.engine.add_hook(hook);
+    }
+
+    /// Returns the configuration the engine processes transactions with.
+    pub fn config(&self) -> &EngineConfig {
+        self.engine.config()
+    }
+
+    /// Returns the counters of the transactions processed by this engine,
+    /// which start from zero whatever the state of the store. Transactions
+    /// processed again after a [`Conflict`] are counted once.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    /// Returns the store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the account of `client`, or `None` if it has none.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read.
+    pub fn account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        self.store.get_account(client)
+    }
+
+    /// Reads all accounts from the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read.
+    pub fn accounts(&self) -> Result<Accounts> {
+        let mut accounts = Accounts::new();
+        self.store.for_each_account(&mut |client, account| {
+            accounts.insert(client, account);
+            Ok(())
+        })?;
+        Ok(accounts)
+    }
+
+    /// Applies a transaction to the stored state and returns the decision that
+    /// was applied, see [`Engine::process`].
+    ///
+    /// After a [`Conflict`], the transaction is processed again on the new
+    /// state, up to [`MAX_CONFLICT_RETRIES`] times, so hooks may see it more
+    /// than once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a balance calculation overflows, the store cannot
+    /// be read or written, or the commit still conflicts after the retries.
+    /// The stored state is left unchanged then.
+    pub fn process(&mut self, tx: Transaction) -> Result<Decision> {
+        let mut retries = 0;
+        loop {
+            let result = self.load(&tx).and_then(|()| {
+                let decision = self.engine.process(tx.clone())?;
+                self.store_changes(&decision)?;
+                Ok(decision)
+            });
+            let state = self.engine.state_mut();
+            state.accounts.clear();
+            state.deposit_history.clear();
+            match result {
+                Ok(decision) => {
+                    self.stats.record(tx.tx_type, &decision);
+                    return Ok(decision);
+                }
+                Err(err) => {
+                    self.store.rollback()?;
+                    if !err.is::<Conflict>() || retries == MAX_CONFLICT_RETRIES {
+                        return Err(err);
+                    }
+                    retries += 1;
+                }
+            }
+        }
+    }
+
+    /// Loads the records `tx` may refer to into the engine.
+    fn load(&mut self, tx: &Transaction) -> Result<()> {
+        let counters = self.store.get_counters()?;
+        let account = self.store.get_account(tx.client)?;
+        let deposit = self.store.get_deposit(tx.tx)?;
+        let state = self.engine.state_mut();
+        state.deposits = counters.deposits;
+        state.transactions = counters.transactions;
+        state
+            .accounts
+            .extend(account.map(|account| (tx.client, account)));
+        state
+            .deposit_history
+            .extend(deposit.map(|deposit| (tx.tx, deposit)));
+        Ok(())
+    }
+
+    /// Writes the records changed by `decision` to the store and commits them.
+    fn store_changes(&mut self, decision: &Decision) -> Result<()> {
+        let state = self.engine.state();
+        if !matches!(decision, Decision::Ignore(_)) {
+            for (client, account) in &state.accounts {
+                self.store.put_account(*client, account)?;
+            }
+            for (tx, deposit) in &state.deposit_history {
+                self.store.put_deposit(*tx, deposit)?;
+            }
+        }
+        self.store.put_counters(StateCounters {
+            deposits: state.deposits,
+            transactions: state.transactions,
+        })?;
+        self.store.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Amount, TxType};
+    use std::str::FromStr;
+
+    #[test]
+    fn in_memory_store_matches_the_engine_and_counts_operations() {
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let txs = [
+            tx(TxType::Deposit, 2, 1, "10"),
+            tx(TxType::Deposit, 1, 2, "5"),
+            tx(TxType::Withdrawal, 1, 3, "7"),
+            tx(TxType::Dispute, 2, 1, "0"),
+            tx(TxType::Resolve, 2, 1, "0"),
+        ];
+
+        let mut engine = Engine::default();
+        let store = InstrumentedStore::new(EngineState::default());
+        let mut stored = StoredEngine::new(EngineConfig::default(), store).unwrap();
+        for tx in txs {
+            assert_eq!(
+                stored.process(tx.clone()).unwrap(),
+                engine.process(tx).unwrap()
+            );
+        }
+        assert_eq!(stored.accounts().unwrap(), *engine.accounts());
+        assert_eq!(
+            stored.store().inner().deposit_history,
+            engine.state().deposit_history
+        );
+
+        // Three reads per transaction; the ignored withdrawal writes only the
+        // counters.
+        assert_eq!(
+            stored.store().metrics(),
+            StoreMetrics {
+                reads: 15,
+                writes: 4 * 3 + 1,
+                scanned: 2,
+                commits: 5,
+                conflicts: 0,
+                errors: 0,
+            }
+        );
+    }
+}
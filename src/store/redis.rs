@@ -0,0 +1,269 @@
+//! [`StateStore`] in a Redis database shared by several servers (`redis` feature).
+//!
+//! Servers behind a load balancer can share one database: each of them runs a
+//! [`StoredEngine`](super::StoredEngine) on its own connection. Reading a
+//! record `WATCH`es its key, and a commit writes the changes in a
+//! `MULTI`/`EXEC` transaction, which Redis aborts if another server changed a
+//! watched key in between. The commit then fails with [`Conflict`] and the
+//! transaction is processed again on the new state.
+//!
+//! Records are kept under keys starting with a prefix, `dh:` by default:
+//!
+//! - `dh:account:<client>` holds an account,
+//! - `dh:deposit:<tx>` holds a deposit history record,
+//! - `dh:clients` is a sorted set of the clients with an account,
+//! - `dh:counters` is a hash of the [`StateCounters`].
+//!
+//! Values are JSON. The counters are not watched but incremented, so they stay
+//! exact without making every pair of concurrent transactions conflict.
+
+use anyhow::{Context, Result};
+use redis::{Commands, Connection, Pipeline, Value};
+use serde::de::DeserializeOwned;
+use std::cell::{Cell, RefCell};
+
+use super::{Conflict, StateCounters, StateStore};
+use crate::engine::DepositRecord;
+use crate::types::{AccountDetails, ClientId, RawClientId, TxId};
+
+/// Default prefix of the keys, see [`RedisStore::prefix`].
+pub const DEFAULT_PREFIX: &str = "dh:";
+
+/// Number of accounts fetched at a time by [`StateStore::for_each_account`].
+const SCAN_CHUNK: usize = 1000;
+
+/// A [`StateStore`] in a Redis database.
+pub struct RedisStore {
+    connection: RefCell<Connection>,
+    prefix: String,
+    /// The changes since the last commit, as a `MULTI`/`EXEC` transaction.
+    pipeline: Pipeline,
+    /// The counters as last read, to increment them by the difference.
+    counters: Cell<StateCounters>,
+}
+
+impl RedisStore {
+    /// Connects to the database at `url`, e.g. `redis://127.0.0.1/0`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL is invalid or the connection fails.
+    pub fn connect(url: &str) -> Result<Self> {
+        let connection = redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .with_context(|| format!("Failed to connect to Redis: {}", url))?;
+        let mut pipeline = redis::pipe();
+        pipeline.atomic();
+        Ok(RedisStore {
+            connection: RefCell::new(connection),
+            prefix: DEFAULT_PREFIX.to_string(),
+            pipeline,
+            counters: Cell::default(),
+        })
+    }
+
+    /// Keeps the records under keys starting with `prefix` instead of
+    /// [`DEFAULT_PREFIX`], so several independent states can share a database.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, name: impl std::fmt::Display) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// Watches `key` and reads the record stored under it.
+    fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let mut connection = self.connection.borrow_mut();
+        redis::cmd("WATCH")
+            .arg(key)
+            .exec(&mut *connection)
+            .context("Failed to read state store")?;
+        let value: Option<Vec<u8>> = connection.get(key).context("Failed to read state store")?;
+        value
+            .map(|value| serde_json::from_slice(&value).context("Failed to parse stored record"))
+            .transpose()
+    }
+}
+
+impl StateStore for RedisStore {
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        self.get(&self.key(format_args!("account:{}", client.0)))
+    }
+
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()> {
+        let key = self.key(format_args!("account:{}", client.0));
+        let clients = self.key("clients");
+        self.pipeline
+            .set(key, serde_json::to_vec(account)?)
+            .ignore()
+            .zadd(clients, client.0, client.0)
+            .ignore();
+        Ok(())
+    }
+
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        self.get(&self.key(format_args!("deposit:{}", tx.0)))
+    }
+
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()> {
+        let key = self.key(format_args!("deposit:{}", tx.0));
+        self.pipeline
+            .set(key, serde_json::to_vec(deposit)?)
+            .ignore();
+        Ok(())
+    }
+
+    fn get_counters(&self) -> Result<StateCounters> {
+        let (deposits, transactions): (Option<u64>, Option<u64>) = self
+            .connection
+            .borrow_mut()
+            .hget(self.key("counters"), &["deposits", "transactions"])
+            .context("Failed to read state store")?;
+        let counters = StateCounters {
+            deposits: deposits.unwrap_or_default(),
+            transactions: transactions.unwrap_or_default(),
+        };
+        self.counters.set(counters);
+        Ok(counters)
+    }
+
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()> {
+        let read = self.counters.get();
+        let key = self.key("counters");
+        for (field, delta) in [
+            ("deposits", counters.deposits.wrapping_sub(read.deposits)),
+            (
+                "transactions",
+                counters.transactions.wrapping_sub(read.transactions),
+            ),
+        ] {
+            if delta != 0 {
+                self.pipeline.hincr(&key, field, delta as i64).ignore();
+            }
+        }
+        self.counters.set(counters);
+        Ok(())
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()> {
+        let mut connection = self.connection.borrow_mut();
+        let clients: Vec<RawClientId> = connection
+            .zrange(self.key("clients"), 0, -1)
+            .context("Failed to read state store")?;
+        for chunk in clients.chunks(SCAN_CHUNK) {
+            let keys: Vec<_> = chunk
+                .iter()
+                .map(|client| self.key(format_args!("account:{}", client)))
+                .collect();
+            let values: Vec<Option<Vec<u8>>> = redis::cmd("MGET")
+                .arg(&keys)
+                .query(&mut *connection)
+                .context("Failed to read state store")?;
+            for (&client, value) in chunk.iter().zip(values) {
+                // An account is written together with its client, so a
+                // missing one can only be a record deleted by hand.
+                let Some(value) = value else { continue };
+                let account =
+                    serde_json::from_slice(&value).context("Failed to parse stored record")?;
+                f(ClientId(client), account)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.pipeline.is_empty() {
+            // Nothing to write, but the watched keys must be released.
+            return self.rollback();
+        }
+        let result: Result<Value> = self
+            .pipeline
+            .query(&mut *self.connection.borrow_mut())
+            .context("Failed to write state store");
+        self.pipeline.clear();
+        if result? == Value::Nil {
+            return Err(Conflict.into());
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.pipeline.clear();
+        redis::cmd("UNWATCH")
+            .exec(&mut *self.connection.borrow_mut())
+            .context("Failed to reset state store connection")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Decision, EngineConfig};
+    use crate::store::StoredEngine;
+    use crate::types::{Amount, Transaction, TxType};
+    use std::str::FromStr;
+
+    /// Runs against the database in `DIAMOND_HANDS_REDIS_URL`, and is skipped
+    /// when it is not set.
+    #[test]
+    fn servers_share_state_and_retry_conflicting_commits() {
+        let Ok(url) = std::env::var("DIAMOND_HANDS_REDIS_URL") else {
+            return;
+        };
+        let prefix = format!("test-{}:", std::process::id());
+        let connect = || RedisStore::connect(&url).unwrap().prefix(&prefix);
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let mut first = StoredEngine::new(EngineConfig::default(), connect()).unwrap();
+        let mut second = StoredEngine::new(EngineConfig::default(), connect()).unwrap();
+
+        first.process(tx(TxType::Deposit, 1, 1, "10")).unwrap();
+        second.process(tx(TxType::Deposit, 1, 2, "5")).unwrap();
+        second.process(tx(TxType::Dispute, 1, 1, "0")).unwrap();
+        assert!(matches!(
+            first.process(tx(TxType::Withdrawal, 1, 3, "6")).unwrap(),
+            Decision::Ignore(_)
+        ));
+
+        // A change between reading and committing aborts the commit.
+        let mut store = connect();
+        store.get_account(ClientId(1)).unwrap();
+        second.process(tx(TxType::Resolve, 1, 1, "0")).unwrap();
+        store
+            .put_account(ClientId(1), &AccountDetails::default())
+            .unwrap();
+        assert!(store.commit().unwrap_err().is::<Conflict>());
+        assert_eq!(
+            second.account(ClientId(1)).unwrap().unwrap().held,
+            Amount::from_str("0").unwrap()
+        );
+
+        first.process(tx(TxType::Withdrawal, 1, 3, "6")).unwrap();
+        let accounts = first.accounts().unwrap();
+        assert_eq!(accounts[&ClientId(1)].total, Amount::from_str("9").unwrap());
+        assert_eq!(
+            first.store().get_counters().unwrap(),
+            StateCounters {
+                deposits: 2,
+                transactions: 6
+            }
+        );
+
+        let keys: Vec<String> = store
+            .connection
+            .borrow_mut()
+            .keys(format!("{}*", prefix))
+            .unwrap();
+        let _: () = store.connection.borrow_mut().del(keys).unwrap();
+    }
+}
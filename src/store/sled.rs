@@ -0,0 +1,224 @@
+//! [`StateStore`] in an embedded sled database (`sled` feature).
+//!
+//! All records live in the default tree of the database, under keys made of a
+//! one-byte prefix and a big-endian ID, so accounts are scanned in client
+//! order:
+//!
+//! - `a<client>` holds an account,
+//! - `d<tx>` holds a deposit history record,
+//! - `counters` holds the [`StateCounters`].
+//!
+//! Values are JSON. A commit applies the changes as one atomic batch and flushes
+//! the database, so a transaction that was answered survives a crash.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+use super::{StateCounters, StateStore};
+use crate::engine::DepositRecord;
+use crate::types::{AccountDetails, ClientId, RawClientId, TxId};
+
+const ACCOUNT_PREFIX: u8 = b'a';
+const DEPOSIT_PREFIX: u8 = b'd';
+const COUNTERS_KEY: &[u8] = b"counters";
+
+/// A [`StateStore`] in a sled database directory.
+pub struct SledStore {
+    db: ::sled::Db,
+    batch: ::sled::Batch,
+}
+
+impl SledStore {
+    /// Opens the database in the directory at `path`, creating it if needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened, e.g. because another
+    /// process has it open.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        // Commits flush on their own, so no background flusher is needed.
+        let db = ::sled::Config::new()
+            .path(path)
+            .flush_every_ms(None)
+            .open()
+            .with_context(|| format!("Failed to open state store: {}", path.display()))?;
+        Ok(SledStore {
+            db,
+            batch: ::sled::Batch::default(),
+        })
+    }
+
+    fn get<T: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<T>> {
+        self.db
+            .get(key)
+            .context("Failed to read state store")?
+            .map(|value| serde_json::from_slice(&value).context("Failed to parse stored record"))
+            .transpose()
+    }
+
+    fn put<T: Serialize>(&mut self, key: &[u8], value: &T) -> Result<()> {
+        self.batch.insert(key, serde_json::to_vec(value)?);
+        Ok(())
+    }
+}
+
+fn account_key(client: ClientId) -> Vec<u8> {
+    [&[ACCOUNT_PREFIX][..], &client.0.to_be_bytes()].concat()
+}
+
+fn deposit_key(tx: TxId) -> Vec<u8> {
+    [&[DEPOSIT_PREFIX][..], &tx.0.to_be_bytes()].concat()
+}
+
+impl StateStore for SledStore {
+    fn get_account(&self, client: ClientId) -> Result<Option<AccountDetails>> {
+        self.get(&account_key(client))
+    }
+
+    fn put_account(&mut self, client: ClientId, account: &AccountDetails) -> Result<()> {
+        self.put(&account_key(client), account)
+    }
+
+    fn get_deposit(&self, tx: TxId) -> Result<Option<DepositRecord>> {
+        self.get(&deposit_key(tx))
+    }
+
+    fn put_deposit(&mut self, tx: TxId, deposit: &DepositRecord) -> Result<()> {
+        self.put(&deposit_key(tx), deposit)
+    }
+
+    fn get_counters(&self) -> Result<StateCounters> {
+        Ok(self.get(COUNTERS_KEY)?.unwrap_or_default())
+    }
+
+    fn put_counters(&mut self, counters: StateCounters) -> Result<()> {
+        self.put(COUNTERS_KEY, &counters)
+    }
+
+    fn for_each_account(
+        &self,
+        f: &mut dyn FnMut(ClientId, AccountDetails) -> Result<()>,
+    ) -> Result<()> {
+        for entry in self.db.scan_prefix([ACCOUNT_PREFIX]) {
+            let (key, value) = entry.context("Failed to read state store")?;
+            let id = key[1..]
+                .try_into()
+                .map(RawClientId::from_be_bytes)
+                .context("Invalid account key in state store")?;
+            let account =
+                serde_json::from_slice(&value).context("Failed to parse stored record")?;
+            f(ClientId(id), account)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.db
+            .apply_batch(std::mem::take(&mut self.batch))
+            .context("Failed to write state store")?;
+        self.db.flush().context("Failed to flush state store")?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.batch = ::sled::Batch::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{Engine, EngineConfig};
+    use crate::store::StoredEngine;
+    use crate::types::{Amount, Transaction, TxType};
+    use std::str::FromStr;
+
+    /// Opens the store at `dir`, waiting for the background threads of a
+    /// dropped handle to release the lock on it.
+    fn reopen(dir: &Path) -> SledStore {
+        for _ in 0..100 {
+            if let Ok(store) = SledStore::open(dir) {
+                return store;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        SledStore::open(dir).unwrap()
+    }
+
+    #[test]
+    fn stored_engine_matches_the_in_memory_engine_across_restarts() {
+        let dir = std::env::temp_dir().join(format!("sled-store-{}", std::process::id()));
+        let tx = |tx_type, client, tx, amount: &str| Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        };
+        let days = [
+            vec![
+                tx(TxType::Deposit, 1, 1, "10"),
+                tx(TxType::Deposit, 2, 2, "5"),
+                tx(TxType::Withdrawal, 2, 3, "7"),
+                tx(TxType::Dispute, 1, 1, "0"),
+            ],
+            vec![
+                tx(TxType::Deposit, 300, 4, "1.5"),
+                tx(TxType::Dispute, 2, 1, "0"),
+                tx(TxType::Chargeback, 1, 1, "0"),
+                tx(TxType::Deposit, 1, 5, "1"),
+            ],
+        ];
+
+        let mut engine = Engine::default();
+        let mut decisions = Vec::new();
+        for tx in days.iter().flatten() {
+            decisions.push(engine.process(tx.clone()).unwrap());
+        }
+
+        let mut stored_decisions = Vec::new();
+        for day in &days {
+            let store = reopen(&dir);
+            let mut stored = StoredEngine::new(EngineConfig::default(), store).unwrap();
+            for tx in day {
+                stored_decisions.push(stored.process(tx.clone()).unwrap());
+            }
+            assert_eq!(stored.engine.state().accounts.len(), 0);
+        }
+        assert_eq!(stored_decisions, decisions);
+
+        let store = reopen(&dir);
+        let mut clients = Vec::new();
+        store
+            .for_each_account(&mut |client, _| {
+                clients.push(client);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(clients, [ClientId(1), ClientId(2), ClientId(300)]);
+        let stored = StoredEngine::new(EngineConfig::default(), store).unwrap();
+        assert_eq!(
+            stored.store().get_counters().unwrap(),
+            StateCounters {
+                deposits: engine.state().deposits,
+                transactions: engine.state().transactions
+            }
+        );
+        assert_eq!(stored.accounts().unwrap(), engine.into_accounts());
+
+        assert!(
+            StoredEngine::new(
+                EngineConfig {
+                    max_memory: Some(1 << 20),
+                    ..Default::default()
+                },
+                stored.store
+            )
+            .is_err()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
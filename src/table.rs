@@ -0,0 +1,274 @@
+//! Human-readable table output of accounts.
+//!
+//! [`write_accounts_as_table`] writes the accounts as an aligned table followed
+//! by a totals row, for operators looking at small runs in a terminal:
+//!
+//! ```text
+//! client  available  held  total  locked
+//! ------  ---------  ----  -----  ------
+//!      1        1.5     0    1.5   false
+//!      2       -2       0   -2      true
+//! ------  ---------  ----  -----  ------
+//!  total       -0.5     0   -0.5       1
+//! ```
+//!
+//! Balances are aligned on the decimal point. The totals row sums the balances
+//! and counts the locked accounts. With colors, the header and totals are bold,
+//! locked accounts are red and negative available balances yellow.
+
+use anyhow::{Context, Result};
+use std::io::{IsTerminal, Write};
+
+use crate::io::OutputOptions;
+use crate::types::{Accounts, Amount, sorted_accounts};
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// When to color the table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When writing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    /// Always
+    Always,
+    /// Never
+    Never,
+}
+
+impl ColorChoice {
+    /// Every choice, in declaration order.
+    pub const ALL: [ColorChoice; 3] = [ColorChoice::Auto, ColorChoice::Always, ColorChoice::Never];
+
+    /// Returns the name of the choice, e.g. `never`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        }
+    }
+
+    /// Returns whether output to `stream` should be colored.
+    pub fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                stream.is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    /// Parses the name returned by [`ColorChoice::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ColorChoice::ALL
+            .into_iter()
+            .find(|choice| choice.as_str() == value)
+            .ok_or_else(|| format!("unknown color choice '{}'", value))
+    }
+}
+
+impl std::fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One cell of the table: its text and how to align it.
+enum Cell {
+    /// Right-aligned text.
+    Text(String),
+    /// An amount, aligned on its decimal point.
+    Amount(String),
+}
+
+impl Cell {
+    fn amount(amount: Amount) -> Cell {
+        Cell::Amount(amount.to_string())
+    }
+
+    /// Returns the widths of the integer and fractional part (including the
+    /// decimal point), or the text width and zero for text cells.
+    fn widths(&self) -> (usize, usize) {
+        match self {
+            Cell::Text(text) => (text.chars().count(), 0),
+            Cell::Amount(text) => {
+                let integer = text.find('.').unwrap_or(text.len());
+                (integer, text.len() - integer)
+            }
+        }
+    }
+}
+
+/// Writes `accounts` as an aligned table with a totals row, in ascending client
+/// order.
+///
+/// The header uses the custom header names and the `locked` column the boolean
+/// encoding of `options`; the delimiter is ignored. With `color`, ANSI escape
+/// sequences highlight the header, totals, locked accounts and negative
+/// available balances.
+///
+/// # Errors
+///
+/// Returns an error if a custom header name refers to an unknown column or if
+/// writing fails.
+pub fn write_accounts_as_table<W: Write>(
+    accounts: &Accounts,
+    mut writer: W,
+    options: &OutputOptions,
+    color: bool,
+) -> Result<()> {
+    let header: Vec<Cell> = options
+        .headers()?
+        .into_iter()
+        .map(|name| Cell::Text(name.to_string()))
+        .collect();
+
+    let mut rows = Vec::with_capacity(accounts.len());
+    let mut totals = [Amount::ZERO; 3];
+    let mut locked = 0usize;
+    for (client, account) in sorted_accounts(accounts) {
+        let balances = [account.available, account.held, account.total];
+        for (total, balance) in totals.iter_mut().zip(balances) {
+            *total = total.saturating_add(balance);
+        }
+        locked += usize::from(account.locked);
+
+        let style = if account.locked {
+            Some(RED)
+        } else if account.available < Amount::ZERO {
+            Some(YELLOW)
+        } else {
+            None
+        };
+        let row = vec![
+            Cell::Text(client.to_string()),
+            Cell::amount(account.available),
+            Cell::amount(account.held),
+            Cell::amount(account.total),
+            Cell::Text(options.bool_style.format(account.locked).to_string()),
+        ];
+        rows.push((row, style));
+    }
+    let totals = vec![
+        Cell::Text("total".to_string()),
+        Cell::amount(totals[0]),
+        Cell::amount(totals[1]),
+        Cell::amount(totals[2]),
+        Cell::Text(locked.to_string()),
+    ];
+
+    // Integer and fractional width of each column; text cells only use the first.
+    let mut widths = [(0usize, 0usize); 5];
+    for row in rows.iter().map(|(row, _)| row).chain([&totals]) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            let (integer, fraction) = cell.widths();
+            width.0 = width.0.max(integer);
+            width.1 = width.1.max(fraction);
+        }
+    }
+    for (width, cell) in widths.iter_mut().zip(&header) {
+        let (name, _) = cell.widths();
+        // Widen the integer part so that the name fits above the amounts.
+        width.0 = width.0.max(name.saturating_sub(width.1));
+    }
+
+    let separator = widths
+        .iter()
+        .map(|(integer, fraction)| "-".repeat(integer + fraction))
+        .collect::<Vec<_>>()
+        .join("  ");
+    let bold = color.then_some(BOLD);
+
+    write_row(&mut writer, &header, &widths, bold)?;
+    writeln!(writer, "{}", separator)?;
+    for (row, style) in &rows {
+        write_row(&mut writer, row, &widths, style.filter(|_| color))?;
+    }
+    writeln!(writer, "{}", separator)?;
+    write_row(&mut writer, &totals, &widths, bold)?;
+    writer.flush().context("Failed to write account table")
+}
+
+fn write_row<W: Write>(
+    writer: &mut W,
+    row: &[Cell],
+    widths: &[(usize, usize)],
+    style: Option<&str>,
+) -> Result<()> {
+    let line = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, &(integer, fraction))| match cell {
+            Cell::Text(text) => format!("{:>width$}", text, width = integer + fraction),
+            Cell::Amount(text) => {
+                let split = text.find('.').unwrap_or(text.len());
+                let (whole, decimals) = text.split_at(split);
+                format!("{:>integer$}{:<fraction$}", whole, decimals)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("  ");
+    match style {
+        Some(style) => writeln!(writer, "{}{}{}", style, line, RESET),
+        None => writeln!(writer, "{}", line),
+    }
+    .context("Failed to write account table")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountDetails, ClientId};
+    use std::str::FromStr;
+
+    fn render(accounts: &Accounts, color: bool) -> String {
+        let mut output = Vec::new();
+        write_accounts_as_table(accounts, &mut output, &OutputOptions::default(), color).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn accounts() -> Accounts {
+        let account = |available: &str, total: &str, locked| AccountDetails {
+            available: Amount::from_str(available).unwrap(),
+            total: Amount::from_str(total).unwrap(),
+            locked,
+            ..Default::default()
+        };
+        let mut accounts = Accounts::new();
+        accounts.insert(ClientId(2), account("-2", "-2", true));
+        accounts.insert(ClientId(1), account("1.5", "1.5", false));
+        accounts
+    }
+
+    #[test]
+    fn aligns_balances_on_the_decimal_point_and_adds_totals() {
+        assert_eq!(
+            render(&accounts(), false),
+            "client  available  held  total  locked\n\
+             ------  ---------  ----  -----  ------\n\
+             \x20    1        1.5     0    1.5   false\n\
+             \x20    2       -2       0   -2      true\n\
+             ------  ---------  ----  -----  ------\n\
+             \x20total       -0.5     0   -0.5       1\n"
+        );
+    }
+
+    #[test]
+    fn colors_header_totals_and_locked_accounts() {
+        let table = render(&accounts(), true);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with(BOLD) && lines[0].ends_with(RESET));
+        assert!(!lines[2].contains('\x1b'));
+        assert!(lines[3].starts_with(RED));
+        assert!(lines[5].starts_with(BOLD));
+    }
+}
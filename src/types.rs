@@ -6,14 +6,20 @@
 //!
 //! # Type Aliases
 //!
-//! - [`ClientId`]: Type alias for client identifiers (u16)
-//! - [`TxId`]: Type alias for transaction identifiers (u32)
-//! - [`Amount`]: Type alias for monetary amounts (Decimal)
-//! - [`Accounts`]: Type alias for the collection of accounts (BTreeMap<ClientId, AccountDetails>)
+//! - [`Amount`]: Type alias for monetary amounts (Decimal, or [`FixedPoint`](crate::fixed_point::FixedPoint)
+//!   with the `fixed-point` feature)
+//! - [`Accounts`]: Type alias for the collection of accounts (HashMap<ClientId, AccountDetails>)
+//!
+//! [`Transaction`], [`AccountDetails`] and [`Accounts`] are generic over the
+//! monetary type (see [`AmountOps`]) and default to [`Amount`].
 //!
 //! # Core Types
 //!
+//! - [`ClientId`]: Client identifier, a newtype around `u16` (`u32` with the `wide-ids` feature)
+//! - [`TxId`]: Transaction identifier, a newtype around `u32` (`u64` with the `wide-ids` feature)
+//! - [`Date`]: Calendar date of value-dated transactions
 //! - [`TxType`]: Enumeration of all possible transaction types (deposit, withdrawal, dispute, resolve, chargeback)
+//! - [`DisputeState`]: The dispute lifecycle state of a deposit
 //! - [`Transaction`]: Represents a single financial transaction with type, client, ID, and amount
 //! - [`AccountDetails`]: Represents the current state of a client's account (balances and lock status)
 //!
@@ -29,40 +35,250 @@
 //!
 //! Creating a deposit transaction:
 //! ```
-//! use project_diamond_hands::types::{Transaction, TxType};
-//! use rust_decimal::Decimal;
+//! use project_diamond_hands::types::{Amount, ClientId, Transaction, TxId, TxType};
 //! use std::str::FromStr;
 //!
 //! let tx = Transaction {
 //!     tx_type: TxType::Deposit,
-//!     client: 1,
-//!     tx: 100,
-//!     amount: Decimal::from_str("10.50").unwrap(),
+//!     client: ClientId(1),
+//!     tx: TxId(100),
+//!     amount: Amount::from_str("10.50").unwrap(),
 //! };
 //! ```
 //!
 //! Creating an account with initial balance:
 //! ```
-//! use project_diamond_hands::types::AccountDetails;
-//! use rust_decimal::Decimal;
+//! use project_diamond_hands::types::{AccountDetails, Amount};
 //! use std::str::FromStr;
 //!
 //! let account = AccountDetails::new_with_balance(
-//!     Decimal::from_str("100.00").unwrap()
+//!     Amount::from_str("100.00").unwrap()
 //! );
 //! ```
 
-use rust_decimal::Decimal;
-use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fmt;
+use std::num::ParseIntError;
 use std::str::FromStr;
 
-pub type ClientId = u16;
-pub type TxId = u32;
-pub type Amount = Decimal;
-pub type Accounts = BTreeMap<ClientId, AccountDetails>;
+use crate::amount::{AmountOps, ParseAmount, deserialize_amount_or_zero};
+
+#[cfg(not(feature = "fixed-point"))]
+pub type Amount = rust_decimal::Decimal;
+#[cfg(feature = "fixed-point")]
+pub type Amount = crate::fixed_point::FixedPoint;
+/// Accounts by client.
+///
+/// The collection is unordered for fast lookups; writers sort by client ID so
+/// the output order stays stable.
+pub type Accounts<A = Amount> = HashMap<ClientId, AccountDetails<A>>;
+
+/// The integer type underlying [`ClientId`].
+#[cfg(not(feature = "wide-ids"))]
+pub type RawClientId = u16;
+#[cfg(feature = "wide-ids")]
+pub type RawClientId = u32;
+/// The integer type underlying [`TxId`].
+#[cfg(not(feature = "wide-ids"))]
+pub type RawTxId = u32;
+#[cfg(feature = "wide-ids")]
+pub type RawTxId = u64;
+
+/// Identifies a client.
+///
+/// Serialized as the plain number, so `ClientId(7)` reads and writes as `7`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct ClientId(pub RawClientId);
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseIntError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        input.parse().map(ClientId)
+    }
+}
+
+impl From<RawClientId> for ClientId {
+    fn from(id: RawClientId) -> Self {
+        ClientId(id)
+    }
+}
+
+impl From<ClientId> for RawClientId {
+    fn from(id: ClientId) -> Self {
+        id.0
+    }
+}
+
+/// Identifies a transaction.
+///
+/// Serialized as the plain number, so `TxId(42)` reads and writes as `42`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct TxId(pub RawTxId);
+
+impl fmt::Display for TxId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for TxId {
+    type Err = ParseIntError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        input.parse().map(TxId)
+    }
+}
+
+impl From<RawTxId> for TxId {
+    fn from(id: RawTxId) -> Self {
+        TxId(id)
+    }
+}
+
+impl From<TxId> for RawTxId {
+    fn from(id: TxId) -> Self {
+        id.0
+    }
+}
+
+/// A calendar date, written `YYYY-MM-DD`, such as the effective date of a
+/// value-dated transaction.
+///
+/// Dates order chronologically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    /// Returns the date, or `None` if it does not exist (e.g. February 30th).
+    pub fn new(year: u16, month: u8, day: u8) -> Option<Self> {
+        (1..=days_in_month(year, month)?)
+            .contains(&day)
+            .then_some(Date { year, month, day })
+    }
+
+    /// Returns the year.
+    pub fn year(self) -> u16 {
+        self.year
+    }
+
+    /// Returns the month, from 1 to 12.
+    pub fn month(self) -> u8 {
+        self.month
+    }
+
+    /// Returns the day of the month, starting at 1.
+    pub fn day(self) -> u8 {
+        self.day
+    }
+
+    /// Returns the number of days from `earlier` to this date, negative if
+    /// `earlier` is later.
+    pub fn days_since(self, earlier: Date) -> i64 {
+        self.day_number() - earlier.day_number()
+    }
+
+    /// Returns the number of days since 0000-03-01 in the proleptic Gregorian
+    /// calendar.
+    fn day_number(self) -> i64 {
+        // Years start in March, so the leap day is the last day of a year.
+        let (year, month) = match self.month {
+            1 | 2 => (i64::from(self.year) - 1, i64::from(self.month) + 9),
+            month => (i64::from(self.year), i64::from(month) - 3),
+        };
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        year * 365 + year.div_euclid(4) - year.div_euclid(100) + year.div_euclid(400) + day_of_year
+    }
+
+    /// Returns the date `days` days later, or `None` after the year 9999.
+    pub fn add_days(self, days: u32) -> Option<Self> {
+        let Date {
+            mut year,
+            mut month,
+            day,
+        } = self;
+        let mut days = days.saturating_add(u32::from(day));
+        loop {
+            let length = u32::from(days_in_month(year, month)?);
+            if days <= length {
+                return Date::new(year, month, days as u8);
+            }
+            days -= length;
+            (year, month) = if month == 12 {
+                ((year < 9999).then_some(year + 1)?, 1)
+            } else {
+                (year, month + 1)
+            };
+        }
+    }
+}
+
+/// Returns the number of days of `month` in `year`, or `None` for an invalid month.
+fn days_in_month(year: u16, month: u8) -> Option<u8> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => Some(31),
+        4 | 6 | 9 | 11 => Some(30),
+        2 if leap => Some(29),
+        2 => Some(28),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid date '{}', expected YYYY-MM-DD", input);
+        let mut parts = input.split('-');
+        let (Some(year), Some(month), Some(day), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let digits =
+            |part: &str, len| part.len() == len && part.bytes().all(|b| b.is_ascii_digit());
+        if !digits(year, 4) || !digits(month, 2) || !digits(day, 2) {
+            return Err(invalid());
+        }
+        // Four and two ASCII digits always fit.
+        Date::new(
+            year.parse().unwrap_or_default(),
+            month.parse().unwrap_or_default(),
+            day.parse().unwrap_or_default(),
+        )
+        .ok_or_else(invalid)
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
 /// Represents the type of a financial transaction.
 ///
@@ -80,6 +296,7 @@ pub type Accounts = BTreeMap<ClientId, AccountDetails>;
 ///
 /// - **Dispute**: Initiates a dispute on a previous transaction. Moves funds
 ///   from available to held balance, freezing them until resolved or chargebacked.
+///   An optional amount disputes only part of the original deposit.
 ///   The total balance remains unchanged.
 ///
 /// - **Resolve**: Resolves a previously disputed transaction. Moves funds
@@ -89,7 +306,41 @@ pub type Accounts = BTreeMap<ClientId, AccountDetails>;
 /// - **Chargeback**: Finalizes a dispute by reversing the original transaction.
 ///   Withdraws funds from both held and total balance, and locks the account.
 ///   This is the final state of a dispute.
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy)]
+///
+/// - **Refund**: Returns a previous deposit, or the given part of it, to the
+///   merchant-side. Withdraws the funds from both available and total balance,
+///   without a dispute and without locking the account.
+///
+/// - **Authorize**: Reserves funds for a later capture, as in card payments.
+///   Increases the held and total balance.
+///
+/// - **Capture**: Settles a previous authorization, or the given part of it,
+///   moving the captured funds from held to available balance. Any remainder is
+///   released. The captured authorization then behaves like a deposit.
+///
+/// - **Close**: Closes the account if no funds are held. Closed accounts ignore
+///   all later transactions.
+///
+/// - **Hold**: Moves the given amount from available to held balance without
+///   referencing a previous transaction, e.g. for a manual risk hold.
+///
+/// - **Release**: Moves the given amount, or everything placed on hold by
+///   holds, from held back to available balance.
+///
+/// - **Interest**: Credits accrued interest to an existing account. Increases
+///   both available balance and total balance, but cannot be disputed.
+///
+/// - **Reversal**: Undoes a previous deposit, or a withdrawal recorded with
+///   [`EngineConfig::reversible_withdrawals`](crate::engine::EngineConfig::reversible_withdrawals),
+///   by moving its amount back. The reversed transaction cannot be disputed,
+///   refunded or reversed afterwards.
+///
+/// - **Open**: Opens the account of a new client with the amount as starting
+///   balance. The starting balance is available but cannot be disputed.
+///   Ignored if the client already has an account.
+///
+/// - **OpenLocked**: Like `Open`, but the account starts locked.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum TxType {
     Deposit,
@@ -97,6 +348,98 @@ pub enum TxType {
     Dispute,
     Resolve,
     Chargeback,
+    Refund,
+    Authorize,
+    Capture,
+    Close,
+    Hold,
+    Release,
+    Interest,
+    Reversal,
+    Open,
+    #[serde(rename = "open_locked")]
+    OpenLocked,
+}
+
+impl TxType {
+    /// Every transaction type, in declaration order.
+    pub const ALL: [TxType; 15] = [
+        TxType::Deposit,
+        TxType::Withdrawal,
+        TxType::Dispute,
+        TxType::Resolve,
+        TxType::Chargeback,
+        TxType::Refund,
+        TxType::Authorize,
+        TxType::Capture,
+        TxType::Close,
+        TxType::Hold,
+        TxType::Release,
+        TxType::Interest,
+        TxType::Reversal,
+        TxType::Open,
+        TxType::OpenLocked,
+    ];
+
+    /// Returns the name of the type as used in the input files, e.g. `deposit`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::Refund => "refund",
+            TxType::Authorize => "authorize",
+            TxType::Capture => "capture",
+            TxType::Close => "close",
+            TxType::Hold => "hold",
+            TxType::Release => "release",
+            TxType::Interest => "interest",
+            TxType::Reversal => "reversal",
+            TxType::Open => "open",
+            TxType::OpenLocked => "open_locked",
+        }
+    }
+}
+
+impl FromStr for TxType {
+    type Err = String;
+
+    /// Parses the name returned by [`TxType::as_str`].
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        TxType::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.as_str() == value)
+            .ok_or_else(|| format!("unknown transaction type '{}'", value))
+    }
+}
+
+impl fmt::Display for TxType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The dispute lifecycle state of a deposit.
+///
+/// A deposit starts `Undisputed`. A dispute moves it to `Disputed`, from where a
+/// resolve moves it to `Resolved` and a chargeback to the final `ChargedBack` state.
+/// Whether a `Resolved` deposit may be disputed again is configurable. An
+/// authorization is `Authorized` until its capture turns it into an `Undisputed`
+/// deposit. Withdrawals recorded for reversals are `Withdrawn`, and a reversal
+/// moves an undisputed or resolved deposit or a withdrawal to the final
+/// `Reversed` state.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeState {
+    Authorized,
+    Undisputed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Withdrawn,
+    Reversed,
 }
 
 /// Represents a single financial transaction.
@@ -107,87 +450,37 @@ pub enum TxType {
 ///
 /// # Fields
 ///
-/// - `tx_type`: The type of transaction (deposit, withdrawal, dispute, resolve, chargeback)
-/// - `client`: The client ID (u16) that this transaction affects
-/// - `tx`: A unique transaction ID (u32) used to reference this transaction
-/// - `amount`: The transaction amount (Decimal), automatically rounded to 4 decimal places
-///   during deserialization. Empty or missing values default to 0.
-#[derive(Debug, Serialize)]
-pub struct Transaction {
+/// - `tx_type`: The type of transaction (deposit, withdrawal, dispute, resolve,
+///   chargeback, refund, authorize, capture, close, hold, release, interest,
+///   reversal)
+/// - `client`: The client ID that this transaction affects
+/// - `tx`: A unique transaction ID used to reference this transaction
+/// - `amount`: The transaction amount. Amounts with more than 4 decimal places are
+///   rounded, truncated or rejected by the reader and the engine according to the
+///   [`PrecisionPolicy`](crate::amount::PrecisionPolicy). Empty or missing values
+///   default to 0. For disputes, refunds and captures a non-zero amount disputes,
+///   refunds or captures only that portion of the referenced transaction.
+#[derive(Debug, Clone, Serialize)]
+pub struct Transaction<A = Amount> {
     #[serde(rename = "type")]
     pub tx_type: TxType,
     pub client: ClientId,
     pub tx: TxId,
     #[serde(deserialize_with = "deserialize_amount_or_zero")]
-    pub amount: Amount,
+    pub amount: A,
 }
 
-/// Custom deserializer for transaction amount.
-///
-/// Handles empty strings and missing values by defaulting to Decimal::ZERO.
-/// This allows dispute, resolve, and chargeback transactions to omit the amount field.
-fn deserialize_amount_or_zero<'de, D>(deserializer: D) -> Result<Amount, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    struct AmountVisitor;
-
-    impl<'de> Visitor<'de> for AmountVisitor {
-        type Value = Decimal;
-
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a decimal number or empty string")
-        }
-
-        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            let trimmed = value.trim();
-            if trimmed.is_empty() {
-                return Ok(Decimal::ZERO);
-            }
-            Decimal::from_str(trimmed)
-                .map_err(|e| de::Error::custom(format!("invalid decimal: {}", e)))
-        }
-
-        fn visit_f64<E>(self, value: f64) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            Decimal::try_from(value)
-                .map_err(|e| de::Error::custom(format!("invalid decimal from float: {}", e)))
-        }
-
-        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            Ok(Decimal::from(value))
-        }
-
-        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
-        where
-            E: de::Error,
-        {
-            Ok(Decimal::from(value))
-        }
-    }
-
-    deserializer.deserialize_any(AmountVisitor)
+fn default_zero<A: AmountOps>() -> A {
+    A::ZERO
 }
 
-fn default_zero() -> Amount {
-    Decimal::ZERO
-}
-
-impl<'de> Deserialize<'de> for Transaction {
+impl<'de, A: AmountOps + ParseAmount> Deserialize<'de> for Transaction<A> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
         #[derive(Deserialize)]
-        struct TransactionHelper {
+        struct TransactionHelper<A: AmountOps + ParseAmount> {
             #[serde(rename = "type")]
             tx_type: TxType,
             client: ClientId,
@@ -196,7 +489,7 @@ impl<'de> Deserialize<'de> for Transaction {
                 deserialize_with = "deserialize_amount_or_zero",
                 default = "default_zero"
             )]
-            amount: Amount,
+            amount: A,
         }
 
         let helper = TransactionHelper::deserialize(deserializer)?;
@@ -217,27 +510,100 @@ impl<'de> Deserialize<'de> for Transaction {
 ///
 /// # Fields
 ///
-/// - `client`: The client ID (u16) that this account belongs to
+/// - `client`: The client ID that this account belongs to
 /// - `availabe`: The available balance - funds that can be withdrawn or used
 ///   (Note: This field name contains a typo but is kept for CSV compatibility)
 /// - `held`: The held balance - funds that are frozen due to an active dispute
 /// - `total`: The total balance - sum of available and held funds (available + held)
 /// - `locked`: Whether the account is locked (true) or unlocked (false).
 ///   Locked accounts cannot process new transactions and typically result from chargebacks.
-#[derive(Debug, Deserialize, Serialize, Default)]
-pub struct AccountDetails {
+/// - `closed`: Whether the account was closed, after which it ignores all
+///   transactions
+/// - `opened_locked`: Whether the account was locked when an `open_locked`
+///   transaction opened it, rather than by a chargeback
+/// - `escrow`: The part of `held` placed on hold by hold transactions, which
+///   only release transactions return
+/// - `chargebacks`: The number of chargebacks applied to the account, which lock
+///   it depending on the engine's lock policy
+/// - `metrics`: Activity counters, only maintained when the engine is configured
+///   to track them
+/// - `tier` and `currency`: The tier and home currency of the client, taken from
+///   the engine's client profiles
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct AccountDetails<A = Amount> {
     pub client: ClientId,
-    pub available: Amount,
-    pub held: Amount,
-    pub total: Amount,
+    pub available: A,
+    pub held: A,
+    pub total: A,
     pub locked: bool,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub opened_locked: bool,
+    #[serde(default)]
+    pub escrow: A,
+    #[serde(default)]
+    pub chargebacks: u32,
+    #[serde(default, skip_serializing_if = "AccountMetrics::is_empty")]
+    pub metrics: AccountMetrics,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+}
+
+/// Activity counters of an account, maintained by
+/// [`Engine::process`](crate::engine::Engine::process) when
+/// [`EngineConfig::account_metrics`](crate::engine::EngineConfig::account_metrics)
+/// is enabled.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct AccountMetrics {
+    /// Number of applied deposits.
+    pub deposits: u64,
+    /// Number of applied withdrawals.
+    pub withdrawals: u64,
+    /// Number of deposits that are currently disputed.
+    pub active_disputes: u64,
+    /// Number of applied chargebacks.
+    pub chargebacks: u64,
+    /// Number of disputes opened, including resolved and charged back ones.
+    #[serde(default)]
+    pub disputes: u64,
+    /// Number of withdrawals applied directly after a deposit, with no other
+    /// transaction of the account in between.
+    #[serde(default)]
+    pub deposit_withdrawal_cycles: u64,
+    /// Whether the last applied transaction of the account was a deposit.
+    #[serde(default)]
+    pub last_was_deposit: bool,
+    /// Position of the last transaction applied to the account among all
+    /// transactions the engine processed, counting from 1, or 0 if unknown.
+    pub last_activity: u64,
 }
 
-impl AccountDetails {
-    pub fn new_with_balance(balance: Amount) -> Self {
-        let mut new_account = AccountDetails::default();
-        new_account.available = balance;
-        new_account.total = balance;
-        new_account
+impl AccountMetrics {
+    /// Returns `true` if no activity was recorded.
+    pub fn is_empty(&self) -> bool {
+        *self == AccountMetrics::default()
     }
 }
+
+impl<A: AmountOps> AccountDetails<A> {
+    pub fn new_with_balance(balance: A) -> Self {
+        AccountDetails {
+            available: balance,
+            total: balance,
+            ..Default::default()
+        }
+    }
+}
+
+/// Returns the accounts ordered by ascending client ID.
+pub fn sorted_accounts<A>(accounts: &Accounts<A>) -> Vec<(ClientId, &AccountDetails<A>)> {
+    let mut sorted: Vec<_> = accounts
+        .iter()
+        .map(|(client, account)| (*client, account))
+        .collect();
+    sorted.sort_unstable_by_key(|(client, _)| *client);
+    sorted
+}
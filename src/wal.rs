@@ -0,0 +1,555 @@
+//! Write-ahead log for the `listen` server.
+//!
+//! With a [`WriteAheadLog`] the server appends every parsed transaction to the
+//! log, and syncs it to disk, before applying it. A transaction that was
+//! answered has therefore been persisted, and [`WriteAheadLog::recover`] rebuilds
+//! the engine after a crash by replaying the log.
+//!
+//! The log directory contains numbered segments and at most one snapshot:
+//!
+//! - `segment-<N>.wal` holds one transaction per line as CSV
+//!   (`deposit,1,1,10.5`). Once a segment reaches the configured size, appends
+//!   continue in the next segment.
+//! - `snapshot.json` holds the full engine state and the number of the first
+//!   segment it does not cover. Taking a snapshot deletes the covered segments,
+//!   so recovery only replays the transactions received since.
+//!
+//! A line cut short by a crash was never acknowledged; recovery drops it.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::engine::{DepositRecord, Engine, EngineConfig, EngineState, TransactionKey};
+use crate::types::{Accounts, Transaction, TxId};
+
+/// File name of the snapshot within the log directory.
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// Size and snapshot settings of a [`WriteAheadLog`].
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    /// Size in bytes after which appends continue in a new segment.
+    pub segment_size: u64,
+    /// Number of appended transactions after which [`WriteAheadLog::snapshot_due`]
+    /// returns `true`.
+    pub snapshot_interval: u64,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        WalConfig {
+            segment_size: 64 << 20,
+            snapshot_interval: 100_000,
+        }
+    }
+}
+
+/// The persisted engine state, as read from `snapshot.json`.
+#[derive(Deserialize)]
+struct Snapshot {
+    /// Number of the first segment that is not part of the snapshot.
+    segment: u64,
+    deposits: u64,
+    accounts: Accounts,
+    deposit_history: HashMap<TxId, DepositRecord>,
+    #[serde(default)]
+    transactions: u64,
+    #[serde(default)]
+    authorizations: BTreeSet<(u64, TxId)>,
+    #[serde(default)]
+    applied: HashSet<TransactionKey>,
+}
+
+impl Snapshot {
+    fn into_state(self) -> EngineState {
+        EngineState {
+            accounts: self.accounts,
+            deposit_history: self.deposit_history,
+            deposits: self.deposits,
+            transactions: self.transactions,
+            authorizations: self.authorizations,
+            applied: self.applied,
+        }
+    }
+}
+
+/// The persisted engine state, as written to `snapshot.json`.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    segment: u64,
+    deposits: u64,
+    accounts: &'a Accounts,
+    deposit_history: &'a HashMap<TxId, DepositRecord>,
+    transactions: u64,
+    authorizations: &'a BTreeSet<(u64, TxId)>,
+    #[serde(skip_serializing_if = "HashSet::is_empty")]
+    applied: &'a HashSet<TransactionKey>,
+}
+
+impl<'a> SnapshotRef<'a> {
+    fn new(engine: &'a Engine, segment: u64) -> Self {
+        let state = engine.state();
+        SnapshotRef {
+            segment,
+            deposits: state.deposits,
+            accounts: &state.accounts,
+            deposit_history: &state.deposit_history,
+            transactions: state.transactions,
+            authorizations: &state.authorizations,
+            applied: &state.applied,
+        }
+    }
+}
+
+/// An append-only, segmented log of received transactions.
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    config: WalConfig,
+    /// Number of the segment appends go to.
+    segment: u64,
+    file: File,
+    segment_bytes: u64,
+    /// Transactions appended since the last snapshot.
+    appended: u64,
+}
+
+impl WriteAheadLog {
+    /// Restores the engine state from the log in `dir` and opens the log for
+    /// appending.
+    ///
+    /// Loads the snapshot (if any) and replays the later segments in order. The
+    /// directory is created if it does not exist, which yields a new engine.
+    /// Transactions the engine failed to apply when they were received fail the
+    /// same way during the replay and are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be read or created, or if the
+    /// snapshot or a complete log line cannot be parsed.
+    pub fn recover(
+        dir: impl AsRef<Path>,
+        engine: EngineConfig,
+        config: WalConfig,
+    ) -> Result<(Engine, WriteAheadLog)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create log directory: {}", dir.display()))?;
+
+        let snapshot = read_snapshot(&dir)?;
+        let first_segment = snapshot.as_ref().map_or(0, |snapshot| snapshot.segment);
+        let mut engine = match snapshot {
+            Some(snapshot) => Engine::with_state(engine, snapshot.into_state()),
+            None => Engine::new(engine),
+        };
+
+        let segments = list_segments(&dir)?;
+        let mut replayed = 0u64;
+        for &segment in &segments {
+            let path = segment_path(&dir, segment);
+            if segment < first_segment {
+                // Covered by the snapshot, but not yet deleted when it was taken.
+                remove_file(&path)?;
+                continue;
+            }
+            for tx in read_segment(&path)? {
+                replayed += 1;
+                if let Err(err) = engine.process(tx) {
+                    tracing::warn!(
+                        error = format!("{:#}", err),
+                        "failed to apply logged transaction"
+                    );
+                }
+            }
+        }
+        tracing::info!(
+            dir = %dir.display(),
+            replayed,
+            "recovered from write-ahead log"
+        );
+
+        let segment = segments
+            .last()
+            .map_or(first_segment, |last| (last + 1).max(first_segment));
+        let file = create_segment(&dir, segment)?;
+        let wal = WriteAheadLog {
+            dir,
+            config,
+            segment,
+            file,
+            segment_bytes: 0,
+            appended: 0,
+        };
+        Ok((engine, wal))
+    }
+
+    /// Appends `tx` to the log and syncs it to disk.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or syncing fails; the transaction must not
+    /// be applied in that case.
+    pub fn append(&mut self, tx: &Transaction) -> Result<()> {
+        if self.segment_bytes >= self.config.segment_size {
+            self.rotate()?;
+        }
+        let line = format!(
+            "{},{},{},{}\n",
+            tx.tx_type.as_str(),
+            tx.client,
+            tx.tx,
+            tx.amount
+        );
+        self.file
+            .write_all(line.as_bytes())
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("Failed to write to log segment {}", self.segment))?;
+        self.segment_bytes += line.len() as u64;
+        self.appended += 1;
+        Ok(())
+    }
+
+    /// Returns whether [`WalConfig::snapshot_interval`] transactions were
+    /// appended since the last snapshot.
+    pub fn snapshot_due(&self) -> bool {
+        self.appended >= self.config.snapshot_interval.max(1)
+    }
+
+    /// Writes a snapshot of `engine`, which must contain every appended
+    /// transaction, and deletes the segments it covers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot cannot be written. Recovery still works
+    /// from the previous snapshot and the remaining segments in that case.
+    pub fn snapshot(&mut self, engine: &Engine) -> Result<()> {
+        self.rotate()?;
+        let snapshot = SnapshotRef::new(engine, self.segment);
+        write_snapshot(&self.dir.join(SNAPSHOT_FILE), &snapshot)?;
+
+        for segment in list_segments(&self.dir)? {
+            if segment < self.segment {
+                remove_file(&segment_path(&self.dir, segment))?;
+            }
+        }
+        self.appended = 0;
+        tracing::info!(
+            accounts = snapshot.accounts.len(),
+            deposits = snapshot.deposit_history.len(),
+            "wrote snapshot"
+        );
+        Ok(())
+    }
+
+    /// Continues appending in a new segment.
+    fn rotate(&mut self) -> Result<()> {
+        self.file = create_segment(&self.dir, self.segment + 1)?;
+        self.segment += 1;
+        self.segment_bytes = 0;
+        Ok(())
+    }
+}
+
+/// Reads the engine state from a snapshot file written by
+/// [`WriteAheadLog::snapshot`], e.g. `wal/snapshot.json`.
+///
+/// Only the snapshot is read; transactions logged after it are not replayed and
+/// the log directory is left untouched.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or parsed.
+pub fn read_snapshot_file(path: impl AsRef<Path>) -> Result<EngineState> {
+    let path = path.as_ref();
+    let file =
+        File::open(path).with_context(|| format!("Failed to open snapshot: {}", path.display()))?;
+    let snapshot: Snapshot = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Failed to parse snapshot: {}", path.display()))?;
+    Ok(snapshot.into_state())
+}
+
+/// Writes the state of `engine` to a snapshot file, e.g. as the checkpoint of a
+/// batch run that a later run continues from with [`read_snapshot_file`].
+///
+/// The file is replaced atomically, so an interrupted write leaves the previous
+/// snapshot intact.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn write_snapshot_file(path: impl AsRef<Path>, engine: &Engine) -> Result<()> {
+    write_snapshot(path.as_ref(), &SnapshotRef::new(engine, 0))
+}
+
+/// Writes `snapshot` to a temporary file next to `path` and renames it to
+/// `path` once it is synced to disk.
+fn write_snapshot(path: &Path, snapshot: &SnapshotRef) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let file = File::create(&temporary)
+        .with_context(|| format!("Failed to create snapshot: {}", temporary.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, snapshot)?;
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("Failed to replace snapshot: {}", path.display()))?;
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("segment-{:010}.wal", segment))
+}
+
+/// Returns the numbers of the segments in `dir`, in ascending order.
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("Failed to read log directory: {}", dir.display()))?
+    {
+        let name = entry?.file_name();
+        if let Some(segment) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("segment-"))
+            .and_then(|name| name.strip_suffix(".wal"))
+            .and_then(|number| number.parse().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+fn create_segment(dir: &Path, segment: u64) -> Result<File> {
+    let path = segment_path(dir, segment);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to create log segment: {}", path.display()))?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+/// Reads the transactions of a segment, truncating a final line that was cut
+/// short by a crash.
+fn read_segment(path: &Path) -> Result<Vec<Transaction>> {
+    let mut contents = fs::read(path)
+        .with_context(|| format!("Failed to read log segment: {}", path.display()))?;
+    let complete = contents
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    if complete < contents.len() {
+        tracing::warn!(
+            path = %path.display(),
+            bytes = contents.len() - complete,
+            "dropping incomplete last line of log segment"
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(complete as u64))
+            .with_context(|| format!("Failed to truncate log segment: {}", path.display()))?;
+        contents.truncate(complete);
+    }
+
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(contents.as_slice())
+        .into_deserialize::<Transaction>()
+        .enumerate()
+        .map(|(index, result)| {
+            result.with_context(|| {
+                format!(
+                    "Failed to parse line {} of log segment: {}",
+                    index + 1,
+                    path.display()
+                )
+            })
+        })
+        .collect()
+}
+
+fn read_snapshot(dir: &Path) -> Result<Option<Snapshot>> {
+    let path = dir.join(SNAPSHOT_FILE);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to open snapshot: {}", path.display()));
+        }
+    };
+    serde_json::from_reader(BufReader::new(file))
+        .map(Some)
+        .with_context(|| format!("Failed to parse snapshot: {}", path.display()))
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to remove log segment: {}", path.display()))
+}
+
+/// Makes created, renamed and removed entries of `dir` durable.
+fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync log directory: {}", dir.display()))?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Amount, ClientId, RawClientId, RawTxId, TxType};
+    use std::str::FromStr;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn transaction(tx_type: TxType, client: RawClientId, tx: RawTxId, amount: &str) -> Transaction {
+        Transaction {
+            tx_type,
+            client: ClientId(client),
+            tx: TxId(tx),
+            amount: Amount::from_str(amount).unwrap(),
+        }
+    }
+
+    /// Appends and applies `transactions` the way the server does.
+    fn receive(engine: &mut Engine, wal: &mut WriteAheadLog, transactions: &[Transaction]) {
+        for tx in transactions {
+            wal.append(tx).unwrap();
+            engine.process(tx.clone()).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_the_log_and_drops_a_torn_last_line() {
+        let dir = temp_dir("replay");
+        let (mut engine, mut wal) =
+            WriteAheadLog::recover(&dir, EngineConfig::default(), WalConfig::default()).unwrap();
+        receive(
+            &mut engine,
+            &mut wal,
+            &[
+                transaction(TxType::Deposit, 1, 1, "10.25"),
+                transaction(TxType::Deposit, 2, 2, "3"),
+                transaction(TxType::Dispute, 1, 1, "0"),
+            ],
+        );
+        // A crash in the middle of the next append.
+        wal.file.write_all(b"withdrawal,2,3,").unwrap();
+        drop(wal);
+
+        let (recovered, _) =
+            WriteAheadLog::recover(&dir, EngineConfig::default(), WalConfig::default()).unwrap();
+        assert_eq!(recovered.accounts(), engine.accounts());
+        assert_eq!(
+            recovered.state().deposit_history,
+            engine.state().deposit_history
+        );
+        let segment = fs::read_to_string(segment_path(&dir, 0)).unwrap();
+        assert!(segment.ends_with("dispute,1,1,0\n"), "{}", segment);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_keep_applied_transactions_for_the_next_run() {
+        let dir = temp_dir("checkpoint");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("checkpoint.json");
+        let config = EngineConfig {
+            idempotency: Some(crate::engine::IdempotencyKey::Tx),
+            ..Default::default()
+        };
+        let mut engine = Engine::new(config.clone());
+        engine
+            .process(transaction(TxType::Deposit, 1, 1, "10"))
+            .unwrap();
+        write_snapshot_file(&path, &engine).unwrap();
+
+        // The next run receives the deposit again from an overlapping file.
+        let mut engine = Engine::with_state(config, read_snapshot_file(&path).unwrap());
+        engine
+            .process(transaction(TxType::Deposit, 1, 1, "10"))
+            .unwrap();
+        engine
+            .process(transaction(TxType::Deposit, 1, 2, "5"))
+            .unwrap();
+        assert_eq!(
+            engine.accounts()[&ClientId(1)].total,
+            Amount::from_str("15").unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_truncate_covered_segments() {
+        let dir = temp_dir("snapshot");
+        let config = WalConfig {
+            segment_size: 1,
+            snapshot_interval: 3,
+        };
+        let (mut engine, mut wal) =
+            WriteAheadLog::recover(&dir, EngineConfig::default(), config.clone()).unwrap();
+        receive(
+            &mut engine,
+            &mut wal,
+            &[
+                transaction(TxType::Deposit, 1, 1, "10.25"),
+                transaction(TxType::Withdrawal, 1, 2, "0.25"),
+            ],
+        );
+        assert!(!wal.snapshot_due());
+        receive(
+            &mut engine,
+            &mut wal,
+            &[transaction(TxType::Deposit, 1, 3, "5")],
+        );
+        assert!(wal.snapshot_due());
+        assert_eq!(list_segments(&dir).unwrap().len(), 3);
+
+        wal.snapshot(&engine).unwrap();
+        assert!(!wal.snapshot_due());
+        assert_eq!(list_segments(&dir).unwrap(), [wal.segment]);
+        let state = read_snapshot_file(dir.join(SNAPSHOT_FILE)).unwrap();
+        assert_eq!(&state.accounts, engine.accounts());
+        assert_eq!(state.deposits, 2);
+        receive(
+            &mut engine,
+            &mut wal,
+            &[transaction(TxType::Dispute, 1, 3, "0")],
+        );
+        drop(wal);
+
+        let (recovered, wal) =
+            WriteAheadLog::recover(&dir, EngineConfig::default(), config).unwrap();
+        assert_eq!(recovered.accounts(), engine.accounts());
+        assert_eq!(
+            recovered.state().deposit_history,
+            engine.state().deposit_history
+        );
+        assert_eq!(recovered.state().deposits, 2);
+        assert_eq!(recovered.stats().transactions(), 1);
+        assert_eq!(list_segments(&dir).unwrap().last(), Some(&wal.segment));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+//! Differential test of the engine against the reference model in
+//! `tests/reference/`, on random sequences of deposits, withdrawals, disputes,
+//! resolves and chargebacks.
+
+mod reference;
+
+use project_diamond_hands::engine::Engine;
+use project_diamond_hands::types::{Amount, ClientId, Transaction, TxId, TxType};
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use reference::{Balances, Reference};
+
+/// A generated operation, turned into a transaction by [`transactions`].
+#[derive(Debug, Clone)]
+struct Operation {
+    tx_type: TxType,
+    client: u8,
+    /// Index of the operation a dispute, resolve or chargeback references.
+    reference: usize,
+    /// Amount in units of 1/10000, negative for invalid amounts.
+    units: i64,
+}
+
+fn operation() -> impl Strategy<Value = Operation> {
+    let tx_type = prop_oneof![
+        4 => Just(TxType::Deposit),
+        3 => Just(TxType::Withdrawal),
+        3 => Just(TxType::Dispute),
+        2 => Just(TxType::Resolve),
+        1 => Just(TxType::Chargeback),
+    ];
+    // Mostly whole amounts, so balances often match exactly, and few negative
+    // or partial dispute amounts.
+    let units = prop_oneof![
+        6 => (0i64..100).prop_map(|whole| whole * 10_000),
+        2 => 0i64..1_000_000,
+        1 => -1_000_000i64..0,
+    ];
+    (tx_type, 1u8..=3, any::<prop::sample::Index>(), units).prop_map(
+        |(tx_type, client, reference, units)| Operation {
+            tx_type,
+            client,
+            reference: reference.index(usize::MAX),
+            units,
+        },
+    )
+}
+
+/// Returns the transactions of `operations`. Deposits and withdrawals have the
+/// index of their operation as transaction ID. The other transactions mostly
+/// reference an earlier deposit of the same client and have no amount, so
+/// dispute lifecycles run through to their end.
+fn transactions(operations: &[Operation]) -> Vec<Transaction> {
+    let mut deposits: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+    let mut transactions = Vec::new();
+    for (index, operation) in operations.iter().enumerate() {
+        let referenced = || {
+            let own = deposits
+                .get(&operation.client)
+                .map_or(&[][..], Vec::as_slice);
+            if own.is_empty() || operation.reference % 10 == 0 {
+                operation.reference % operations.len()
+            } else {
+                own[operation.reference % own.len()]
+            }
+        };
+        let (tx, units) = match operation.tx_type {
+            TxType::Deposit | TxType::Withdrawal => (index, operation.units),
+            TxType::Dispute if operation.units % 3 == 0 => (referenced(), operation.units),
+            _ => (referenced(), 0),
+        };
+        if operation.tx_type == TxType::Deposit {
+            deposits.entry(operation.client).or_default().push(index);
+        }
+        transactions.push(Transaction {
+            tx_type: operation.tx_type,
+            client: ClientId(operation.client.into()),
+            tx: TxId(tx.try_into().unwrap()),
+            amount: amount(units),
+        });
+    }
+    transactions
+}
+
+fn amount(units: i64) -> Amount {
+    let sign = if units < 0 { "-" } else { "" };
+    let units = units.unsigned_abs();
+    Amount::from_str(&format!("{}{}.{:04}", sign, units / 10_000, units % 10_000)).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn engine_matches_the_reference_model(
+        operations in prop::collection::vec(operation(), 1..120)
+    ) {
+        let mut engine = Engine::default();
+        let mut reference = Reference::default();
+        for tx in transactions(&operations) {
+            reference.process(&tx);
+            engine.process(tx).unwrap();
+        }
+
+        let accounts: BTreeMap<_, _> = engine
+            .accounts()
+            .iter()
+            .map(|(client, account)| {
+                let balances = Balances {
+                    available: account.available,
+                    held: account.held,
+                    total: account.total,
+                    locked: account.locked,
+                };
+                (*client, balances)
+            })
+            .collect();
+        prop_assert_eq!(accounts, reference.accounts());
+    }
+}
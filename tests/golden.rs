@@ -0,0 +1,12 @@
+//! Golden-file cases of `tests/golden/`, see the `golden` module.
+//!
+//! Run `UPDATE_GOLDEN=1 cargo test --test golden` to write the expected files
+//! from the actual accounts.
+
+#[test]
+fn golden_cases() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    if let Err(err) = project_diamond_hands::golden::check_cases(dir) {
+        panic!("{:#}", err);
+    }
+}
@@ -0,0 +1,146 @@
+//! A reference implementation of the default processing rules for deposits,
+//! withdrawals, disputes, resolves and chargebacks.
+//!
+//! It is deliberately naive: it only keeps the list of accepted transactions
+//! and derives every balance and dispute state from it again for each new
+//! transaction, so it is quadratic in the input but easy to check against the
+//! rules in the README. The engine has to produce the same accounts.
+
+use project_diamond_hands::types::{Amount, ClientId, Transaction, TxId, TxType};
+use std::collections::BTreeMap;
+
+/// The balances of a client's account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Balances {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+/// The accepted transactions, in order, with the amount they moved: the
+/// deposited or withdrawn amount, the amount a dispute held, or the amount a
+/// resolve or chargeback settled.
+#[derive(Debug, Clone, Default)]
+pub struct Reference {
+    accepted: Vec<(Transaction, Amount)>,
+}
+
+impl Reference {
+    /// Applies `tx` if the rules accept it.
+    pub fn process(&mut self, tx: &Transaction) {
+        if let Some(amount) = self.accepted_amount(tx) {
+            self.accepted.push((tx.clone(), amount));
+        }
+    }
+
+    /// Returns the accounts of all clients with an accepted transaction.
+    pub fn accounts(&self) -> BTreeMap<ClientId, Balances> {
+        self.accepted
+            .iter()
+            .map(|(tx, _)| (tx.client, self.balances(tx.client).unwrap()))
+            .collect()
+    }
+
+    /// Returns the amount `tx` moves, or `None` if it is ignored.
+    fn accepted_amount(&self, tx: &Transaction) -> Option<Amount> {
+        let account = self.balances(tx.client);
+        if tx.amount < Amount::ZERO || account.is_some_and(|account| account.locked) {
+            return None;
+        }
+        match tx.tx_type {
+            TxType::Deposit => Some(tx.amount),
+            TxType::Withdrawal => (tx.amount <= account?.available).then_some(tx.amount),
+            TxType::Dispute => {
+                let deposited = self.deposited(tx)?;
+                // A resolved deposit may be disputed again, a charged back one not.
+                if self.active_dispute(tx.tx).is_some() || self.charged_back(tx.tx) {
+                    return None;
+                }
+                let amount = if tx.amount == Amount::ZERO {
+                    deposited
+                } else {
+                    tx.amount
+                };
+                (amount <= deposited && amount <= account?.available).then_some(amount)
+            }
+            TxType::Resolve | TxType::Chargeback => {
+                self.deposited(tx)?;
+                self.active_dispute(tx.tx)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the balances of `client` derived from the accepted transactions,
+    /// or `None` if the client has none.
+    fn balances(&self, client: ClientId) -> Option<Balances> {
+        let mut transactions = self
+            .accepted
+            .iter()
+            .filter(|(tx, _)| tx.client == client)
+            .peekable();
+        transactions.peek()?;
+        let (mut available, mut held, mut locked) = (Amount::ZERO, Amount::ZERO, false);
+        for (tx, amount) in transactions {
+            let amount = *amount;
+            match tx.tx_type {
+                TxType::Deposit => available = available.checked_add(amount).unwrap(),
+                TxType::Withdrawal => available = available.checked_sub(amount).unwrap(),
+                TxType::Dispute => {
+                    available = available.checked_sub(amount).unwrap();
+                    held = held.checked_add(amount).unwrap();
+                }
+                TxType::Resolve => {
+                    available = available.checked_add(amount).unwrap();
+                    held = held.checked_sub(amount).unwrap();
+                }
+                TxType::Chargeback => {
+                    held = held.checked_sub(amount).unwrap();
+                    locked = true;
+                }
+                _ => unreachable!("only the handled types are accepted"),
+            }
+        }
+        Some(Balances {
+            available,
+            held,
+            total: available.checked_add(held).unwrap(),
+            locked,
+        })
+    }
+
+    /// Returns the amount of the accepted deposit `tx` references, if it is a
+    /// deposit of the same client.
+    fn deposited(&self, tx: &Transaction) -> Option<Amount> {
+        self.accepted.iter().find_map(|(deposit, amount)| {
+            (deposit.tx_type == TxType::Deposit
+                && deposit.tx == tx.tx
+                && deposit.client == tx.client)
+                .then_some(*amount)
+        })
+    }
+
+    /// Returns the amount held by the dispute of deposit `tx` if its last
+    /// accepted dispute, resolve or chargeback is a dispute.
+    fn active_dispute(&self, tx: TxId) -> Option<Amount> {
+        self.accepted
+            .iter()
+            .rev()
+            .find(|(settlement, _)| {
+                settlement.tx == tx
+                    && matches!(
+                        settlement.tx_type,
+                        TxType::Dispute | TxType::Resolve | TxType::Chargeback
+                    )
+            })
+            .filter(|(settlement, _)| settlement.tx_type == TxType::Dispute)
+            .map(|(_, amount)| *amount)
+    }
+
+    fn charged_back(&self, tx: TxId) -> bool {
+        self.accepted
+            .iter()
+            .any(|(settlement, _)| settlement.tx == tx && settlement.tx_type == TxType::Chargeback)
+    }
+}
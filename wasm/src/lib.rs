@@ -0,0 +1,20 @@
+//! WebAssembly bindings of the engine.
+//!
+//! Build them for the browser with `wasm-pack build --target web` in this
+//! directory; the functions process CSV text with the same logic as the
+//! application.
+
+use project_diamond_hands::embed;
+use project_diamond_hands::engine::EngineConfig;
+use wasm_bindgen::prelude::*;
+
+/// Processes the transactions of the CSV text `input` and returns the accounts
+/// as CSV text, as `diamond-hands` would print them with default options.
+///
+/// Throws an `Error` with the message of the first failure, e.g. a record that
+/// cannot be parsed.
+#[wasm_bindgen]
+pub fn process_csv(input: &str) -> Result<String, JsError> {
+    embed::process_csv(input, EngineConfig::default())
+        .map_err(|err| JsError::new(&format!("{:#}", err)))
+}